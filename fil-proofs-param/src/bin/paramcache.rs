@@ -9,7 +9,7 @@ use filecoin_proofs::{
         WINDOW_POST_SECTOR_COUNT, WINNING_POST_CHALLENGE_COUNT, WINNING_POST_SECTOR_COUNT,
     },
     parameters::{public_params, window_post_public_params, winning_post_public_params},
-    types::{PoRepConfig, PoStConfig, SectorSize},
+    types::{PoRepConfig, PoStConfig, SectorSize, SectorUpdateConfig},
     with_shape, PoStType,
 };
 use humansize::{file_size_opts, FileSize};
@@ -24,8 +24,8 @@ use storage_proofs_porep::stacked::{StackedCircuit, StackedCompound, StackedDrg}
 use storage_proofs_post::fallback::{FallbackPoSt, FallbackPoStCircuit, FallbackPoStCompound};
 use storage_proofs_update::constants::TreeRHasher;
 use storage_proofs_update::{
-    circuit::EmptySectorUpdateCircuit, compound::EmptySectorUpdateCompound, EmptySectorUpdate,
-    PublicParams,
+    circuit::EmptySectorUpdateCircuit, compound::EmptySectorUpdateCompound, poseidon,
+    EmptySectorUpdate, PublicParams,
 };
 use structopt::StructOpt;
 
@@ -142,6 +142,38 @@ fn cache_empty_sector_update_params<Tree: 'static + MerkleTreeTrait<Hasher = Tre
     .expect("failed to get verifying key");
 }
 
+fn cache_empty_sector_update_poseidon_params<
+    Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>,
+>(
+    porep_config: PoRepConfig,
+) {
+    info!("generating EmptySectorUpdate Poseidon groth params");
+
+    let public_params =
+        SectorUpdateConfig::from_porep_config_poseidon(&porep_config).public_params();
+
+    let circuit = <poseidon::EmptySectorUpdateCompound<Tree> as CompoundProof<
+        poseidon::EmptySectorUpdate<Tree>,
+        poseidon::EmptySectorUpdateCircuit<Tree>,
+    >>::blank_circuit(&public_params);
+
+    let _ = <poseidon::EmptySectorUpdateCompound<Tree> as CompoundProof<
+        poseidon::EmptySectorUpdate<Tree>,
+        poseidon::EmptySectorUpdateCircuit<Tree>,
+    >>::groth_params::<OsRng>(Some(&mut OsRng), &public_params)
+    .expect("failed to get groth params");
+
+    let _ =
+        <poseidon::EmptySectorUpdateCompound<Tree>>::get_param_metadata(circuit, &public_params)
+            .expect("failed to get metadata");
+
+    let _ = <poseidon::EmptySectorUpdateCompound<Tree> as CompoundProof<
+        poseidon::EmptySectorUpdate<Tree>,
+        poseidon::EmptySectorUpdateCircuit<Tree>,
+    >>::verifying_key::<OsRng>(Some(&mut OsRng), &public_params)
+    .expect("failed to get verifying key");
+}
+
 #[derive(Debug, StructOpt)]
 #[structopt(
     name = "paramcache",
@@ -156,6 +188,11 @@ struct Opt {
         help = "Only cache EmptySectorUpdate groth params."
     )]
     only_sector_update: bool,
+    #[structopt(
+        long,
+        help = "Also cache the groth params of the Poseidon variant of EmptySectorUpdate."
+    )]
+    sector_update_poseidon: bool,
     #[structopt(
         short = "z",
         long,
@@ -220,6 +257,14 @@ fn generate_params_empty_sector_update(sector_size: u64, api_version: ApiVersion
     );
 }
 
+fn generate_params_empty_sector_update_poseidon(sector_size: u64, api_version: ApiVersion) {
+    with_shape!(
+        sector_size,
+        cache_empty_sector_update_poseidon_params,
+        PoRepConfig::new_groth16(sector_size, [0; 32], api_version)
+    );
+}
+
 pub fn main() {
     // Create a stderr logger for all log levels.
     env::set_var("RUST_LOG", "paramcache");
@@ -287,12 +332,18 @@ pub fn main() {
 
         if opts.only_sector_update {
             generate_params_empty_sector_update(sector_size, api_version);
+            if opts.sector_update_poseidon {
+                generate_params_empty_sector_update_poseidon(sector_size, api_version);
+            }
         } else {
             generate_params_post(sector_size, api_version);
 
             if !opts.only_post {
                 generate_params_porep(sector_size, api_version);
                 generate_params_empty_sector_update(sector_size, api_version);
+                if opts.sector_update_poseidon {
+                    generate_params_empty_sector_update_poseidon(sector_size, api_version);
+                }
             }
         }
 
@@ -0,0 +1,81 @@
+use std::fs::read;
+use std::path::Path;
+
+use anyhow::{ensure, Context, Result};
+use bincode::deserialize;
+use fil_proofs_tooling::shared;
+use filecoin_proofs::types::SealCommitPhase1Output;
+use filecoin_proofs::{check_seal_commit_phase1_output, with_shape};
+use log::info;
+use storage_proofs_core::{
+    api_version::{ApiFeature, ApiVersion},
+    merkle::MerkleTreeTrait,
+};
+
+fn check_circuit<Tree: 'static + MerkleTreeTrait>(
+    sector_size: u64,
+    api_version: ApiVersion,
+    api_features: Vec<ApiFeature>,
+    phase1_output_path: &Path,
+) -> Result<()> {
+    let phase1_output_bytes = read(phase1_output_path).with_context(|| {
+        format!(
+            "could not read file phase1_output_path={:?}",
+            phase1_output_path
+        )
+    })?;
+    let phase1_output: SealCommitPhase1Output<Tree> = deserialize(&phase1_output_bytes)
+        .with_context(|| {
+            format!(
+                "could not deserialize file phase1_output_path={:?}",
+                phase1_output_path
+            )
+        })?;
+
+    let porep_config = shared::get_porep_config(sector_size, api_version, api_features);
+    let check = check_seal_commit_phase1_output(&porep_config, &phase1_output)?;
+
+    println!("vanilla proofs valid: {}", check.vanilla_proofs_valid);
+    for partition in &check.partitions {
+        println!(
+            "partition {}: constraints: {}, inputs: {}, public inputs match: {}",
+            partition.partition,
+            partition.num_constraints,
+            partition.num_inputs,
+            partition.inputs_match
+        );
+        if let Some(synthesis_error) = &partition.synthesis_error {
+            println!("  synthesis failed: {}", synthesis_error);
+        }
+        if let Some(unsatisfied_constraint) = &partition.unsatisfied_constraint {
+            println!("  first unsatisfied constraint: {}", unsatisfied_constraint);
+        }
+    }
+
+    ensure!(
+        check.is_ok(),
+        "commit phase 1 output does not satisfy the circuit"
+    );
+    Ok(())
+}
+
+pub fn run(
+    sector_size: usize,
+    api_version: ApiVersion,
+    api_features: Vec<ApiFeature>,
+    phase1_output_path: &Path,
+) -> Result<()> {
+    info!(
+        "Benchy Check Circuit: sector-size={}, api_version={}, input={:?}",
+        sector_size, api_version, phase1_output_path
+    );
+
+    with_shape!(
+        sector_size as u64,
+        check_circuit,
+        sector_size as u64,
+        api_version,
+        api_features,
+        phase1_output_path,
+    )
+}
@@ -43,6 +43,40 @@ fn blake2s_count(bytes: usize) -> anyhow::Result<Report> {
     })
 }
 
+fn blake3_count(bytes: usize) -> anyhow::Result<Report> {
+    let rng = &mut rand::thread_rng();
+
+    let mut cs = TestConstraintSystem::<Fr>::new();
+    let mut data = vec![0u8; bytes];
+    rng.fill_bytes(&mut data);
+
+    let data_bits: Vec<Boolean> = {
+        let mut cs = cs.namespace(|| "data");
+        bytes_into_boolean_vec(&mut cs, Some(data.as_slice()), data.len())
+            .expect("failed to convert to boolean vector")
+    };
+
+    let out: Vec<bool> = filecoin_hashers::blake3::blake3_circuit(&mut cs, &data_bits)?
+        .into_iter()
+        .map(|b| b.get_value().expect("failed to get bool value"))
+        .collect();
+
+    assert!(cs.is_satisfied(), "constraints not satisfied");
+
+    let expected = blake3::hash(&data);
+    assert_eq!(
+        expected.as_bytes(),
+        &bits_to_bytes(&out[..])[..],
+        "circuit and non circuit do not match"
+    );
+
+    Ok(Report {
+        hash_fn: "blake3".into(),
+        bytes,
+        constraints: cs.num_constraints(),
+    })
+}
+
 fn sha256_count(bytes: usize) -> anyhow::Result<Report> {
     let mut rng = rand::thread_rng();
 
@@ -84,6 +118,10 @@ pub fn run() -> anyhow::Result<()> {
         blake2s_count(64)?,
         blake2s_count(128)?,
         blake2s_count(256)?,
+        blake3_count(32)?,
+        blake3_count(64)?,
+        blake3_count(128)?,
+        blake3_count(256)?,
         sha256_count(32)?,
         sha256_count(64)?,
         sha256_count(128)?,
@@ -1,6 +1,7 @@
 //requires nightly, or later stable version
 //#![warn(clippy::unwrap_used)]
 
+use std::path::PathBuf;
 use std::str::FromStr;
 
 use anyhow::Result;
@@ -9,6 +10,7 @@ use clap::{builder::PossibleValuesParser, Arg, ArgMatches, Command};
 
 use storage_proofs_core::api_version::{ApiFeature, ApiVersion};
 
+mod check_circuit;
 mod hash_fns;
 mod merkleproofs;
 mod porep;
@@ -287,6 +289,38 @@ fn main() -> Result<()> {
                 .takes_value(false),
         );
 
+    let check_circuit_cmd = Command::new("check-circuit")
+        .about("Check a persisted commit phase 1 output against the PoRep circuit")
+        .arg(
+            Arg::new("input")
+                .long("input")
+                .required(true)
+                .help("The bincode encoded commit phase 1 output (e.g. commit-phase1-output in the porep cache)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("size")
+                .long("size")
+                .required(true)
+                .help("The data size (e.g. 2KiB)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("api_features")
+                .long("api-features")
+                .value_delimiter(',')
+                .value_parser(PossibleValuesParser::new(API_FEATURES))
+                .help("The api_features to use, comma separated (e.g. synthetic-porep)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("api_version")
+                .long("api-version")
+                .help("The api_version to use (default: 1.2.0)")
+                .default_value("1.2.0")
+                .takes_value(true),
+        );
+
     let matches = Command::new("benchy")
         .version("0.1")
         .arg_required_else_help(true)
@@ -296,6 +330,7 @@ fn main() -> Result<()> {
         .subcommand(hash_cmd)
         .subcommand(porep_cmd)
         .subcommand(merkleproof_cmd)
+        .subcommand(check_circuit_cmd)
         .get_matches();
 
     match matches.subcommand() {
@@ -407,6 +442,14 @@ fn main() -> Result<()> {
                 test_resume,
             )?;
         }
+        Some(("check-circuit", m)) => {
+            let input = PathBuf::from(m.value_of_t::<String>("input")?);
+            let sector_size = Byte::from_str(m.value_of_t::<String>("size")?)?.get_bytes() as usize;
+            let api_version = ApiVersion::from_str(&m.value_of_t::<String>("api_version")?)?;
+            let api_features = parse_api_features(m)?;
+
+            check_circuit::run(sector_size, api_version, api_features, &input)?;
+        }
         _ => unreachable!(),
     }
 
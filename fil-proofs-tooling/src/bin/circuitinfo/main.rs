@@ -1,3 +1,7 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::PathBuf;
 use std::str::FromStr;
 
 use bellperson::{util_cs::bench_cs::BenchCS, Circuit};
@@ -16,8 +20,16 @@ use storage_proofs_core::{
 };
 use storage_proofs_porep::stacked::{StackedCompound, StackedDrg};
 use storage_proofs_post::fallback::{FallbackPoSt, FallbackPoStCircuit, FallbackPoStCompound};
+use storage_proofs_update::{
+    constants::TreeRHasher, poseidon, EmptySectorUpdate, EmptySectorUpdateCircuit,
+    EmptySectorUpdateCompound, PublicParams as UpdatePublicParams,
+};
 use structopt::StructOpt;
 
+mod profile;
+
+use profile::{diff_tree_string, ConstraintProfile, ProfilingCS};
+
 struct CircuitInfo {
     constraints: usize,
     inputs: usize,
@@ -35,7 +47,36 @@ fn circuit_info<C: Circuit<Fr>>(circuit: C) -> CircuitInfo {
     }
 }
 
-fn get_porep_info<Tree: 'static + MerkleTreeTrait>(porep_config: PoRepConfig) -> CircuitInfo {
+/// Synthesizes the circuit and returns its per-namespace constraint breakdown.
+fn circuit_profile<C: Circuit<Fr>>(name: &str, circuit: C) -> ConstraintProfile {
+    let mut cs = ProfilingCS::new(name);
+    circuit.synthesize(&mut cs).expect("failed to synthesize");
+    cs.finish()
+}
+
+/// Either counts the constraints of a circuit or, if `profile` is set, returns its full
+/// per-namespace breakdown.
+fn circuit_info_or_profile<C: Circuit<Fr>>(
+    name: &str,
+    circuit: C,
+    profile: bool,
+) -> (CircuitInfo, Option<ConstraintProfile>) {
+    if profile {
+        let circuit_profile = circuit_profile(name, circuit);
+        let info = CircuitInfo {
+            constraints: circuit_profile.constraints,
+            inputs: circuit_profile.inputs,
+        };
+        (info, Some(circuit_profile))
+    } else {
+        (circuit_info(circuit), None)
+    }
+}
+
+fn get_porep_info<Tree: 'static + MerkleTreeTrait>(
+    porep_config: PoRepConfig,
+    profile: bool,
+) -> (CircuitInfo, Option<ConstraintProfile>) {
     info!("PoRep info");
 
     let public_params =
@@ -46,10 +87,13 @@ fn get_porep_info<Tree: 'static + MerkleTreeTrait>(porep_config: PoRepConfig) ->
         _,
     >>::blank_circuit(&public_params);
 
-    circuit_info(circuit)
+    circuit_info_or_profile("StackedCircuit", circuit, profile)
 }
 
-fn get_winning_post_info<Tree: 'static + MerkleTreeTrait>(post_config: &PoStConfig) -> CircuitInfo {
+fn get_winning_post_info<Tree: 'static + MerkleTreeTrait>(
+    post_config: &PoStConfig,
+    profile: bool,
+) -> (CircuitInfo, Option<ConstraintProfile>) {
     info!("Winning PoSt info");
 
     let post_public_params = winning_post_public_params::<Tree>(post_config)
@@ -60,10 +104,13 @@ fn get_winning_post_info<Tree: 'static + MerkleTreeTrait>(post_config: &PoStConf
         FallbackPoStCircuit<Tree>,
     >>::blank_circuit(&post_public_params);
 
-    circuit_info(circuit)
+    circuit_info_or_profile("FallbackPoStCircuit", circuit, profile)
 }
 
-fn get_window_post_info<Tree: 'static + MerkleTreeTrait>(post_config: &PoStConfig) -> CircuitInfo {
+fn get_window_post_info<Tree: 'static + MerkleTreeTrait>(
+    post_config: &PoStConfig,
+    profile: bool,
+) -> (CircuitInfo, Option<ConstraintProfile>) {
     info!("Window PoSt info");
 
     let post_public_params = window_post_public_params::<Tree>(post_config)
@@ -74,7 +121,41 @@ fn get_window_post_info<Tree: 'static + MerkleTreeTrait>(post_config: &PoStConfi
         FallbackPoStCircuit<Tree>,
     >>::blank_circuit(&post_public_params);
 
-    circuit_info(circuit)
+    circuit_info_or_profile("FallbackPoStCircuit", circuit, profile)
+}
+
+fn get_update_info<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>>(
+    sector_size: u64,
+    profile: bool,
+) -> (CircuitInfo, Option<ConstraintProfile>) {
+    info!("Empty Sector Update info");
+
+    let public_params = UpdatePublicParams::from_sector_size(sector_size);
+
+    let circuit: EmptySectorUpdateCircuit<Tree> =
+        <EmptySectorUpdateCompound<Tree> as CompoundProof<
+            EmptySectorUpdate<Tree>,
+            EmptySectorUpdateCircuit<Tree>,
+        >>::blank_circuit(&public_params);
+
+    circuit_info_or_profile("EmptySectorUpdateCircuit", circuit, profile)
+}
+
+fn get_update_poseidon_info<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>>(
+    sector_size: u64,
+    profile: bool,
+) -> (CircuitInfo, Option<ConstraintProfile>) {
+    info!("Empty Sector Update (Poseidon) info");
+
+    let public_params = UpdatePublicParams::from_sector_size_poseidon(sector_size);
+
+    let circuit: poseidon::EmptySectorUpdateCircuit<Tree> =
+        <poseidon::EmptySectorUpdateCompound<Tree> as CompoundProof<
+            poseidon::EmptySectorUpdate<Tree>,
+            poseidon::EmptySectorUpdateCircuit<Tree>,
+        >>::blank_circuit(&public_params);
+
+    circuit_info_or_profile("EmptySectorUpdatePoseidonCircuit", circuit, profile)
 }
 
 #[derive(Debug, StructOpt)]
@@ -86,13 +167,47 @@ struct Opt {
     window: bool,
     #[structopt(long)]
     porep: bool,
+    #[structopt(long, help = "Empty Sector Update (SnapDeals) circuit")]
+    update: bool,
+    #[structopt(
+        long,
+        help = "Empty Sector Update (SnapDeals) circuit with Poseidon challenges"
+    )]
+    update_poseidon: bool,
+    #[structopt(
+        long,
+        help = "Print a per-namespace breakdown of the constraints and witness allocations"
+    )]
+    profile: bool,
+    #[structopt(
+        long,
+        default_value = "3",
+        help = "Maximum namespace depth of the printed breakdown and diff"
+    )]
+    depth: usize,
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Write the breakdown of all circuits as JSON into this file (implies --profile)"
+    )]
+    json: Option<PathBuf>,
+    #[structopt(
+        long,
+        parse(from_os_str),
+        number_of_values = 2,
+        value_names = &["OLD", "NEW"],
+        help = "Compare two JSON files written by --json and print the differences"
+    )]
+    diff: Vec<PathBuf>,
     #[structopt(short = "z", long, use_delimiter = true)]
     constraints_for_sector_sizes: Vec<u64>,
     #[structopt(default_value = "1.0.0", long)]
     api_version: String,
 }
 
-fn winning_post_info(sector_size: u64, api_version: ApiVersion) -> CircuitInfo {
+type Info = (CircuitInfo, Option<ConstraintProfile>);
+
+fn winning_post_info(sector_size: u64, api_version: ApiVersion, profile: bool) -> Info {
     with_shape!(
         sector_size,
         get_winning_post_info,
@@ -103,11 +218,12 @@ fn winning_post_info(sector_size: u64, api_version: ApiVersion) -> CircuitInfo {
             typ: PoStType::Winning,
             priority: true,
             api_version,
-        }
+        },
+        profile
     )
 }
 
-fn window_post_info(sector_size: u64, api_version: ApiVersion) -> CircuitInfo {
+fn window_post_info(sector_size: u64, api_version: ApiVersion, profile: bool) -> Info {
     with_shape!(
         sector_size,
         get_window_post_info,
@@ -122,11 +238,12 @@ fn window_post_info(sector_size: u64, api_version: ApiVersion) -> CircuitInfo {
             typ: PoStType::Window,
             priority: true,
             api_version,
-        }
+        },
+        profile
     )
 }
 
-fn porep_info(sector_size: u64, api_version: ApiVersion) -> (CircuitInfo, usize) {
+fn porep_info(sector_size: u64, api_version: ApiVersion, profile: bool) -> (Info, usize) {
     let partitions = PoRepProofPartitions(
         *POREP_PARTITIONS
             .read()
@@ -137,11 +254,43 @@ fn porep_info(sector_size: u64, api_version: ApiVersion) -> (CircuitInfo, usize)
     let info = with_shape!(
         sector_size,
         get_porep_info,
-        PoRepConfig::new_groth16(sector_size, [0; 32], api_version)
+        PoRepConfig::new_groth16(sector_size, [0; 32], api_version),
+        profile
     );
     (info, partitions.into())
 }
 
+fn update_info(sector_size: u64, profile: bool) -> Info {
+    with_shape!(sector_size, get_update_info, sector_size, profile)
+}
+
+fn update_poseidon_info(sector_size: u64, profile: bool) -> Info {
+    with_shape!(sector_size, get_update_poseidon_info, sector_size, profile)
+}
+
+fn read_profiles(path: &PathBuf) -> BTreeMap<String, ConstraintProfile> {
+    let file = File::open(path).unwrap_or_else(|_| panic!("failed to open {}", path.display()));
+    serde_json::from_reader(BufReader::new(file))
+        .unwrap_or_else(|_| panic!("failed to parse {}", path.display()))
+}
+
+fn print_diff(old_path: &PathBuf, new_path: &PathBuf, depth: usize) {
+    let old = read_profiles(old_path);
+    let new = read_profiles(new_path);
+
+    let mut keys: Vec<&String> = new.keys().collect();
+    keys.extend(old.keys().filter(|key| !new.contains_key(*key)));
+
+    for key in keys {
+        let diff = diff_tree_string(old.get(key), new.get(key), depth);
+        if diff.is_empty() {
+            println!("{}: no changes", key);
+        } else {
+            println!("{}:\n{}", key, diff);
+        }
+    }
+}
+
 // Run this from the command-line to get info about circuits.
 pub fn main() {
     // The logger is used and every message from this tool is also logged into those logs.
@@ -151,6 +300,11 @@ pub fn main() {
 
     let opts = Opt::from_args();
 
+    if !opts.diff.is_empty() {
+        print_diff(&opts.diff[0], &opts.diff[1], opts.depth);
+        return;
+    }
+
     // Display interactive menu if no sizes are given
     let sizes: Vec<u64> = if opts.constraints_for_sector_sizes.is_empty() {
         let sector_sizes = SUPPORTED_SECTOR_SIZES
@@ -207,9 +361,24 @@ pub fn main() {
     let count_winning = opts.winning;
     let count_window = opts.window;
     let count_porep = opts.porep;
+    let count_update = opts.update;
+    let count_update_poseidon = opts.update_poseidon;
+    let profile = opts.profile || opts.json.is_some();
+    let depth = opts.depth;
     let api_version = ApiVersion::from_str(&opts.api_version)
         .expect("Failed to parse api_version from semver string");
 
+    // All collected breakdowns, keyed by sector size and circuit.
+    let mut profiles = BTreeMap::new();
+    let mut print_profile = |key: String, circuit_profile: Option<ConstraintProfile>| {
+        if let Some(circuit_profile) = circuit_profile {
+            // Only the printed breakdown is limited to `depth`, the JSON output contains the full
+            // tree.
+            println!("{}", circuit_profile.to_tree_string(depth));
+            profiles.insert(key, circuit_profile);
+        }
+    };
+
     for sector_size in sizes {
         let human_size = sector_size
             .file_size(file_size_opts::BINARY)
@@ -217,27 +386,57 @@ pub fn main() {
         println!("Getting circuit info for sector size: {}", human_size);
 
         if count_winning {
-            let info = winning_post_info(sector_size, api_version);
+            let (info, circuit_profile) = winning_post_info(sector_size, api_version, profile);
             println!(
                 "{} Winning PoSt constraints: {}, public inputs: {}, partitions: 1",
                 human_size, info.constraints, info.inputs
             );
+            print_profile(format!("{} winning-post", sector_size), circuit_profile);
         }
 
         if count_window {
-            let info = window_post_info(sector_size, api_version);
+            let (info, circuit_profile) = window_post_info(sector_size, api_version, profile);
             println!(
                 "{} Window PoSt constraints (per partition): {}, public inputs (per partition): {}, partitions: <depends on input size>",
                 human_size, info.constraints, info.inputs
             );
+            print_profile(format!("{} window-post", sector_size), circuit_profile);
         }
 
         if count_porep {
-            let (info, partitions) = porep_info(sector_size, api_version);
+            let ((info, circuit_profile), partitions) =
+                porep_info(sector_size, api_version, profile);
             println!(
                 "{} PoRep constraints: {}, public inputs: {}, partitions: {}",
                 human_size, info.constraints, info.inputs, partitions
             );
+            print_profile(format!("{} porep", sector_size), circuit_profile);
         }
+
+        if count_update {
+            let (info, circuit_profile) = update_info(sector_size, profile);
+            println!(
+                "{} Empty Sector Update constraints (per partition): {}, public inputs (per partition): {}",
+                human_size, info.constraints, info.inputs
+            );
+            print_profile(format!("{} update", sector_size), circuit_profile);
+        }
+
+        if count_update_poseidon {
+            let (info, circuit_profile) = update_poseidon_info(sector_size, profile);
+            println!(
+                "{} Empty Sector Update (Poseidon) constraints: {}, public inputs: {}, partitions: 1",
+                human_size, info.constraints, info.inputs
+            );
+            print_profile(format!("{} update-poseidon", sector_size), circuit_profile);
+        }
+    }
+
+    if let Some(json_path) = opts.json {
+        let file = File::create(&json_path)
+            .unwrap_or_else(|_| panic!("failed to create {}", json_path.display()));
+        serde_json::to_writer_pretty(BufWriter::new(file), &profiles)
+            .expect("failed to write constraint profiles");
+        println!("Wrote constraint profiles to {}", json_path.display());
     }
 }
@@ -0,0 +1,344 @@
+use std::cmp::Ordering;
+
+use bellperson::{ConstraintSystem, Index, LinearCombination, SynthesisError, Variable};
+use blstrs::Scalar as Fr;
+use serde::{Deserialize, Serialize};
+
+/// Constraint, witness (aux) and public input counts of a single bellperson namespace. The counts
+/// include all of the namespace's descendants.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConstraintProfile {
+    pub name: String,
+    pub constraints: usize,
+    pub aux: usize,
+    pub inputs: usize,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<ConstraintProfile>,
+}
+
+impl ConstraintProfile {
+    fn new(name: String) -> Self {
+        ConstraintProfile {
+            name,
+            ..Default::default()
+        }
+    }
+
+    /// Adds the counts of `other` to `self` and merges the children by name, so that namespaces
+    /// which are entered multiple times (e.g. from within a loop) end up in a single node.
+    fn absorb(&mut self, other: ConstraintProfile) {
+        self.constraints += other.constraints;
+        self.aux += other.aux;
+        self.inputs += other.inputs;
+        for child in other.children {
+            self.add_child(child);
+        }
+    }
+
+    fn add_child(&mut self, child: ConstraintProfile) {
+        match self.children.iter_mut().find(|c| c.name == child.name) {
+            Some(existing) => existing.absorb(child),
+            None => self.children.push(child),
+        }
+    }
+
+    fn child(&self, name: &str) -> Option<&ConstraintProfile> {
+        self.children.iter().find(|c| c.name == name)
+    }
+
+    /// Renders the profile as an indented tree, descending at most `max_depth` levels.
+    pub fn to_tree_string(&self, max_depth: usize) -> String {
+        let mut out = String::new();
+        self.write_tree(&mut out, 0, max_depth);
+        out
+    }
+
+    fn write_tree(&self, out: &mut String, depth: usize, max_depth: usize) {
+        out.push_str(&format!(
+            "{}{}: constraints: {}, aux: {}, inputs: {}\n",
+            "  ".repeat(depth),
+            self.name,
+            self.constraints,
+            self.aux,
+            self.inputs,
+        ));
+        if depth < max_depth {
+            for child in &self.children {
+                child.write_tree(out, depth + 1, max_depth);
+            }
+        }
+    }
+}
+
+/// A constraint system which does not store any constraints or assignments (similar to
+/// `BenchCS`), but records the number of constraints and allocations per namespace.
+pub struct ProfilingCS {
+    // The currently open namespaces, the first element is the root.
+    stack: Vec<ConstraintProfile>,
+    num_aux: usize,
+    num_inputs: usize,
+}
+
+impl ProfilingCS {
+    pub fn new(name: &str) -> Self {
+        let mut root = ConstraintProfile::new(name.to_string());
+        // Account for the implicit `ONE` input.
+        root.inputs = 1;
+        ProfilingCS {
+            stack: vec![root],
+            num_aux: 0,
+            num_inputs: 1,
+        }
+    }
+
+    /// Returns the profile of the synthesized circuit. Namespaces that were not popped are closed.
+    pub fn finish(mut self) -> ConstraintProfile {
+        while self.stack.len() > 1 {
+            self.pop_namespace();
+        }
+        self.stack.pop().expect("root namespace must exist")
+    }
+
+    fn current(&mut self) -> &mut ConstraintProfile {
+        self.stack.last_mut().expect("root namespace must exist")
+    }
+}
+
+impl ConstraintSystem<Fr> for ProfilingCS {
+    type Root = Self;
+
+    fn alloc<F, A, AR>(&mut self, _: A, _f: F) -> Result<Variable, SynthesisError>
+    where
+        F: FnOnce() -> Result<Fr, SynthesisError>,
+        A: FnOnce() -> AR,
+        AR: Into<String>,
+    {
+        // Don't invoke `f`, the circuit may be blank.
+        self.current().aux += 1;
+        self.num_aux += 1;
+        Ok(Variable::new_unchecked(Index::Aux(self.num_aux - 1)))
+    }
+
+    fn alloc_input<F, A, AR>(&mut self, _: A, _f: F) -> Result<Variable, SynthesisError>
+    where
+        F: FnOnce() -> Result<Fr, SynthesisError>,
+        A: FnOnce() -> AR,
+        AR: Into<String>,
+    {
+        self.current().inputs += 1;
+        self.num_inputs += 1;
+        Ok(Variable::new_unchecked(Index::Input(self.num_inputs - 1)))
+    }
+
+    fn enforce<A, AR, LA, LB, LC>(&mut self, _: A, _a: LA, _b: LB, _c: LC)
+    where
+        A: FnOnce() -> AR,
+        AR: Into<String>,
+        LA: FnOnce(LinearCombination<Fr>) -> LinearCombination<Fr>,
+        LB: FnOnce(LinearCombination<Fr>) -> LinearCombination<Fr>,
+        LC: FnOnce(LinearCombination<Fr>) -> LinearCombination<Fr>,
+    {
+        self.current().constraints += 1;
+    }
+
+    fn push_namespace<NR, N>(&mut self, name_fn: N)
+    where
+        NR: Into<String>,
+        N: FnOnce() -> NR,
+    {
+        self.stack.push(ConstraintProfile::new(name_fn().into()));
+    }
+
+    fn pop_namespace(&mut self) {
+        assert!(self.stack.len() > 1, "cannot pop the root namespace");
+        let child = self.stack.pop().expect("namespace stack is not empty");
+        let parent = self.current();
+        parent.constraints += child.constraints;
+        parent.aux += child.aux;
+        parent.inputs += child.inputs;
+        parent.add_child(child);
+    }
+
+    fn get_root(&mut self) -> &mut Self::Root {
+        self
+    }
+}
+
+/// Writes the difference between two profiles as an indented tree. Only nodes whose counts differ
+/// are printed; nodes which only exist in one of the profiles are printed with a zero count on
+/// the other side.
+pub fn diff_tree_string(
+    old: Option<&ConstraintProfile>,
+    new: Option<&ConstraintProfile>,
+    max_depth: usize,
+) -> String {
+    let mut out = String::new();
+    write_diff(&mut out, old, new, 0, max_depth);
+    out
+}
+
+fn write_diff(
+    out: &mut String,
+    old: Option<&ConstraintProfile>,
+    new: Option<&ConstraintProfile>,
+    depth: usize,
+    max_depth: usize,
+) {
+    if old == new {
+        return;
+    }
+
+    let old_constraints = old.map_or(0, |p| p.constraints);
+    let new_constraints = new.map_or(0, |p| p.constraints);
+    let old_aux = old.map_or(0, |p| p.aux);
+    let new_aux = new.map_or(0, |p| p.aux);
+
+    let name = new.or(old).map(|p| p.name.as_str()).unwrap_or_default();
+    out.push_str(&format!(
+        "{}{}: constraints: {} -> {} ({}), aux: {} -> {} ({})\n",
+        "  ".repeat(depth),
+        name,
+        old_constraints,
+        new_constraints,
+        signed_delta(old_constraints, new_constraints),
+        old_aux,
+        new_aux,
+        signed_delta(old_aux, new_aux),
+    ));
+
+    if depth >= max_depth {
+        return;
+    }
+
+    // Children of the new profile first (in synthesis order), followed by the ones that were
+    // removed.
+    let empty = Vec::new();
+    let old_children = old.map_or(&empty, |p| &p.children);
+    let new_children = new.map_or(&empty, |p| &p.children);
+    for child in new_children {
+        write_diff(
+            out,
+            old.and_then(|p| p.child(&child.name)),
+            Some(child),
+            depth + 1,
+            max_depth,
+        );
+    }
+    for child in old_children {
+        if new.and_then(|p| p.child(&child.name)).is_none() {
+            write_diff(out, Some(child), None, depth + 1, max_depth);
+        }
+    }
+}
+
+fn signed_delta(old: usize, new: usize) -> String {
+    match new.cmp(&old) {
+        Ordering::Greater => format!("+{}", new - old),
+        Ordering::Less => format!("-{}", old - new),
+        Ordering::Equal => "0".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bellperson::Circuit;
+
+    // Allocates one input, then `rounds` times a namespace with one allocation and one constraint,
+    // which itself contains a nested namespace with another allocation and constraint.
+    struct TestCircuit {
+        rounds: usize,
+    }
+
+    impl Circuit<Fr> for TestCircuit {
+        fn synthesize<CS: ConstraintSystem<Fr>>(self, cs: &mut CS) -> Result<(), SynthesisError> {
+            cs.alloc_input(|| "input", || Ok(Fr::from(1u64)))?;
+            for _ in 0..self.rounds {
+                let mut cs = cs.namespace(|| "round");
+                let a = cs.alloc(|| "a", || Ok(Fr::from(2u64)))?;
+                cs.enforce(|| "a * a", |lc| lc + a, |lc| lc + a, |lc| lc + a);
+
+                let mut cs = cs.namespace(|| "inner");
+                let b = cs.alloc(|| "b", || Ok(Fr::from(3u64)))?;
+                cs.enforce(|| "b * b", |lc| lc + b, |lc| lc + b, |lc| lc + b);
+            }
+            Ok(())
+        }
+    }
+
+    fn test_profile(rounds: usize) -> ConstraintProfile {
+        let mut cs = ProfilingCS::new("test");
+        TestCircuit { rounds }
+            .synthesize(&mut cs)
+            .expect("failed to synthesize");
+        cs.finish()
+    }
+
+    #[test]
+    fn test_profiling_cs() {
+        let profile = test_profile(3);
+
+        assert_eq!(profile.constraints, 6);
+        assert_eq!(profile.aux, 6);
+        assert_eq!(profile.inputs, 2);
+
+        // The namespaces entered in every round are merged.
+        assert_eq!(profile.children.len(), 1);
+        let round = profile.child("round").expect("round namespace is missing");
+        assert_eq!((round.constraints, round.aux, round.inputs), (6, 6, 0));
+        let inner = round.child("inner").expect("inner namespace is missing");
+        assert_eq!((inner.constraints, inner.aux, inner.inputs), (3, 3, 0));
+        assert!(inner.children.is_empty());
+    }
+
+    #[test]
+    fn test_tree_string_depth() {
+        let profile = test_profile(1);
+
+        assert_eq!(
+            profile.to_tree_string(0),
+            "test: constraints: 2, aux: 2, inputs: 2\n"
+        );
+        assert_eq!(
+            profile.to_tree_string(1),
+            "test: constraints: 2, aux: 2, inputs: 2\n  round: constraints: 2, aux: 2, inputs: 0\n"
+        );
+        assert!(profile
+            .to_tree_string(2)
+            .ends_with("    inner: constraints: 1, aux: 1, inputs: 0\n"));
+    }
+
+    #[test]
+    fn test_json_contains_full_tree() {
+        let profile = test_profile(2);
+
+        let json = serde_json::to_string(&profile).expect("failed to serialize");
+        let deserialized: ConstraintProfile =
+            serde_json::from_str(&json).expect("failed to deserialize");
+        assert_eq!(deserialized, profile);
+        assert!(deserialized
+            .child("round")
+            .and_then(|round| round.child("inner"))
+            .is_some());
+    }
+
+    #[test]
+    fn test_diff_tree_string() {
+        let old = test_profile(2);
+        let new = test_profile(3);
+
+        assert!(diff_tree_string(Some(&old), Some(&old), 2).is_empty());
+        assert_eq!(
+            diff_tree_string(Some(&old), Some(&new), 2),
+            "test: constraints: 4 -> 6 (+2), aux: 4 -> 6 (+2)\n\
+             \x20 round: constraints: 4 -> 6 (+2), aux: 4 -> 6 (+2)\n\
+             \x20   inner: constraints: 2 -> 3 (+1), aux: 2 -> 3 (+1)\n"
+        );
+        // A removed circuit is compared against zero.
+        assert_eq!(
+            diff_tree_string(Some(&old), None, 0),
+            "test: constraints: 4 -> 0 (-4), aux: 4 -> 0 (-4)\n"
+        );
+    }
+}
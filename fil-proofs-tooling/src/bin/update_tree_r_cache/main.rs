@@ -1,26 +1,24 @@
-use std::fs::{self, create_dir_all, remove_dir_all, OpenOptions};
+use std::fs::{self, create_dir_all};
 use std::path::{Path, PathBuf};
 
 use anyhow::{ensure, Context, Result};
 use bincode::deserialize;
 use clap::{Arg, Command};
-use filecoin_hashers::Hasher;
+use filecoin_hashers::Domain;
 use filecoin_proofs::{
     is_sector_shape_base, is_sector_shape_sub2, is_sector_shape_sub8, is_sector_shape_top2,
-    with_shape, DefaultTreeDomain, PersistentAux, SectorShapeBase, SectorShapeSub2,
-    SectorShapeSub8, SectorShapeTop2, OCT_ARITY,
+    rebuild_tree_r_last, verify_tree_r_last, with_shape, Commitment, DefaultTreeDomain,
+    PersistentAux, PoRepConfig, SectorShapeBase, SectorShapeSub2, SectorShapeSub8, SectorShapeTop2,
+    TreeRLastCheck, TreeRLastRoots, OCT_ARITY,
 };
-use generic_array::typenum::Unsigned;
-use memmap2::MmapOptions;
 use merkletree::{
     merkle::get_merkle_tree_len,
-    store::{ExternalReader, LevelCacheStore, ReplicaConfig, Store, StoreConfig},
+    store::{ExternalReader, LevelCacheStore, ReplicaConfig, StoreConfig},
 };
 use storage_proofs_core::{
+    api_version::ApiVersion,
     cache_key::CacheKey,
-    merkle::{
-        create_lc_tree, get_base_tree_count, split_config_and_replica, LCTree, MerkleTreeTrait,
-    },
+    merkle::{get_base_tree_count, split_config_and_replica, MerkleTreeTrait},
     util::{default_rows_to_discard, NODE_SIZE},
 };
 use tempfile::tempdir;
@@ -115,68 +113,49 @@ fn get_persistent_aux(cache: &Path) -> Result<PersistentAux<DefaultTreeDomain>>
     Ok(p_aux)
 }
 
-fn build_tree_r_last<Tree: MerkleTreeTrait>(
+// Only the sector size of the PoRep config is used for tree_r_last.
+fn porep_config(sector_size: usize) -> PoRepConfig {
+    PoRepConfig::new_groth16(sector_size as u64, [0; 32], ApiVersion::V1_2_0)
+}
+
+fn as_domain(commitment: &Commitment) -> Result<DefaultTreeDomain> {
+    DefaultTreeDomain::try_from_bytes(commitment)
+}
+
+fn rebuild<Tree: 'static + MerkleTreeTrait>(
     sector_size: usize,
     cache: &Path,
     replica_path: &Path,
-) -> Result<(<Tree::Hasher as Hasher>::Domain, Vec<DefaultTreeDomain>)> {
-    let (tree_count, base_tree_leafs, configs, replica_config) =
-        get_tree_r_info(sector_size, cache, replica_path)?;
+) -> Result<TreeRLastRoots> {
+    rebuild_tree_r_last::<_, _, Tree>(&porep_config(sector_size), replica_path, cache)
+}
 
-    let f_data = OpenOptions::new()
-        .read(true)
-        .write(true)
-        .open(replica_path)
-        .with_context(|| format!("could not open replica_path={:?}", replica_path))?;
-    let input_mmap = unsafe {
-        MmapOptions::new()
-            .map(&f_data)
-            .with_context(|| format!("could not mmap replica_path={:?}", replica_path))?
-    };
+fn run_rebuild(sector_size: usize, cache: &Path, replica_path: &Path) -> Result<()> {
+    // If the cache dir doesn't exist, create it
+    create_dir_all(cache)?;
 
-    let mut base_tree_roots: Vec<DefaultTreeDomain> = Vec::with_capacity(tree_count);
-    for (i, config) in configs.iter().enumerate().take(tree_count) {
-        let offset = replica_config.offsets[i];
+    let roots = with_shape!(
+        sector_size as u64,
+        rebuild,
+        sector_size,
+        cache,
+        replica_path
+    )?;
 
-        let slice = &input_mmap[offset..(offset + (sector_size / tree_count))];
-        let store_path = StoreConfig::data_path(&config.path, &config.id);
+    for (i, root) in roots.base_tree_roots.iter().enumerate() {
         println!(
-            "Building tree_r_last {}/{}, [nodes={}, rows_to_discard={}, offsets={}-{}] in {:?}",
+            "Rebuilt tree_r_last {}/{} with base tree root {:?}",
             i + 1,
-            tree_count,
-            base_tree_leafs,
-            config.rows_to_discard,
-            offset,
-            (offset + (sector_size / tree_count)),
-            &store_path
+            roots.base_tree_roots.len(),
+            as_domain(root)?
         );
-        let tree = SectorShapeBase::from_byte_slice_with_config(slice, config.clone())?;
-        base_tree_roots.push(tree.root());
     }
+    println!(
+        "CommRLast [rebuilt tree_r_last root]: {:?}",
+        as_domain(&roots.comm_r_last)?
+    );
 
-    let tree_r_last = create_lc_tree::<
-        LCTree<Tree::Hasher, Tree::Arity, Tree::SubTreeArity, Tree::TopTreeArity>,
-    >(
-        get_merkle_tree_len(base_tree_leafs, Tree::Arity::to_usize())?,
-        &configs,
-        &replica_config,
-    )?;
-
-    Ok((tree_r_last.root(), base_tree_roots))
-}
-
-fn run_rebuild(
-    sector_size: usize,
-    cache: &Path,
-    replica_path: &Path,
-) -> Result<(DefaultTreeDomain, Vec<DefaultTreeDomain>)> {
-    with_shape!(
-        sector_size as u64,
-        build_tree_r_last,
-        sector_size,
-        cache,
-        replica_path
-    )
+    Ok(())
 }
 
 fn run_inspect(sector_size: usize, cache: &Path, replica_path: &Path) -> Result<()> {
@@ -205,104 +184,93 @@ fn run_inspect(sector_size: usize, cache: &Path, replica_path: &Path) -> Result<
     Ok(())
 }
 
+fn verify<Tree: 'static + MerkleTreeTrait>(
+    sector_size: usize,
+    cache: &Path,
+    replica_path: &Path,
+    scratch_path: &Path,
+) -> Result<TreeRLastCheck> {
+    verify_tree_r_last::<_, _, _, Tree>(
+        &porep_config(sector_size),
+        replica_path,
+        cache,
+        scratch_path,
+    )
+}
+
 fn run_verify(sector_size: usize, cache: &Path, replica_path: &Path) -> Result<()> {
-    let (tree_count, base_tree_leafs, configs, replica_config) =
-        get_tree_r_info(sector_size, cache, replica_path)?;
-    let base_tree_len = get_merkle_tree_len(base_tree_leafs, OCT_ARITY)?;
+    // Rebuild the tree_r_last in a new temp dir so as not to interfere with any existing ones on
+    // disk. The directory is removed when it goes out of scope, also on error.
+    let scratch_dir = tempdir()?;
+    let check = with_shape!(
+        sector_size as u64,
+        verify,
+        sector_size,
+        cache,
+        replica_path,
+        scratch_dir.path()
+    )?;
 
-    let match_str = |a, b| -> &str {
-        if a == b {
+    let match_str = |matches: bool| -> &str {
+        if matches {
             "MATCH"
         } else {
             "MISMATCH"
         }
     };
 
-    // First, read the roots from the cached trees on disk
-    let mut cached_base_tree_roots: Vec<DefaultTreeDomain> = Vec::with_capacity(tree_count);
-    for (i, config) in configs.iter().enumerate().take(tree_count) {
-        let store = LevelCacheStore::new_from_disk_with_reader(
-            base_tree_len,
-            OCT_ARITY,
-            config,
-            ExternalReader::new_from_config(&replica_config, i)?,
-        )?;
-        cached_base_tree_roots.push(store.last()?);
-    }
-
-    // Retrieve the tree_r_last root from the cached trees on disk.
-    let tree_r_last_root = get_tree_r_last_root(
-        base_tree_leafs,
-        sector_size as u64,
-        &configs,
-        &replica_config,
-    )?;
-
-    // Read comm_r_last from the persistent aux in the cache dir
-    let p_aux: PersistentAux<DefaultTreeDomain> = {
-        let p_aux_path = cache.join(CacheKey::PAux.to_string());
-        let p_aux_bytes = fs::read(&p_aux_path)
-            .with_context(|| format!("could not read file p_aux={:?}", p_aux_path))?;
-
-        deserialize(&p_aux_bytes)
-    }?;
-
-    // Rebuild each of the tree_r_last base trees (in a new temp dir so as not to interfere
-    // with any existing ones on disk) and check if the roots match what's cached on disk
-    let tmp_dir = tempdir().unwrap();
-    let tmp_path = tmp_dir.path();
-    create_dir_all(tmp_path)?;
-
-    let (rebuilt_tree_r_last_root, rebuilt_base_tree_roots) =
-        run_rebuild(sector_size, tmp_path, replica_path)?;
-
-    remove_dir_all(tmp_path)?;
-
-    let status = match_str(tree_r_last_root, p_aux.comm_r_last);
-    let rebuilt_status = match_str(rebuilt_tree_r_last_root, p_aux.comm_r_last);
-
     println!();
-    for (i, (cached_root, rebuilt_root)) in cached_base_tree_roots
+    let tree_count = check.rebuilt.base_tree_roots.len();
+    for (i, (cached_root, rebuilt_root)) in check
+        .cached_base_tree_roots
         .iter()
-        .zip(rebuilt_base_tree_roots)
+        .zip(&check.rebuilt.base_tree_roots)
         .enumerate()
     {
         println!(
             "tree_r_last {}/{} inspection shows a {} of base tree root {:?}",
             i + 1,
             tree_count,
-            match_str(*cached_root, rebuilt_root),
-            rebuilt_root
+            match_str(cached_root.as_ref() == Some(rebuilt_root)),
+            as_domain(rebuilt_root)?
         );
-        if *cached_root != rebuilt_root {
-            println!(
+        match cached_root {
+            Some(cached_root) if cached_root != rebuilt_root => println!(
                 "Cached root {:?}, Rebuilt root {:?}",
-                cached_root, rebuilt_root
-            );
+                as_domain(cached_root)?,
+                as_domain(rebuilt_root)?
+            ),
+            Some(_) => {}
+            None => println!("Cached base tree could not be read"),
         }
     }
 
+    let cached_comm_r_last = check
+        .cached_comm_r_last
+        .as_ref()
+        .map(as_domain)
+        .transpose()?;
     println!();
     println!(
         "CommRLast from p_aux                : {:?}",
-        p_aux.comm_r_last
+        as_domain(&check.expected_comm_r_last)?
     );
     println!(
         "CommRLast [cached tree_r_last root] : {:?}",
-        tree_r_last_root
+        cached_comm_r_last
     );
     println!(
         "CommRLast [rebuilt tree_r_last root]: {:?}",
-        rebuilt_tree_r_last_root
+        as_domain(&check.rebuilt.comm_r_last)?
     );
     println!();
     println!(
-        " Cached inspection shows a {} of CommRLast {:?}",
-        status, tree_r_last_root
+        " Cached inspection shows a {} of CommRLast",
+        match_str(check.cached_comm_r_last == Some(check.expected_comm_r_last))
     );
     println!(
-        "Rebuilt inspection shows a {} of CommRLast {:?}",
-        rebuilt_status, rebuilt_tree_r_last_root
+        "Rebuilt inspection shows a {} of CommRLast",
+        match_str(check.rebuilt.comm_r_last == check.expected_comm_r_last)
     );
 
     Ok(())
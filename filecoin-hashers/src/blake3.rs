@@ -0,0 +1,616 @@
+use std::fmt::{self, Debug, Formatter};
+use std::hash::Hasher as StdHasher;
+use std::panic::panic_any;
+
+use anyhow::ensure;
+use bellperson::{
+    gadgets::{boolean::Boolean, multieq::MultiEq, multipack, num::AllocatedNum, uint32::UInt32},
+    ConstraintSystem, SynthesisError,
+};
+use blstrs::Scalar as Fr;
+use ff::{Field, PrimeField};
+use merkletree::{
+    hash::{Algorithm, Hashable},
+    merkle::Element,
+};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+
+use crate::types::{Domain, HashFunction, Hasher};
+
+#[derive(Default, Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Blake3Hasher {}
+
+impl Hasher for Blake3Hasher {
+    type Domain = Blake3Domain;
+    type Function = Blake3Function;
+
+    fn name() -> String {
+        "Blake3Hasher".into()
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Blake3Function(blake3::Hasher);
+
+impl PartialEq for Blake3Function {
+    fn eq(&self, other: &Self) -> bool {
+        format!("{:?}", self) == format!("{:?}", other)
+    }
+}
+
+impl Eq for Blake3Function {}
+
+impl Debug for Blake3Function {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "Blake3Function({:?})", self.0)
+    }
+}
+
+impl StdHasher for Blake3Function {
+    #[inline]
+    fn write(&mut self, msg: &[u8]) {
+        self.0.update(msg);
+    }
+
+    #[inline]
+    fn finish(&self) -> u64 {
+        unreachable!("unused by Function -- should never be called")
+    }
+}
+
+#[derive(
+    Copy, Clone, PartialEq, Eq, Debug, PartialOrd, Ord, Default, Serialize, Deserialize, Hash,
+)]
+pub struct Blake3Domain(pub [u8; 32]);
+
+impl AsRef<Blake3Domain> for Blake3Domain {
+    fn as_ref(&self) -> &Self {
+        self
+    }
+}
+
+impl Blake3Domain {
+    pub fn trim_to_fr32(&mut self) {
+        // strip last two bits, to ensure result is in Fr.
+        self.0[31] &= 0b0011_1111;
+    }
+}
+
+impl AsRef<[u8]> for Blake3Domain {
+    fn as_ref(&self) -> &[u8] {
+        &self.0[..]
+    }
+}
+
+impl Hashable<Blake3Function> for Blake3Domain {
+    fn hash(&self, state: &mut Blake3Function) {
+        state.write(self.as_ref())
+    }
+}
+
+impl From<Fr> for Blake3Domain {
+    fn from(val: Fr) -> Self {
+        Blake3Domain(val.to_repr())
+    }
+}
+
+impl Element for Blake3Domain {
+    fn byte_len() -> usize {
+        32
+    }
+
+    fn from_slice(bytes: &[u8]) -> Self {
+        match Blake3Domain::try_from_bytes(bytes) {
+            Ok(res) => res,
+            Err(err) => panic_any(err),
+        }
+    }
+
+    fn copy_to_slice(&self, bytes: &mut [u8]) {
+        bytes.copy_from_slice(&self.0);
+    }
+}
+
+impl From<Blake3Domain> for Fr {
+    fn from(val: Blake3Domain) -> Self {
+        Fr::from_repr_vartime(val.0).expect("from_repr failure")
+    }
+}
+
+impl Domain for Blake3Domain {
+    fn into_bytes(&self) -> Vec<u8> {
+        self.0.to_vec()
+    }
+
+    fn try_from_bytes(raw: &[u8]) -> anyhow::Result<Self> {
+        ensure!(
+            raw.len() == 32 && u32::from(raw[31]) <= Fr::NUM_BITS,
+            "invalid amount of bytes"
+        );
+
+        let mut res = Blake3Domain::default();
+        res.0.copy_from_slice(&raw[0..32]);
+        Ok(res)
+    }
+
+    fn write_bytes(&self, dest: &mut [u8]) -> anyhow::Result<()> {
+        ensure!(dest.len() >= 32, "too many bytes");
+        dest[0..32].copy_from_slice(&self.0[..]);
+        Ok(())
+    }
+
+    fn random<R: RngCore>(rng: &mut R) -> Self {
+        // generating an Fr and converting it, to ensure we stay in the field
+        Fr::random(rng).into()
+    }
+}
+
+impl From<blake3::Hash> for Blake3Domain {
+    fn from(hash: blake3::Hash) -> Self {
+        let mut res = Blake3Domain(*hash.as_bytes());
+        res.trim_to_fr32();
+
+        res
+    }
+}
+
+impl HashFunction<Blake3Domain> for Blake3Function {
+    fn hash(data: &[u8]) -> Blake3Domain {
+        blake3::hash(data).into()
+    }
+
+    fn hash2(a: &Blake3Domain, b: &Blake3Domain) -> Blake3Domain {
+        blake3::Hasher::new()
+            .update(a.as_ref())
+            .update(b.as_ref())
+            .finalize()
+            .into()
+    }
+
+    fn hash_multi_leaf_circuit<Arity, CS: ConstraintSystem<Fr>>(
+        mut cs: CS,
+        leaves: &[AllocatedNum<Fr>],
+        _height: usize,
+    ) -> Result<AllocatedNum<Fr>, SynthesisError> {
+        let mut bits = Vec::with_capacity(leaves.len() * Fr::CAPACITY as usize);
+        for (i, leaf) in leaves.iter().enumerate() {
+            bits.extend_from_slice(
+                &leaf.to_bits_le(cs.namespace(|| format!("{}_num_into_bits", i)))?,
+            );
+            while bits.len() % 8 != 0 {
+                bits.push(Boolean::Constant(false));
+            }
+        }
+        Self::hash_circuit(cs, &bits)
+    }
+
+    fn hash_leaf_bits_circuit<CS: ConstraintSystem<Fr>>(
+        cs: CS,
+        left: &[Boolean],
+        right: &[Boolean],
+        _height: usize,
+    ) -> Result<AllocatedNum<Fr>, SynthesisError> {
+        let mut preimage: Vec<Boolean> = vec![];
+
+        preimage.extend_from_slice(left);
+        while preimage.len() % 8 != 0 {
+            preimage.push(Boolean::Constant(false));
+        }
+
+        preimage.extend_from_slice(right);
+        while preimage.len() % 8 != 0 {
+            preimage.push(Boolean::Constant(false));
+        }
+
+        Self::hash_circuit(cs, &preimage[..])
+    }
+
+    fn hash_circuit<CS: ConstraintSystem<Fr>>(
+        mut cs: CS,
+        bits: &[Boolean],
+    ) -> Result<AllocatedNum<Fr>, SynthesisError> {
+        let alloc_bits = blake3_circuit(cs.namespace(|| "hash"), bits)?;
+
+        multipack::pack_bits(cs.namespace(|| "pack"), &alloc_bits)
+    }
+
+    fn hash2_circuit<CS>(
+        mut cs: CS,
+        a_num: &AllocatedNum<Fr>,
+        b_num: &AllocatedNum<Fr>,
+    ) -> Result<AllocatedNum<Fr>, SynthesisError>
+    where
+        CS: ConstraintSystem<Fr>,
+    {
+        // Allocate as booleans
+        let a = a_num.to_bits_le(cs.namespace(|| "a_bits"))?;
+        let b = b_num.to_bits_le(cs.namespace(|| "b_bits"))?;
+
+        let mut preimage: Vec<Boolean> = vec![];
+
+        preimage.extend_from_slice(&a);
+        while preimage.len() % 8 != 0 {
+            preimage.push(Boolean::Constant(false));
+        }
+
+        preimage.extend_from_slice(&b);
+        while preimage.len() % 8 != 0 {
+            preimage.push(Boolean::Constant(false));
+        }
+
+        Self::hash_circuit(cs, &preimage[..])
+    }
+}
+
+impl Algorithm<Blake3Domain> for Blake3Function {
+    #[inline]
+    fn hash(&mut self) -> Blake3Domain {
+        self.0.finalize().into()
+    }
+
+    #[inline]
+    fn reset(&mut self) {
+        self.0.reset();
+    }
+
+    fn leaf(&mut self, leaf: Blake3Domain) -> Blake3Domain {
+        leaf
+    }
+
+    fn node(&mut self, left: Blake3Domain, right: Blake3Domain, _height: usize) -> Blake3Domain {
+        left.hash(self);
+        right.hash(self);
+        self.hash()
+    }
+
+    fn multi_node(&mut self, parts: &[Blake3Domain], _height: usize) -> Blake3Domain {
+        for part in parts {
+            part.hash(self)
+        }
+        self.hash()
+    }
+}
+
+impl From<[u8; 32]> for Blake3Domain {
+    #[inline]
+    fn from(val: [u8; 32]) -> Self {
+        Blake3Domain(val)
+    }
+}
+
+impl From<Blake3Domain> for [u8; 32] {
+    #[inline]
+    fn from(val: Blake3Domain) -> Self {
+        val.0
+    }
+}
+
+const IV: [u32; 8] = [
+    0x6A09_E667,
+    0xBB67_AE85,
+    0x3C6E_F372,
+    0xA54F_F53A,
+    0x510E_527F,
+    0x9B05_688C,
+    0x1F83_D9AB,
+    0x5BE0_CD19,
+];
+
+const MSG_PERMUTATION: [usize; 16] = [2, 6, 3, 10, 7, 0, 4, 13, 1, 11, 12, 5, 9, 14, 15, 8];
+
+const CHUNK_START: u32 = 1 << 0;
+const CHUNK_END: u32 = 1 << 1;
+const PARENT: u32 = 1 << 2;
+const ROOT: u32 = 1 << 3;
+
+const BLOCK_BITS: usize = 64 * 8;
+const CHUNK_BITS: usize = 1024 * 8;
+
+/// The BLAKE3 mixing function, which is the same as BLAKE2s'.
+#[allow(clippy::too_many_arguments)]
+fn mixing_g<CS: ConstraintSystem<Fr>, M>(
+    mut cs: M,
+    v: &mut [UInt32],
+    a: usize,
+    b: usize,
+    c: usize,
+    d: usize,
+    x: &UInt32,
+    y: &UInt32,
+) -> Result<(), SynthesisError>
+where
+    M: ConstraintSystem<Fr, Root = MultiEq<Fr, CS>>,
+{
+    v[a] = UInt32::addmany(
+        cs.namespace(|| "mixing step 1"),
+        &[v[a].clone(), v[b].clone(), x.clone()],
+    )?;
+    v[d] = v[d].xor(cs.namespace(|| "mixing step 2"), &v[a])?.rotr(16);
+    v[c] = UInt32::addmany(
+        cs.namespace(|| "mixing step 3"),
+        &[v[c].clone(), v[d].clone()],
+    )?;
+    v[b] = v[b].xor(cs.namespace(|| "mixing step 4"), &v[c])?.rotr(12);
+    v[a] = UInt32::addmany(
+        cs.namespace(|| "mixing step 5"),
+        &[v[a].clone(), v[b].clone(), y.clone()],
+    )?;
+    v[d] = v[d].xor(cs.namespace(|| "mixing step 6"), &v[a])?.rotr(8);
+    v[c] = UInt32::addmany(
+        cs.namespace(|| "mixing step 7"),
+        &[v[c].clone(), v[d].clone()],
+    )?;
+    v[b] = v[b].xor(cs.namespace(|| "mixing step 8"), &v[c])?.rotr(7);
+
+    Ok(())
+}
+
+/// The BLAKE3 compression function, returning the new chaining value.
+fn compress<CS: ConstraintSystem<Fr>>(
+    mut cs: CS,
+    cv: &[UInt32],
+    block: &[UInt32],
+    counter: u64,
+    block_len: u32,
+    flags: u32,
+) -> Result<Vec<UInt32>, SynthesisError> {
+    assert_eq!(cv.len(), 8);
+    assert_eq!(block.len(), 16);
+
+    let mut v = Vec::with_capacity(16);
+    v.extend_from_slice(cv);
+    v.extend(IV[..4].iter().map(|word| UInt32::constant(*word)));
+    v.push(UInt32::constant(counter as u32));
+    v.push(UInt32::constant((counter >> 32) as u32));
+    v.push(UInt32::constant(block_len));
+    v.push(UInt32::constant(flags));
+
+    let mut m = block.to_vec();
+    {
+        let mut cs = MultiEq::new(&mut cs);
+
+        for round in 0..7 {
+            let mut cs = cs.namespace(|| format!("round {}", round));
+
+            for (i, (a, b, c, d)) in [
+                (0, 4, 8, 12),
+                (1, 5, 9, 13),
+                (2, 6, 10, 14),
+                (3, 7, 11, 15),
+                (0, 5, 10, 15),
+                (1, 6, 11, 12),
+                (2, 7, 8, 13),
+                (3, 4, 9, 14),
+            ]
+            .iter()
+            .copied()
+            .enumerate()
+            {
+                mixing_g(
+                    cs.namespace(|| format!("mixing invocation {}", i + 1)),
+                    &mut v,
+                    a,
+                    b,
+                    c,
+                    d,
+                    &m[2 * i],
+                    &m[2 * i + 1],
+                )?;
+            }
+
+            m = MSG_PERMUTATION.iter().map(|i| m[*i].clone()).collect();
+        }
+    }
+
+    (0..8)
+        .map(|i| {
+            v[i].xor(
+                cs.namespace(|| format!("v[{i}] ^ v[{i} + 8]", i = i)),
+                &v[i + 8],
+            )
+        })
+        .collect()
+}
+
+/// Returns the chaining value of a chunk of at most 1024 bytes.
+fn chunk_cv<CS: ConstraintSystem<Fr>>(
+    mut cs: CS,
+    input: &[Boolean],
+    chunk_counter: u64,
+    root: bool,
+) -> Result<Vec<UInt32>, SynthesisError> {
+    let num_blocks = input.len().div_ceil(BLOCK_BITS).max(1);
+
+    let mut cv: Vec<UInt32> = IV.iter().map(|word| UInt32::constant(*word)).collect();
+    for i in 0..num_blocks {
+        let block_bits = &input[(i * BLOCK_BITS).min(input.len())..]
+            [..BLOCK_BITS.min(input.len().saturating_sub(i * BLOCK_BITS))];
+        let block: Vec<UInt32> = (0..16)
+            .map(|word| {
+                let mut bits: Vec<Boolean> = block_bits
+                    .iter()
+                    .skip(word * 32)
+                    .take(32)
+                    .cloned()
+                    .collect();
+                bits.resize(32, Boolean::constant(false));
+                UInt32::from_bits(&bits)
+            })
+            .collect();
+
+        let mut flags = 0;
+        if i == 0 {
+            flags |= CHUNK_START;
+        }
+        if i == num_blocks - 1 {
+            flags |= CHUNK_END;
+            if root {
+                flags |= ROOT;
+            }
+        }
+        cv = compress(
+            cs.namespace(|| format!("block {}", i)),
+            &cv,
+            &block,
+            chunk_counter,
+            (block_bits.len() / 8) as u32,
+            flags,
+        )?;
+    }
+
+    Ok(cv)
+}
+
+/// Returns the chaining value of the subtree whose leftmost chunk is `first_chunk`.
+fn subtree_cv<CS: ConstraintSystem<Fr>>(
+    mut cs: CS,
+    input: &[Boolean],
+    first_chunk: u64,
+    root: bool,
+) -> Result<Vec<UInt32>, SynthesisError> {
+    let num_chunks = input.len().div_ceil(CHUNK_BITS);
+    if num_chunks <= 1 {
+        return chunk_cv(cs, input, first_chunk, root);
+    }
+
+    // The left subtree holds the largest power of two number of chunks, leaving at least one
+    // chunk in the right subtree.
+    let left_chunks = 1 << (usize::BITS - 1 - (num_chunks - 1).leading_zeros());
+    let (left, right) = input.split_at(left_chunks * CHUNK_BITS);
+    let mut block = subtree_cv(cs.namespace(|| "left"), left, first_chunk, false)?;
+    block.extend(subtree_cv(
+        cs.namespace(|| "right"),
+        right,
+        first_chunk + left_chunks as u64,
+        false,
+    )?);
+
+    let key: Vec<UInt32> = IV.iter().map(|word| UInt32::constant(*word)).collect();
+    let flags = if root { PARENT | ROOT } else { PARENT };
+    compress(cs.namespace(|| "parent"), &key, &block, 0, 64, flags)
+}
+
+/// Computes the 256-bit BLAKE3 hash of `input`, whose bits are in little-endian order within
+/// every byte. The output bits are in the same order.
+pub fn blake3_circuit<CS: ConstraintSystem<Fr>>(
+    cs: CS,
+    input: &[Boolean],
+) -> Result<Vec<Boolean>, SynthesisError> {
+    assert_eq!(input.len() % 8, 0, "input must be a whole number of bytes");
+
+    let cv = subtree_cv(cs, input, 0, true)?;
+
+    Ok(cv.into_iter().flat_map(|word| word.into_bits()).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bellperson::{gadgets::boolean::AllocatedBit, util_cs::test_cs::TestConstraintSystem};
+    use generic_array::typenum::U2;
+    use merkletree::{merkle::MerkleTree, store::VecStore};
+    use rand::{Rng, SeedableRng};
+    use rand_xorshift::XorShiftRng;
+
+    const TEST_SEED: [u8; 16] = [
+        0x59, 0x62, 0xbe, 0x5d, 0x76, 0x3d, 0x31, 0x8d, 0x17, 0xdb, 0x37, 0x32, 0x54, 0x06, 0xbc,
+        0xe5,
+    ];
+
+    fn alloc_bytes<CS: ConstraintSystem<Fr>>(mut cs: CS, data: &[u8]) -> Vec<Boolean> {
+        data.iter()
+            .flat_map(|byte| (0..8).map(move |i| (byte >> i) & 1 == 1))
+            .enumerate()
+            .map(|(j, bit)| {
+                Boolean::from(
+                    AllocatedBit::alloc(cs.namespace(|| format!("bit {}", j)), Some(bit))
+                        .expect("failed to allocate bit"),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_blake3_circuit() {
+        let mut rng = XorShiftRng::from_seed(TEST_SEED);
+
+        // Single block, multiple blocks, a full chunk and trees of two and three chunks.
+        for len in [0, 1, 32, 64, 65, 256, 1024, 1025, 2049] {
+            let data: Vec<u8> = (0..len).map(|_| rng.gen()).collect();
+
+            let mut cs = TestConstraintSystem::<Fr>::new();
+            let input = alloc_bytes(cs.namespace(|| "input"), &data);
+            let out = blake3_circuit(cs.namespace(|| "blake3"), &input)
+                .expect("failed to synthesize blake3");
+            assert!(cs.is_satisfied(), "constraints not satisfied");
+
+            let out_bytes: Vec<u8> = out
+                .chunks(8)
+                .map(|bits| {
+                    bits.iter().enumerate().fold(0u8, |byte, (i, bit)| {
+                        byte | ((bit.get_value().expect("bit value must be set") as u8) << i)
+                    })
+                })
+                .collect();
+            assert_eq!(
+                &out_bytes[..],
+                blake3::hash(&data).as_bytes(),
+                "len {}",
+                len
+            );
+        }
+    }
+
+    #[test]
+    fn test_blake3_hash2_circuit() {
+        let mut rng = XorShiftRng::from_seed(TEST_SEED);
+
+        for _ in 0..5 {
+            let a = Blake3Domain::random(&mut rng);
+            let b = Blake3Domain::random(&mut rng);
+
+            let mut cs = TestConstraintSystem::<Fr>::new();
+            let a_num = AllocatedNum::alloc(cs.namespace(|| "a"), || Ok(a.into()))
+                .expect("failed to allocate a");
+            let b_num = AllocatedNum::alloc(cs.namespace(|| "b"), || Ok(b.into()))
+                .expect("failed to allocate b");
+            let out = Blake3Function::hash2_circuit(cs.namespace(|| "hash2"), &a_num, &b_num)
+                .expect("failed to synthesize hash2");
+            assert!(cs.is_satisfied(), "constraints not satisfied");
+
+            let expected = Blake3Function::hash2(&a, &b);
+            assert_eq!(out.get_value(), Some(expected.into()));
+            assert_eq!(
+                expected,
+                <Blake3Function as HashFunction<_>>::hash(&[a.0, b.0].concat())
+            );
+        }
+    }
+
+    #[test]
+    fn test_blake3_hasher() {
+        let leaves = [
+            Blake3Domain(Fr::ONE.to_repr()),
+            Blake3Domain(Fr::ZERO.to_repr()),
+            Blake3Domain(Fr::ZERO.to_repr()),
+            Blake3Domain(Fr::ONE.to_repr()),
+        ];
+
+        let t = MerkleTree::<Blake3Domain, Blake3Function, VecStore<_>, U2>::new(
+            leaves.iter().copied(),
+        )
+        .expect("merkle tree new failure");
+        assert_eq!(t.leafs(), 4);
+
+        let h01 = Blake3Function::hash2(&leaves[0], &leaves[1]);
+        let h23 = Blake3Function::hash2(&leaves[2], &leaves[3]);
+        assert_eq!(t.root(), Blake3Function::hash2(&h01, &h23));
+
+        let p = t.gen_proof(2).expect("gen_proof failure");
+        assert!(p.validate::<Blake3Function>().expect("failed to validate"));
+        // Every node is a valid field element.
+        assert!(t.root().0[31] <= 0b0011_1111);
+    }
+}
@@ -0,0 +1,599 @@
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+
+use anyhow::{bail, ensure, Context, Result};
+use bellperson::groth16;
+use blstrs::{Bls12, Scalar as Fr};
+use filecoin_hashers::Domain;
+use log::{info, trace};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use storage_proofs_core::api_version::{ApiFeature, ApiVersion};
+use storage_proofs_post::fallback::PublicSector;
+
+use crate::{
+    api::{
+        aggregate_empty_sector_update_proofs, aggregate_seal_commit_proofs,
+        util::{get_aggregate_target_len, pad_inputs_to_target, pad_proofs_to_target},
+    },
+    constants::FIP92_MAX_NI_POREP_AGGREGATION_PROOFS,
+    types::{
+        AggregateSnarkProof, ChallengeSeed, Commitment, EmptySectorUpdateProof, MerkleTreeTrait,
+        PoRepConfig, ProverId, SealCommitOutput, SectorUpdateConfig, SectorUpdateProofInputs,
+        Ticket, TreeRHasher,
+    },
+    with_shape,
+};
+
+/// The version of the format written by `AggregationBuilder::save`.
+pub const AGGREGATION_BUILDER_VERSION: u16 = 1;
+
+/// Pads the proofs to the next power of two (at least two) by duplicating the last one, as
+/// required by SnarkPack.
+pub(crate) fn pad_aggregate_proofs(proofs: &mut Vec<groth16::Proof<Bls12>>) -> Result<()> {
+    let target_proofs_len = get_aggregate_target_len(proofs.len());
+    ensure!(
+        target_proofs_len > 1,
+        "cannot aggregate less than two proofs"
+    );
+
+    trace!(
+        "padding {} proofs to target_len {}",
+        proofs.len(),
+        target_proofs_len
+    );
+
+    // If we're not at the pow2 target, duplicate the last proof until we are.
+    pad_proofs_to_target(proofs, target_proofs_len)
+}
+
+/// Pads the public inputs in the same way `pad_aggregate_proofs` pads the proofs, so that they
+/// match an aggregate of `aggregated_proofs_len` proofs.
+pub(crate) fn pad_aggregate_inputs(
+    inputs: &[Vec<Fr>],
+    aggregated_proofs_len: usize,
+) -> Result<Vec<Vec<Fr>>> {
+    ensure!(aggregated_proofs_len != 0, "cannot verify zero proofs");
+    ensure!(!inputs.is_empty(), "cannot verify with empty inputs");
+    ensure!(
+        aggregated_proofs_len > 1,
+        "cannot verify less than two proofs"
+    );
+    ensure!(
+        aggregated_proofs_len == aggregated_proofs_len.next_power_of_two(),
+        "cannot verify non-pow2 aggregate proofs"
+    );
+
+    let num_inputs = inputs.len();
+    let num_inputs_per_proof = get_aggregate_target_len(num_inputs) / aggregated_proofs_len;
+    ensure!(
+        num_inputs_per_proof != 0,
+        "invalid number of inputs provided",
+    );
+    let target_inputs_len = aggregated_proofs_len * num_inputs_per_proof;
+
+    trace!(
+        "got {} inputs with {} inputs per proof, target_len is {}",
+        num_inputs,
+        num_inputs_per_proof,
+        target_inputs_len,
+    );
+
+    pad_inputs_to_target(inputs, num_inputs_per_proof, target_inputs_len)
+}
+
+/// Hashes the inputs the SnarkPack transcript of aggregated seal proofs is bound to.
+///
+/// For standard PoRep, the transcript includes a hash of each aggregated PoRep's challenge seed
+/// and comm_r (pair-wise); however since NI-PoRep does not use a seed to generate its challenges,
+/// the seeds are ignored and only the comm_rs are included.
+pub(crate) fn seal_aggregate_transcript(
+    porep_config: &PoRepConfig,
+    comm_rs: &[Commitment],
+    seeds: &[Ticket],
+) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    if porep_config.feature_enabled(ApiFeature::NonInteractivePoRep) {
+        for comm_r in comm_rs {
+            hasher.update(comm_r);
+        }
+    } else {
+        for (seed, comm_r) in seeds.iter().zip(comm_rs) {
+            hasher.update(seed);
+            hasher.update(comm_r);
+        }
+    }
+    hasher.finalize().into()
+}
+
+/// Hashes the inputs the SnarkPack transcript of aggregated sector update proofs is bound to: the
+/// h value and the commitments of every sector, in order.
+pub(crate) fn sector_update_aggregate_transcript(inputs: &[SectorUpdateProofInputs]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    for input in inputs {
+        hasher.update([input.h as u8]);
+        hasher.update(input.comm_r_old);
+        hasher.update(input.comm_r_new);
+        hasher.update(input.comm_d_new);
+    }
+    hasher.finalize().into()
+}
+
+/// Hashes the inputs the SnarkPack transcript of an aggregated Window PoSt is bound to: the
+/// randomness, the prover id and the sectors of every partition, in partition order.
+pub(crate) fn window_post_aggregate_transcript<D: Domain>(
+    randomness: &ChallengeSeed,
+    prover_id: &ProverId,
+    sectors: &[PublicSector<D>],
+    sectors_per_partition: usize,
+) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(randomness);
+    hasher.update(prover_id);
+    for (k, partition_sectors) in sectors.chunks(sectors_per_partition).enumerate() {
+        hasher.update((k as u64).to_le_bytes());
+        hasher.update((partition_sectors.len() as u64).to_le_bytes());
+        for sector in partition_sectors {
+            hasher.update(u64::from(sector.id).to_le_bytes());
+            hasher.update(AsRef::<[u8]>::as_ref(&sector.comm_r));
+        }
+    }
+    hasher.finalize().into()
+}
+
+/// The kind of proofs collected by an `AggregationBuilder`, along with the public data of every
+/// proof that the aggregate is bound to.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum AggregationInputs {
+    Seal {
+        comm_rs: Vec<Commitment>,
+        seeds: Vec<Ticket>,
+    },
+    SectorUpdate(Vec<SectorUpdateProofInputs>),
+}
+
+// Dispatch target of `AggregationBuilder::finalize`, as `with_shape!` only passes the tree.
+fn aggregate_sector_update_proofs<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>>(
+    porep_config: &PoRepConfig,
+    proofs: &[EmptySectorUpdateProof],
+    inputs: &[SectorUpdateProofInputs],
+    aggregate_version: groth16::aggregate::AggregateVersion,
+) -> Result<AggregateSnarkProof> {
+    aggregate_empty_sector_update_proofs::<Tree>(porep_config, proofs, inputs, aggregate_version)
+}
+
+/// Collects seal or sector update proofs for SnarkPack aggregation.
+///
+/// Unlike `aggregate_seal_commit_proofs` and `aggregate_empty_sector_update_proofs`, the proofs
+/// don't need to be known up front: they are kept unpadded, so that more can be appended (or
+/// another builder merged) at any time, and the builder can be saved to disk and loaded again to
+/// resume. Padding only happens in `finalize`, which can be called as often as needed.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AggregationBuilder {
+    version: u16,
+    porep_config: PoRepConfig,
+    inputs: AggregationInputs,
+    proofs: Vec<Vec<u8>>,
+}
+
+impl AggregationBuilder {
+    /// Creates an empty builder for seal commit proofs.
+    pub fn new_seal(porep_config: &PoRepConfig) -> Self {
+        AggregationBuilder {
+            version: AGGREGATION_BUILDER_VERSION,
+            porep_config: porep_config.clone(),
+            inputs: AggregationInputs::Seal {
+                comm_rs: Vec::new(),
+                seeds: Vec::new(),
+            },
+            proofs: Vec::new(),
+        }
+    }
+
+    /// Creates an empty builder for empty sector update proofs.
+    pub fn new_sector_update(porep_config: &PoRepConfig) -> Result<Self> {
+        ensure!(
+            porep_config.api_version >= ApiVersion::V1_2_0,
+            "Empty Sector Update proof aggregation is supported in ApiVersion 1.2.0 or later"
+        );
+
+        Ok(AggregationBuilder {
+            version: AGGREGATION_BUILDER_VERSION,
+            porep_config: porep_config.clone(),
+            inputs: AggregationInputs::SectorUpdate(Vec::new()),
+            proofs: Vec::new(),
+        })
+    }
+
+    pub fn porep_config(&self) -> &PoRepConfig {
+        &self.porep_config
+    }
+
+    pub fn inputs(&self) -> &AggregationInputs {
+        &self.inputs
+    }
+
+    /// The number of sector proofs collected so far.
+    pub fn len(&self) -> usize {
+        self.proofs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.proofs.is_empty()
+    }
+
+    /// Appends the seal proof of a sector.
+    ///
+    /// # Arguments
+    ///
+    /// * `comm_r` - the sector's replica commitment.
+    /// * `seed` - the seed used to derive the PoRep challenges, ignored for NI-PoRep.
+    /// * `commit_output` - the sector's proof as returned from `seal_commit_phase2`.
+    pub fn append_seal(
+        &mut self,
+        comm_r: Commitment,
+        seed: Ticket,
+        commit_output: &SealCommitOutput,
+    ) -> Result<()> {
+        let partitions = usize::from(self.porep_config.partitions);
+        check_proof_bytes(&commit_output.proof, partitions)?;
+        if self
+            .porep_config
+            .feature_enabled(ApiFeature::NonInteractivePoRep)
+        {
+            ensure!(
+                self.proofs.len() < FIP92_MAX_NI_POREP_AGGREGATION_PROOFS,
+                "cannot aggregate more than {} NI-PoRep proofs",
+                FIP92_MAX_NI_POREP_AGGREGATION_PROOFS
+            );
+        }
+
+        match &mut self.inputs {
+            AggregationInputs::Seal { comm_rs, seeds } => {
+                comm_rs.push(comm_r);
+                seeds.push(seed);
+            }
+            AggregationInputs::SectorUpdate(_) => {
+                bail!("cannot append a seal proof to a sector update aggregation")
+            }
+        }
+        self.proofs.push(commit_output.proof.clone());
+
+        Ok(())
+    }
+
+    /// Appends the empty sector update proof of a sector.
+    ///
+    /// # Arguments
+    ///
+    /// * `proof` - the sector's proof as returned from `generate_empty_sector_update_proof`.
+    /// * `input` - the sector's public inputs; `h` must match the one of all other sectors.
+    pub fn append_sector_update(
+        &mut self,
+        proof: &EmptySectorUpdateProof,
+        input: SectorUpdateProofInputs,
+    ) -> Result<()> {
+        let config = SectorUpdateConfig::from_porep_config(&self.porep_config);
+        check_proof_bytes(&proof.0, usize::from(config.update_partitions))?;
+
+        match &mut self.inputs {
+            AggregationInputs::SectorUpdate(inputs) => {
+                if let Some(first) = inputs.first() {
+                    ensure!(
+                        first.h == input.h,
+                        "mismatched h values in sector update aggregation inputs!"
+                    );
+                }
+                inputs.push(input);
+            }
+            AggregationInputs::Seal { .. } => {
+                bail!("cannot append a sector update proof to a seal aggregation")
+            }
+        }
+        self.proofs.push(proof.0.clone());
+
+        Ok(())
+    }
+
+    /// Appends all proofs of `other`, which must collect the same kind of proofs for the same
+    /// porep config.
+    pub fn merge(&mut self, other: AggregationBuilder) -> Result<()> {
+        ensure!(
+            self.porep_config.sector_size == other.porep_config.sector_size
+                && self.porep_config.porep_id == other.porep_config.porep_id
+                && self.porep_config.api_version == other.porep_config.api_version
+                && self.porep_config.api_features == other.porep_config.api_features,
+            "cannot merge aggregation builders with different porep configs"
+        );
+
+        match (&mut self.inputs, other.inputs) {
+            (
+                AggregationInputs::Seal { comm_rs, seeds },
+                AggregationInputs::Seal {
+                    comm_rs: other_comm_rs,
+                    seeds: other_seeds,
+                },
+            ) => {
+                if self
+                    .porep_config
+                    .feature_enabled(ApiFeature::NonInteractivePoRep)
+                {
+                    ensure!(
+                        comm_rs.len() + other_comm_rs.len()
+                            <= FIP92_MAX_NI_POREP_AGGREGATION_PROOFS,
+                        "cannot aggregate more than {} NI-PoRep proofs",
+                        FIP92_MAX_NI_POREP_AGGREGATION_PROOFS
+                    );
+                }
+                comm_rs.extend(other_comm_rs);
+                seeds.extend(other_seeds);
+            }
+            (AggregationInputs::SectorUpdate(inputs), AggregationInputs::SectorUpdate(other)) => {
+                if let (Some(first), Some(other_first)) = (inputs.first(), other.first()) {
+                    ensure!(
+                        first.h == other_first.h,
+                        "mismatched h values in sector update aggregation inputs!"
+                    );
+                }
+                inputs.extend(other);
+            }
+            _ => bail!("cannot merge seal and sector update aggregation builders"),
+        }
+        self.proofs.extend(other.proofs);
+
+        Ok(())
+    }
+
+    /// Aggregates all proofs collected so far, padding them as needed. The builder is left
+    /// unchanged, so that more proofs can be appended and a new aggregate created afterwards.
+    ///
+    /// The proofs are aggregated for the sector shape of the porep config's sector size.
+    pub fn finalize(
+        &self,
+        aggregate_version: groth16::aggregate::AggregateVersion,
+    ) -> Result<AggregateSnarkProof> {
+        info!("aggregation_builder_finalize:start");
+
+        let aggregate_proof = match &self.inputs {
+            AggregationInputs::Seal { comm_rs, seeds } => {
+                let commit_outputs: Vec<_> = self
+                    .proofs
+                    .iter()
+                    .map(|proof| SealCommitOutput {
+                        proof: proof.clone(),
+                    })
+                    .collect();
+                with_shape!(
+                    u64::from(self.porep_config.sector_size),
+                    aggregate_seal_commit_proofs,
+                    &self.porep_config,
+                    comm_rs,
+                    seeds,
+                    &commit_outputs,
+                    aggregate_version,
+                )?
+            }
+            AggregationInputs::SectorUpdate(inputs) => {
+                let proofs: Vec<_> = self
+                    .proofs
+                    .iter()
+                    .cloned()
+                    .map(EmptySectorUpdateProof)
+                    .collect();
+                with_shape!(
+                    u64::from(self.porep_config.sector_size),
+                    aggregate_sector_update_proofs,
+                    &self.porep_config,
+                    &proofs,
+                    inputs,
+                    aggregate_version,
+                )?
+            }
+        };
+
+        info!("aggregation_builder_finalize:finish");
+
+        Ok(aggregate_proof)
+    }
+
+    /// Writes the builder to `path`. The file is replaced atomically, so that an interrupted
+    /// write never loses the previously saved state.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+
+        let file = File::create(&tmp_path)
+            .with_context(|| format!("could not create file {:?}", tmp_path))?;
+        let mut writer = BufWriter::new(file);
+        bincode::serialize_into(&mut writer, self)?;
+        writer
+            .into_inner()
+            .map_err(|err| err.into_error())?
+            .sync_all()?;
+        fs::rename(&tmp_path, path)
+            .with_context(|| format!("could not move {:?} to {:?}", tmp_path, path))?;
+
+        Ok(())
+    }
+
+    /// Reads a builder written by `save`.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let file = File::open(path).with_context(|| format!("could not open file {:?}", path))?;
+        let builder: AggregationBuilder = bincode::deserialize_from(BufReader::new(file))
+            .with_context(|| format!("failed to deserialize aggregation builder {:?}", path))?;
+
+        ensure!(
+            builder.version == AGGREGATION_BUILDER_VERSION,
+            "unsupported aggregation builder version {}, expected {}",
+            builder.version,
+            AGGREGATION_BUILDER_VERSION
+        );
+        let num_inputs = match &builder.inputs {
+            AggregationInputs::Seal { comm_rs, seeds } => {
+                ensure!(
+                    comm_rs.len() == seeds.len(),
+                    "invalid comm_rs and seeds len mismatch"
+                );
+                comm_rs.len()
+            }
+            AggregationInputs::SectorUpdate(inputs) => inputs.len(),
+        };
+        ensure!(
+            num_inputs == builder.proofs.len(),
+            "aggregation builder has {} proofs but {} inputs",
+            builder.proofs.len(),
+            num_inputs
+        );
+
+        Ok(builder)
+    }
+}
+
+/// Checks that `bytes` holds exactly `partitions` well-formed groth16 proofs, so that malformed
+/// proofs are rejected when appended rather than when finalizing, possibly much later.
+fn check_proof_bytes(bytes: &[u8], partitions: usize) -> Result<()> {
+    groth16::Proof::<Bls12>::read_many(bytes, partitions)
+        .with_context(|| format!("invalid proof bytes for {} partitions", partitions))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use blstrs::{G1Affine, G1Projective, G2Affine, G2Projective};
+    use tempfile::tempdir;
+
+    fn proof_bytes(partitions: usize) -> Vec<u8> {
+        let g1 = G1Affine::from(G1Projective::hash_to_curve(b"a", b"test", &[]));
+        let g2 = G2Affine::from(G2Projective::hash_to_curve(b"b", b"test", &[]));
+        let proof = groth16::Proof::<Bls12> {
+            a: g1,
+            b: g2,
+            c: g1,
+        };
+        let mut bytes = Vec::new();
+        for _ in 0..partitions {
+            proof.write(&mut bytes).expect("write failed");
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_pad_aggregate_inputs() {
+        let inputs: Vec<Vec<Fr>> = (0..3u64).map(|i| vec![Fr::from(i)]).collect();
+
+        let padded = pad_aggregate_inputs(&inputs, 4).expect("pad failed");
+        assert_eq!(padded.len(), 4);
+        assert_eq!(&padded[..3], &inputs[..]);
+        assert_eq!(padded[3], inputs[2]);
+
+        assert!(pad_aggregate_inputs(&inputs, 1).is_err());
+        assert!(pad_aggregate_inputs(&inputs, 3).is_err());
+        assert!(pad_aggregate_inputs(&inputs, 8).is_err());
+        assert!(pad_aggregate_inputs(&[], 4).is_err());
+    }
+
+    #[test]
+    fn test_seal_aggregate_transcript() {
+        let porep_id = [1u8; 32];
+        let interactive = PoRepConfig::new_groth16(2048, porep_id, ApiVersion::V1_2_0);
+        let ni = PoRepConfig::new_groth16_with_features(
+            2048,
+            porep_id,
+            ApiVersion::V1_2_0,
+            vec![ApiFeature::NonInteractivePoRep],
+        )
+        .expect("config failed");
+
+        let comm_rs = [[2u8; 32], [3u8; 32]];
+        let seeds = [[4u8; 32], [5u8; 32]];
+        let other_seeds = [[6u8; 32], [7u8; 32]];
+
+        assert_ne!(
+            seal_aggregate_transcript(&interactive, &comm_rs, &seeds),
+            seal_aggregate_transcript(&interactive, &comm_rs, &other_seeds),
+        );
+        assert_eq!(
+            seal_aggregate_transcript(&ni, &comm_rs, &seeds),
+            seal_aggregate_transcript(&ni, &comm_rs, &other_seeds),
+        );
+    }
+
+    #[test]
+    fn test_aggregation_builder_rejects_malformed_proofs() {
+        let porep_config = PoRepConfig::new_groth16(2048, [1u8; 32], ApiVersion::V1_2_0);
+        let mut builder = AggregationBuilder::new_seal(&porep_config);
+
+        let output = SealCommitOutput {
+            proof: vec![0u8; 17],
+        };
+        assert!(builder.append_seal([1u8; 32], [2u8; 32], &output).is_err());
+        assert!(builder.is_empty());
+
+        let update_input = SectorUpdateProofInputs {
+            h: 1,
+            comm_r_old: [1u8; 32],
+            comm_r_new: [2u8; 32],
+            comm_d_new: [3u8; 32],
+        };
+        assert!(builder
+            .append_sector_update(&EmptySectorUpdateProof(Vec::new()), update_input)
+            .is_err());
+
+        let update_builder =
+            AggregationBuilder::new_sector_update(&porep_config).expect("builder failed");
+        assert!(builder.merge(update_builder).is_err());
+    }
+
+    #[test]
+    fn test_aggregation_builder_save_load_merge() {
+        let porep_config = PoRepConfig::new_groth16(2048, [1u8; 32], ApiVersion::V1_2_0);
+        let partitions = usize::from(porep_config.partitions);
+        let output = SealCommitOutput {
+            proof: proof_bytes(partitions),
+        };
+
+        let mut builder = AggregationBuilder::new_seal(&porep_config);
+        builder
+            .append_seal([1u8; 32], [2u8; 32], &output)
+            .expect("append failed");
+
+        let dir = tempdir().expect("tempdir failed");
+        let path = dir.path().join("aggregation");
+        builder.save(&path).expect("save failed");
+
+        let mut resumed = AggregationBuilder::load(&path).expect("load failed");
+        assert_eq!(resumed.len(), 1);
+        resumed
+            .append_seal([3u8; 32], [4u8; 32], &output)
+            .expect("append failed");
+
+        let mut other = AggregationBuilder::new_seal(&porep_config);
+        other
+            .append_seal([5u8; 32], [6u8; 32], &output)
+            .expect("append failed");
+        resumed.merge(other).expect("merge failed");
+        assert_eq!(resumed.len(), 3);
+
+        match resumed.inputs() {
+            AggregationInputs::Seal { comm_rs, seeds } => {
+                assert_eq!(comm_rs, &[[1u8; 32], [3u8; 32], [5u8; 32]]);
+                assert_eq!(seeds, &[[2u8; 32], [4u8; 32], [6u8; 32]]);
+            }
+            AggregationInputs::SectorUpdate(_) => panic!("wrong aggregation kind"),
+        }
+
+        let other_config = PoRepConfig::new_groth16(2048, [2u8; 32], ApiVersion::V1_2_0);
+        assert!(resumed
+            .merge(AggregationBuilder::new_seal(&other_config))
+            .is_err());
+
+        std::fs::write(&path, b"garbage").expect("write failed");
+        assert!(AggregationBuilder::load(&path).is_err());
+    }
+}
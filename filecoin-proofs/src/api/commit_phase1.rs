@@ -0,0 +1,437 @@
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+
+use anyhow::{ensure, Context, Result};
+use filecoin_hashers::{Domain, Hasher, PoseidonArity};
+use log::info;
+use serde::{Deserialize, Serialize};
+use storage_proofs_core::{
+    drgraph::BASE_DEGREE,
+    merkle::{MerkleMultiProof, MerkleProof, MerkleTreeTrait},
+    proof::ProofScheme,
+};
+use storage_proofs_porep::stacked::{self, ColumnProof, StackedDrg, EXP_DEGREE};
+use typenum::U2;
+
+use crate::{
+    api::{
+        as_safe_commitment,
+        util::{append_checksummed_body, checksummed_body, CHECKSUM_LEN},
+    },
+    constants::{DefaultPieceDomain, DefaultPieceHasher},
+    parameters::public_params,
+    types::{Commitment, PoRepConfig, SealCommitPhase1Output, Ticket, VanillaSealProof},
+};
+
+/// The version of the encoding written by `encode_seal_commit_phase1_output`.
+pub const SEAL_COMMIT_PHASE1_OUTPUT_VERSION: u16 = 1;
+
+const MAGIC: [u8; 4] = *b"FC1O";
+/// Set if the vanilla proofs are stored in the compact form.
+const FLAG_COMPACT: u8 = 1;
+/// Magic (4 bytes), version (2 bytes), flags (1 byte), sector size (8 bytes) and body length
+/// (8 bytes).
+const HEADER_LEN: usize = 23;
+
+type TreeRMerkleProof<Tree> = MerkleProof<
+    <Tree as MerkleTreeTrait>::Hasher,
+    <Tree as MerkleTreeTrait>::Arity,
+    <Tree as MerkleTreeTrait>::SubTreeArity,
+    <Tree as MerkleTreeTrait>::TopTreeArity,
+>;
+type TreeRMultiProof<Tree> = MerkleMultiProof<
+    <Tree as MerkleTreeTrait>::Hasher,
+    <Tree as MerkleTreeTrait>::Arity,
+    <Tree as MerkleTreeTrait>::SubTreeArity,
+    <Tree as MerkleTreeTrait>::TopTreeArity,
+>;
+type TreeRDomain<Tree> = <<Tree as MerkleTreeTrait>::Hasher as Hasher>::Domain;
+type TreeDMultiProof = MerkleMultiProof<DefaultPieceHasher, U2>;
+
+/// A vanilla seal proof reduced to the columns it opens. Its Merkle proofs are taken from the
+/// multiproofs of the output, the labeling and encoding proofs are derived from the column proofs
+/// on decoding.
+#[derive(Serialize, Deserialize)]
+struct CompactVanillaSealProof {
+    challenge: u32,
+    drg_parents: Vec<u32>,
+    exp_parents: Vec<u32>,
+}
+
+/// All challenges open the same trees, hence the Merkle proofs into each tree are merged into a
+/// multiproof, which stores every node at most once. Columns that are opened by several
+/// challenges are stored once as well.
+#[derive(Serialize, Deserialize)]
+#[serde(bound = "")]
+struct CompactSealCommitPhase1Output<Tree: MerkleTreeTrait> {
+    comm_r: Commitment,
+    comm_d: Commitment,
+    replica_id: TreeRDomain<Tree>,
+    seed: Ticket,
+    ticket: Ticket,
+    tree_d_proof: TreeDMultiProof,
+    tree_c_proof: TreeRMultiProof<Tree>,
+    tree_r_last_proof: TreeRMultiProof<Tree>,
+    /// The rows of every opened column, sorted by column index.
+    columns: Vec<(u32, Vec<TreeRDomain<Tree>>)>,
+    vanilla_proofs: Vec<Vec<CompactVanillaSealProof>>,
+}
+
+/// Adds the column of `proof` to `columns` and its inclusion proof to `inclusion_proofs`, unless
+/// the column was added before. Returns the column index.
+fn insert_column<Tree: MerkleTreeTrait>(
+    proof: &ColumnProof<TreeRMerkleProof<Tree>>,
+    columns: &mut BTreeMap<u32, Vec<TreeRDomain<Tree>>>,
+    inclusion_proofs: &mut Vec<TreeRMerkleProof<Tree>>,
+) -> Result<u32> {
+    let index = proof.column().index();
+    ensure!(
+        TreeRMultiProof::<Tree>::leaf_position(proof.inclusion_proof())? == index as usize,
+        "inclusion proof of column {} opens a different column",
+        index
+    );
+    let rows = proof.column().rows();
+    match columns.get(&index) {
+        Some(existing) => ensure!(
+            existing[..] == rows[..],
+            "vanilla proofs disagree on column {}",
+            index
+        ),
+        None => {
+            columns.insert(index, rows.to_vec());
+            inclusion_proofs.push(proof.inclusion_proof().clone());
+        }
+    }
+    Ok(index)
+}
+
+/// Expands a multiproof into the proofs of its leaves, by leaf position.
+fn proofs_by_leaf<H, A, B, C>(
+    multiproof: &MerkleMultiProof<H, A, B, C>,
+) -> Result<BTreeMap<usize, MerkleProof<H, A, B, C>>>
+where
+    H: Hasher,
+    A: 'static + PoseidonArity,
+    B: 'static + PoseidonArity,
+    C: 'static + PoseidonArity,
+{
+    let positions = multiproof.leaves().iter().map(|(pos, _)| *pos);
+    Ok(positions.zip(multiproof.proofs()?).collect())
+}
+
+fn get_proof<P: Clone>(proofs: &BTreeMap<usize, P>, pos: u32) -> Result<P> {
+    proofs
+        .get(&(pos as usize))
+        .cloned()
+        .with_context(|| format!("missing proof of node {}", pos))
+}
+
+// Checks the shape of the column proofs of a challenge, as `VanillaSealProof::from_parts` reads
+// every layer of every parent column and expects the parents of the graph.
+fn ensure_columns_consistent<Tree: MerkleTreeTrait>(
+    c_x: &ColumnProof<TreeRMerkleProof<Tree>>,
+    drg_parents: &[ColumnProof<TreeRMerkleProof<Tree>>],
+    exp_parents: &[ColumnProof<TreeRMerkleProof<Tree>>],
+) -> Result<()> {
+    let num_layers = c_x.column().rows().len();
+    ensure!(num_layers > 0, "vanilla proof contains an empty column");
+    ensure!(
+        drg_parents.len() == BASE_DEGREE && exp_parents.len() == EXP_DEGREE,
+        "vanilla proof has {} drg and {} exp parents, expected {} and {}",
+        drg_parents.len(),
+        exp_parents.len(),
+        BASE_DEGREE,
+        EXP_DEGREE
+    );
+    ensure!(
+        drg_parents
+            .iter()
+            .chain(exp_parents)
+            .all(|parent| parent.column().rows().len() == num_layers),
+        "vanilla proof contains a parent column without {} layers",
+        num_layers
+    );
+    Ok(())
+}
+
+fn compact_phase1_output<Tree: 'static + MerkleTreeTrait>(
+    phase1_output: &SealCommitPhase1Output<Tree>,
+) -> Result<CompactSealCommitPhase1Output<Tree>> {
+    let mut comm_d_proofs = Vec::new();
+    let mut comm_r_last_proofs = Vec::new();
+    let mut inclusion_proofs = Vec::new();
+    let mut columns = BTreeMap::new();
+
+    let mut vanilla_proofs = Vec::with_capacity(phase1_output.vanilla_proofs.len());
+    for partition_proofs in &phase1_output.vanilla_proofs {
+        let mut compact_proofs = Vec::with_capacity(partition_proofs.len());
+        for proof in partition_proofs {
+            let column_proofs = &proof.replica_column_proofs;
+            let challenge =
+                insert_column::<Tree>(&column_proofs.c_x, &mut columns, &mut inclusion_proofs)?;
+            ensure!(
+                TreeDMultiProof::leaf_position(&proof.comm_d_proofs)? == challenge as usize
+                    && TreeRMultiProof::<Tree>::leaf_position(&proof.comm_r_last_proof)?
+                        == challenge as usize,
+                "vanilla proof of challenge {} opens different nodes",
+                challenge
+            );
+            comm_d_proofs.push(proof.comm_d_proofs.clone());
+            comm_r_last_proofs.push(proof.comm_r_last_proof.clone());
+
+            let mut insert_parents = |parents: &[ColumnProof<TreeRMerkleProof<Tree>>]| {
+                parents
+                    .iter()
+                    .map(|parent| {
+                        insert_column::<Tree>(parent, &mut columns, &mut inclusion_proofs)
+                    })
+                    .collect::<Result<Vec<_>>>()
+            };
+            compact_proofs.push(CompactVanillaSealProof {
+                challenge,
+                drg_parents: insert_parents(&column_proofs.drg_parents)?,
+                exp_parents: insert_parents(&column_proofs.exp_parents)?,
+            });
+        }
+        vanilla_proofs.push(compact_proofs);
+    }
+
+    Ok(CompactSealCommitPhase1Output {
+        comm_r: phase1_output.comm_r,
+        comm_d: phase1_output.comm_d,
+        replica_id: phase1_output.replica_id,
+        seed: phase1_output.seed,
+        ticket: phase1_output.ticket,
+        tree_d_proof: MerkleMultiProof::from_proofs(&comm_d_proofs)?,
+        tree_c_proof: MerkleMultiProof::from_proofs(&inclusion_proofs)?,
+        tree_r_last_proof: MerkleMultiProof::from_proofs(&comm_r_last_proofs)?,
+        columns: columns.into_iter().collect(),
+        vanilla_proofs,
+    })
+}
+
+fn expand_phase1_output<Tree: 'static + MerkleTreeTrait>(
+    compact: CompactSealCommitPhase1Output<Tree>,
+) -> Result<SealCommitPhase1Output<Tree>> {
+    let comm_d_proofs = proofs_by_leaf(&compact.tree_d_proof)?;
+    let comm_r_last_proofs = proofs_by_leaf(&compact.tree_r_last_proof)?;
+    let inclusion_proofs = proofs_by_leaf(&compact.tree_c_proof)?;
+    let num_columns = compact.columns.len();
+    let columns: BTreeMap<_, _> = compact.columns.into_iter().collect();
+    ensure!(
+        columns.len() == num_columns,
+        "compact commit phase 1 output contains duplicate columns"
+    );
+
+    let column_proof = |index: u32| -> Result<ColumnProof<TreeRMerkleProof<Tree>>> {
+        let rows = columns
+            .get(&index)
+            .with_context(|| format!("missing column {}", index))?;
+        ColumnProof::from_column(
+            stacked::Column::new(index, rows.clone())?,
+            get_proof(&inclusion_proofs, index)?,
+        )
+    };
+
+    let vanilla_proofs = compact
+        .vanilla_proofs
+        .iter()
+        .map(|partition_proofs| {
+            partition_proofs
+                .iter()
+                .map(|proof| {
+                    let c_x = column_proof(proof.challenge)?;
+                    let drg_parents = proof
+                        .drg_parents
+                        .iter()
+                        .map(|parent| column_proof(*parent))
+                        .collect::<Result<Vec<_>>>()?;
+                    let exp_parents = proof
+                        .exp_parents
+                        .iter()
+                        .map(|parent| column_proof(*parent))
+                        .collect::<Result<Vec<_>>>()?;
+                    ensure_columns_consistent::<Tree>(&c_x, &drg_parents, &exp_parents)?;
+
+                    Ok(VanillaSealProof::<Tree>::from_parts(
+                        get_proof(&comm_d_proofs, proof.challenge)?,
+                        c_x,
+                        drg_parents,
+                        exp_parents,
+                        get_proof(&comm_r_last_proofs, proof.challenge)?,
+                    ))
+                })
+                .collect::<Result<Vec<_>>>()
+        })
+        .collect::<Result<_>>()?;
+
+    Ok(SealCommitPhase1Output {
+        vanilla_proofs,
+        comm_r: compact.comm_r,
+        comm_d: compact.comm_d,
+        replica_id: compact.replica_id,
+        seed: compact.seed,
+        ticket: compact.ticket,
+    })
+}
+
+/// Encodes the output of commit phase 1, e.g. to transfer it to a different machine for commit
+/// phase 2.
+///
+/// The encoding starts with a versioned header that also contains the sector size and ends with a
+/// SHA-256 checksum. If `compact` is set, the Merkle proofs into each tree are merged into a
+/// multiproof, every opened column is stored only once and the labeling and encoding proofs are
+/// omitted, as they can be derived from the column proofs.
+///
+/// # Arguments
+///
+/// * `porep_config` - this sector's porep config that contains the number of bytes in the sector.
+/// * `phase1_output` - the output of `seal_commit_phase1`.
+/// * `compact` - whether to use the compact encoding of the vanilla proofs.
+pub fn encode_seal_commit_phase1_output<Tree: 'static + MerkleTreeTrait>(
+    porep_config: &PoRepConfig,
+    phase1_output: &SealCommitPhase1Output<Tree>,
+    compact: bool,
+) -> Result<Vec<u8>> {
+    info!("encode_seal_commit_phase1_output:start");
+
+    let (flags, body) = if compact {
+        (
+            FLAG_COMPACT,
+            bincode::serialize(&compact_phase1_output(phase1_output)?)?,
+        )
+    } else {
+        (0, bincode::serialize(phase1_output)?)
+    };
+
+    let mut bytes = Vec::with_capacity(HEADER_LEN + body.len() + CHECKSUM_LEN);
+    bytes.extend_from_slice(&MAGIC);
+    bytes.extend_from_slice(&SEAL_COMMIT_PHASE1_OUTPUT_VERSION.to_le_bytes());
+    bytes.push(flags);
+    bytes.extend_from_slice(&u64::from(porep_config.sector_size).to_le_bytes());
+    append_checksummed_body(&mut bytes, &body);
+
+    info!("encode_seal_commit_phase1_output:finish");
+    Ok(bytes)
+}
+
+/// Decodes the output of commit phase 1 that was encoded with
+/// `encode_seal_commit_phase1_output` and validates it with
+/// `validate_seal_commit_phase1_output`, so that a corrupt or mismatching output is rejected
+/// before commit phase 2 is started.
+///
+/// # Arguments
+///
+/// * `porep_config` - this sector's porep config that contains the number of bytes in the sector.
+/// * `bytes` - the encoded commit phase 1 output.
+pub fn decode_seal_commit_phase1_output<Tree: 'static + MerkleTreeTrait>(
+    porep_config: &PoRepConfig,
+    bytes: &[u8],
+) -> Result<SealCommitPhase1Output<Tree>> {
+    info!("decode_seal_commit_phase1_output:start");
+
+    ensure!(
+        bytes.len() >= HEADER_LEN + CHECKSUM_LEN,
+        "commit phase 1 output is too short ({} bytes)",
+        bytes.len()
+    );
+    ensure!(
+        bytes[..4] == MAGIC,
+        "data is not an encoded commit phase 1 output"
+    );
+
+    let version = u16::from_le_bytes(bytes[4..6].try_into()?);
+    ensure!(
+        version == SEAL_COMMIT_PHASE1_OUTPUT_VERSION,
+        "unsupported commit phase 1 output version {}, expected {}",
+        version,
+        SEAL_COMMIT_PHASE1_OUTPUT_VERSION
+    );
+    let flags = bytes[6];
+    ensure!(
+        flags & !FLAG_COMPACT == 0,
+        "unknown commit phase 1 output flags {:#x}",
+        flags
+    );
+    let sector_size = u64::from_le_bytes(bytes[7..15].try_into()?);
+    ensure!(
+        sector_size == u64::from(porep_config.sector_size),
+        "commit phase 1 output is for sector size {}, expected {}",
+        sector_size,
+        u64::from(porep_config.sector_size)
+    );
+    let body = checksummed_body(bytes, HEADER_LEN, "commit phase 1 output")?;
+    let phase1_output = if flags & FLAG_COMPACT != 0 {
+        let compact = bincode::deserialize(body)
+            .context("failed to deserialize compact commit phase 1 output")?;
+        expand_phase1_output(compact)?
+    } else {
+        bincode::deserialize(body).context("failed to deserialize commit phase 1 output")?
+    };
+
+    validate_seal_commit_phase1_output(porep_config, &phase1_output)?;
+
+    info!("decode_seal_commit_phase1_output:finish");
+    Ok(phase1_output)
+}
+
+/// Checks that the vanilla proofs of a commit phase 1 output are valid for its comm_r, comm_d
+/// and seed. This is a lot cheaper than commit phase 2 and catches any output it would fail on
+/// due to invalid vanilla proofs.
+///
+/// # Arguments
+///
+/// * `porep_config` - this sector's porep config that contains the number of bytes in the sector.
+/// * `phase1_output` - the output of `seal_commit_phase1`.
+pub fn validate_seal_commit_phase1_output<Tree: 'static + MerkleTreeTrait>(
+    porep_config: &PoRepConfig,
+    phase1_output: &SealCommitPhase1Output<Tree>,
+) -> Result<()> {
+    let SealCommitPhase1Output {
+        vanilla_proofs,
+        comm_d,
+        comm_r,
+        replica_id,
+        seed,
+        ticket: _,
+    } = phase1_output;
+
+    ensure!(comm_d != &[0; 32], "Invalid all zero commitment (comm_d)");
+    ensure!(comm_r != &[0; 32], "Invalid all zero commitment (comm_r)");
+    ensure!(seed != &[0; 32], "Invalid porep challenge seed");
+    ensure!(
+        vanilla_proofs.len() == usize::from(porep_config.partitions),
+        "expected vanilla proofs for {} partitions, got {}",
+        usize::from(porep_config.partitions),
+        vanilla_proofs.len(),
+    );
+    ensure!(
+        vanilla_proofs
+            .iter()
+            .all(|partition_proofs| !partition_proofs.is_empty()),
+        "C1 output contains no vanilla proofs",
+    );
+
+    let public_inputs = stacked::PublicInputs {
+        replica_id: *replica_id,
+        tau: Some(stacked::Tau {
+            comm_d: DefaultPieceDomain::try_from_bytes(comm_d)?,
+            comm_r: as_safe_commitment(comm_r, "comm_r")?,
+        }),
+        k: None,
+        seed: Some(*seed),
+    };
+    let public_params = public_params::<Tree>(porep_config)?;
+
+    let valid = StackedDrg::<Tree, DefaultPieceHasher>::verify_all_partitions(
+        &public_params,
+        &public_inputs,
+        vanilla_proofs,
+    )?;
+    ensure!(
+        valid,
+        "vanilla proofs do not match comm_r, comm_d and seed of the commit phase 1 output"
+    );
+
+    Ok(())
+}
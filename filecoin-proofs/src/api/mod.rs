@@ -25,25 +25,36 @@ use crate::{
         MINIMUM_RESERVED_BYTES_FOR_PIECE_IN_FULLY_ALIGNED_SECTOR as MINIMUM_PIECE_SIZE,
     },
     parameters::public_params,
-    pieces::{get_piece_alignment, sum_piece_bytes_with_alignment},
+    pieces::{
+        get_piece_alignment, is_zero_range, sum_piece_bytes_with_alignment, verify_pieces,
+        zero_ranges, EmptySource,
+    },
     types::{
         Commitment, MerkleTreeTrait, PaddedBytesAmount, PieceInfo, PoRepConfig, ProverId,
         SealPreCommitPhase1Output, Ticket, UnpaddedByteIndex, UnpaddedBytesAmount,
     },
 };
 
+mod aggregate;
+mod commit_phase1;
 mod fake_seal;
+mod ni_porep;
 mod post_util;
 mod seal;
 mod update;
+mod update_vanilla;
 mod util;
 mod window_post;
 mod winning_post;
 
+pub use aggregate::*;
+pub use commit_phase1::*;
 pub use fake_seal::*;
+pub use ni_porep::*;
 pub use post_util::*;
 pub use seal::*;
 pub use update::*;
+pub use update_vanilla::*;
 pub use util::*;
 pub use window_post::*;
 pub use winning_post::*;
@@ -124,6 +135,87 @@ pub fn get_unsealed_range<T: Into<PathBuf> + AsRef<Path>, Tree: 'static + Merkle
     result
 }
 
+/// Like `get_unsealed_range`, but consults the layout of the sector's pieces first. If the
+/// requested range lies entirely within the alignment padding or a zero piece (see
+/// `zero_ranges`), the zeros are written directly and the sector is not unsealed at all. This
+/// makes reads from CC sectors and from the padding of partially filled sectors cheap.
+///
+/// # Arguments
+///
+/// * `porep_config` - porep configuration containing the sector size.
+/// * `cache_path` - path to the directory in which the sector data's Merkle Tree is written.
+/// * `sealed_path` - path to the sealed sector file that we will unseal and read a byte range.
+/// * `output_path` - path to a file that we will write the requested byte range to.
+/// * `prover_id` - the prover-id that sealed the sector.
+/// * `sector_id` - the sector-id of the sealed sector.
+/// * `comm_d` - the commitment to the sector's data.
+/// * `ticket` - the ticket that was used to generate the sector's replica-id.
+/// * `piece_infos` - the pieces of the sector, which must match `comm_d`.
+/// * `offset` - the byte index in the unsealed sector of the first byte that we want to read.
+/// * `num_bytes` - the number of bytes that we want to read.
+#[allow(clippy::too_many_arguments)]
+pub fn get_unsealed_range_with_pieces<
+    T: Into<PathBuf> + AsRef<Path>,
+    Tree: 'static + MerkleTreeTrait,
+>(
+    porep_config: &PoRepConfig,
+    cache_path: T,
+    sealed_path: T,
+    output_path: T,
+    prover_id: ProverId,
+    sector_id: SectorId,
+    comm_d: Commitment,
+    ticket: Ticket,
+    piece_infos: &[PieceInfo],
+    offset: UnpaddedByteIndex,
+    num_bytes: UnpaddedBytesAmount,
+) -> Result<UnpaddedBytesAmount> {
+    info!("get_unsealed_range_with_pieces:start");
+
+    ensure!(
+        verify_pieces(&comm_d, piece_infos, porep_config.sector_size)?,
+        "pieces and comm_d do not match"
+    );
+
+    let zero_ranges = zero_ranges(porep_config.sector_size, piece_infos)?;
+    let range = u64::from(offset)..u64::from(offset) + u64::from(num_bytes);
+
+    let result = if is_zero_range(&zero_ranges, &range) {
+        trace!("range {:?} is known to be zero, skipping unsealing", range);
+        write_zeros(output_path, num_bytes)
+    } else {
+        get_unsealed_range::<_, Tree>(
+            porep_config,
+            cache_path,
+            sealed_path,
+            output_path,
+            prover_id,
+            sector_id,
+            comm_d,
+            ticket,
+            offset,
+            num_bytes,
+        )
+    };
+
+    info!("get_unsealed_range_with_pieces:finish");
+    result
+}
+
+/// Writes `num_bytes` zeros to a newly created file at `output_path`.
+fn write_zeros<T: AsRef<Path>>(
+    output_path: T,
+    num_bytes: UnpaddedBytesAmount,
+) -> Result<UnpaddedBytesAmount> {
+    let f_out = File::create(&output_path)
+        .with_context(|| format!("could not create output_path={:?}", output_path.as_ref()))?;
+    let mut buf_f_out = BufWriter::new(f_out);
+    let written = io::copy(&mut EmptySource::new(num_bytes.into()), &mut buf_f_out)?;
+    buf_f_out.flush()?;
+
+    Ok(UnpaddedBytesAmount(written))
+}
+
 /// Unseals the sector read from `sealed_sector` and returns the bytes for a
 /// piece whose first (unpadded) byte begins at `offset` and ends at `offset`
 /// plus `num_bytes`, inclusive. Note that the entire sector is unsealed each
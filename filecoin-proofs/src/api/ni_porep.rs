@@ -0,0 +1,266 @@
+use std::path::Path;
+
+use anyhow::{ensure, Result};
+use bellperson::groth16::aggregate::AggregateVersion;
+use log::info;
+use storage_proofs_core::{api_version::ApiFeature, sector::SectorId};
+
+use crate::{
+    api::{
+        aggregate_seal_commit_proofs, seal_commit_phase1_inner, seal_commit_phase2_circuit_proofs,
+        verify_aggregate_seal_commit_proofs_with_records,
+    },
+    constants::{FIP92_MAX_NI_POREP_AGGREGATION_PROOFS, FIP92_MIN_NI_POREP_AGGREGATION_PROOFS},
+    types::{
+        AggregateSnarkProof, Commitment, MerkleTreeTrait, NiSealProofRecord, PieceInfo,
+        PoRepConfig, ProverId, SealCommitOutput, SealPreCommitOutput, SealProofRecord, Ticket,
+    },
+};
+
+/// NI-PoRep challenges are derived from comm_r alone, but the interactive seal APIs this module
+/// builds on require a non-zero seed, so this placeholder is passed to them.
+const NI_POREP_SEED: Ticket = [1; 32];
+
+/// NI-PoRep proofs are always aggregated with SnarkPack v2.
+const NI_POREP_AGGREGATE_VERSION: AggregateVersion = AggregateVersion::V2;
+
+fn ensure_ni_porep(porep_config: &PoRepConfig) -> Result<()> {
+    ensure!(
+        porep_config.feature_enabled(ApiFeature::NonInteractivePoRep),
+        "NonInteractivePoRep is not enabled in the porep config"
+    );
+    ensure!(
+        porep_config.api_version >= ApiFeature::NonInteractivePoRep.first_supported_version(),
+        "API version does not support NonInteractivePoRep"
+    );
+
+    Ok(())
+}
+
+fn ensure_ni_porep_aggregation_bounds(num_proofs: usize) -> Result<()> {
+    ensure!(
+        (FIP92_MIN_NI_POREP_AGGREGATION_PROOFS..=FIP92_MAX_NI_POREP_AGGREGATION_PROOFS)
+            .contains(&num_proofs),
+        "{} proofs is outside of FIP-0092 specified NI-PoRep aggregation bounds [{}, {}]",
+        num_proofs,
+        FIP92_MIN_NI_POREP_AGGREGATION_PROOFS,
+        FIP92_MAX_NI_POREP_AGGREGATION_PROOFS,
+    );
+
+    Ok(())
+}
+
+/// Generates the NI-PoRep circuit proofs of a sector, whose challenges are derived from its
+/// comm_r rather than from a chain seed. The output is not verifiable on its own, it must be
+/// aggregated with `aggregate_ni_porep`, alone or together with the proofs of other sectors.
+///
+/// # Arguments
+///
+/// * `porep_config` - this sector's porep config, with `ApiFeature::NonInteractivePoRep` enabled.
+/// * `cache_path` - path to the sector's cache directory.
+/// * `replica_path` - path to the sealed sector.
+/// * `prover_id` - the prover-id that sealed this sector.
+/// * `sector_id` - this sector's sector-id.
+/// * `ticket` - the ticket that was used to generate this sector's replica-id.
+/// * `pre_commit` - the output of `seal_pre_commit_phase2`.
+/// * `piece_infos` - the piece info (commitment and byte length) for each piece in this sector.
+#[allow(clippy::too_many_arguments)]
+pub fn seal_ni_porep<T: AsRef<Path>, Tree: 'static + MerkleTreeTrait>(
+    porep_config: &PoRepConfig,
+    cache_path: T,
+    replica_path: T,
+    prover_id: ProverId,
+    sector_id: SectorId,
+    ticket: Ticket,
+    pre_commit: SealPreCommitOutput,
+    piece_infos: &[PieceInfo],
+) -> Result<SealCommitOutput> {
+    info!("seal_ni_porep:start: {:?}", sector_id);
+
+    ensure_ni_porep(porep_config)?;
+
+    let phase1_output = seal_commit_phase1_inner::<T, Tree>(
+        porep_config,
+        cache_path,
+        replica_path,
+        prover_id,
+        sector_id,
+        ticket,
+        Some(NI_POREP_SEED),
+        pre_commit,
+        piece_infos,
+        false,
+    )?;
+    let out = seal_commit_phase2_circuit_proofs::<Tree>(porep_config, phase1_output, sector_id)?;
+
+    info!("seal_ni_porep:finish: {:?}", sector_id);
+    Ok(out)
+}
+
+/// Aggregates the NI-PoRep proofs of between `FIP92_MIN_NI_POREP_AGGREGATION_PROOFS` and
+/// `FIP92_MAX_NI_POREP_AGGREGATION_PROOFS` sectors into a single proof. The bounds are checked
+/// before any parameters are loaded.
+///
+/// # Arguments
+///
+/// * `porep_config` - the porep config of the sectors, with `ApiFeature::NonInteractivePoRep`
+///    enabled.
+/// * `comm_rs` - an ordered list of the sectors' replica commitments.
+/// * `commit_outputs` - an ordered list of proofs returned from `seal_ni_porep`.
+pub fn aggregate_ni_porep<Tree: 'static + MerkleTreeTrait>(
+    porep_config: &PoRepConfig,
+    comm_rs: &[Commitment],
+    commit_outputs: &[SealCommitOutput],
+) -> Result<AggregateSnarkProof> {
+    info!("aggregate_ni_porep:start");
+
+    ensure_ni_porep(porep_config)?;
+    ensure_ni_porep_aggregation_bounds(commit_outputs.len())?;
+    ensure!(
+        comm_rs.len() == commit_outputs.len(),
+        "invalid comm_rs and commit_outputs len mismatch"
+    );
+    ensure!(
+        comm_rs.iter().all(|comm_r| comm_r != &[0; 32]),
+        "Invalid all zero commitment (comm_r)"
+    );
+
+    let seeds = vec![NI_POREP_SEED; comm_rs.len()];
+    let aggregate_proof = aggregate_seal_commit_proofs::<Tree>(
+        porep_config,
+        comm_rs,
+        &seeds,
+        commit_outputs,
+        NI_POREP_AGGREGATE_VERSION,
+    )?;
+
+    info!("aggregate_ni_porep:finish");
+    Ok(aggregate_proof)
+}
+
+/// Verifies an aggregate NI-PoRep proof returned from `aggregate_ni_porep`. The number of
+/// records is checked against the FIP-0092 bounds, and every record is checked, before the proof
+/// is verified.
+///
+/// # Arguments
+///
+/// * `porep_config` - the porep config of the sectors, with `ApiFeature::NonInteractivePoRep`
+///    enabled.
+/// * `aggregate_proof_bytes` - the aggregate proof returned from `aggregate_ni_porep`.
+/// * `records` - an ordered list of the sectors whose proofs were aggregated, which must match
+///    the ordering of the proofs when aggregated.
+pub fn verify_ni_porep<Tree: 'static + MerkleTreeTrait>(
+    porep_config: &PoRepConfig,
+    aggregate_proof_bytes: AggregateSnarkProof,
+    records: &[NiSealProofRecord],
+) -> Result<bool> {
+    info!("verify_ni_porep:start");
+
+    ensure_ni_porep(porep_config)?;
+    ensure_ni_porep_aggregation_bounds(records.len())?;
+
+    let records: Vec<_> = records
+        .iter()
+        .map(|record| SealProofRecord {
+            comm_r: record.comm_r,
+            comm_d: record.comm_d,
+            prover_id: record.prover_id,
+            sector_id: record.sector_id,
+            ticket: record.ticket,
+            seed: NI_POREP_SEED,
+        })
+        .collect();
+    let result = verify_aggregate_seal_commit_proofs_with_records::<Tree>(
+        porep_config,
+        aggregate_proof_bytes,
+        &records,
+        NI_POREP_AGGREGATE_VERSION,
+    )?;
+
+    info!("verify_ni_porep:finish");
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use storage_proofs_core::api_version::ApiVersion;
+
+    use crate::constants::{SectorShape2KiB, SECTOR_SIZE_2_KIB};
+
+    fn ni_porep_config(api_features: Vec<ApiFeature>) -> PoRepConfig {
+        PoRepConfig::new_groth16_with_features(
+            SECTOR_SIZE_2_KIB,
+            [0u8; 32],
+            ApiVersion::V1_2_0,
+            api_features,
+        )
+        .expect("failed to create porep config")
+    }
+
+    fn record(i: u64) -> NiSealProofRecord {
+        NiSealProofRecord {
+            comm_r: [1; 32],
+            comm_d: [2; 32],
+            prover_id: [3; 32],
+            sector_id: SectorId::from(i),
+            ticket: [4; 32],
+        }
+    }
+
+    #[test]
+    fn test_ni_porep_aggregation_bounds() {
+        let porep_config = ni_porep_config(vec![ApiFeature::NonInteractivePoRep]);
+
+        for num_proofs in [
+            FIP92_MIN_NI_POREP_AGGREGATION_PROOFS - 1,
+            FIP92_MAX_NI_POREP_AGGREGATION_PROOFS + 1,
+        ] {
+            let comm_rs = vec![[1; 32]; num_proofs];
+            let commit_outputs = vec![SealCommitOutput { proof: Vec::new() }; num_proofs];
+            let err =
+                aggregate_ni_porep::<SectorShape2KiB>(&porep_config, &comm_rs, &commit_outputs)
+                    .expect_err("aggregation out of bounds must fail");
+            assert!(err.to_string().contains("FIP-0092"), "{}", err);
+
+            let records: Vec<_> = (0..num_proofs as u64).map(record).collect();
+            let err = verify_ni_porep::<SectorShape2KiB>(&porep_config, Vec::new(), &records)
+                .expect_err("verification out of bounds must fail");
+            assert!(err.to_string().contains("FIP-0092"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_ni_porep_requires_feature() {
+        let porep_config = ni_porep_config(Vec::new());
+
+        assert!(aggregate_ni_porep::<SectorShape2KiB>(
+            &porep_config,
+            &[[1; 32]],
+            &[SealCommitOutput { proof: Vec::new() }],
+        )
+        .is_err());
+        assert!(
+            verify_ni_porep::<SectorShape2KiB>(&porep_config, Vec::new(), &[record(0)]).is_err()
+        );
+    }
+
+    #[test]
+    fn test_ni_porep_rejects_mismatched_inputs() {
+        let porep_config = ni_porep_config(vec![ApiFeature::NonInteractivePoRep]);
+
+        assert!(aggregate_ni_porep::<SectorShape2KiB>(
+            &porep_config,
+            &[[1; 32], [1; 32]],
+            &[SealCommitOutput { proof: Vec::new() }],
+        )
+        .is_err());
+        assert!(aggregate_ni_porep::<SectorShape2KiB>(
+            &porep_config,
+            &[[0; 32]],
+            &[SealCommitOutput { proof: Vec::new() }],
+        )
+        .is_err());
+    }
+}
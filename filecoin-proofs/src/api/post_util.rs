@@ -1,15 +1,24 @@
 use std::collections::BTreeMap;
+use std::convert::{TryFrom, TryInto};
 
 use anyhow::{anyhow, ensure, Context, Result};
 use filecoin_hashers::Hasher;
 use log::{debug, info};
-use storage_proofs_core::{merkle::MerkleTreeTrait, proof::ProofScheme, sector::SectorId};
+use serde::{Deserialize, Serialize};
+use storage_proofs_core::{
+    merkle::{MerkleMultiProof, MerkleTreeTrait},
+    proof::ProofScheme,
+    sector::SectorId,
+};
 use storage_proofs_post::fallback::{
     self, generate_leaf_challenge, get_challenge_index, FallbackPoSt, SectorProof,
 };
 
 use crate::{
-    api::as_safe_commitment,
+    api::{
+        as_safe_commitment,
+        util::{append_checksummed_body, checksummed_body, CHECKSUM_LEN},
+    },
     types::{
         ChallengeSeed, FallbackPoStSectorProof, PoStConfig, PrivateReplicaInfo, ProverId,
         VanillaProof,
@@ -143,6 +152,144 @@ pub fn generate_single_vanilla_proof<Tree: 'static + MerkleTreeTrait>(
     })
 }
 
+type TreeMultiProof<Tree> = MerkleMultiProof<
+    <Tree as MerkleTreeTrait>::Hasher,
+    <Tree as MerkleTreeTrait>::Arity,
+    <Tree as MerkleTreeTrait>::SubTreeArity,
+    <Tree as MerkleTreeTrait>::TopTreeArity,
+>;
+type TreeDomain<Tree> = <<Tree as MerkleTreeTrait>::Hasher as Hasher>::Domain;
+
+/// The version of the encoding written by `encode_fallback_post_sector_proof`.
+pub const FALLBACK_POST_SECTOR_PROOF_VERSION: u16 = 1;
+
+const SECTOR_PROOF_MAGIC: [u8; 4] = *b"FPSP";
+/// Magic (4 bytes), version (2 bytes) and body length (8 bytes).
+const SECTOR_PROOF_HEADER_LEN: usize = 14;
+
+/// The inclusion proofs of a sector proof, merged into a multiproof.
+#[derive(Serialize, Deserialize)]
+#[serde(bound = "")]
+struct CompactSectorProof<Tree: MerkleTreeTrait> {
+    /// The challenged leaves, in the order of the inclusion proofs.
+    challenges: Vec<u64>,
+    inclusion_proof: TreeMultiProof<Tree>,
+    comm_c: TreeDomain<Tree>,
+    comm_r_last: TreeDomain<Tree>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(bound = "")]
+struct CompactFallbackPoStSectorProof<Tree: MerkleTreeTrait> {
+    sector_id: SectorId,
+    comm_r: TreeDomain<Tree>,
+    sectors: Vec<CompactSectorProof<Tree>>,
+}
+
+/// Encodes a vanilla proof generated by `generate_single_vanilla_proof`, e.g. to transfer it to
+/// the machine that generates the PoSt. The challenges of a sector open the same tree, hence their
+/// inclusion proofs are merged into a multiproof, which stores every node at most once.
+///
+/// The encoding starts with a versioned header and ends with a SHA-256 checksum.
+///
+/// # Arguments
+///
+/// * `vanilla_proof` - the vanilla proof of a single sector.
+pub fn encode_fallback_post_sector_proof<Tree: 'static + MerkleTreeTrait>(
+    vanilla_proof: &FallbackPoStSectorProof<Tree>,
+) -> Result<Vec<u8>> {
+    let sectors = vanilla_proof
+        .vanilla_proof
+        .sectors
+        .iter()
+        .map(|sector| {
+            let challenges = sector
+                .inclusion_proofs
+                .iter()
+                .map(|proof| Ok(TreeMultiProof::<Tree>::leaf_position(proof)? as u64))
+                .collect::<Result<_>>()?;
+            Ok(CompactSectorProof::<Tree> {
+                challenges,
+                inclusion_proof: MerkleMultiProof::from_proofs(&sector.inclusion_proofs)?,
+                comm_c: sector.comm_c,
+                comm_r_last: sector.comm_r_last,
+            })
+        })
+        .collect::<Result<_>>()?;
+    let body = bincode::serialize(&CompactFallbackPoStSectorProof::<Tree> {
+        sector_id: vanilla_proof.sector_id,
+        comm_r: vanilla_proof.comm_r,
+        sectors,
+    })?;
+
+    let mut bytes = Vec::with_capacity(SECTOR_PROOF_HEADER_LEN + body.len() + CHECKSUM_LEN);
+    bytes.extend_from_slice(&SECTOR_PROOF_MAGIC);
+    bytes.extend_from_slice(&FALLBACK_POST_SECTOR_PROOF_VERSION.to_le_bytes());
+    append_checksummed_body(&mut bytes, &body);
+    Ok(bytes)
+}
+
+/// Decodes a vanilla proof that was encoded with `encode_fallback_post_sector_proof`. The proof
+/// itself is checked when the PoSt is generated.
+///
+/// # Arguments
+///
+/// * `bytes` - the encoded vanilla proof.
+pub fn decode_fallback_post_sector_proof<Tree: 'static + MerkleTreeTrait>(
+    bytes: &[u8],
+) -> Result<FallbackPoStSectorProof<Tree>> {
+    ensure!(
+        bytes.len() >= SECTOR_PROOF_HEADER_LEN + CHECKSUM_LEN,
+        "vanilla PoSt proof is too short ({} bytes)",
+        bytes.len()
+    );
+    ensure!(
+        bytes[..4] == SECTOR_PROOF_MAGIC,
+        "data is not an encoded vanilla PoSt proof"
+    );
+    let version = u16::from_le_bytes(bytes[4..6].try_into()?);
+    ensure!(
+        version == FALLBACK_POST_SECTOR_PROOF_VERSION,
+        "unsupported vanilla PoSt proof version {}, expected {}",
+        version,
+        FALLBACK_POST_SECTOR_PROOF_VERSION
+    );
+    let body = checksummed_body(bytes, SECTOR_PROOF_HEADER_LEN, "vanilla PoSt proof")?;
+    let compact: CompactFallbackPoStSectorProof<Tree> =
+        bincode::deserialize(body).context("failed to deserialize vanilla PoSt proof")?;
+
+    let sectors = compact
+        .sectors
+        .into_iter()
+        .map(|sector| {
+            let positions = sector.inclusion_proof.leaves().iter().map(|(pos, _)| *pos);
+            let proofs: BTreeMap<_, _> = positions.zip(sector.inclusion_proof.proofs()?).collect();
+            let inclusion_proofs = sector
+                .challenges
+                .iter()
+                .map(|challenge| {
+                    usize::try_from(*challenge)
+                        .ok()
+                        .and_then(|challenge| proofs.get(&challenge))
+                        .cloned()
+                        .with_context(|| format!("missing inclusion proof of leaf {}", challenge))
+                })
+                .collect::<Result<_>>()?;
+            Ok(SectorProof {
+                inclusion_proofs,
+                comm_c: sector.comm_c,
+                comm_r_last: sector.comm_r_last,
+            })
+        })
+        .collect::<Result<_>>()?;
+
+    Ok(FallbackPoStSectorProof {
+        sector_id: compact.sector_id,
+        comm_r: compact.comm_r,
+        vanilla_proof: fallback::Proof { sectors },
+    })
+}
+
 // Partition a flat vector of vanilla sector proofs.  The post_config
 // (PoSt) type is required in order to determine the proper shape of
 // the returned partitioned proofs.
@@ -3,13 +3,12 @@ use std::path::{Path, PathBuf};
 
 use anyhow::{anyhow, ensure, Context, Result};
 use bellperson::groth16;
-use blstrs::Scalar as Fr;
+use blstrs::{Bls12, Scalar as Fr};
 use filecoin_hashers::{Domain, Hasher};
-use log::{info, trace};
+use log::{info, trace, warn};
 use memmap2::MmapOptions;
-use merkletree::store::{DiskStore, Store, StoreConfig};
+use merkletree::store::{DiskStore, ExternalReader, LevelCacheStore, Store, StoreConfig};
 use rayon::prelude::*;
-use sha2::{Digest, Sha256};
 use storage_proofs_core::{
     api_version::ApiFeature,
     cache_key::CacheKey,
@@ -17,8 +16,8 @@ use storage_proofs_core::{
     drgraph::Graph,
     measurements::{measure_op, Operation},
     merkle::{
-        create_base_merkle_tree, get_base_tree_count, split_config, BinaryMerkleTree,
-        MerkleTreeTrait,
+        create_base_merkle_tree, create_lc_tree, get_base_tree_count, split_config,
+        split_config_and_replica, BinaryMerkleTree, MerkleTreeTrait,
     },
     multi_proof::MultiProof,
     proof::ProofScheme,
@@ -28,14 +27,18 @@ use storage_proofs_core::{
 };
 use storage_proofs_porep::stacked::{
     self, generate_replica_id, ChallengeRequirements, Labels, LabelsCache, StackedCompound,
-    StackedDrg, Tau, TemporaryAuxCache,
+    StackedDrg, Tau, TemporaryAuxCache, SYNTHETIC_POREP_SELECTED_PROOFS_KEY,
+    SYNTHETIC_POREP_VANILLA_PROOFS_EXT, SYNTHETIC_POREP_VANILLA_PROOFS_KEY,
 };
 use storage_proofs_update::vanilla::prepare_tree_r_data;
 use typenum::{Unsigned, U11, U2};
 
 use crate::{
-    api::util::{get_aggregate_target_len, pad_inputs_to_target, pad_proofs_to_target},
-    api::{as_safe_commitment, commitment_from_fr, get_base_tree_leafs, get_base_tree_size, util},
+    api::{
+        aggregate::{pad_aggregate_inputs, pad_aggregate_proofs, seal_aggregate_transcript},
+        as_safe_commitment, commitment_from_fr, get_base_tree_leafs, get_base_tree_size, util,
+        util::get_aggregate_target_len,
+    },
     caches::{
         get_stacked_params, get_stacked_srs_key, get_stacked_srs_verifier_key,
         get_stacked_verifying_key,
@@ -45,11 +48,12 @@ use crate::{
         FIP92_MAX_NI_POREP_AGGREGATION_PROOFS, FIP92_MIN_NI_POREP_AGGREGATION_PROOFS,
         SINGLE_PARTITION_PROOF_LEN,
     },
-    parameters::setup_params,
+    parameters::{public_params, setup_params},
     pieces::{self, verify_pieces},
     types::{
-        AggregateSnarkProof, Commitment, PieceInfo, PoRepConfig, ProverId, SealCommitOutput,
-        SealCommitPhase1Output, SealPreCommitOutput, SealPreCommitPhase1Output, SectorSize, Ticket,
+        AggregateSnarkProof, Commitment, PieceInfo, PoRepConfig, ProverId, SealCircuitCheck,
+        SealCommitOutput, SealCommitPhase1Output, SealPreCommitOutput, SealPreCommitPhase1Output,
+        SealProofRecord, SectorSize, SynthProofsInfo, Ticket, TreeRLastCheck, TreeRLastRoots,
         BINARY_ARITY,
     },
 };
@@ -346,6 +350,201 @@ pub fn generate_synth_proofs<T: AsRef<Path>, Tree: 'static + MerkleTreeTrait>(
     Ok(())
 }
 
+fn synth_proofs_paths(cache_path: &Path) -> (PathBuf, PathBuf) {
+    let path = |key| cache_path.join(format!("{}.{}", key, SYNTHETIC_POREP_VANILLA_PROOFS_EXT));
+    (
+        path(SYNTHETIC_POREP_VANILLA_PROOFS_KEY),
+        path(SYNTHETIC_POREP_SELECTED_PROOFS_KEY),
+    )
+}
+
+fn synth_proofs_info<Tree: 'static + MerkleTreeTrait>(
+    path: PathBuf,
+    selected: bool,
+    info: stacked::SynthProofsInfo<<Tree::Hasher as Hasher>::Domain, DefaultPieceDomain>,
+) -> SynthProofsInfo {
+    SynthProofsInfo {
+        path,
+        selected,
+        file_size: info.file_size,
+        num_proofs: info.num_proofs,
+        comm_d: commitment_from_fr(info.comm_d.into()),
+        comm_c: commitment_from_fr(info.comm_c.into()),
+        comm_r_last: commitment_from_fr(info.comm_r_last.into()),
+    }
+}
+
+fn synth_proofs_public_inputs<Tree: 'static + MerkleTreeTrait>(
+    porep_config: &PoRepConfig,
+    prover_id: ProverId,
+    sector_id: SectorId,
+    ticket: Ticket,
+    seed: Option<Ticket>,
+    pre_commit: &SealPreCommitOutput,
+) -> Result<stacked::PublicInputs<<Tree::Hasher as Hasher>::Domain, DefaultPieceDomain>> {
+    ensure!(
+        porep_config.feature_enabled(ApiFeature::SyntheticPoRep),
+        "synth-porep must be enabled to use synthetic proofs",
+    );
+
+    let replica_id = generate_replica_id::<Tree::Hasher, _>(
+        &prover_id,
+        sector_id.into(),
+        &ticket,
+        pre_commit.comm_d,
+        &porep_config.porep_id,
+    );
+
+    Ok(stacked::PublicInputs {
+        replica_id,
+        tau: Some(stacked::Tau {
+            comm_d: as_safe_commitment(&pre_commit.comm_d, "comm_d")?,
+            comm_r: as_safe_commitment(&pre_commit.comm_r, "comm_r")?,
+        }),
+        k: None,
+        seed,
+    })
+}
+
+/// Returns the size, the number of challenge proofs and the roots of a sector's synthetic proofs
+/// file, which is either the one written by `generate_synth_proofs` or the one written by
+/// `select_synth_proofs`. The former is reported while it exists. The proofs are not checked, see
+/// `validate_synth_proofs`.
+///
+/// # Arguments
+///
+/// * `porep_config` - this sector's porep config that contains the number of bytes in the sector.
+/// * `cache_path` - path to a directory in which the sector data's Merkle Tree can be written.
+pub fn inspect_synth_proofs<Tree: 'static + MerkleTreeTrait>(
+    porep_config: &PoRepConfig,
+    cache_path: &Path,
+) -> Result<SynthProofsInfo> {
+    info!("inspect_synth_proofs:start");
+
+    let public_params = public_params::<Tree>(porep_config)?;
+    let (path, selected_path) = synth_proofs_paths(cache_path);
+    let (path, selected) = if path.exists() {
+        (path, false)
+    } else {
+        ensure!(
+            selected_path.exists(),
+            "no synthetic proofs found in {:?}",
+            cache_path
+        );
+        (selected_path, true)
+    };
+
+    let info = StackedDrg::<Tree, DefaultPieceHasher>::inspect_synth_proofs(
+        &path,
+        public_params.graph.size(),
+        public_params.num_layers,
+    )?;
+
+    info!("inspect_synth_proofs:finish");
+    Ok(synth_proofs_info::<Tree>(path, selected, info))
+}
+
+/// Checks that the synthetic proofs written by `generate_synth_proofs` belong to this sector:
+/// their roots must match `comm_d` and `comm_r`, and there must be a proof for every synthetic
+/// challenge.
+///
+/// # Arguments
+///
+/// * `porep_config` - this sector's porep config that contains the number of bytes in the sector.
+/// * `cache_path` - path to a directory in which the sector data's Merkle Tree can be written.
+/// * `prover_id` - the prover-id that sealed this sector.
+/// * `sector_id` - this sector's sector-id.
+/// * `ticket` - the ticket that was used to generate this sector's replica-id.
+/// * `pre_commit` - the output of `seal_pre_commit_phase2`.
+pub fn validate_synth_proofs<Tree: 'static + MerkleTreeTrait>(
+    porep_config: &PoRepConfig,
+    cache_path: &Path,
+    prover_id: ProverId,
+    sector_id: SectorId,
+    ticket: Ticket,
+    pre_commit: &SealPreCommitOutput,
+) -> Result<SynthProofsInfo> {
+    info!("validate_synth_proofs:start: {:?}", sector_id);
+
+    let public_params = public_params::<Tree>(porep_config)?;
+    let pub_inputs = synth_proofs_public_inputs::<Tree>(
+        porep_config,
+        prover_id,
+        sector_id,
+        ticket,
+        None,
+        pre_commit,
+    )?;
+    let (path, _) = synth_proofs_paths(cache_path);
+
+    let info = StackedDrg::<Tree, DefaultPieceHasher>::validate_synth_proofs(
+        &path,
+        public_params.graph.size(),
+        public_params.num_layers,
+        &pub_inputs,
+    )?;
+
+    info!("validate_synth_proofs:finish: {:?}", sector_id);
+    Ok(synth_proofs_info::<Tree>(path, false, info))
+}
+
+/// Once the porep challenge seed is known, validates the synthetic proofs written by
+/// `generate_synth_proofs` and writes a file which only contains the proofs of the selected porep
+/// challenges. `seal_commit_phase1` reads the proofs from that file when given the same seed.
+///
+/// The full synthetic proofs file is kept, so that proofs can be selected again if the seed
+/// changes (e.g. after a chain reorg). Once the seed is final it can be removed by the caller, or
+/// together with the selected proofs by `clear_synthetic_proofs` after `seal_commit_phase1`.
+///
+/// # Arguments
+///
+/// * `porep_config` - this sector's porep config that contains the number of bytes in the sector.
+/// * `cache_path` - path to a directory in which the sector data's Merkle Tree can be written.
+/// * `prover_id` - the prover-id that sealed this sector.
+/// * `sector_id` - this sector's sector-id.
+/// * `ticket` - the ticket that was used to generate this sector's replica-id.
+/// * `seed` - the seed used to derive the porep challenges.
+/// * `pre_commit` - the output of `seal_pre_commit_phase2`.
+pub fn select_synth_proofs<Tree: 'static + MerkleTreeTrait>(
+    porep_config: &PoRepConfig,
+    cache_path: &Path,
+    prover_id: ProverId,
+    sector_id: SectorId,
+    ticket: Ticket,
+    seed: Ticket,
+    pre_commit: &SealPreCommitOutput,
+) -> Result<SynthProofsInfo> {
+    info!("select_synth_proofs:start: {:?}", sector_id);
+
+    let pub_inputs = synth_proofs_public_inputs::<Tree>(
+        porep_config,
+        prover_id,
+        sector_id,
+        ticket,
+        Some(seed),
+        pre_commit,
+    )?;
+    let public_params = public_params::<Tree>(porep_config)?;
+    let synth_challenges = match &public_params.challenges {
+        stacked::Challenges::Synth(synth_challenges) => synth_challenges,
+        _ => unreachable!("synth-porep is enabled"),
+    };
+    let (path, selected_path) = synth_proofs_paths(cache_path);
+
+    let info = StackedDrg::<Tree, DefaultPieceHasher>::select_synth_proofs(
+        &path,
+        &selected_path,
+        public_params.graph.size(),
+        public_params.num_layers,
+        &pub_inputs,
+        synth_challenges,
+        usize::from(porep_config.partitions),
+    )?;
+
+    info!("select_synth_proofs:finish: {:?}", sector_id);
+    Ok(synth_proofs_info::<Tree>(selected_path, true, info))
+}
+
 #[allow(clippy::too_many_arguments)]
 pub fn seal_commit_phase1<T: AsRef<Path>, Tree: 'static + MerkleTreeTrait>(
     porep_config: &PoRepConfig,
@@ -586,6 +785,72 @@ pub fn seal_commit_phase2_circuit_proofs<Tree: 'static + MerkleTreeTrait>(
     Ok(SealCommitOutput { proof: buf })
 }
 
+/// Checks the vanilla proofs of a `SealCommitPhase1Output` and synthesizes the circuit of every
+/// partition into a test constraint system, without generating any SNARK. This is a debugging aid
+/// for commit phase 2 proofs that fail to verify, it tells whether the vanilla proofs, the public
+/// inputs or the circuit are at fault. It needs no Groth16 parameters, but a lot of time and
+/// memory.
+///
+/// # Arguments
+///
+/// * `porep_config` - this sector's porep config that contains the number of bytes in the sector.
+/// * `phase1_output` - the output of `seal_commit_phase1`.
+pub fn check_seal_commit_phase1_output<Tree: 'static + MerkleTreeTrait>(
+    porep_config: &PoRepConfig,
+    phase1_output: &SealCommitPhase1Output<Tree>,
+) -> Result<SealCircuitCheck> {
+    info!("check_seal_commit_phase1_output:start");
+
+    let SealCommitPhase1Output {
+        vanilla_proofs,
+        comm_d,
+        comm_r,
+        replica_id,
+        seed,
+        ticket: _,
+    } = phase1_output;
+
+    ensure!(
+        vanilla_proofs.len() == usize::from(porep_config.partitions),
+        "expected vanilla proofs for {} partitions, got {}",
+        usize::from(porep_config.partitions),
+        vanilla_proofs.len(),
+    );
+
+    let comm_r_safe = as_safe_commitment(comm_r, "comm_r")?;
+    let comm_d_safe = DefaultPieceDomain::try_from_bytes(comm_d)?;
+
+    let public_inputs = stacked::PublicInputs {
+        replica_id: *replica_id,
+        tau: Some(stacked::Tau {
+            comm_d: comm_d_safe,
+            comm_r: comm_r_safe,
+        }),
+        k: None,
+        seed: Some(*seed),
+    };
+
+    let public_params = public_params::<Tree>(porep_config)?;
+
+    let vanilla_proofs_valid = StackedDrg::<Tree, DefaultPieceHasher>::verify_all_partitions(
+        &public_params,
+        &public_inputs,
+        vanilla_proofs,
+    )?;
+
+    let partitions = StackedCompound::<Tree, DefaultPieceHasher>::check_circuits(
+        &public_inputs,
+        vanilla_proofs,
+        &public_params,
+    )?;
+
+    info!("check_seal_commit_phase1_output:finish");
+    Ok(SealCircuitCheck {
+        vanilla_proofs_valid,
+        partitions,
+    })
+}
+
 #[allow(clippy::too_many_arguments)]
 pub fn seal_commit_phase2<Tree: 'static + MerkleTreeTrait>(
     porep_config: &PoRepConfig,
@@ -788,39 +1053,9 @@ pub fn aggregate_seal_commit_proofs<Tree: 'static + MerkleTreeTrait>(
         proofs.len(),
     );
 
-    let target_proofs_len = get_aggregate_target_len(proofs.len());
-    ensure!(
-        target_proofs_len > 1,
-        "cannot aggregate less than two proofs"
-    );
-
-    trace!(
-        "aggregate_seal_commit_proofs will pad proofs to target_len {}",
-        target_proofs_len
-    );
+    pad_aggregate_proofs(&mut proofs)?;
 
-    // If we're not at the pow2 target, duplicate the last proof until we are.
-    pad_proofs_to_target(&mut proofs, target_proofs_len)?;
-
-    // For standard PoRep, the SnarkPack transcript should include a hash of each aggregated PoRep's
-    // challenge seed and comm_r (pair-wise); however since NI-PoRep does not use a seed to generate
-    // it's challenges, any challenge seeds provided as arguments to this function should be ignored
-    // (and thus not be included in an NI-PoRep's SnarkPack transcript).
-    let hashed_seeds_and_comm_rs: [u8; 32] = {
-        let mut hasher = Sha256::new();
-        if porep_config.feature_enabled(ApiFeature::NonInteractivePoRep) {
-            for comm_r in comm_rs.iter() {
-                hasher.update(comm_r);
-            }
-        } else {
-            for cur in seeds.iter().zip(comm_rs.iter()) {
-                let (seed, comm_r) = cur;
-                hasher.update(seed);
-                hasher.update(comm_r);
-            }
-        }
-        hasher.finalize().into()
-    };
+    let hashed_seeds_and_comm_rs = seal_aggregate_transcript(porep_config, comm_rs, seeds);
 
     let srs_prover_key = get_stacked_srs_key::<Tree>(porep_config, proofs.len())?;
     let aggregate_proof = StackedCompound::<Tree, DefaultPieceHasher>::aggregate_proofs(
@@ -862,8 +1097,6 @@ pub fn verify_aggregate_seal_commit_proofs<Tree: 'static + MerkleTreeTrait>(
 
     let aggregated_proofs_len = aggregate_proof.tmipp.gipa.nproofs as usize;
 
-    ensure!(aggregated_proofs_len != 0, "cannot verify zero proofs");
-    ensure!(!commit_inputs.is_empty(), "cannot verify with empty inputs");
     ensure!(
         comm_rs.len() == seeds.len(),
         "invalid comm_rs and seeds len mismatch"
@@ -874,55 +1107,14 @@ pub fn verify_aggregate_seal_commit_proofs<Tree: 'static + MerkleTreeTrait>(
         aggregated_proofs_len,
     );
 
-    ensure!(
-        aggregated_proofs_len > 1,
-        "cannot verify less than two proofs"
-    );
-    ensure!(
-        aggregated_proofs_len == aggregated_proofs_len.next_power_of_two(),
-        "cannot verify non-pow2 aggregate seal proofs"
-    );
-
-    let num_inputs = commit_inputs.len();
-    let num_inputs_per_proof = get_aggregate_target_len(num_inputs) / aggregated_proofs_len;
-    let target_inputs_len = aggregated_proofs_len * num_inputs_per_proof;
-    ensure!(
-        target_inputs_len % aggregated_proofs_len == 0,
-        "invalid number of inputs provided",
-    );
-
-    trace!(
-        "verify_aggregate_seal_commit_proofs got {} inputs with {} inputs per proof",
-        num_inputs,
-        target_inputs_len / aggregated_proofs_len,
-    );
-
     // Pad public inputs if needed.
-    let commit_inputs =
-        pad_inputs_to_target(&commit_inputs, num_inputs_per_proof, target_inputs_len)?;
+    let commit_inputs = pad_aggregate_inputs(&commit_inputs, aggregated_proofs_len)?;
 
     let verifying_key = get_stacked_verifying_key::<Tree>(porep_config)?;
     let srs_verifier_key =
         get_stacked_srs_verifier_key::<Tree>(porep_config, aggregated_proofs_len)?;
 
-    // For standard PoRep, the SnarkPack transcript should include a hash of each aggregated PoRep's
-    // challenge seed and comm_r (pair-wise); however NI-PoRep's transcript should only include
-    // comm_r (as NI-PoRep does not use a seed to generate its challenges).
-    let hashed_seeds_and_comm_rs: [u8; 32] = {
-        let mut hasher = Sha256::new();
-        if porep_config.feature_enabled(ApiFeature::NonInteractivePoRep) {
-            for comm_r in comm_rs.iter() {
-                hasher.update(comm_r);
-            }
-        } else {
-            for cur in seeds.iter().zip(comm_rs.iter()) {
-                let (seed, comm_r) = cur;
-                hasher.update(seed);
-                hasher.update(comm_r);
-            }
-        }
-        hasher.finalize().into()
-    };
+    let hashed_seeds_and_comm_rs = seal_aggregate_transcript(porep_config, comm_rs, seeds);
 
     trace!("start verifying aggregate proof");
     let result = StackedCompound::<Tree, DefaultPieceHasher>::verify_aggregate_proofs(
@@ -940,6 +1132,93 @@ pub fn verify_aggregate_seal_commit_proofs<Tree: 'static + MerkleTreeTrait>(
     Ok(result)
 }
 
+/// Given a porep_config, an aggregate proof and the public data of every aggregated seal proof,
+/// this method derives the public inputs and verifies the aggregate seal proof.
+///
+/// Every record is checked before the aggregate proof is verified, an error naming the first
+/// malformed record is returned.
+///
+/// # Arguments
+///
+/// * `porep_config` - this sector's porep config that contains the number of bytes in the sector.
+/// * `aggregate_proof_bytes` - the returned aggregate proof from 'aggreate_seal_commit_proofs'.
+/// * `records` - an ordered list of the sectors whose seal proofs were aggregated, which must
+///    match the ordering of the seal proofs when aggregated.
+pub fn verify_aggregate_seal_commit_proofs_with_records<Tree: 'static + MerkleTreeTrait>(
+    porep_config: &PoRepConfig,
+    aggregate_proof_bytes: AggregateSnarkProof,
+    records: &[SealProofRecord],
+    aggregate_version: groth16::aggregate::AggregateVersion,
+) -> Result<bool> {
+    info!("verify_aggregate_seal_commit_proofs_with_records:start");
+
+    ensure!(!records.is_empty(), "cannot verify without seal records");
+
+    let mut comm_rs = Vec::with_capacity(records.len());
+    let mut seeds = Vec::with_capacity(records.len());
+    let mut commit_inputs = Vec::new();
+    for (i, record) in records.iter().enumerate() {
+        let inputs = check_seal_proof_record::<Tree>(porep_config, record).with_context(|| {
+            format!(
+                "malformed seal record {} (sector {})",
+                i,
+                u64::from(record.sector_id)
+            )
+        })?;
+        comm_rs.push(record.comm_r);
+        seeds.push(record.seed);
+        commit_inputs.extend(inputs);
+    }
+
+    let aggregate_proof = groth16::aggregate::AggregateProof::<Bls12>::read(std::io::Cursor::new(
+        &aggregate_proof_bytes,
+    ))
+    .context("invalid aggregate proof")?;
+    let aggregated_proofs_len = aggregate_proof.tmipp.gipa.nproofs as usize;
+    let expected_proofs_len =
+        get_aggregate_target_len(records.len() * usize::from(porep_config.partitions));
+    ensure!(
+        aggregated_proofs_len == expected_proofs_len,
+        "aggregate proof contains {} proofs, expected {} for {} seal records",
+        aggregated_proofs_len,
+        expected_proofs_len,
+        records.len()
+    );
+
+    let result = verify_aggregate_seal_commit_proofs::<Tree>(
+        porep_config,
+        aggregate_proof_bytes,
+        &comm_rs,
+        &seeds,
+        commit_inputs,
+        aggregate_version,
+    )?;
+
+    info!("verify_aggregate_seal_commit_proofs_with_records:finish");
+
+    Ok(result)
+}
+
+/// Checks a seal record and returns its public inputs.
+fn check_seal_proof_record<Tree: 'static + MerkleTreeTrait>(
+    porep_config: &PoRepConfig,
+    record: &SealProofRecord,
+) -> Result<Vec<Vec<Fr>>> {
+    if !porep_config.feature_enabled(ApiFeature::NonInteractivePoRep) {
+        ensure!(record.seed != [0; 32], "Invalid porep challenge seed");
+    }
+
+    get_seal_inputs::<Tree>(
+        porep_config,
+        record.comm_r,
+        record.comm_d,
+        record.prover_id,
+        record.sector_id,
+        record.ticket,
+        record.seed,
+    )
+}
+
 /// Computes a sectors's `comm_d` given its pieces.
 ///
 /// # Arguments
@@ -1211,9 +1490,23 @@ where
         rows_to_discard: default_rows_to_discard(base_tree_leafs, TreeR::Arity::to_usize()),
     };
 
+    build_tree_r_last::<TreeR>(sector_size, replica_path.as_ref(), tree_r_last_config)
+}
+
+/// Builds tree_r_last from the replica, split into base trees as described by
+/// `tree_r_last_config`, and returns its root.
+pub(crate) fn build_tree_r_last<TreeR: 'static + MerkleTreeTrait>(
+    sector_size: u64,
+    replica_path: &Path,
+    tree_r_last_config: StoreConfig,
+) -> Result<<TreeR::Hasher as Hasher>::Domain> {
+    let leaf_count = sector_size as usize / NODE_SIZE;
+    let base_tree_count = get_base_tree_count::<TreeR>();
+    let base_tree_leafs = leaf_count / base_tree_count;
+
     let replica_base_tree_size = get_base_tree_size::<DefaultBinaryTree>(sector_size.into())?;
     let replica_base_tree_leafs = get_base_tree_leafs::<DefaultBinaryTree>(replica_base_tree_size)?;
-    let replica = DiskStore::new_from_disk_with_path(replica_base_tree_leafs, &replica_path)?;
+    let replica = DiskStore::new_from_disk_with_path(replica_base_tree_leafs, replica_path)?;
 
     // This argument is currently unused by this invocation, but required for the API.
     let mut unused_data = Data::empty();
@@ -1223,7 +1516,7 @@ where
         base_tree_leafs,
         base_tree_count,
         tree_r_last_config,
-        PathBuf::from(replica_path.as_ref()),
+        replica_path.to_path_buf(),
         &replica,
         // By default, the replica file is manipulated, use the prepare function from the empty
         // sector update, that only prepares the data for use on the GPU if needed.
@@ -1232,6 +1525,222 @@ where
     Ok(tree_r_last.root())
 }
 
+/// Returns the tree_r_last config for a sector whose trees are (or will be) stored in
+/// `cache_path`. The `rows_to_discard` of an existing t_aux is preserved, as the sector might not
+/// have been sealed with the default value.
+pub(crate) fn tree_r_last_config<Tree: 'static + MerkleTreeTrait>(
+    sector_size: u64,
+    cache_path: &Path,
+) -> Result<StoreConfig> {
+    let base_tree_leafs = sector_size as usize / NODE_SIZE / get_base_tree_count::<Tree>();
+
+    let rows_to_discard = if cache_path.join(CacheKey::TAux.to_string()).exists() {
+        util::get_t_aux::<Tree>(cache_path, sector_size)?
+            .tree_r_last_config
+            .rows_to_discard
+    } else {
+        default_rows_to_discard(base_tree_leafs, Tree::Arity::to_usize())
+    };
+
+    Ok(StoreConfig {
+        path: cache_path.to_path_buf(),
+        id: CacheKey::CommRLastTree.to_string(),
+        size: Some(get_base_tree_size::<Tree>(SectorSize(sector_size))?),
+        rows_to_discard,
+    })
+}
+
+/// Reads the roots of the base trees of a tree_r_last stored on disk. Trees which cannot be read
+/// have a root of `None`.
+fn read_tree_r_last_base_roots<Tree: 'static + MerkleTreeTrait>(
+    sector_size: u64,
+    replica_path: &Path,
+    tree_r_last_config: StoreConfig,
+) -> Result<Vec<Option<<Tree::Hasher as Hasher>::Domain>>> {
+    let base_tree_count = get_base_tree_count::<Tree>();
+    let base_tree_leafs = sector_size as usize / NODE_SIZE / base_tree_count;
+    let base_tree_len = get_base_tree_size::<Tree>(SectorSize(sector_size))?;
+
+    let (configs, replica_config) = split_config_and_replica(
+        tree_r_last_config,
+        replica_path.to_path_buf(),
+        base_tree_leafs,
+        base_tree_count,
+    )?;
+
+    Ok(configs
+        .iter()
+        .enumerate()
+        .map(|(i, config)| {
+            let root = ExternalReader::new_from_config(&replica_config, i)
+                .and_then(|reader| {
+                    LevelCacheStore::<<Tree::Hasher as Hasher>::Domain, _>::new_from_disk_with_reader(
+                        base_tree_len,
+                        Tree::Arity::to_usize(),
+                        config,
+                        reader,
+                    )
+                })
+                .and_then(|store| store.last());
+            match root {
+                Ok(root) => Some(root),
+                Err(err) => {
+                    warn!("could not read tree_r_last base tree {}: {:?}", i, err);
+                    None
+                }
+            }
+        })
+        .collect())
+}
+
+/// Builds tree_r_last and returns the roots of the tree and of its base trees.
+fn rebuild_tree_r_last_roots<Tree: 'static + MerkleTreeTrait>(
+    sector_size: u64,
+    replica_path: &Path,
+    tree_r_last_config: StoreConfig,
+) -> Result<TreeRLastRoots> {
+    let root = build_tree_r_last::<Tree>(sector_size, replica_path, tree_r_last_config.clone())?;
+    let base_tree_roots =
+        read_tree_r_last_base_roots::<Tree>(sector_size, replica_path, tree_r_last_config)?
+            .into_iter()
+            .enumerate()
+            .map(|(i, root)| {
+                root.map(|root| commitment_from_fr(root.into()))
+                    .with_context(|| format!("rebuilt base tree {} could not be read", i))
+            })
+            .collect::<Result<_>>()?;
+
+    Ok(TreeRLastRoots {
+        comm_r_last: commitment_from_fr(root.into()),
+        base_tree_roots,
+    })
+}
+
+/// Removes the files of the base trees of a tree_r_last described by `tree_r_last_config`. Files
+/// which don't exist are skipped.
+pub(crate) fn remove_tree_r_last<Tree: 'static + MerkleTreeTrait>(
+    tree_r_last_config: &StoreConfig,
+) -> Result<()> {
+    for config in split_config(tree_r_last_config.clone(), get_base_tree_count::<Tree>())? {
+        let path = StoreConfig::data_path(&config.path, &config.id);
+        if path.exists() {
+            fs::remove_file(&path).with_context(|| format!("Failed to delete {:?}", path))?;
+        }
+    }
+    Ok(())
+}
+
+/// Rebuilds tree_r_last of a sealed sector from its replica and stores it in `cache_path`,
+/// replacing any existing tree_r_last files.
+///
+/// The tree is split into base trees according to the sector shape, and the `rows_to_discard` of
+/// the t_aux in `cache_path` is used if present. Returns the roots of the rebuilt tree, which can
+/// be checked against the sector's `comm_r_last`.
+pub fn rebuild_tree_r_last<R, C, Tree: 'static + MerkleTreeTrait>(
+    porep_config: &PoRepConfig,
+    replica_path: R,
+    cache_path: C,
+) -> Result<TreeRLastRoots>
+where
+    R: AsRef<Path>,
+    C: AsRef<Path>,
+{
+    info!("rebuild_tree_r_last:start");
+
+    let sector_size = u64::from(porep_config.sector_size);
+    let replica_path = replica_path.as_ref();
+    let cache_path = cache_path.as_ref();
+    ensure!(
+        metadata(cache_path)?.is_dir(),
+        "cache_path must be a directory"
+    );
+    ensure!(
+        metadata(replica_path)?.len() == sector_size,
+        "replica size does not match the sector size"
+    );
+
+    let tree_r_last_config = tree_r_last_config::<Tree>(sector_size, cache_path)?;
+    let roots = rebuild_tree_r_last_roots::<Tree>(sector_size, replica_path, tree_r_last_config);
+
+    info!("rebuild_tree_r_last:finish");
+    roots
+}
+
+/// Checks the tree_r_last stored in `cache_path` against the `comm_r_last` in p_aux and against a
+/// tree rebuilt from the replica.
+///
+/// The tree is rebuilt into `scratch_path`, so that the cached tree is not modified, and removed
+/// from it afterwards. The check reports the base trees which are missing or corrupted; a failed
+/// check is not an error.
+pub fn verify_tree_r_last<R, C, S, Tree: 'static + MerkleTreeTrait>(
+    porep_config: &PoRepConfig,
+    replica_path: R,
+    cache_path: C,
+    scratch_path: S,
+) -> Result<TreeRLastCheck>
+where
+    R: AsRef<Path>,
+    C: AsRef<Path>,
+    S: AsRef<Path>,
+{
+    info!("verify_tree_r_last:start");
+
+    let sector_size = u64::from(porep_config.sector_size);
+    let replica_path = replica_path.as_ref();
+    let cache_path = cache_path.as_ref();
+    ensure!(
+        cache_path != scratch_path.as_ref(),
+        "scratch_path must differ from cache_path"
+    );
+
+    let p_aux = util::get_p_aux::<Tree>(cache_path)?;
+    let tree_r_last_config = tree_r_last_config::<Tree>(sector_size, cache_path)?;
+
+    let cached_base_tree_roots =
+        read_tree_r_last_base_roots::<Tree>(sector_size, replica_path, tree_r_last_config.clone())?;
+    let cached_comm_r_last = if cached_base_tree_roots.iter().all(Option::is_some) {
+        let base_tree_count = get_base_tree_count::<Tree>();
+        let (configs, replica_config) = split_config_and_replica(
+            tree_r_last_config.clone(),
+            replica_path.to_path_buf(),
+            sector_size as usize / NODE_SIZE / base_tree_count,
+            base_tree_count,
+        )?;
+        create_lc_tree::<Tree>(
+            get_base_tree_size::<Tree>(SectorSize(sector_size))?,
+            &configs,
+            &replica_config,
+        )
+        .map(|tree| commitment_from_fr(tree.root().into()))
+        .map_err(|err| warn!("could not read cached tree_r_last: {:?}", err))
+        .ok()
+    } else {
+        None
+    };
+
+    // Rebuild with the same configuration as the cached tree.
+    let scratch_config = StoreConfig {
+        path: scratch_path.as_ref().to_path_buf(),
+        ..tree_r_last_config
+    };
+    let rebuilt =
+        rebuild_tree_r_last_roots::<Tree>(sector_size, replica_path, scratch_config.clone());
+    // The rebuilt tree is only needed for its roots, don't leave it behind, even on error.
+    remove_tree_r_last::<Tree>(&scratch_config)?;
+    let rebuilt = rebuilt?;
+
+    info!("verify_tree_r_last:finish");
+    Ok(TreeRLastCheck {
+        expected_comm_r_last: commitment_from_fr(p_aux.comm_r_last.into()),
+        cached_comm_r_last,
+        cached_base_tree_roots: cached_base_tree_roots
+            .into_iter()
+            .map(|root| root.map(|root| commitment_from_fr(root.into())))
+            .collect(),
+        rebuilt,
+    })
+}
+
 /// Generate the merkle tree on top of the labels (TreeC).
 ///
 /// The generated trees are stored in `output_dir`, usually the cache directory. The `input_dir`
@@ -1,18 +1,20 @@
 use std::cmp;
-use std::fs;
-use std::io::{Read, Write};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
 use std::path::Path;
 
 use anyhow::{ensure, Context, Result};
 use bellperson::groth16;
 use blstrs::Scalar as Fr;
 use ff::PrimeField;
-use filecoin_hashers::{Domain, Hasher};
-use fr32::bytes_into_fr;
+use filecoin_hashers::{Domain, HashFunction, Hasher};
+use fr32::{bytes_into_fr, write_unpadded};
 use generic_array::typenum::Unsigned;
 use log::{info, trace};
+use memmap2::MmapOptions;
 use merkletree::merkle::get_merkle_tree_len;
-use merkletree::store::StoreConfig;
+use merkletree::store::{Store, StoreConfig};
 use rayon::prelude::*;
 use sha2::{Digest, Sha256};
 use storage_proofs_core::{
@@ -21,32 +23,62 @@ use storage_proofs_core::{
     merkle::{get_base_tree_count, MerkleTreeTrait},
     multi_proof::MultiProof,
     proof::ProofScheme,
+    sector::SectorId,
     util::NODE_SIZE,
 };
-use storage_proofs_porep::stacked::TemporaryAux;
+use storage_proofs_porep::stacked::{
+    self, generate_replica_id, LabelsCache, PersistentAux, StackedDrg, TemporaryAux,
+};
 use storage_proofs_update::{
     constants::{h_default, TreeDArity, TreeDDomain, TreeRDomain, TreeRHasher},
-    phi,
+    phi, poseidon,
     vanilla::Rhos,
     EmptySectorUpdate, EmptySectorUpdateCompound, PartitionProof, PrivateInputs, PublicInputs,
     PublicParams, SetupParams,
 };
 
 use crate::{
-    api::util::{self, get_aggregate_target_len, pad_inputs_to_target, pad_proofs_to_target},
+    api::{
+        add_piece,
+        aggregate::{
+            pad_aggregate_inputs, pad_aggregate_proofs, sector_update_aggregate_transcript,
+        },
+        seal::{build_tree_r_last, remove_tree_r_last, tree_r_last_config},
+        util::{self, as_safe_commitment, commitment_from_fr, get_aggregate_target_len},
+    },
     caches::{
-        get_empty_sector_update_params, get_empty_sector_update_verifying_key, get_stacked_srs_key,
-        get_stacked_srs_verifier_key,
+        get_empty_sector_update_params, get_empty_sector_update_poseidon_params,
+        get_empty_sector_update_poseidon_verifying_key, get_empty_sector_update_verifying_key,
+        get_stacked_srs_key, get_stacked_srs_verifier_key,
     },
     chunk_iter::ChunkIterator,
+    commitment_reader::CommitmentReader,
     constants::{DefaultPieceDomain, DefaultPieceHasher},
-    pieces::verify_pieces,
+    parameters::public_params,
+    pieces::{
+        compute_comm_d, is_zero_range, sum_piece_bytes_with_alignment, verify_pieces, zero_ranges,
+        EmptySource,
+    },
     types::{
         AggregateSnarkProof, Commitment, EmptySectorUpdateEncoded, EmptySectorUpdateProof,
-        PieceInfo, PoRepConfig, SectorUpdateConfig, SectorUpdateProofInputs,
+        PaddedBytesAmount, PieceInfo, PoRepConfig, ProverId, SectorKeySource, SectorUpdateAudit,
+        SectorUpdateConfig, SectorUpdateProofInputs, Ticket, UnpaddedByteIndex,
+        UnpaddedBytesAmount, UpdateProofType,
     },
 };
 
+// The maximum number of (padded) bytes that are decoded at once when reading a range from an
+// updated sector. It's a multiple of the fr32 block size.
+const UPDATED_RANGE_WINDOW_BYTES: u64 = 128 * 8192;
+
+// The id of the store the regenerated sector key labels are written to.
+const REGENERATED_SECTOR_KEY_ID: &str = "sector-key-labels";
+
+// The ids of the stores the trees rebuilt by `audit_sector_update` are written to.
+const AUDIT_SECTOR_KEY_TREE_ID: &str = "audit-sector-key-tree-r-last";
+const AUDIT_REPLICA_TREE_ID: &str = "audit-replica-tree-r-last";
+const AUDIT_TREE_D_ID: &str = "audit-tree-d-new";
+
 // Re-instantiate a t_aux with the new cache path, then use the tree_d
 // and tree_r_last configs from it.  This is done to preserve the
 // original tree configuration info (in particular, the
@@ -86,10 +118,66 @@ fn get_new_configs_from_t_aux_old<Tree: 'static + MerkleTreeTrait<Hasher = TreeR
     Ok((tree_d_new_config, tree_r_last_new_config))
 }
 
+// Same as `get_new_configs_from_t_aux_old`, but for the Poseidon variant, where TreeDNew has the
+// same shape as TreeR. Its config thus describes a single base-tree of TreeR's arity, which is
+// kept in full as the leafs are not stored anywhere else.
+fn get_new_configs_from_t_aux_old_poseidon<
+    Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>,
+>(
+    t_aux: &TemporaryAux<Tree, DefaultPieceHasher>,
+    new_cache_path: &Path,
+    nodes_count: usize,
+) -> Result<(StoreConfig, StoreConfig)> {
+    let (tree_d_new_config, tree_r_last_new_config) =
+        get_new_configs_from_t_aux_old::<Tree>(t_aux, new_cache_path, nodes_count)?;
+
+    let tree_d_new_config = StoreConfig {
+        size: tree_r_last_new_config.size,
+        rows_to_discard: 0,
+        ..tree_d_new_config
+    };
+
+    Ok((tree_d_new_config, tree_r_last_new_config))
+}
+
+pub(crate) fn ensure_update_proof_type(
+    config: &SectorUpdateConfig,
+    update_proof_type: UpdateProofType,
+) -> Result<()> {
+    ensure!(
+        config.update_proof_type == update_proof_type,
+        "expected a {:?} sector update config, found {:?}",
+        update_proof_type,
+        config.update_proof_type,
+    );
+    Ok(())
+}
+
+// Returns the SHA256 CommD of the first `nodes_count` nodes of the staged data, which is what the
+// pieces commit to.
+fn staged_data_comm_d(staged_data_path: &Path, nodes_count: usize) -> Result<Commitment> {
+    let staged_data = File::open(staged_data_path)
+        .with_context(|| format!("could not open path={:?}", staged_data_path))?;
+    let mut commitment_reader =
+        CommitmentReader::new(BufReader::new(staged_data).take((nodes_count * NODE_SIZE) as u64));
+    let bytes_read = io::copy(&mut commitment_reader, &mut io::sink())?;
+    ensure!(
+        bytes_read == (nodes_count * NODE_SIZE) as u64,
+        "staged data is smaller than the sector"
+    );
+
+    let mut comm_d = [0; 32];
+    commitment_reader.finish()?.write_bytes(&mut comm_d)?;
+    Ok(comm_d)
+}
+
 /// Encodes data into an existing replica.  The original replica is
 /// not modified and the resulting output data is written as
 /// new_replica_path (with required artifacts located in
 /// new_cache_path).
+///
+/// `config.update_proof_type` selects the kind of TreeDNew that is
+/// built, the returned comm_d_new is the root of that tree.
 #[allow(clippy::too_many_arguments)]
 pub fn encode_into<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>>(
     config: &SectorUpdateConfig,
@@ -101,6 +189,7 @@ pub fn encode_into<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>>(
     piece_infos: &[PieceInfo],
 ) -> Result<EmptySectorUpdateEncoded> {
     info!("encode_into:start");
+    config.ensure_valid_h()?;
 
     ensure!(
         fs::metadata(sector_key_cache_path)?.is_dir(),
@@ -113,30 +202,406 @@ pub fn encode_into<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>>(
         fs::metadata(new_cache_path)?.is_dir(),
         "new_cache_path must be a directory"
     );
-    let (tree_d_new_config, tree_r_last_new_config) =
-        get_new_configs_from_t_aux_old::<Tree>(&t_aux, new_cache_path, config.nodes_count)?;
+    let comm_c = <Tree::Hasher as Hasher>::Domain::try_from_bytes(&p_aux.comm_c.into_bytes())?;
+    let comm_r_last_old =
+        <Tree::Hasher as Hasher>::Domain::try_from_bytes(&p_aux.comm_r_last.into_bytes())?;
 
-    let (comm_r_domain, comm_r_last_domain, comm_d_domain) =
-        EmptySectorUpdate::<Tree>::encode_into(
-            config.nodes_count,
-            tree_d_new_config,
-            tree_r_last_new_config,
-            <Tree::Hasher as Hasher>::Domain::try_from_bytes(&p_aux.comm_c.into_bytes())?,
-            <Tree::Hasher as Hasher>::Domain::try_from_bytes(&p_aux.comm_r_last.into_bytes())?,
-            new_replica_path,
-            sector_key_path,
-            staged_data_path,
-            h_default(config.nodes_count),
-        )?;
+    let mut comm_d = [0; 32];
+    let mut comm_r = [0; 32];
+    let mut comm_r_last = [0; 32];
+
+    // The commitment the pieces are verified against.
+    let comm_d_pieces = match config.update_proof_type {
+        UpdateProofType::Sha256 => {
+            let (tree_d_new_config, tree_r_last_new_config) =
+                get_new_configs_from_t_aux_old::<Tree>(&t_aux, new_cache_path, config.nodes_count)?;
+
+            let (comm_r_domain, comm_r_last_domain, comm_d_domain) =
+                EmptySectorUpdate::<Tree>::encode_into(
+                    config.nodes_count,
+                    tree_d_new_config,
+                    tree_r_last_new_config,
+                    comm_c,
+                    comm_r_last_old,
+                    new_replica_path,
+                    sector_key_path,
+                    staged_data_path,
+                    config.h,
+                )?;
+
+            comm_d_domain.write_bytes(&mut comm_d)?;
+            comm_r_domain.write_bytes(&mut comm_r)?;
+            comm_r_last_domain.write_bytes(&mut comm_r_last)?;
+
+            comm_d
+        }
+        UpdateProofType::Poseidon => {
+            let (tree_d_new_config, tree_r_last_new_config) =
+                get_new_configs_from_t_aux_old_poseidon::<Tree>(
+                    &t_aux,
+                    new_cache_path,
+                    config.nodes_count,
+                )?;
+
+            let (comm_r_domain, comm_r_last_domain, comm_d_domain) =
+                poseidon::EmptySectorUpdate::<Tree>::encode_into(
+                    config.nodes_count,
+                    tree_d_new_config,
+                    tree_r_last_new_config,
+                    comm_c,
+                    comm_r_last_old,
+                    new_replica_path,
+                    sector_key_path,
+                    staged_data_path,
+                    config.h,
+                )?;
+
+            comm_d_domain.write_bytes(&mut comm_d)?;
+            comm_r_domain.write_bytes(&mut comm_r)?;
+            comm_r_last_domain.write_bytes(&mut comm_r_last)?;
+
+            // CommDNew is a Poseidon root, whereas the pieces commit to the SHA256 root of the
+            // staged data.
+            staged_data_comm_d(staged_data_path, config.nodes_count)?
+        }
+    };
+
+    ensure!(
+        verify_pieces(&comm_d_pieces, piece_infos, config.sector_size)?,
+        "pieces and comm_d do not match"
+    );
+
+    let encoded =
+        persist_encoded::<Tree>(p_aux, &t_aux, new_cache_path, comm_d, comm_r, comm_r_last)?;
+
+    info!("encode_into:finish");
+
+    Ok(encoded)
+}
+
+/// Encodes data into an existing replica, just like [`encode_into`], but the data is read from
+/// the given piece sources instead of a staged sector file.
+///
+/// The pieces are fr32-padded on the fly and written, with the same alignment as [`add_piece`]
+/// uses, into `new_replica_path`, which serves as staging area. The remainder of the sector is
+/// filled with zeros. The data is then encoded in place, hence no staged data file is written or
+/// read. `new_replica_path` must already have the size of the sector. The commitment of each
+/// piece is computed while reading it and must match the corresponding `piece_infos` entry.
+///
+/// The encoding can't start while the pieces are written, as it's randomized by `comm_d_new`,
+/// which is only known once all of the data is. The new replica is therefore written first, then
+/// read again to build TreeDNew before it's encoded in place.
+#[allow(clippy::too_many_arguments)]
+pub fn encode_into_from_pieces<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>, R: Read>(
+    config: &SectorUpdateConfig,
+    new_replica_path: &Path,
+    new_cache_path: &Path,
+    sector_key_path: &Path,
+    sector_key_cache_path: &Path,
+    piece_sources: &mut [R],
+    piece_infos: &[PieceInfo],
+) -> Result<EmptySectorUpdateEncoded> {
+    info!("encode_into_from_pieces:start");
+    config.ensure_valid_h()?;
+
+    ensure!(
+        fs::metadata(sector_key_cache_path)?.is_dir(),
+        "sector_key_cache_path must be a directory",
+    );
+    let p_aux = util::get_p_aux::<Tree>(sector_key_cache_path)?;
+    let t_aux = util::get_t_aux::<Tree>(sector_key_cache_path, u64::from(config.sector_size))?;
+
+    ensure!(
+        fs::metadata(new_cache_path)?.is_dir(),
+        "new_cache_path must be a directory"
+    );
+    ensure!(
+        piece_sources.len() == piece_infos.len(),
+        "got {} piece sources, but {} piece infos",
+        piece_sources.len(),
+        piece_infos.len(),
+    );
+
+    write_pieces_into_replica(config, new_replica_path, piece_sources, piece_infos)?;
+
+    let comm_c = <Tree::Hasher as Hasher>::Domain::try_from_bytes(&p_aux.comm_c.into_bytes())?;
+    let comm_r_last_old =
+        <Tree::Hasher as Hasher>::Domain::try_from_bytes(&p_aux.comm_r_last.into_bytes())?;
 
     let mut comm_d = [0; 32];
     let mut comm_r = [0; 32];
     let mut comm_r_last = [0; 32];
 
-    comm_d_domain.write_bytes(&mut comm_d)?;
-    comm_r_domain.write_bytes(&mut comm_r)?;
-    comm_r_last_domain.write_bytes(&mut comm_r_last)?;
+    match config.update_proof_type {
+        UpdateProofType::Sha256 => {
+            let (tree_d_new_config, tree_r_last_new_config) =
+                get_new_configs_from_t_aux_old::<Tree>(&t_aux, new_cache_path, config.nodes_count)?;
+
+            let (comm_r_domain, comm_r_last_domain, comm_d_domain) =
+                EmptySectorUpdate::<Tree>::encode_in_place(
+                    config.nodes_count,
+                    tree_d_new_config,
+                    tree_r_last_new_config,
+                    comm_c,
+                    comm_r_last_old,
+                    new_replica_path,
+                    sector_key_path,
+                    config.h,
+                )?;
+
+            comm_d_domain.write_bytes(&mut comm_d)?;
+            comm_r_domain.write_bytes(&mut comm_r)?;
+            comm_r_last_domain.write_bytes(&mut comm_r_last)?;
+
+            ensure!(
+                verify_pieces(&comm_d, piece_infos, config.sector_size)?,
+                "pieces and comm_d do not match"
+            );
+        }
+        UpdateProofType::Poseidon => {
+            let (tree_d_new_config, tree_r_last_new_config) =
+                get_new_configs_from_t_aux_old_poseidon::<Tree>(
+                    &t_aux,
+                    new_cache_path,
+                    config.nodes_count,
+                )?;
+
+            // CommDNew is a Poseidon root, so the pieces are checked against the SHA256 CommD of
+            // the written data instead.
+            ensure!(
+                verify_pieces(
+                    &staged_data_comm_d(new_replica_path, config.nodes_count)?,
+                    piece_infos,
+                    config.sector_size
+                )?,
+                "pieces and comm_d do not match"
+            );
+
+            let (comm_r_domain, comm_r_last_domain, comm_d_domain) =
+                poseidon::EmptySectorUpdate::<Tree>::encode_in_place(
+                    config.nodes_count,
+                    tree_d_new_config,
+                    tree_r_last_new_config,
+                    comm_c,
+                    comm_r_last_old,
+                    new_replica_path,
+                    sector_key_path,
+                    config.h,
+                )?;
+
+            comm_d_domain.write_bytes(&mut comm_d)?;
+            comm_r_domain.write_bytes(&mut comm_r)?;
+            comm_r_last_domain.write_bytes(&mut comm_r_last)?;
+        }
+    }
+
+    let encoded =
+        persist_encoded::<Tree>(p_aux, &t_aux, new_cache_path, comm_d, comm_r, comm_r_last)?;
+
+    info!("encode_into_from_pieces:finish");
+
+    Ok(encoded)
+}
+
+/// Replaces the data of an already updated replica with the staged data. The result is the same
+/// as [`remove_encoded_data`] followed by [`encode_into`], but the new replica is produced in a
+/// single pass over the files, without the sector key.
+///
+/// `data_path` is the data the replica currently encodes, which is committed to by `comm_d`, the
+/// `comm_d_new` of the current update. `comm_r_old` is the commitment of the sector key, i.e. of
+/// the original sector, which the new update proof is generated against. The proof itself still
+/// needs the sector key.
+///
+/// Returns the commitments of the new replica and the public inputs of its update proof.
+#[allow(clippy::too_many_arguments)]
+pub fn reencode_into<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>>(
+    config: &SectorUpdateConfig,
+    new_replica_path: &Path,
+    new_cache_path: &Path,
+    replica_path: &Path,
+    replica_cache_path: &Path,
+    data_path: &Path,
+    comm_r_old: Commitment,
+    comm_d: Commitment,
+    staged_data_path: &Path,
+    piece_infos: &[PieceInfo],
+) -> Result<(EmptySectorUpdateEncoded, SectorUpdateProofInputs)> {
+    info!("reencode_into:start");
+    config.ensure_valid_h()?;
+
+    ensure!(
+        fs::metadata(replica_cache_path)?.is_dir(),
+        "replica_cache_path must be a directory",
+    );
+    let p_aux = util::get_p_aux::<Tree>(replica_cache_path)?;
+    let t_aux = util::get_t_aux::<Tree>(replica_cache_path, u64::from(config.sector_size))?;
 
+    ensure!(
+        fs::metadata(new_cache_path)?.is_dir(),
+        "new_cache_path must be a directory"
+    );
+    let comm_c = <Tree::Hasher as Hasher>::Domain::try_from_bytes(&p_aux.comm_c.into_bytes())?;
+    let comm_r_old_domain = TreeRDomain::try_from_bytes(&comm_r_old)?;
+    let comm_d_domain = TreeDDomain::try_from_bytes(&comm_d)?;
+
+    let mut comm_d_new = [0; 32];
+    let mut comm_r_new = [0; 32];
+    let mut comm_r_last_new = [0; 32];
+
+    let comm_d_pieces = match config.update_proof_type {
+        UpdateProofType::Sha256 => {
+            let (tree_d_new_config, tree_r_last_new_config) =
+                get_new_configs_from_t_aux_old::<Tree>(&t_aux, new_cache_path, config.nodes_count)?;
+
+            let (comm_r_domain, comm_r_last_domain, comm_d_new_domain) =
+                EmptySectorUpdate::<Tree>::reencode_into(
+                    config.nodes_count,
+                    tree_d_new_config,
+                    tree_r_last_new_config,
+                    comm_c,
+                    comm_r_old_domain,
+                    comm_d_domain,
+                    new_replica_path,
+                    replica_path,
+                    data_path,
+                    staged_data_path,
+                    config.h,
+                )?;
+
+            comm_d_new_domain.write_bytes(&mut comm_d_new)?;
+            comm_r_domain.write_bytes(&mut comm_r_new)?;
+            comm_r_last_domain.write_bytes(&mut comm_r_last_new)?;
+
+            comm_d_new
+        }
+        UpdateProofType::Poseidon => {
+            let (tree_d_new_config, tree_r_last_new_config) =
+                get_new_configs_from_t_aux_old_poseidon::<Tree>(
+                    &t_aux,
+                    new_cache_path,
+                    config.nodes_count,
+                )?;
+
+            let (comm_r_domain, comm_r_last_domain, comm_d_new_domain) =
+                poseidon::EmptySectorUpdate::<Tree>::reencode_into(
+                    config.nodes_count,
+                    tree_d_new_config,
+                    tree_r_last_new_config,
+                    comm_c,
+                    comm_r_old_domain,
+                    comm_d_domain,
+                    new_replica_path,
+                    replica_path,
+                    data_path,
+                    staged_data_path,
+                    config.h,
+                )?;
+
+            comm_d_new_domain.write_bytes(&mut comm_d_new)?;
+            comm_r_domain.write_bytes(&mut comm_r_new)?;
+            comm_r_last_domain.write_bytes(&mut comm_r_last_new)?;
+
+            staged_data_comm_d(staged_data_path, config.nodes_count)?
+        }
+    };
+
+    ensure!(
+        verify_pieces(&comm_d_pieces, piece_infos, config.sector_size)?,
+        "pieces and comm_d do not match"
+    );
+
+    let encoded = persist_encoded::<Tree>(
+        p_aux,
+        &t_aux,
+        new_cache_path,
+        comm_d_new,
+        comm_r_new,
+        comm_r_last_new,
+    )?;
+    let proof_inputs = SectorUpdateProofInputs {
+        h: config.h,
+        comm_r_old,
+        comm_r_new: encoded.comm_r_new,
+        comm_d_new: encoded.comm_d_new,
+    };
+
+    info!("reencode_into:finish");
+
+    Ok((encoded, proof_inputs))
+}
+
+// Writes the fr32-padded pieces into the new replica, followed by zeros up to the sector size.
+fn write_pieces_into_replica<R: Read>(
+    config: &SectorUpdateConfig,
+    new_replica_path: &Path,
+    piece_sources: &mut [R],
+    piece_infos: &[PieceInfo],
+) -> Result<()> {
+    let sector_bytes = u64::from(config.sector_size);
+
+    // Reject pieces that don't fit before anything is written.
+    let piece_lengths: Vec<UnpaddedBytesAmount> = piece_infos
+        .iter()
+        .map(|piece_info| piece_info.size)
+        .collect();
+    let pieces_bytes = u64::from(PaddedBytesAmount::from(sum_piece_bytes_with_alignment(
+        &piece_lengths,
+    )));
+    ensure!(
+        pieces_bytes <= sector_bytes,
+        "pieces do not fit into the sector ({} > {} bytes)",
+        pieces_bytes,
+        sector_bytes
+    );
+
+    let new_replica = OpenOptions::new()
+        .write(true)
+        .open(new_replica_path)
+        .with_context(|| format!("could not open path={:?}", new_replica_path))?;
+    ensure!(
+        new_replica.metadata()?.len() == sector_bytes,
+        "new replica must be exactly {} bytes",
+        sector_bytes
+    );
+    let mut new_replica = BufWriter::new(new_replica);
+
+    for (index, (source, piece_info)) in piece_sources.iter_mut().zip(piece_infos).enumerate() {
+        let (written_piece_info, _) = add_piece(
+            source,
+            &mut new_replica,
+            piece_info.size,
+            &piece_lengths[..index],
+        )?;
+        ensure!(
+            written_piece_info.commitment == piece_info.commitment,
+            "piece {} does not match its commitment",
+            index
+        );
+    }
+
+    io::copy(
+        &mut io::repeat(0).take(sector_bytes - pieces_bytes),
+        &mut new_replica,
+    )?;
+    new_replica
+        .into_inner()
+        .map_err(|err| err.into_error())?
+        .sync_data()?;
+
+    Ok(())
+}
+
+// Checks the commitments of a newly encoded replica and persists p_aux and t_aux into the
+// new_cache_path.
+#[cfg_attr(feature = "fixed-rows-to-discard", allow(unused_variables))]
+fn persist_encoded<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>>(
+    p_aux: PersistentAux<<Tree::Hasher as Hasher>::Domain>,
+    t_aux: &TemporaryAux<Tree, DefaultPieceHasher>,
+    new_cache_path: &Path,
+    comm_d: Commitment,
+    comm_r: Commitment,
+    comm_r_last: Commitment,
+) -> Result<EmptySectorUpdateEncoded> {
     // Note that there's nothing inherently incorrect about zero
     // commitments, but given that this check exists during the
     // sealing process and may have historically been hit, this is
@@ -147,19 +612,13 @@ pub fn encode_into<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>>(
         comm_r_last != [0; 32],
         "Invalid all zero commitment (comm_r)"
     );
-    ensure!(
-        verify_pieces(&comm_d, piece_infos, config.sector_size)?,
-        "pieces and comm_d do not match"
-    );
 
     // Persist p_aux and t_aux into the new_cache_path here
     let mut p_aux = p_aux;
-    p_aux.comm_r_last = comm_r_last_domain;
+    p_aux.comm_r_last = <Tree::Hasher as Hasher>::Domain::try_from_bytes(&comm_r_last)?;
     util::persist_p_aux::<Tree>(&p_aux, new_cache_path)?;
     #[cfg(not(feature = "fixed-rows-to-discard"))]
-    util::persist_t_aux::<Tree>(&t_aux, new_cache_path)?;
-
-    info!("encode_into:finish");
+    util::persist_t_aux::<Tree>(t_aux, new_cache_path)?;
 
     Ok(EmptySectorUpdateEncoded {
         comm_r_new: comm_r,
@@ -177,6 +636,9 @@ pub fn encode_into<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>>(
 /// needed in order to do the decoding correctly. The `nodes_count` is the total number of nodes
 /// within the file. The `num_nodes` defines how many nodes will be decoded, starting from the
 /// current position.
+///
+/// The data is assumed to be encoded with the default `h`, use [`decode_from_range_with_config`]
+/// if it was encoded with a different one.
 #[allow(clippy::too_many_arguments)]
 pub fn decode_from_range<R: Read, S: Read, W: Write>(
     nodes_count: usize,
@@ -187,11 +649,62 @@ pub fn decode_from_range<R: Read, S: Read, W: Write>(
     output_data: &mut W,
     nodes_offset: usize,
     num_nodes: usize,
+) -> Result<()> {
+    decode_range(
+        nodes_count,
+        h_default(nodes_count),
+        comm_d,
+        comm_r,
+        input_data,
+        sector_key_data,
+        output_data,
+        nodes_offset,
+        num_nodes,
+    )
+}
+
+/// Decodes a range of data that was encoded with the given sector update config, e.g. one with a
+/// non-default `h`, see [`decode_from_range`].
+#[allow(clippy::too_many_arguments)]
+pub fn decode_from_range_with_config<R: Read, S: Read, W: Write>(
+    config: &SectorUpdateConfig,
+    comm_d: Commitment,
+    comm_r: Commitment,
+    input_data: R,
+    sector_key_data: S,
+    output_data: &mut W,
+    nodes_offset: usize,
+    num_nodes: usize,
+) -> Result<()> {
+    config.ensure_valid_h()?;
+    decode_range(
+        config.nodes_count,
+        config.h,
+        comm_d,
+        comm_r,
+        input_data,
+        sector_key_data,
+        output_data,
+        nodes_offset,
+        num_nodes,
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+fn decode_range<R: Read, S: Read, W: Write>(
+    nodes_count: usize,
+    h: usize,
+    comm_d: Commitment,
+    comm_r: Commitment,
+    input_data: R,
+    sector_key_data: S,
+    output_data: &mut W,
+    nodes_offset: usize,
+    num_nodes: usize,
 ) -> Result<()> {
     let comm_d_domain = TreeDDomain::try_from_bytes(&comm_d[..])?;
     let comm_r_domain = TreeRDomain::try_from_bytes(&comm_r[..])?;
     let phi = phi(&comm_d_domain, &comm_r_domain);
-    let h = h_default(nodes_count);
     let rho_invs = Rhos::new_inv_range(&phi, h, nodes_count, nodes_offset, num_nodes);
 
     let bytes_length = num_nodes * NODE_SIZE;
@@ -258,6 +771,7 @@ pub fn decode_from<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>>(
     comm_d_new: Commitment,
 ) -> Result<()> {
     info!("decode_from:start");
+    config.ensure_valid_h()?;
 
     let p_aux = util::get_p_aux::<Tree>(sector_key_cache_path)?;
 
@@ -270,13 +784,691 @@ pub fn decode_from<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>>(
         <Tree::Hasher as Hasher>::Domain::try_from_bytes(&p_aux.comm_c.into_bytes())?,
         comm_d_new.into(),
         <Tree::Hasher as Hasher>::Domain::try_from_bytes(&p_aux.comm_r_last.into_bytes())?,
-        h_default(config.nodes_count),
+        config.h,
     )?;
 
     info!("decode_from:finish");
     Ok(())
 }
 
+/// Decodes the bytes of a piece from an updated sector, whose first (unpadded) byte begins at
+/// `offset` and ends at `offset` plus `num_bytes`, and writes them into `output_path`. This is the
+/// counterpart of [`get_unsealed_range`](crate::get_unsealed_range) for updated sectors.
+///
+/// Only the nodes that contain the requested range are read from the replica and the sector key
+/// and decoded, the result is un-bit-padded before it's written.
+///
+/// # Arguments
+///
+/// * `config` - the config of the sector update.
+/// * `replica_path` - path to the updated replica.
+/// * `sector_key` - where the sector key is read from, see [`SectorKeySource`].
+/// * `output_path` - path to a file that we will write the requested byte range to.
+/// * `comm_r_old` - the commitment to the replica of the original sector.
+/// * `comm_d_new` - the commitment to the data of the updated sector.
+/// * `offset` - the byte index in the unsealed sector of the first byte that we want to read.
+/// * `num_bytes` - the number of bytes that we want to read.
+#[allow(clippy::too_many_arguments)]
+pub fn get_unsealed_range_from_updated_sector<
+    Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>,
+>(
+    config: &SectorUpdateConfig,
+    replica_path: &Path,
+    sector_key: SectorKeySource<'_>,
+    output_path: &Path,
+    comm_r_old: Commitment,
+    comm_d_new: Commitment,
+    offset: UnpaddedByteIndex,
+    num_bytes: UnpaddedBytesAmount,
+) -> Result<UnpaddedBytesAmount> {
+    info!("get_unsealed_range_from_updated_sector:start");
+
+    let result = unsealed_range_from_updated_sector::<Tree>(
+        config,
+        replica_path,
+        sector_key,
+        output_path,
+        comm_r_old,
+        comm_d_new,
+        &[],
+        offset,
+        num_bytes,
+    );
+
+    info!("get_unsealed_range_from_updated_sector:finish");
+    result
+}
+
+/// Like [`get_unsealed_range_from_updated_sector`], but consults the layout of the sector's
+/// pieces. The parts of the range that lie within the alignment padding or a zero piece (see
+/// [`zero_ranges`](crate::pieces::zero_ranges)) are written as zeros without being decoded. The
+/// sector key is only read, or regenerated, if some part of the range needs decoding.
+///
+/// # Arguments
+///
+/// * `config` - the config of the sector update.
+/// * `replica_path` - path to the updated replica.
+/// * `sector_key` - where the sector key is read from, see [`SectorKeySource`].
+/// * `output_path` - path to a file that we will write the requested byte range to.
+/// * `comm_r_old` - the commitment to the replica of the original sector.
+/// * `comm_d_new` - the commitment to the data of the updated sector.
+/// * `piece_infos` - the pieces of the updated sector, which must match `comm_d_new`.
+/// * `offset` - the byte index in the unsealed sector of the first byte that we want to read.
+/// * `num_bytes` - the number of bytes that we want to read.
+#[allow(clippy::too_many_arguments)]
+pub fn get_unsealed_range_from_updated_sector_with_pieces<
+    Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>,
+>(
+    config: &SectorUpdateConfig,
+    replica_path: &Path,
+    sector_key: SectorKeySource<'_>,
+    output_path: &Path,
+    comm_r_old: Commitment,
+    comm_d_new: Commitment,
+    piece_infos: &[PieceInfo],
+    offset: UnpaddedByteIndex,
+    num_bytes: UnpaddedBytesAmount,
+) -> Result<UnpaddedBytesAmount> {
+    info!("get_unsealed_range_from_updated_sector_with_pieces:start");
+
+    ensure!(
+        verify_pieces(&comm_d_new, piece_infos, config.sector_size)?,
+        "pieces and comm_d_new do not match"
+    );
+    let zero_ranges = zero_ranges(config.sector_size, piece_infos)?;
+
+    let result = unsealed_range_from_updated_sector::<Tree>(
+        config,
+        replica_path,
+        sector_key,
+        output_path,
+        comm_r_old,
+        comm_d_new,
+        &zero_ranges,
+        offset,
+        num_bytes,
+    );
+
+    info!("get_unsealed_range_from_updated_sector_with_pieces:finish");
+    result
+}
+
+// Decodes a range from an updated sector. The windows whose output lies entirely within
+// `zero_ranges` are written as zeros, the sector key is opened or regenerated once the first
+// window that needs decoding is reached.
+#[allow(clippy::too_many_arguments)]
+fn unsealed_range_from_updated_sector<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>>(
+    config: &SectorUpdateConfig,
+    replica_path: &Path,
+    sector_key: SectorKeySource<'_>,
+    output_path: &Path,
+    comm_r_old: Commitment,
+    comm_d_new: Commitment,
+    zero_ranges: &[Range<u64>],
+    offset: UnpaddedByteIndex,
+    num_bytes: UnpaddedBytesAmount,
+) -> Result<UnpaddedBytesAmount> {
+    let result = unsealed_range_from_updated_sector_inner::<Tree>(
+        config,
+        replica_path,
+        sector_key,
+        output_path,
+        comm_r_old,
+        comm_d_new,
+        zero_ranges,
+        offset,
+        num_bytes,
+    );
+
+    // The regenerated sector key is as large as the sector, don't leave it behind, even on error.
+    if let SectorKeySource::Regenerate { cache_path, .. } = sector_key {
+        remove_sector_key_labels(cache_path)?;
+    }
+
+    result
+}
+
+#[allow(clippy::too_many_arguments)]
+fn unsealed_range_from_updated_sector_inner<
+    Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>,
+>(
+    config: &SectorUpdateConfig,
+    replica_path: &Path,
+    sector_key: SectorKeySource<'_>,
+    output_path: &Path,
+    comm_r_old: Commitment,
+    comm_d_new: Commitment,
+    zero_ranges: &[Range<u64>],
+    offset: UnpaddedByteIndex,
+    num_bytes: UnpaddedBytesAmount,
+) -> Result<UnpaddedBytesAmount> {
+    config.ensure_valid_h()?;
+
+    let sector_bytes = u64::from(config.sector_size);
+    let offset = u64::from(offset);
+    let end = offset + u64::from(num_bytes);
+    ensure!(
+        end <= u64::from(UnpaddedBytesAmount::from(config.sector_size)),
+        "range {}..{} is out of bounds of the sector",
+        offset,
+        end
+    );
+
+    let mut replica = File::open(replica_path)
+        .with_context(|| format!("could not open replica_path={:?}", replica_path))?;
+    ensure!(
+        replica.metadata()?.len() == sector_bytes,
+        "replica must be exactly {} bytes",
+        sector_bytes
+    );
+
+    let output = File::create(output_path)
+        .with_context(|| format!("could not create output_path={:?}", output_path))?;
+    let mut output = BufWriter::new(output);
+
+    // The sector is decoded in windows of whole fr32 blocks, i.e. 127 unpadded bytes that are
+    // padded to 128 bytes.
+    let window_bytes = cmp::min(UPDATED_RANGE_WINDOW_BYTES, sector_bytes);
+    let end_padded = cmp::min(end.div_ceil(127) * 128, sector_bytes);
+    let mut decoded = Vec::with_capacity(window_bytes as usize);
+    let mut sector_key_bytes = Vec::new();
+    let mut sector_key_file = None;
+    let mut regenerated_sector_key = None;
+    let mut position = offset;
+    while position < end {
+        let window_start = position / 127 * 128;
+        let window_end = cmp::min(window_start + window_bytes, end_padded);
+        let nodes_offset = window_start as usize / NODE_SIZE;
+        let num_nodes = (window_end - window_start) as usize / NODE_SIZE;
+
+        let window_offset = position - window_start / 128 * 127;
+        let len = cmp::min(
+            end - position,
+            (window_end - window_start) / 128 * 127 - window_offset,
+        );
+
+        if is_zero_range(zero_ranges, &(position..position + len)) {
+            io::copy(&mut EmptySource::new(len as usize), &mut output)?;
+            position += len;
+            continue;
+        }
+
+        if sector_key_file.is_none() && regenerated_sector_key.is_none() {
+            match sector_key {
+                SectorKeySource::File(sector_key_path) => {
+                    let file = File::open(sector_key_path).with_context(|| {
+                        format!("could not open sector_key_path={:?}", sector_key_path)
+                    })?;
+                    ensure!(
+                        file.metadata()?.len() == sector_bytes,
+                        "sector key must be exactly {} bytes",
+                        sector_bytes
+                    );
+                    sector_key_file = Some(file);
+                }
+                SectorKeySource::Regenerate {
+                    porep_config,
+                    cache_path,
+                    prover_id,
+                    sector_id,
+                    ticket,
+                    comm_d,
+                } => {
+                    ensure!(
+                        porep_config.sector_size == config.sector_size,
+                        "sector sizes of the PoRep and the update config do not match"
+                    );
+                    ensure!(
+                        comm_d == compute_comm_d(porep_config.sector_size, &[])?,
+                        "only the sector key of a committed capacity sector can be regenerated"
+                    );
+                    regenerated_sector_key = Some(regenerate_sector_key_labels::<Tree>(
+                        porep_config,
+                        cache_path,
+                        prover_id,
+                        sector_id,
+                        ticket,
+                        comm_d,
+                    )?);
+                }
+            }
+        }
+
+        replica.seek(SeekFrom::Start(window_start))?;
+        let replica_window = (&mut replica).take(window_end - window_start);
+        decoded.clear();
+        match (&mut sector_key_file, &regenerated_sector_key) {
+            (Some(sector_key_file), _) => {
+                sector_key_file.seek(SeekFrom::Start(window_start))?;
+                let sector_key_window = (&mut *sector_key_file).take(window_end - window_start);
+                decode_from_range_with_config(
+                    config,
+                    comm_d_new,
+                    comm_r_old,
+                    replica_window,
+                    sector_key_window,
+                    &mut decoded,
+                    nodes_offset,
+                    num_nodes,
+                )?;
+            }
+            (None, Some(labels)) => {
+                sector_key_bytes.clear();
+                for label in labels
+                    .labels_for_last_layer()?
+                    .read_range(nodes_offset..nodes_offset + num_nodes)?
+                {
+                    sector_key_bytes.extend_from_slice(AsRef::<[u8]>::as_ref(&label));
+                }
+                decode_from_range_with_config(
+                    config,
+                    comm_d_new,
+                    comm_r_old,
+                    replica_window,
+                    &sector_key_bytes[..],
+                    &mut decoded,
+                    nodes_offset,
+                    num_nodes,
+                )?;
+            }
+            (None, None) => unreachable!("the sector key was opened above"),
+        }
+
+        write_unpadded(&decoded, &mut output, window_offset as usize, len as usize)
+            .context("write_unpadded failed")?;
+        position += len;
+    }
+    output.flush()?;
+
+    Ok(UnpaddedBytesAmount(end - offset))
+}
+
+// Regenerates the labels of the last SDR layer of a sector with the given comm_d. For a committed
+// capacity sector, which contains only zeros, those labels are its replica, i.e. the sector key.
+// The labels are stored in `cache_path` and can be removed with `remove_sector_key_labels`.
+fn regenerate_sector_key_labels<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>>(
+    porep_config: &PoRepConfig,
+    cache_path: &Path,
+    prover_id: ProverId,
+    sector_id: SectorId,
+    ticket: Ticket,
+    comm_d: Commitment,
+) -> Result<LabelsCache<Tree>> {
+    info!("regenerating sector key labels");
+
+    let comm_d = as_safe_commitment::<DefaultPieceDomain, _>(&comm_d, "comm_d")?;
+    let replica_id = generate_replica_id::<Tree::Hasher, _>(
+        &prover_id,
+        sector_id.into(),
+        &ticket,
+        comm_d,
+        &porep_config.porep_id,
+    );
+
+    let pp: stacked::PublicParams<Tree> = public_params(porep_config)?;
+    let config = StoreConfig::new(cache_path, REGENERATED_SECTOR_KEY_ID.to_string(), 0);
+    StackedDrg::<Tree, DefaultPieceHasher>::generate_labels_for_decoding(
+        &pp.graph,
+        pp.num_layers,
+        &replica_id,
+        config,
+    )
+}
+
+// Removes the labels stored by `regenerate_sector_key_labels` from `cache_path`, if there are any.
+fn remove_sector_key_labels(cache_path: &Path) -> Result<()> {
+    let labels_path = StoreConfig::data_path(cache_path, REGENERATED_SECTOR_KEY_ID);
+    if labels_path.exists() {
+        fs::remove_file(&labels_path)
+            .with_context(|| format!("Failed to delete {:?}", labels_path))?;
+    }
+    Ok(())
+}
+
+/// Regenerates the sector key of an updated sector, i.e. the replica of the original sector, from
+/// the inputs of its seal and writes it to `output`. Writing it to a file makes the sector
+/// decodable with [`decode_from`] and its data removable with [`remove_encoded_data`] again after
+/// the sector key file was deleted.
+///
+/// The sector key is the labels of the last SDR layer encoded with the original data. All SDR
+/// layers are run over the whole sector, their labels are temporarily stored in `cache_path`.
+///
+/// # Arguments
+///
+/// * `porep_config` - the porep config of the original sector.
+/// * `cache_path` - path to a directory where the labels are temporarily stored.
+/// * `prover_id` - the prover id of the original seal.
+/// * `sector_id` - the sector id of the original seal.
+/// * `ticket` - the ticket of the original seal.
+/// * `comm_d` - the commitment to the data of the original sector.
+/// * `original_data_path` - path to the fr32-padded data of the original sector, `None` for a
+///   committed capacity sector, whose data is all zeros.
+/// * `output` - where the sector key is written to.
+#[allow(clippy::too_many_arguments)]
+pub fn regenerate_sector_key<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>, W: Write>(
+    porep_config: &PoRepConfig,
+    cache_path: &Path,
+    prover_id: ProverId,
+    sector_id: SectorId,
+    ticket: Ticket,
+    comm_d: Commitment,
+    original_data_path: Option<&Path>,
+    output: W,
+) -> Result<()> {
+    info!("regenerate_sector_key:start");
+
+    let sector_bytes = u64::from(porep_config.sector_size);
+    let nodes_count = sector_bytes as usize / NODE_SIZE;
+    let mut original_data = match original_data_path {
+        Some(original_data_path) => {
+            ensure!(
+                staged_data_comm_d(original_data_path, nodes_count)? == comm_d,
+                "original data does not match comm_d"
+            );
+            let file = File::open(original_data_path).with_context(|| {
+                format!("could not open original_data_path={:?}", original_data_path)
+            })?;
+            Some(BufReader::new(file))
+        }
+        None => {
+            ensure!(
+                comm_d == compute_comm_d(porep_config.sector_size, &[])?,
+                "comm_d is not the one of a committed capacity sector, the original data is needed"
+            );
+            None
+        }
+    };
+
+    let result = (|| -> Result<()> {
+        let labels = regenerate_sector_key_labels::<Tree>(
+            porep_config,
+            cache_path,
+            prover_id,
+            sector_id,
+            ticket,
+            comm_d,
+        )?;
+
+        let mut output = BufWriter::new(output);
+        let chunk_nodes = UPDATED_RANGE_WINDOW_BYTES as usize / NODE_SIZE;
+        let mut data = vec![0; chunk_nodes * NODE_SIZE];
+        let last_layer = labels.labels_for_last_layer()?;
+        for nodes_offset in (0..nodes_count).step_by(chunk_nodes) {
+            let num_nodes = cmp::min(chunk_nodes, nodes_count - nodes_offset);
+            let data = &mut data[..num_nodes * NODE_SIZE];
+            if let Some(original_data) = &mut original_data {
+                original_data.read_exact(data)?;
+            }
+
+            let labels_chunk = last_layer.read_range(nodes_offset..nodes_offset + num_nodes)?;
+            for (label, data_node) in labels_chunk.iter().zip(data.chunks(NODE_SIZE)) {
+                let key: Fr = (*label).into();
+                let data_fr = bytes_into_fr(data_node)?;
+                output.write_all(&(key + data_fr).to_repr())?;
+            }
+        }
+        output.flush()?;
+        Ok(())
+    })();
+    // The labels are as large as the sector, don't leave them behind, even on error.
+    remove_sector_key_labels(cache_path)?;
+    result?;
+
+    info!("regenerate_sector_key:finish");
+
+    Ok(())
+}
+
+/// Audits an empty sector update from its files alone, without the caches of the original or the
+/// updated sector.
+///
+/// The trees over the sector key and the replica are rebuilt in `scratch_path` and hashed with
+/// `comm_c` into comm_r_old and comm_r_new, comm_d_new is computed from the new data. Checking
+/// comm_r_old also checks that the given comm_c is the one of the sector key. In addition,
+/// `num_samples` nodes, derived from `seed`, are checked to be the new data encoded with the
+/// sector key.
+///
+/// comm_c is not part of any public data, so only the sector owner can run the audit. Where the
+/// cache of the original sector is available, `audit_sector_update_with_cache` reads comm_c from
+/// it.
+///
+/// A failed audit is not an error, see [`SectorUpdateAudit::is_ok`].
+///
+/// # Arguments
+///
+/// * `config` - the config of the sector update.
+/// * `replica_path` - path to the updated replica.
+/// * `sector_key_path` - path to the sector key, i.e. the replica of the original sector.
+/// * `staged_data_path` - path to the fr32-padded new data of the sector.
+/// * `scratch_path` - path to a directory where the trees are rebuilt, they are removed afterwards.
+/// * `comm_c` - the comm_c of the original sector.
+/// * `comm_r_old` - the commitment to the replica of the original sector.
+/// * `comm_r_new` - the commitment to the updated replica.
+/// * `comm_d_new` - the commitment to the new data.
+/// * `num_samples` - the number of nodes whose encoding is checked.
+/// * `seed` - the randomness the sampled nodes are derived from.
+#[allow(clippy::too_many_arguments)]
+pub fn audit_sector_update<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>>(
+    config: &SectorUpdateConfig,
+    replica_path: &Path,
+    sector_key_path: &Path,
+    staged_data_path: &Path,
+    scratch_path: &Path,
+    comm_c: Commitment,
+    comm_r_old: Commitment,
+    comm_r_new: Commitment,
+    comm_d_new: Commitment,
+    num_samples: usize,
+    seed: [u8; 32],
+) -> Result<SectorUpdateAudit> {
+    info!("audit_sector_update:start");
+    config.ensure_valid_h()?;
+
+    let sector_bytes = u64::from(config.sector_size);
+    for (name, path) in [
+        ("replica", replica_path),
+        ("sector key", sector_key_path),
+        ("staged data", staged_data_path),
+    ] {
+        let len = fs::metadata(path)
+            .with_context(|| format!("could not read metadata of path={:?}", path))?
+            .len();
+        ensure!(
+            len == sector_bytes,
+            "{} must be exactly {} bytes",
+            name,
+            sector_bytes
+        );
+    }
+    ensure!(
+        fs::metadata(scratch_path)?.is_dir(),
+        "scratch_path must be a directory"
+    );
+
+    let comm_c_domain = TreeRDomain::try_from_bytes(&comm_c)?;
+    let sector_key_tree_config = StoreConfig {
+        id: AUDIT_SECTOR_KEY_TREE_ID.to_string(),
+        ..tree_r_last_config::<Tree>(sector_bytes, scratch_path)?
+    };
+    let replica_tree_config = StoreConfig {
+        id: AUDIT_REPLICA_TREE_ID.to_string(),
+        ..sector_key_tree_config.clone()
+    };
+    let tree_d_config = StoreConfig {
+        path: scratch_path.into(),
+        id: AUDIT_TREE_D_ID.to_string(),
+        size: Some(get_merkle_tree_len(
+            config.nodes_count / get_base_tree_count::<Tree>(),
+            Tree::Arity::to_usize(),
+        )?),
+        rows_to_discard: 0,
+    };
+
+    let commitments = (|| -> Result<(Commitment, Commitment, Commitment)> {
+        let root_r_old = build_tree_r_last::<Tree>(
+            sector_bytes,
+            sector_key_path,
+            sector_key_tree_config.clone(),
+        )?;
+        let root_r_new =
+            build_tree_r_last::<Tree>(sector_bytes, replica_path, replica_tree_config.clone())?;
+        let computed_comm_r_old = commitment_from_fr(
+            <TreeRHasher as Hasher>::Function::hash2(&comm_c_domain, &root_r_old).into(),
+        );
+        let computed_comm_r_new = commitment_from_fr(
+            <TreeRHasher as Hasher>::Function::hash2(&comm_c_domain, &root_r_new).into(),
+        );
+
+        let computed_comm_d_new = match config.update_proof_type {
+            UpdateProofType::Sha256 => staged_data_comm_d(staged_data_path, config.nodes_count)?,
+            UpdateProofType::Poseidon => {
+                let staged_data = File::open(staged_data_path)
+                    .with_context(|| format!("could not open path={:?}", staged_data_path))?;
+                let staged_data = unsafe {
+                    MmapOptions::new()
+                        .len(sector_bytes as usize)
+                        .map(&staged_data)
+                        .with_context(|| format!("could not mmap path={:?}", staged_data_path))?
+                };
+                let root_d = poseidon::EmptySectorUpdate::<Tree>::create_tree_d(
+                    config.nodes_count,
+                    tree_d_config.clone(),
+                    &staged_data,
+                )?;
+                commitment_from_fr(root_d.into())
+            }
+        };
+
+        Ok((
+            computed_comm_r_old,
+            computed_comm_r_new,
+            computed_comm_d_new,
+        ))
+    })();
+    // The rebuilt trees are only needed for their roots, don't leave them behind, even on error.
+    // The Poseidon TreeDNew has the shape of TreeR, hence it's removed the same way.
+    for tree_config in [
+        &sector_key_tree_config,
+        &replica_tree_config,
+        &tree_d_config,
+    ] {
+        remove_tree_r_last::<Tree>(tree_config)?;
+    }
+    let (computed_comm_r_old, computed_comm_r_new, computed_comm_d_new) = commitments?;
+
+    // The encoding is checked against the expected commitments, as those are what the proofs
+    // are verified against.
+    let phi = phi(
+        &TreeDDomain::try_from_bytes(&comm_d_new)?,
+        &TreeRDomain::try_from_bytes(&comm_r_old)?,
+    );
+    let rhos = Rhos::new(&phi, config.h, config.nodes_count);
+
+    let sampled_nodes: Vec<u64> = (0..num_samples as u64)
+        .map(|i| {
+            let hash = Sha256::new()
+                .chain_update(seed)
+                .chain_update(i.to_le_bytes())
+                .finalize();
+            let mut bytes = [0; 8];
+            bytes.copy_from_slice(&hash[..8]);
+            u64::from_le_bytes(bytes) % config.nodes_count as u64
+        })
+        .collect();
+
+    let mut replica = File::open(replica_path)
+        .with_context(|| format!("could not open replica_path={:?}", replica_path))?;
+    let mut sector_key = File::open(sector_key_path)
+        .with_context(|| format!("could not open sector_key_path={:?}", sector_key_path))?;
+    let mut staged_data = File::open(staged_data_path)
+        .with_context(|| format!("could not open staged_data_path={:?}", staged_data_path))?;
+    let mut mismatched_nodes = Vec::new();
+    for &node in &sampled_nodes {
+        let replica_fr = read_node_fr(&mut replica, node)?;
+        let sector_key_fr = read_node_fr(&mut sector_key, node)?;
+        let data_fr = read_node_fr(&mut staged_data, node)?;
+        let encoded = match (replica_fr, sector_key_fr, data_fr) {
+            (Some(replica_fr), Some(sector_key_fr), Some(data_fr)) => {
+                replica_fr == sector_key_fr + data_fr * rhos.get(node as usize)
+            }
+            // A node that is not a field element can't be part of a valid encoding.
+            _ => false,
+        };
+        if !encoded {
+            mismatched_nodes.push(node);
+        }
+    }
+
+    info!("audit_sector_update:finish");
+    Ok(SectorUpdateAudit {
+        expected: SectorUpdateProofInputs {
+            h: config.h,
+            comm_r_old,
+            comm_r_new,
+            comm_d_new,
+        },
+        computed_comm_r_old,
+        computed_comm_r_new,
+        computed_comm_d_new,
+        sampled_nodes,
+        mismatched_nodes,
+    })
+}
+
+/// Audits an empty sector update like `audit_sector_update`, with the comm_c read from the p_aux
+/// file in the cache of the original sector.
+///
+/// # Arguments
+///
+/// * `config` - the config of the sector update.
+/// * `replica_path` - path to the updated replica.
+/// * `sector_key_path` - path to the sector key, i.e. the replica of the original sector.
+/// * `sector_key_cache_path` - path to the cache of the original sector.
+/// * `staged_data_path` - path to the fr32-padded new data of the sector.
+/// * `scratch_path` - path to a directory where the trees are rebuilt, they are removed afterwards.
+/// * `comm_r_old` - the commitment to the replica of the original sector.
+/// * `comm_r_new` - the commitment to the updated replica.
+/// * `comm_d_new` - the commitment to the new data.
+/// * `num_samples` - the number of nodes whose encoding is checked.
+/// * `seed` - the randomness the sampled nodes are derived from.
+#[allow(clippy::too_many_arguments)]
+pub fn audit_sector_update_with_cache<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>>(
+    config: &SectorUpdateConfig,
+    replica_path: &Path,
+    sector_key_path: &Path,
+    sector_key_cache_path: &Path,
+    staged_data_path: &Path,
+    scratch_path: &Path,
+    comm_r_old: Commitment,
+    comm_r_new: Commitment,
+    comm_d_new: Commitment,
+    num_samples: usize,
+    seed: [u8; 32],
+) -> Result<SectorUpdateAudit> {
+    let p_aux = util::get_p_aux::<Tree>(sector_key_cache_path)?;
+    audit_sector_update::<Tree>(
+        config,
+        replica_path,
+        sector_key_path,
+        staged_data_path,
+        scratch_path,
+        commitment_from_fr(p_aux.comm_c.into()),
+        comm_r_old,
+        comm_r_new,
+        comm_d_new,
+        num_samples,
+        seed,
+    )
+}
+
+// Reads the node with the given index, returns `None` if it's not a valid field element.
+fn read_node_fr(file: &mut File, node: u64) -> Result<Option<Fr>> {
+    let mut bytes = [0; NODE_SIZE];
+    file.seek(SeekFrom::Start(node * NODE_SIZE as u64))?;
+    file.read_exact(&mut bytes)?;
+    Ok(bytes_into_fr(&bytes).ok())
+}
+
 /// Removes encoded data and outputs the sector key.
 #[allow(clippy::too_many_arguments)]
 pub fn remove_encoded_data<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>>(
@@ -289,6 +1481,7 @@ pub fn remove_encoded_data<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>
     comm_d_new: Commitment,
 ) -> Result<()> {
     info!("remove_data:start");
+    config.ensure_valid_h()?;
 
     let p_aux = util::get_p_aux::<Tree>(replica_cache_path)?;
     let t_aux = util::get_t_aux::<Tree>(replica_cache_path, u64::from(config.sector_size))?;
@@ -307,7 +1500,7 @@ pub fn remove_encoded_data<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>
         <Tree::Hasher as Hasher>::Domain::try_from_bytes(&p_aux.comm_c.into_bytes())?,
         comm_d_new.into(),
         <Tree::Hasher as Hasher>::Domain::try_from_bytes(&p_aux.comm_r_last.into_bytes())?,
-        h_default(config.nodes_count),
+        config.h,
     )?;
 
     // Persist p_aux and t_aux into the sector_key_cache_path here
@@ -335,6 +1528,9 @@ pub fn generate_single_partition_proof<Tree: 'static + MerkleTreeTrait<Hasher =
     replica_cache_path: &Path,
 ) -> Result<PartitionProof<Tree>> {
     info!("generate_single_partition_proof:start");
+    config.ensure_valid_h()?;
+
+    ensure_update_proof_type(&config, UpdateProofType::Sha256)?;
 
     let comm_r_old_safe = <TreeRHasher as Hasher>::Domain::try_from_bytes(&comm_r_old)?;
     let comm_r_new_safe = <TreeRHasher as Hasher>::Domain::try_from_bytes(&comm_r_new)?;
@@ -390,6 +1586,9 @@ pub fn verify_single_partition_proof<Tree: 'static + MerkleTreeTrait<Hasher = Tr
     comm_d_new: Commitment,
 ) -> Result<bool> {
     info!("verify_single_partition_proof:start");
+    config.ensure_valid_h()?;
+
+    ensure_update_proof_type(&config, UpdateProofType::Sha256)?;
 
     let comm_r_old_safe = <TreeRHasher as Hasher>::Domain::try_from_bytes(&comm_r_old)?;
     let comm_r_new_safe = <TreeRHasher as Hasher>::Domain::try_from_bytes(&comm_r_new)?;
@@ -430,6 +1629,9 @@ pub fn generate_partition_proofs<Tree: 'static + MerkleTreeTrait<Hasher = TreeRH
     replica_cache_path: &Path,
 ) -> Result<Vec<PartitionProof<Tree>>> {
     info!("generate_partition_proofs:start");
+    config.ensure_valid_h()?;
+
+    ensure_update_proof_type(&config, UpdateProofType::Sha256)?;
 
     let comm_r_old_safe = <TreeRHasher as Hasher>::Domain::try_from_bytes(&comm_r_old)?;
     let comm_r_new_safe = <TreeRHasher as Hasher>::Domain::try_from_bytes(&comm_r_new)?;
@@ -484,6 +1686,9 @@ pub fn verify_partition_proofs<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHas
     comm_d_new: Commitment,
 ) -> Result<bool> {
     info!("verify_partition_proofs:start");
+    config.ensure_valid_h()?;
+
+    ensure_update_proof_type(&config, UpdateProofType::Sha256)?;
 
     let comm_r_old_safe = <TreeRHasher as Hasher>::Domain::try_from_bytes(&comm_r_old)?;
     let comm_r_new_safe = <TreeRHasher as Hasher>::Domain::try_from_bytes(&comm_r_new)?;
@@ -513,20 +1718,123 @@ pub fn verify_partition_proofs<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHas
 pub fn generate_empty_sector_update_proof_with_vanilla<
     Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>,
 >(
-    porep_config: &PoRepConfig,
-    vanilla_proofs: Vec<PartitionProof<Tree>>,
+    porep_config: &PoRepConfig,
+    vanilla_proofs: Vec<PartitionProof<Tree>>,
+    comm_r_old: Commitment,
+    comm_r_new: Commitment,
+    comm_d_new: Commitment,
+) -> Result<EmptySectorUpdateProof> {
+    generate_empty_sector_update_proof_with_vanilla_and_config::<Tree>(
+        &SectorUpdateConfig::from_porep_config(porep_config),
+        vanilla_proofs,
+        comm_r_old,
+        comm_r_new,
+        comm_d_new,
+    )
+}
+
+/// Same as [`generate_empty_sector_update_proof_with_vanilla`], but takes the SHA256 sector update
+/// config the sector was encoded with, e.g. one with a non-default `h`.
+pub fn generate_empty_sector_update_proof_with_vanilla_and_config<
+    Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>,
+>(
+    config: &SectorUpdateConfig,
+    vanilla_proofs: Vec<PartitionProof<Tree>>,
+    comm_r_old: Commitment,
+    comm_r_new: Commitment,
+    comm_d_new: Commitment,
+) -> Result<EmptySectorUpdateProof> {
+    info!("generate_empty_sector_update_proof_with_vanilla:start");
+
+    let comm_r_old_safe = <TreeRHasher as Hasher>::Domain::try_from_bytes(&comm_r_old)?;
+    let comm_r_new_safe = <TreeRHasher as Hasher>::Domain::try_from_bytes(&comm_r_new)?;
+
+    let comm_d_new_safe = DefaultPieceDomain::try_from_bytes(&comm_d_new)?;
+
+    config.ensure_valid_h()?;
+    ensure_update_proof_type(config, UpdateProofType::Sha256)?;
+
+    let partitions = usize::from(config.update_partitions);
+    let public_inputs: storage_proofs_update::PublicInputs = PublicInputs {
+        k: partitions,
+        comm_r_old: comm_r_old_safe,
+        comm_d_new: comm_d_new_safe,
+        comm_r_new: comm_r_new_safe,
+        h: config.h,
+    };
+
+    let setup_params_compound = compound_proof::SetupParams {
+        vanilla_params: SetupParams {
+            sector_bytes: u64::from(config.sector_size),
+        },
+        partitions: Some(partitions),
+        priority: false,
+    };
+    let pub_params_compound = EmptySectorUpdateCompound::<Tree>::setup(&setup_params_compound)?;
+
+    let groth_params = get_empty_sector_update_params::<Tree>(config)?;
+    let proofs = EmptySectorUpdateCompound::prove_with_vanilla(
+        &pub_params_compound,
+        &public_inputs,
+        vanilla_proofs,
+        &groth_params,
+    )?;
+
+    info!("generate_empty_sector_update_proof_with_vanilla:finish");
+
+    let proofs_bytes = util::proofs_to_bytes(&proofs)?;
+    Ok(EmptySectorUpdateProof(proofs_bytes))
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn generate_empty_sector_update_proof<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>>(
+    porep_config: &PoRepConfig,
+    comm_r_old: Commitment,
+    comm_r_new: Commitment,
+    comm_d_new: Commitment,
+    sector_key_path: &Path,
+    sector_key_cache_path: &Path,
+    replica_path: &Path,
+    replica_cache_path: &Path,
+) -> Result<EmptySectorUpdateProof> {
+    generate_empty_sector_update_proof_with_config::<Tree>(
+        &SectorUpdateConfig::from_porep_config(porep_config),
+        comm_r_old,
+        comm_r_new,
+        comm_d_new,
+        sector_key_path,
+        sector_key_cache_path,
+        replica_path,
+        replica_cache_path,
+    )
+}
+
+/// Same as [`generate_empty_sector_update_proof`], but takes the SHA256 sector update config the
+/// sector was encoded with, e.g. one with a non-default `h`.
+#[allow(clippy::too_many_arguments)]
+pub fn generate_empty_sector_update_proof_with_config<
+    Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>,
+>(
+    config: &SectorUpdateConfig,
     comm_r_old: Commitment,
     comm_r_new: Commitment,
     comm_d_new: Commitment,
+    sector_key_path: &Path,
+    sector_key_cache_path: &Path,
+    replica_path: &Path,
+    replica_cache_path: &Path,
 ) -> Result<EmptySectorUpdateProof> {
-    info!("generate_empty_sector_update_proof_with_vanilla:start");
+    info!("generate_empty_sector_update_proof:start");
 
     let comm_r_old_safe = <TreeRHasher as Hasher>::Domain::try_from_bytes(&comm_r_old)?;
     let comm_r_new_safe = <TreeRHasher as Hasher>::Domain::try_from_bytes(&comm_r_new)?;
 
     let comm_d_new_safe = DefaultPieceDomain::try_from_bytes(&comm_d_new)?;
 
-    let config = SectorUpdateConfig::from_porep_config(porep_config);
+    config.ensure_valid_h()?;
+    ensure_update_proof_type(config, UpdateProofType::Sha256)?;
+
+    let p_aux_old = util::get_p_aux::<Tree>(sector_key_cache_path)?;
 
     let partitions = usize::from(config.update_partitions);
     let public_inputs: storage_proofs_update::PublicInputs = PublicInputs {
@@ -537,6 +1845,20 @@ pub fn generate_empty_sector_update_proof_with_vanilla<
         h: config.h,
     };
 
+    let t_aux_old = util::get_t_aux::<Tree>(sector_key_cache_path, u64::from(config.sector_size))?;
+
+    let (tree_d_new_config, tree_r_last_new_config) =
+        get_new_configs_from_t_aux_old::<Tree>(&t_aux_old, replica_cache_path, config.nodes_count)?;
+
+    let private_inputs: PrivateInputs = PrivateInputs {
+        comm_c: p_aux_old.comm_c,
+        tree_r_old_config: t_aux_old.tree_r_last_config,
+        old_replica_path: sector_key_path.to_path_buf(),
+        tree_d_new_config,
+        tree_r_new_config: tree_r_last_new_config,
+        replica_path: replica_path.to_path_buf(),
+    };
+
     let setup_params_compound = compound_proof::SetupParams {
         vanilla_params: SetupParams {
             sector_bytes: u64::from(config.sector_size),
@@ -546,23 +1868,29 @@ pub fn generate_empty_sector_update_proof_with_vanilla<
     };
     let pub_params_compound = EmptySectorUpdateCompound::<Tree>::setup(&setup_params_compound)?;
 
-    let groth_params = get_empty_sector_update_params::<Tree>(porep_config)?;
-    let proofs = EmptySectorUpdateCompound::prove_with_vanilla(
+    let groth_params = get_empty_sector_update_params::<Tree>(config)?;
+    let proofs = EmptySectorUpdateCompound::prove(
         &pub_params_compound,
         &public_inputs,
-        vanilla_proofs,
+        &private_inputs,
         &groth_params,
     )?;
 
-    info!("generate_empty_sector_update_proof_with_vanilla:finish");
+    info!("generate_empty_sector_update_proof:finish");
 
     let proofs_bytes = util::proofs_to_bytes(&proofs)?;
     Ok(EmptySectorUpdateProof(proofs_bytes))
 }
 
+/// Generates the same proof as [`generate_empty_sector_update_proof_with_config`], but proves
+/// `partitions_per_batch` partitions at a time, from the vanilla proofs to the SNARKs. Only the
+/// vanilla proofs and circuits of a single batch are held in memory, which bounds the peak memory
+/// usage for sectors with many partitions.
 #[allow(clippy::too_many_arguments)]
-pub fn generate_empty_sector_update_proof<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>>(
-    porep_config: &PoRepConfig,
+pub fn generate_empty_sector_update_proof_in_batches<
+    Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>,
+>(
+    config: &SectorUpdateConfig,
     comm_r_old: Commitment,
     comm_r_new: Commitment,
     comm_d_new: Commitment,
@@ -570,15 +1898,22 @@ pub fn generate_empty_sector_update_proof<Tree: 'static + MerkleTreeTrait<Hasher
     sector_key_cache_path: &Path,
     replica_path: &Path,
     replica_cache_path: &Path,
+    partitions_per_batch: usize,
 ) -> Result<EmptySectorUpdateProof> {
-    info!("generate_empty_sector_update_proof:start");
+    info!("generate_empty_sector_update_proof_in_batches:start");
+
+    ensure!(
+        partitions_per_batch > 0,
+        "at least one partition must be proven per batch"
+    );
 
     let comm_r_old_safe = <TreeRHasher as Hasher>::Domain::try_from_bytes(&comm_r_old)?;
     let comm_r_new_safe = <TreeRHasher as Hasher>::Domain::try_from_bytes(&comm_r_new)?;
 
     let comm_d_new_safe = DefaultPieceDomain::try_from_bytes(&comm_d_new)?;
 
-    let config = SectorUpdateConfig::from_porep_config(porep_config);
+    config.ensure_valid_h()?;
+    ensure_update_proof_type(config, UpdateProofType::Sha256)?;
 
     let p_aux_old = util::get_p_aux::<Tree>(sector_key_cache_path)?;
 
@@ -613,16 +1948,47 @@ pub fn generate_empty_sector_update_proof<Tree: 'static + MerkleTreeTrait<Hasher
         priority: false,
     };
     let pub_params_compound = EmptySectorUpdateCompound::<Tree>::setup(&setup_params_compound)?;
+    let vanilla_params = &pub_params_compound.vanilla_params;
+
+    let groth_params = get_empty_sector_update_params::<Tree>(config)?;
+    let mut proofs = Vec::with_capacity(partitions);
+    for first_partition in (0..partitions).step_by(partitions_per_batch) {
+        let batch = first_partition..cmp::min(first_partition + partitions_per_batch, partitions);
+        info!("proving partitions {:?}", batch);
+
+        let vanilla_proofs = EmptySectorUpdate::<Tree>::prove_partitions(
+            vanilla_params,
+            &public_inputs,
+            &private_inputs,
+            batch.clone(),
+        )?;
+        for (k, vanilla_proof) in batch.zip(&vanilla_proofs) {
+            let partition_inputs =
+                EmptySectorUpdate::<Tree>::with_partition(public_inputs.clone(), Some(k));
+            ensure!(
+                EmptySectorUpdate::<Tree>::verify(
+                    vanilla_params,
+                    &partition_inputs,
+                    vanilla_proof
+                )?,
+                "sanity check failed for partition {}",
+                k
+            );
+        }
 
-    let groth_params = get_empty_sector_update_params::<Tree>(porep_config)?;
-    let proofs = EmptySectorUpdateCompound::prove(
-        &pub_params_compound,
-        &public_inputs,
-        &private_inputs,
-        &groth_params,
-    )?;
+        proofs.extend(
+            EmptySectorUpdateCompound::<Tree>::circuit_proofs_for_partitions(
+                &public_inputs,
+                vanilla_proofs,
+                first_partition,
+                vanilla_params,
+                &groth_params,
+                pub_params_compound.priority,
+            )?,
+        );
+    }
 
-    info!("generate_empty_sector_update_proof:finish");
+    info!("generate_empty_sector_update_proof_in_batches:finish");
 
     let proofs_bytes = util::proofs_to_bytes(&proofs)?;
     Ok(EmptySectorUpdateProof(proofs_bytes))
@@ -634,6 +2000,26 @@ pub fn verify_empty_sector_update_proof<Tree: 'static + MerkleTreeTrait<Hasher =
     comm_r_old: Commitment,
     comm_r_new: Commitment,
     comm_d_new: Commitment,
+) -> Result<bool> {
+    verify_empty_sector_update_proof_with_config::<Tree>(
+        &SectorUpdateConfig::from_porep_config(porep_config),
+        proof_bytes,
+        comm_r_old,
+        comm_r_new,
+        comm_d_new,
+    )
+}
+
+/// Same as [`verify_empty_sector_update_proof`], but takes the SHA256 sector update config the
+/// proof was generated with, e.g. one with a non-default `h`.
+pub fn verify_empty_sector_update_proof_with_config<
+    Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>,
+>(
+    config: &SectorUpdateConfig,
+    proof_bytes: &[u8],
+    comm_r_old: Commitment,
+    comm_r_new: Commitment,
+    comm_d_new: Commitment,
 ) -> Result<bool> {
     info!("verify_empty_sector_update_proof:start");
 
@@ -642,7 +2028,8 @@ pub fn verify_empty_sector_update_proof<Tree: 'static + MerkleTreeTrait<Hasher =
 
     let comm_d_new_safe = DefaultPieceDomain::try_from_bytes(&comm_d_new)?;
 
-    let config = SectorUpdateConfig::from_porep_config(porep_config);
+    config.ensure_valid_h()?;
+    ensure_update_proof_type(config, UpdateProofType::Sha256)?;
     let partitions = usize::from(config.update_partitions);
     let public_inputs: storage_proofs_update::PublicInputs = PublicInputs {
         k: partitions,
@@ -660,7 +2047,7 @@ pub fn verify_empty_sector_update_proof<Tree: 'static + MerkleTreeTrait<Hasher =
     };
     let pub_params_compound = EmptySectorUpdateCompound::<Tree>::setup(&setup_params_compound)?;
 
-    let verifying_key = get_empty_sector_update_verifying_key::<Tree>(porep_config)?;
+    let verifying_key = get_empty_sector_update_verifying_key::<Tree>(config)?;
     let multi_proof = MultiProof::new_from_bytes(Some(partitions), proof_bytes, &verifying_key)?;
     let valid =
         EmptySectorUpdateCompound::verify(&pub_params_compound, &public_inputs, &multi_proof, &())?;
@@ -688,6 +2075,24 @@ pub fn get_sector_update_inputs<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHa
     comm_r_old: Commitment,
     comm_r_new: Commitment,
     comm_d_new: Commitment,
+) -> Result<Vec<Vec<Fr>>> {
+    get_sector_update_inputs_with_config::<Tree>(
+        &SectorUpdateConfig::from_porep_config(porep_config),
+        comm_r_old,
+        comm_r_new,
+        comm_d_new,
+    )
+}
+
+/// Same as [`get_sector_update_inputs`], but takes the SHA256 sector update config the proof was
+/// generated with, e.g. one with a non-default `h`.
+pub fn get_sector_update_inputs_with_config<
+    Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>,
+>(
+    config: &SectorUpdateConfig,
+    comm_r_old: Commitment,
+    comm_r_new: Commitment,
+    comm_d_new: Commitment,
 ) -> Result<Vec<Vec<Fr>>> {
     trace!("get_sector_update_inputs:start");
 
@@ -713,7 +2118,8 @@ pub fn get_sector_update_inputs<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHa
 
     let comm_d_new_safe = DefaultPieceDomain::try_from_bytes(&comm_d_new)?;
 
-    let config = SectorUpdateConfig::from_porep_config(porep_config);
+    config.ensure_valid_h()?;
+    ensure_update_proof_type(config, UpdateProofType::Sha256)?;
     let partitions = usize::from(config.update_partitions);
 
     let public_inputs: storage_proofs_update::PublicInputs = PublicInputs {
@@ -749,22 +2155,6 @@ pub fn get_sector_update_inputs<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHa
     Ok(inputs)
 }
 
-// Hash all of the commitments into an ordered digest for the aggregate proof method.
-fn get_hashed_commitments(sector_update_inputs: &[SectorUpdateProofInputs]) -> [u8; 32] {
-    let hashed_commitments: [u8; 32] = {
-        let mut hasher = Sha256::new();
-        for input in sector_update_inputs.iter() {
-            hasher.update([input.h as u8]);
-            hasher.update(input.comm_r_old);
-            hasher.update(input.comm_r_new);
-            hasher.update(input.comm_d_new);
-        }
-        hasher.finalize().into()
-    };
-
-    hashed_commitments
-}
-
 pub fn aggregate_empty_sector_update_proofs<
     Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>,
 >(
@@ -818,20 +2208,9 @@ pub fn aggregate_empty_sector_update_proofs<
     // Note that the proofs count here is not the same as the input
     // proofs since the multi proof type takes the partitions into
     // account
-    let target_proofs_len = get_aggregate_target_len(proofs.len());
-    ensure!(
-        target_proofs_len > 1,
-        "cannot aggregate less than two proofs"
-    );
-    trace!(
-        "aggregate_sector_update_proofs will pad proofs to target_len {}",
-        target_proofs_len
-    );
+    pad_aggregate_proofs(&mut proofs)?;
 
-    // If we're not at the pow2 target, duplicate the last proof until we are.
-    pad_proofs_to_target(&mut proofs, target_proofs_len)?;
-
-    let hashed_commitments = get_hashed_commitments(sector_update_inputs);
+    let hashed_commitments = sector_update_aggregate_transcript(sector_update_inputs);
     let srs_prover_key = get_stacked_srs_key::<Tree>(porep_config, proofs.len())?;
     let aggregate_proof = EmptySectorUpdateCompound::<Tree>::aggregate_proofs(
         &srs_prover_key,
@@ -876,7 +2255,6 @@ pub fn verify_aggregate_sector_update_proofs<
 
     let aggregated_proofs_len = aggregate_proof.tmipp.gipa.nproofs as usize;
 
-    ensure!(aggregated_proofs_len != 0, "cannot verify zero proofs");
     ensure!(
         !sector_update_inputs.is_empty(),
         "cannot verify with empty inputs"
@@ -898,44 +2276,18 @@ pub fn verify_aggregate_sector_update_proofs<
         aggregated_proofs_len,
     );
 
+    // Note that each vector in 'sector_update_inputs' are the public
+    // inputs to exactly one Groth16 proof
     ensure!(
-        aggregated_proofs_len > 1,
-        "cannot verify less than two proofs"
-    );
-    ensure!(
-        aggregated_proofs_len == aggregated_proofs_len.next_power_of_two(),
-        "cannot verify non-pow2 aggregate seal proofs"
-    );
-
-    let num_inputs = sector_update_inputs.len();
-
-    // Note that 'num_inputs_per_proof' should always be exactly 1 --
-    // each vector in 'sector_update_inputs' are the public inputs to
-    // one Groth16 proof
-    let num_inputs_per_proof = get_aggregate_target_len(num_inputs) / aggregated_proofs_len;
-    ensure!(num_inputs_per_proof == 1, "num_inputs per proof mismatch");
-    let target_inputs_len = aggregated_proofs_len * num_inputs_per_proof;
-
-    trace!(
-        "verify_aggregate_sector_update_proofs got {} combined inputs with {} inputs per proof, target_len is {}",
-        num_inputs,
-        num_inputs_per_proof,
-        target_inputs_len,
-    );
-
-    ensure!(
-        target_inputs_len % aggregated_proofs_len == 0,
-        "invalid number of inputs provided",
+        get_aggregate_target_len(sector_update_inputs.len()) == aggregated_proofs_len,
+        "num_inputs per proof mismatch"
     );
+    let sector_update_inputs = pad_aggregate_inputs(&sector_update_inputs, aggregated_proofs_len)?;
 
-    let sector_update_inputs = pad_inputs_to_target(
-        &sector_update_inputs,
-        num_inputs_per_proof,
-        target_inputs_len,
+    let hashed_commitments = sector_update_aggregate_transcript(inputs);
+    let verifying_key = get_empty_sector_update_verifying_key::<Tree>(
+        &SectorUpdateConfig::from_porep_config(porep_config),
     )?;
-
-    let hashed_commitments = get_hashed_commitments(inputs);
-    let verifying_key = get_empty_sector_update_verifying_key::<Tree>(porep_config)?;
     let srs_verifier_key =
         get_stacked_srs_verifier_key::<Tree>(porep_config, aggregated_proofs_len)?;
 
@@ -954,3 +2306,237 @@ pub fn verify_aggregate_sector_update_proofs<
 
     Ok(result)
 }
+
+fn poseidon_public_inputs(
+    config: &SectorUpdateConfig,
+    comm_r_old: Commitment,
+    comm_r_new: Commitment,
+    comm_d_new: Commitment,
+) -> Result<poseidon::vanilla::PublicInputs> {
+    Ok(poseidon::vanilla::PublicInputs {
+        comm_r_old: <TreeRHasher as Hasher>::Domain::try_from_bytes(&comm_r_old)?,
+        comm_d_new: <TreeRHasher as Hasher>::Domain::try_from_bytes(&comm_d_new)?,
+        comm_r_new: <TreeRHasher as Hasher>::Domain::try_from_bytes(&comm_r_new)?,
+        h: config.h,
+    })
+}
+
+fn poseidon_private_inputs<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>>(
+    config: &SectorUpdateConfig,
+    sector_key_path: &Path,
+    sector_key_cache_path: &Path,
+    replica_path: &Path,
+    replica_cache_path: &Path,
+) -> Result<PrivateInputs> {
+    let p_aux_old = util::get_p_aux::<Tree>(sector_key_cache_path)?;
+    let t_aux_old = util::get_t_aux::<Tree>(sector_key_cache_path, u64::from(config.sector_size))?;
+
+    let (tree_d_new_config, tree_r_last_new_config) =
+        get_new_configs_from_t_aux_old_poseidon::<Tree>(
+            &t_aux_old,
+            replica_cache_path,
+            config.nodes_count,
+        )?;
+
+    Ok(PrivateInputs {
+        comm_c: p_aux_old.comm_c,
+        tree_r_old_config: t_aux_old.tree_r_last_config,
+        old_replica_path: sector_key_path.to_path_buf(),
+        tree_d_new_config,
+        tree_r_new_config: tree_r_last_new_config,
+        replica_path: replica_path.to_path_buf(),
+    })
+}
+
+/// Generate the vanilla proof of the Poseidon variant, which consists of a single partition.
+#[allow(clippy::too_many_arguments)]
+pub fn generate_poseidon_partition_proof<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>>(
+    config: SectorUpdateConfig,
+    comm_r_old: Commitment,
+    comm_r_new: Commitment,
+    comm_d_new: Commitment,
+    sector_key_path: &Path,
+    sector_key_cache_path: &Path,
+    replica_path: &Path,
+    replica_cache_path: &Path,
+) -> Result<poseidon::vanilla::PartitionProof<Tree>> {
+    info!("generate_poseidon_partition_proof:start");
+    config.ensure_valid_h()?;
+
+    ensure_update_proof_type(&config, UpdateProofType::Poseidon)?;
+
+    let public_params = config.public_params();
+    let public_inputs = poseidon_public_inputs(&config, comm_r_old, comm_r_new, comm_d_new)?;
+    let private_inputs = poseidon_private_inputs::<Tree>(
+        &config,
+        sector_key_path,
+        sector_key_cache_path,
+        replica_path,
+        replica_cache_path,
+    )?;
+
+    let partition_proof = poseidon::EmptySectorUpdate::<Tree>::prove(
+        &public_params,
+        &public_inputs,
+        &private_inputs,
+    )?;
+
+    info!("generate_poseidon_partition_proof:finish");
+
+    Ok(partition_proof)
+}
+
+/// Verify the vanilla proof of the Poseidon variant.
+pub fn verify_poseidon_partition_proof<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>>(
+    config: SectorUpdateConfig,
+    proof: poseidon::vanilla::PartitionProof<Tree>,
+    comm_r_old: Commitment,
+    comm_r_new: Commitment,
+    comm_d_new: Commitment,
+) -> Result<bool> {
+    info!("verify_poseidon_partition_proof:start");
+    config.ensure_valid_h()?;
+
+    ensure_update_proof_type(&config, UpdateProofType::Poseidon)?;
+
+    let public_params = config.public_params();
+    let public_inputs = poseidon_public_inputs(&config, comm_r_old, comm_r_new, comm_d_new)?;
+
+    let valid =
+        poseidon::EmptySectorUpdate::<Tree>::verify(&public_params, &public_inputs, &proof)?;
+
+    info!("verify_poseidon_partition_proof:finish");
+
+    Ok(valid)
+}
+
+pub fn generate_empty_sector_update_poseidon_proof_with_vanilla<
+    Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>,
+>(
+    config: SectorUpdateConfig,
+    vanilla_proof: poseidon::vanilla::PartitionProof<Tree>,
+    comm_r_old: Commitment,
+    comm_r_new: Commitment,
+    comm_d_new: Commitment,
+) -> Result<EmptySectorUpdateProof> {
+    info!("generate_empty_sector_update_poseidon_proof_with_vanilla:start");
+    config.ensure_valid_h()?;
+
+    ensure_update_proof_type(&config, UpdateProofType::Poseidon)?;
+
+    let public_inputs = poseidon_public_inputs(&config, comm_r_old, comm_r_new, comm_d_new)?;
+
+    let setup_params_compound = compound_proof::SetupParams {
+        vanilla_params: SetupParams {
+            sector_bytes: u64::from(config.sector_size),
+        },
+        partitions: Some(1),
+        priority: false,
+    };
+    let pub_params_compound =
+        poseidon::EmptySectorUpdateCompound::<Tree>::setup(&setup_params_compound)?;
+
+    let groth_params = get_empty_sector_update_poseidon_params::<Tree>(&config)?;
+    let proofs = poseidon::EmptySectorUpdateCompound::prove_with_vanilla(
+        &pub_params_compound,
+        &public_inputs,
+        vec![vanilla_proof],
+        &groth_params,
+    )?;
+
+    info!("generate_empty_sector_update_poseidon_proof_with_vanilla:finish");
+
+    let proofs_bytes = util::proofs_to_bytes(&proofs)?;
+    Ok(EmptySectorUpdateProof(proofs_bytes))
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn generate_empty_sector_update_poseidon_proof<
+    Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>,
+>(
+    config: SectorUpdateConfig,
+    comm_r_old: Commitment,
+    comm_r_new: Commitment,
+    comm_d_new: Commitment,
+    sector_key_path: &Path,
+    sector_key_cache_path: &Path,
+    replica_path: &Path,
+    replica_cache_path: &Path,
+) -> Result<EmptySectorUpdateProof> {
+    info!("generate_empty_sector_update_poseidon_proof:start");
+    config.ensure_valid_h()?;
+
+    ensure_update_proof_type(&config, UpdateProofType::Poseidon)?;
+
+    let public_inputs = poseidon_public_inputs(&config, comm_r_old, comm_r_new, comm_d_new)?;
+    let private_inputs = poseidon_private_inputs::<Tree>(
+        &config,
+        sector_key_path,
+        sector_key_cache_path,
+        replica_path,
+        replica_cache_path,
+    )?;
+
+    let setup_params_compound = compound_proof::SetupParams {
+        vanilla_params: SetupParams {
+            sector_bytes: u64::from(config.sector_size),
+        },
+        partitions: Some(1),
+        priority: false,
+    };
+    let pub_params_compound =
+        poseidon::EmptySectorUpdateCompound::<Tree>::setup(&setup_params_compound)?;
+
+    let groth_params = get_empty_sector_update_poseidon_params::<Tree>(&config)?;
+    let proofs = poseidon::EmptySectorUpdateCompound::prove(
+        &pub_params_compound,
+        &public_inputs,
+        &private_inputs,
+        &groth_params,
+    )?;
+
+    info!("generate_empty_sector_update_poseidon_proof:finish");
+
+    let proofs_bytes = util::proofs_to_bytes(&proofs)?;
+    Ok(EmptySectorUpdateProof(proofs_bytes))
+}
+
+pub fn verify_empty_sector_update_poseidon_proof<
+    Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>,
+>(
+    config: SectorUpdateConfig,
+    proof_bytes: &[u8],
+    comm_r_old: Commitment,
+    comm_r_new: Commitment,
+    comm_d_new: Commitment,
+) -> Result<bool> {
+    info!("verify_empty_sector_update_poseidon_proof:start");
+    config.ensure_valid_h()?;
+
+    ensure_update_proof_type(&config, UpdateProofType::Poseidon)?;
+
+    let public_inputs = poseidon_public_inputs(&config, comm_r_old, comm_r_new, comm_d_new)?;
+
+    let setup_params_compound = compound_proof::SetupParams {
+        vanilla_params: SetupParams {
+            sector_bytes: u64::from(config.sector_size),
+        },
+        partitions: Some(1),
+        priority: true,
+    };
+    let pub_params_compound =
+        poseidon::EmptySectorUpdateCompound::<Tree>::setup(&setup_params_compound)?;
+
+    let verifying_key = get_empty_sector_update_poseidon_verifying_key::<Tree>(&config)?;
+    let multi_proof = MultiProof::new_from_bytes(Some(1), proof_bytes, &verifying_key)?;
+    let valid = poseidon::EmptySectorUpdateCompound::verify(
+        &pub_params_compound,
+        &public_inputs,
+        &multi_proof,
+        &(),
+    )?;
+
+    info!("verify_empty_sector_update_poseidon_proof:finish");
+
+    Ok(valid)
+}
@@ -0,0 +1,168 @@
+use std::convert::TryInto;
+
+use anyhow::{ensure, Context, Result};
+use log::info;
+use storage_proofs_update::constants::TreeRHasher;
+
+use crate::{
+    api::{
+        update::ensure_update_proof_type,
+        util::{append_checksummed_body, checksummed_body, CHECKSUM_LEN},
+        verify_partition_proofs,
+    },
+    types::{
+        Commitment, MerkleTreeTrait, PartitionProof, SectorUpdateConfig, UpdatePartitionProofs,
+        UpdateProofType,
+    },
+};
+
+/// The version of the encoding written by `encode_update_partition_proofs`.
+pub const UPDATE_PARTITION_PROOFS_VERSION: u16 = 1;
+
+const MAGIC: [u8; 4] = *b"FUPP";
+/// Magic (4 bytes), version (2 bytes), flags (1 byte), sector size (8 bytes), h (2 bytes),
+/// partition count (2 bytes), comm_r_old, comm_r_new and comm_d_new (32 bytes each) and body
+/// length (8 bytes).
+const HEADER_LEN: usize = 123;
+
+/// Encodes the vanilla partition proofs of an empty sector update together with the public inputs
+/// they were generated for, e.g. to transfer them from the storage host to a separate host that
+/// generates the SNARK with `generate_empty_sector_update_proof_with_vanilla_and_config`.
+///
+/// The encoding starts with a versioned header that contains the sector size, `h`, the number of
+/// partitions and the commitments, and ends with a SHA-256 checksum.
+///
+/// # Arguments
+///
+/// * `config` - the sector update config the proofs were generated with.
+/// * `comm_r_old` - a commitment to a sector's previous replica.
+/// * `comm_r_new` - a commitment to a sector's current replica.
+/// * `comm_d_new` - a commitment to a sector's current data.
+/// * `partition_proofs` - the output of `generate_partition_proofs`.
+pub fn encode_update_partition_proofs<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>>(
+    config: &SectorUpdateConfig,
+    comm_r_old: Commitment,
+    comm_r_new: Commitment,
+    comm_d_new: Commitment,
+    partition_proofs: &[PartitionProof<Tree>],
+) -> Result<Vec<u8>> {
+    info!("encode_update_partition_proofs:start");
+    config.ensure_valid_h()?;
+
+    ensure_update_proof_type(config, UpdateProofType::Sha256)?;
+    let partitions = usize::from(config.update_partitions);
+    ensure!(
+        partition_proofs.len() == partitions,
+        "expected {} partition proofs, found {}",
+        partitions,
+        partition_proofs.len()
+    );
+
+    let body = bincode::serialize(partition_proofs)?;
+
+    let mut bytes = Vec::with_capacity(HEADER_LEN + body.len() + CHECKSUM_LEN);
+    bytes.extend_from_slice(&MAGIC);
+    bytes.extend_from_slice(&UPDATE_PARTITION_PROOFS_VERSION.to_le_bytes());
+    bytes.push(0);
+    bytes.extend_from_slice(&u64::from(config.sector_size).to_le_bytes());
+    bytes.extend_from_slice(&(config.h as u16).to_le_bytes());
+    bytes.extend_from_slice(&(partitions as u16).to_le_bytes());
+    bytes.extend_from_slice(&comm_r_old);
+    bytes.extend_from_slice(&comm_r_new);
+    bytes.extend_from_slice(&comm_d_new);
+    append_checksummed_body(&mut bytes, &body);
+
+    info!("encode_update_partition_proofs:finish");
+    Ok(bytes)
+}
+
+/// Decodes vanilla partition proofs that were encoded with `encode_update_partition_proofs` and
+/// verifies them against the embedded commitments, so that corrupt or invalid proofs are rejected
+/// before the SNARK is generated.
+///
+/// The `h` of the returned proofs is the one they were encoded with, `config.h` is ignored.
+///
+/// # Arguments
+///
+/// * `config` - the sector update config of the sector.
+/// * `bytes` - the encoded partition proofs.
+pub fn decode_update_partition_proofs<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>>(
+    config: &SectorUpdateConfig,
+    bytes: &[u8],
+) -> Result<UpdatePartitionProofs<Tree>> {
+    info!("decode_update_partition_proofs:start");
+
+    ensure_update_proof_type(config, UpdateProofType::Sha256)?;
+    ensure!(
+        bytes.len() >= HEADER_LEN + CHECKSUM_LEN,
+        "update partition proofs are too short ({} bytes)",
+        bytes.len()
+    );
+    ensure!(
+        bytes[..4] == MAGIC,
+        "data is not encoded update partition proofs"
+    );
+
+    let version = u16::from_le_bytes(bytes[4..6].try_into()?);
+    ensure!(
+        version == UPDATE_PARTITION_PROOFS_VERSION,
+        "unsupported update partition proofs version {}, expected {}",
+        version,
+        UPDATE_PARTITION_PROOFS_VERSION
+    );
+    let flags = bytes[6];
+    ensure!(
+        flags == 0,
+        "unknown update partition proofs flags {:#x}",
+        flags
+    );
+    let sector_size = u64::from_le_bytes(bytes[7..15].try_into()?);
+    ensure!(
+        sector_size == u64::from(config.sector_size),
+        "update partition proofs are for sector size {}, expected {}",
+        sector_size,
+        u64::from(config.sector_size)
+    );
+    let h = u16::from_le_bytes(bytes[15..17].try_into()?) as usize;
+    let mut proof_config = *config;
+    proof_config.set_h(h)?;
+    let partitions = u16::from_le_bytes(bytes[17..19].try_into()?) as usize;
+    ensure!(
+        partitions == usize::from(config.update_partitions),
+        "update partition proofs have {} partitions, expected {}",
+        partitions,
+        usize::from(config.update_partitions)
+    );
+    let comm_r_old: Commitment = bytes[19..51].try_into()?;
+    let comm_r_new: Commitment = bytes[51..83].try_into()?;
+    let comm_d_new: Commitment = bytes[83..115].try_into()?;
+    let body = checksummed_body(bytes, HEADER_LEN, "update partition proofs")?;
+
+    let partition_proofs: Vec<PartitionProof<Tree>> =
+        bincode::deserialize(body).context("failed to deserialize update partition proofs")?;
+    ensure!(
+        partition_proofs.len() == partitions,
+        "expected {} partition proofs, found {}",
+        partitions,
+        partition_proofs.len()
+    );
+    ensure!(
+        verify_partition_proofs(
+            proof_config,
+            &partition_proofs,
+            comm_r_old,
+            comm_r_new,
+            comm_d_new
+        )?,
+        "update partition proofs do not verify"
+    );
+
+    info!("decode_update_partition_proofs:finish");
+    Ok(UpdatePartitionProofs {
+        comm_r_old,
+        comm_r_new,
+        comm_d_new,
+        h,
+        partition_proofs,
+    })
+}
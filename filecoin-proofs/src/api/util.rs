@@ -1,4 +1,9 @@
-use std::{fs, mem::size_of, path::Path};
+use std::{
+    convert::{TryFrom, TryInto},
+    fs,
+    mem::size_of,
+    path::Path,
+};
 
 use anyhow::{ensure, Context, Result};
 use bellperson::groth16::{self, Proof};
@@ -7,6 +12,7 @@ use filecoin_hashers::{Domain, Hasher};
 use fr32::{bytes_into_fr, fr_into_bytes};
 use log::trace;
 use merkletree::merkle::{get_merkle_tree_leafs, get_merkle_tree_len};
+use sha2::{Digest, Sha256};
 use storage_proofs_core::{
     cache_key::CacheKey,
     merkle::{get_base_tree_count, MerkleTreeTrait},
@@ -20,13 +26,220 @@ use crate::{
     types::{Commitment, PoRepConfig, SectorSize, SectorUpdateConfig},
 };
 
+/// Converts a commitment, given either as raw bytes or as a Filecoin CID of any commitment kind,
+/// into a domain element, checking that it is a valid field element.
 pub fn as_safe_commitment<H: Domain, T: AsRef<str>>(
-    comm: &[u8; 32],
+    comm: &(impl AsCommitment + ?Sized),
+    commitment_name: T,
+) -> Result<H> {
+    commitment_into_domain(comm, None, commitment_name.as_ref())
+}
+
+/// Same as [`as_safe_commitment`], but a CID must be of `kind`.
+pub fn as_safe_commitment_of_kind<H: Domain, T: AsRef<str>>(
+    comm: &(impl AsCommitment + ?Sized),
+    kind: CommitmentKind,
     commitment_name: T,
 ) -> Result<H> {
-    bytes_into_fr(comm)
+    commitment_into_domain(comm, Some(kind), commitment_name.as_ref())
+}
+
+fn commitment_into_domain<H: Domain>(
+    comm: &(impl AsCommitment + ?Sized),
+    kind: Option<CommitmentKind>,
+    commitment_name: &str,
+) -> Result<H> {
+    let comm = comm
+        .as_commitment(kind)
+        .with_context(|| format!("Invalid commitment ({})", commitment_name))?;
+    bytes_into_fr(&comm)
         .map(Into::into)
-        .with_context(|| format!("Invalid commitment ({})", commitment_name.as_ref(),))
+        .with_context(|| format!("Invalid commitment ({})", commitment_name))
+}
+
+/// A commitment given either as its raw bytes or as a Filecoin CID string.
+pub trait AsCommitment {
+    /// Returns the raw commitment. If `kind` is given, a CID must be of that kind. Raw bytes don't
+    /// carry their kind, so they are returned as is.
+    fn as_commitment(&self, kind: Option<CommitmentKind>) -> Result<Commitment>;
+}
+
+impl AsCommitment for Commitment {
+    fn as_commitment(&self, _kind: Option<CommitmentKind>) -> Result<Commitment> {
+        Ok(*self)
+    }
+}
+
+impl AsCommitment for str {
+    fn as_commitment(&self, kind: Option<CommitmentKind>) -> Result<Commitment> {
+        let (comm, cid_kind) = cid_to_commitment(self)?;
+        if let Some(kind) = kind {
+            ensure!(
+                cid_kind == kind,
+                "CID {:?} is a {:?} commitment, expected a {:?} one",
+                self,
+                cid_kind,
+                kind
+            );
+        }
+        Ok(comm)
+    }
+}
+
+impl AsCommitment for String {
+    fn as_commitment(&self, kind: Option<CommitmentKind>) -> Result<Commitment> {
+        self.as_str().as_commitment(kind)
+    }
+}
+
+/// The kinds of commitments, which are encoded with different CID codecs and multihashes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CommitmentKind {
+    /// comm_d and piece commitments (CommP).
+    Unsealed,
+    /// comm_r, including the comm_r of updated sectors.
+    Sealed,
+}
+
+const CID_VERSION: u64 = 1;
+const FIL_COMMITMENT_UNSEALED: u64 = 0xf101;
+const FIL_COMMITMENT_SEALED: u64 = 0xf102;
+const SHA2_256_TRUNC254_PADDED: u64 = 0x1012;
+const POSEIDON_BLS12_381_A2_FC1: u64 = 0xb401;
+
+/// The multibase prefix of lowercase base32, the default encoding of CIDv1 strings.
+const MULTIBASE_BASE32: char = 'b';
+const BASE32_ALPHABET: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+
+impl CommitmentKind {
+    /// Returns the multicodec and the multihash code of this kind of commitment.
+    fn codes(self) -> (u64, u64) {
+        match self {
+            CommitmentKind::Unsealed => (FIL_COMMITMENT_UNSEALED, SHA2_256_TRUNC254_PADDED),
+            CommitmentKind::Sealed => (FIL_COMMITMENT_SEALED, POSEIDON_BLS12_381_A2_FC1),
+        }
+    }
+}
+
+/// Encodes a commitment as a base32 CIDv1 string, with the codec and multihash of `kind`.
+pub fn commitment_to_cid(comm: &Commitment, kind: CommitmentKind) -> Result<String> {
+    bytes_into_fr(comm).context("Invalid commitment, it is not a valid field element")?;
+
+    let (codec, multihash) = kind.codes();
+    let mut cid = Vec::with_capacity(40);
+    for value in [CID_VERSION, codec, multihash, comm.len() as u64] {
+        write_varint(&mut cid, value);
+    }
+    cid.extend_from_slice(comm);
+
+    let mut encoded = String::with_capacity(1 + (cid.len() * 8).div_ceil(5));
+    encoded.push(MULTIBASE_BASE32);
+    encoded.push_str(&base32_encode(&cid));
+    Ok(encoded)
+}
+
+/// Decodes a base32 CIDv1 string into a commitment and its kind. The codec and multihash must be
+/// those of a Filecoin commitment, and the commitment must be a valid field element.
+pub fn cid_to_commitment(cid: &str) -> Result<(Commitment, CommitmentKind)> {
+    let encoded = cid
+        .strip_prefix(MULTIBASE_BASE32)
+        .or_else(|| cid.strip_prefix('B'))
+        .with_context(|| format!("CID {:?} is not base32 encoded", cid))?;
+    let bytes = base32_decode(&encoded.to_ascii_lowercase())
+        .with_context(|| format!("CID {:?} is not valid base32", cid))?;
+
+    let mut rest = &bytes[..];
+    let mut next = || {
+        read_varint(&mut rest)
+            .with_context(|| format!("CID {:?} has a truncated or non-minimal varint", cid))
+    };
+    let (version, codec, multihash, len) = (next()?, next()?, next()?, next()?);
+    ensure!(version == CID_VERSION, "CID {:?} is not a CIDv1", cid);
+    let kind = [CommitmentKind::Unsealed, CommitmentKind::Sealed]
+        .iter()
+        .copied()
+        .find(|kind| kind.codes() == (codec, multihash))
+        .with_context(|| {
+            format!(
+                "CID {:?} has codec {:#x} and multihash {:#x}, which is not a Filecoin commitment",
+                cid, codec, multihash
+            )
+        })?;
+    ensure!(
+        len == 32 && rest.len() == 32,
+        "CID {:?} does not contain a 32 byte commitment",
+        cid
+    );
+
+    let mut comm = [0; 32];
+    comm.copy_from_slice(rest);
+    bytes_into_fr(&comm).with_context(|| format!("CID {:?} is not a valid commitment", cid))?;
+
+    Ok((comm, kind))
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+// Reads a varint, only the minimal encoding of a value is accepted, so that a CID has a single
+// valid encoding.
+fn read_varint(bytes: &mut &[u8]) -> Option<u64> {
+    let mut value = 0u64;
+    // Multiformats varints are at most 9 bytes long.
+    for (i, byte) in bytes.iter().take(9).enumerate() {
+        value |= u64::from(byte & 0x7f) << (7 * i);
+        if byte & 0x80 == 0 {
+            // A trailing zero byte only adds leading zero bits.
+            if i > 0 && *byte == 0 {
+                return None;
+            }
+            *bytes = &bytes[i + 1..];
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// Encodes to RFC 4648 base32, lowercase and without padding.
+fn base32_encode(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity((bytes.len() * 8).div_ceil(5));
+    let (mut buffer, mut bits) = (0u16, 0);
+    for byte in bytes {
+        buffer = (buffer << 8) | u16::from(*byte);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(BASE32_ALPHABET[(buffer >> bits) as usize & 0x1f] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(BASE32_ALPHABET[(buffer << (5 - bits)) as usize & 0x1f] as char);
+    }
+    out
+}
+
+fn base32_decode(encoded: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(encoded.len() * 5 / 8);
+    let (mut buffer, mut bits) = (0u16, 0);
+    for c in encoded.bytes() {
+        let value = BASE32_ALPHABET.iter().position(|a| *a == c)? as u16;
+        buffer = (buffer << 5) | value;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+        }
+    }
+    // The left over bits must only be padding.
+    if buffer & ((1 << bits) - 1) != 0 {
+        return None;
+    }
+    Some(out)
 }
 
 pub fn commitment_from_fr(fr: Fr) -> Commitment {
@@ -57,6 +270,56 @@ pub(crate) fn proofs_to_bytes(proofs: &[Proof<Bls12>]) -> Result<Vec<u8>> {
     Ok(out)
 }
 
+/// Length of the SHA-256 checksum at the end of an encoding written by [`append_checksummed_body`].
+pub(crate) const CHECKSUM_LEN: usize = 32;
+
+/// Appends the length of `body` (8 bytes), the body itself and a SHA-256 checksum over all
+/// preceding bytes to the header in `bytes`.
+pub(crate) fn append_checksummed_body(bytes: &mut Vec<u8>, body: &[u8]) {
+    bytes.reserve(size_of::<u64>() + body.len() + CHECKSUM_LEN);
+    bytes.extend_from_slice(&(body.len() as u64).to_le_bytes());
+    bytes.extend_from_slice(body);
+    let checksum = Sha256::digest(&bytes[..]);
+    bytes.extend_from_slice(&checksum);
+}
+
+/// Returns the body of an encoding written by [`append_checksummed_body`], after checking its
+/// length and checksum. `header_len` is the length of the header including the body length, and
+/// `name` describes the encoded data in error messages.
+pub(crate) fn checksummed_body<'a>(
+    bytes: &'a [u8],
+    header_len: usize,
+    name: &str,
+) -> Result<&'a [u8]> {
+    ensure!(
+        bytes.len() >= header_len + CHECKSUM_LEN,
+        "{} is truncated ({} bytes)",
+        name,
+        bytes.len()
+    );
+    let body_len = u64::from_le_bytes(bytes[header_len - size_of::<u64>()..header_len].try_into()?);
+    let total_len = usize::try_from(body_len)
+        .ok()
+        .and_then(|body_len| header_len.checked_add(body_len))
+        .and_then(|len| len.checked_add(CHECKSUM_LEN))
+        .with_context(|| format!("{} has an invalid body length {}", name, body_len))?;
+    ensure!(
+        bytes.len() == total_len,
+        "{} is truncated or has an invalid length ({} bytes, expected {})",
+        name,
+        bytes.len(),
+        total_len
+    );
+
+    let (data, checksum) = bytes.split_at(total_len - CHECKSUM_LEN);
+    ensure!(
+        Sha256::digest(data).as_slice() == checksum,
+        "{} checksum mismatch",
+        name
+    );
+    Ok(&data[header_len..])
+}
+
 /// Persist p_aux.
 pub(crate) fn persist_p_aux<Tree: MerkleTreeTrait>(
     p_aux: &PersistentAux<<Tree::Hasher as Hasher>::Domain>,
@@ -248,15 +511,20 @@ pub fn get_sector_update_h_select_from_porep_config(porep_config: &PoRepConfig)
     SectorUpdateConfig::from_porep_config(porep_config).h
 }
 
-#[cfg(all(test, feature = "fixed-rows-to-discard"))]
+#[cfg(test)]
 mod tests {
     use super::*;
 
+    use filecoin_hashers::sha256::Sha256Domain;
+    #[cfg(feature = "fixed-rows-to-discard")]
     use storage_proofs_core::util::{self, NODE_SIZE};
 
+    use crate::{compute_comm_d, SECTOR_SIZE_2_KIB};
+    #[cfg(feature = "fixed-rows-to-discard")]
     use crate::{SectorShape32GiB, SECTOR_SIZE_32_GIB};
 
     /// Testing whether the default values are set if there's no `t_aux` file.
+    #[cfg(feature = "fixed-rows-to-discard")]
     #[test]
     fn test_get_t_aux_defaults() {
         let dir_does_not_exist = Path::new("/path/does/not/exist");
@@ -273,6 +541,7 @@ mod tests {
     }
 
     /// Testing whether the values from the `t_aux` file are used in case there is any.
+    #[cfg(feature = "fixed-rows-to-discard")]
     #[test]
     fn test_get_t_aux_file_exists() {
         let cache_dir = tempfile::tempdir()
@@ -308,4 +577,122 @@ mod tests {
             custom_t_aux.tree_r_last_config.rows_to_discard
         );
     }
+
+    #[test]
+    fn test_empty_comm_d_cid() {
+        // The well known CID of the comm_d of an empty 2KiB sector.
+        let comm_d =
+            compute_comm_d(SectorSize(SECTOR_SIZE_2_KIB), &[]).expect("failed to compute comm_d");
+        let cid =
+            commitment_to_cid(&comm_d, CommitmentKind::Unsealed).expect("failed to encode CID");
+        assert_eq!(
+            cid,
+            "baga6ea4seaqpy7usqklokfx2vxuynmupslkeutzexe2uqurdg5vhtebhxqmpqmy"
+        );
+        assert_eq!(
+            cid_to_commitment(&cid).expect("failed to decode CID"),
+            (comm_d, CommitmentKind::Unsealed)
+        );
+    }
+
+    #[test]
+    fn test_commitment_cid_roundtrip() {
+        for kind in [CommitmentKind::Unsealed, CommitmentKind::Sealed] {
+            for comm in [[0; 32], [7; 32], commitment_from_fr(Fr::from(u64::MAX))] {
+                let cid = commitment_to_cid(&comm, kind).expect("failed to encode CID");
+                assert_eq!(
+                    cid_to_commitment(&cid).expect("failed to decode CID"),
+                    (comm, kind)
+                );
+                assert_eq!(
+                    cid_to_commitment(&cid.to_uppercase()).expect("failed to decode CID"),
+                    (comm, kind)
+                );
+
+                let from_raw: Sha256Domain = as_safe_commitment_of_kind(&comm, kind, "comm")
+                    .expect("raw commitment rejected");
+                let from_cid: Sha256Domain = as_safe_commitment_of_kind(cid.as_str(), kind, "comm")
+                    .expect("CID commitment rejected");
+                assert_eq!(from_raw, from_cid);
+                let from_any_cid: Sha256Domain =
+                    as_safe_commitment(cid.as_str(), "comm").expect("CID commitment rejected");
+                assert_eq!(from_raw, from_any_cid);
+
+                let other = match kind {
+                    CommitmentKind::Unsealed => CommitmentKind::Sealed,
+                    CommitmentKind::Sealed => CommitmentKind::Unsealed,
+                };
+                assert!(
+                    as_safe_commitment_of_kind::<Sha256Domain, _>(cid.as_str(), other, "comm")
+                        .is_err()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_invalid_commitment_cids() {
+        // Not a valid field element.
+        assert!(commitment_to_cid(&[0xff; 32], CommitmentKind::Sealed).is_err());
+
+        let cid = commitment_to_cid(&[7; 32], CommitmentKind::Sealed).expect("failed to encode");
+        // Truncated, extended and not base32.
+        assert!(cid_to_commitment(&cid[..cid.len() - 2]).is_err());
+        assert!(cid_to_commitment(&format!("{}aa", cid)).is_err());
+        assert!(cid_to_commitment(&cid.replacen('b', "z", 1)).is_err());
+        assert!(cid_to_commitment(&format!("{}1", cid)).is_err());
+        assert!(as_safe_commitment_of_kind::<Sha256Domain, _>(
+            "bafy",
+            CommitmentKind::Sealed,
+            "comm_r"
+        )
+        .is_err());
+
+        // A sealed codec with the unsealed multihash.
+        let mut bytes = Vec::new();
+        for value in [
+            CID_VERSION,
+            FIL_COMMITMENT_SEALED,
+            SHA2_256_TRUNC254_PADDED,
+            32,
+        ] {
+            write_varint(&mut bytes, value);
+        }
+        bytes.extend_from_slice(&[7; 32]);
+        let mismatched = format!("b{}", base32_encode(&bytes));
+        assert!(cid_to_commitment(&mismatched).is_err());
+
+        // A valid CID, whose version is not minimally encoded.
+        let mut bytes = vec![0x81, 0x00];
+        for value in [FIL_COMMITMENT_SEALED, POSEIDON_BLS12_381_A2_FC1, 32] {
+            write_varint(&mut bytes, value);
+        }
+        bytes.extend_from_slice(&[7; 32]);
+        let non_minimal = format!("b{}", base32_encode(&bytes));
+        assert!(cid_to_commitment(&non_minimal).is_err());
+        let minimal = [&[0x01][..], &bytes[2..]].concat();
+        assert!(cid_to_commitment(&format!("b{}", base32_encode(&minimal))).is_ok());
+    }
+
+    #[test]
+    fn test_checksummed_body() {
+        let mut bytes = b"head".to_vec();
+        append_checksummed_body(&mut bytes, b"body");
+        let header_len = 4 + size_of::<u64>();
+        assert_eq!(
+            checksummed_body(&bytes, header_len, "test").expect("failed to decode"),
+            b"body"
+        );
+
+        // Truncated and corrupted.
+        assert!(checksummed_body(&bytes[..bytes.len() - 1], header_len, "test").is_err());
+        let mut corrupted = bytes.clone();
+        corrupted[header_len] ^= 1;
+        assert!(checksummed_body(&corrupted, header_len, "test").is_err());
+
+        // A body length that overflows the total length.
+        let mut overflowing = bytes;
+        overflowing[4..header_len].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(checksummed_body(&overflowing, header_len, "test").is_err());
+    }
 }
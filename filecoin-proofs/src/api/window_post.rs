@@ -1,13 +1,15 @@
 use std::collections::BTreeMap;
 
 use anyhow::{ensure, Context, Result};
-use filecoin_hashers::Hasher;
+use bellperson::groth16;
+use filecoin_hashers::{Domain, Hasher};
 use log::info;
-use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
+use rayon::prelude::{IntoParallelIterator, IntoParallelRefIterator, ParallelIterator};
 use storage_proofs_core::{
     compound_proof::{self, CompoundProof},
     merkle::MerkleTreeTrait,
     multi_proof::MultiProof,
+    proof::ProofScheme,
     sector::SectorId,
 };
 use storage_proofs_post::fallback::{
@@ -16,14 +18,19 @@ use storage_proofs_post::fallback::{
 
 use crate::{
     api::{
+        aggregate::{pad_aggregate_inputs, pad_aggregate_proofs, window_post_aggregate_transcript},
         as_safe_commitment, get_partitions_for_window_post, partition_vanilla_proofs,
         single_partition_vanilla_proofs, util,
+        util::get_aggregate_target_len,
+    },
+    caches::{
+        get_post_params, get_post_verifying_key, get_window_post_srs_key,
+        get_window_post_srs_verifier_key,
     },
-    caches::{get_post_params, get_post_verifying_key},
     parameters::window_post_setup_params,
     types::{
-        ChallengeSeed, FallbackPoStSectorProof, PoStConfig, PrivateReplicaInfo, ProverId,
-        PublicReplicaInfo, SnarkProof,
+        AggregateSnarkProof, ChallengeSeed, FallbackPoStSectorProof, PoStConfig,
+        PrivateReplicaInfo, ProverId, PublicReplicaInfo, SnarkProof,
     },
     PartitionSnarkProof, PoStType,
 };
@@ -314,3 +321,165 @@ pub fn generate_single_window_post_with_vanilla<Tree: 'static + MerkleTreeTrait>
     let proofs_bytes = util::proofs_to_bytes(&proofs)?;
     Ok(PartitionSnarkProof(proofs_bytes))
 }
+
+fn window_post_public_sectors<D: Domain>(
+    replicas: &BTreeMap<SectorId, PublicReplicaInfo>,
+) -> Result<Vec<PublicSector<D>>> {
+    replicas
+        .iter()
+        .map(|(sector_id, replica)| {
+            let comm_r = replica
+                .safe_comm_r()
+                .with_context(|| format!("safe_comm_r failed: {:?}", sector_id))?;
+            Ok(PublicSector {
+                id: *sector_id,
+                comm_r,
+            })
+        })
+        .collect()
+}
+
+/// Aggregates the partition proofs of a Window proof-of-spacetime into a single SnarkPack proof.
+///
+/// The `proof` is the output of `generate_window_post` (or of
+/// `merge_window_post_partition_proofs`) for the given `replicas`. The number of partition proofs
+/// is padded to a power of two. The aggregate proof is bound to the `randomness`, the `prover_id`
+/// and the sectors of every partition.
+pub fn aggregate_window_post_proofs<Tree: 'static + MerkleTreeTrait>(
+    post_config: &PoStConfig,
+    randomness: &ChallengeSeed,
+    prover_id: ProverId,
+    replicas: &BTreeMap<SectorId, PublicReplicaInfo>,
+    proof: &[u8],
+    aggregate_version: groth16::aggregate::AggregateVersion,
+) -> Result<AggregateSnarkProof> {
+    info!("aggregate_window_post_proofs:start");
+    ensure!(
+        post_config.typ == PoStType::Window,
+        "invalid post config type"
+    );
+    ensure!(!replicas.is_empty(), "cannot aggregate without sectors");
+
+    let partitions = get_partitions_for_window_post(replicas.len(), post_config).unwrap_or(1);
+    let verifying_key = get_post_verifying_key::<Tree>(post_config)?;
+    let mut proofs =
+        MultiProof::new_from_bytes(Some(partitions), proof, &verifying_key)?.circuit_proofs;
+
+    pad_aggregate_proofs(&mut proofs)?;
+
+    let pub_sectors = window_post_public_sectors::<<Tree::Hasher as Hasher>::Domain>(replicas)?;
+    let transcript = window_post_aggregate_transcript(
+        randomness,
+        &prover_id,
+        &pub_sectors,
+        post_config.sector_count,
+    );
+
+    let srs_prover_key = get_window_post_srs_key::<Tree>(post_config, proofs.len())?;
+    let aggregate_proof = FallbackPoStCompound::<Tree>::aggregate_proofs(
+        &srs_prover_key,
+        &transcript,
+        proofs.as_slice(),
+        aggregate_version,
+    )?;
+    let mut aggregate_proof_bytes = Vec::new();
+    aggregate_proof.write(&mut aggregate_proof_bytes)?;
+
+    info!("aggregate_window_post_proofs:finish");
+
+    Ok(aggregate_proof_bytes)
+}
+
+/// Verifies an aggregated Window proof-of-spacetime, as created by `aggregate_window_post_proofs`.
+pub fn verify_aggregate_window_post_proofs<Tree: 'static + MerkleTreeTrait>(
+    post_config: &PoStConfig,
+    randomness: &ChallengeSeed,
+    prover_id: ProverId,
+    replicas: &BTreeMap<SectorId, PublicReplicaInfo>,
+    aggregate_proof_bytes: &[u8],
+    aggregate_version: groth16::aggregate::AggregateVersion,
+) -> Result<bool> {
+    info!("verify_aggregate_window_post_proofs:start");
+    ensure!(
+        post_config.typ == PoStType::Window,
+        "invalid post config type"
+    );
+    ensure!(!replicas.is_empty(), "cannot verify without sectors");
+
+    let aggregate_proof =
+        groth16::aggregate::AggregateProof::read(std::io::Cursor::new(aggregate_proof_bytes))?;
+    let aggregated_proofs_len = aggregate_proof.tmipp.gipa.nproofs as usize;
+
+    let partitions = get_partitions_for_window_post(replicas.len(), post_config);
+    let num_partitions = partitions.unwrap_or(1);
+    ensure!(
+        aggregated_proofs_len == get_aggregate_target_len(num_partitions),
+        "aggregate proof contains {} proofs, expected {} for {} partitions",
+        aggregated_proofs_len,
+        get_aggregate_target_len(num_partitions),
+        num_partitions,
+    );
+
+    let randomness_safe = as_safe_commitment(randomness, "randomness")?;
+    let prover_id_safe = as_safe_commitment(&prover_id, "prover_id")?;
+
+    let setup_params = compound_proof::SetupParams {
+        vanilla_params: window_post_setup_params(post_config),
+        partitions,
+        priority: false,
+    };
+    let pub_params: compound_proof::PublicParams<'_, FallbackPoSt<'_, Tree>> =
+        FallbackPoStCompound::setup(&setup_params)?;
+
+    if !<FallbackPoSt<'_, Tree> as ProofScheme<'_>>::satisfies_requirements(
+        &pub_params.vanilla_params,
+        &fallback::ChallengeRequirements {
+            minimum_challenge_count: post_config.challenge_count * post_config.sector_count,
+        },
+        num_partitions,
+    ) {
+        return Ok(false);
+    }
+
+    let pub_sectors = window_post_public_sectors(replicas)?;
+    let transcript = window_post_aggregate_transcript(
+        randomness,
+        &prover_id,
+        &pub_sectors,
+        post_config.sector_count,
+    );
+
+    let pub_inputs = fallback::PublicInputs {
+        randomness: randomness_safe,
+        prover_id: prover_id_safe,
+        sectors: pub_sectors,
+        k: None,
+    };
+    let inputs = (0..num_partitions)
+        .into_par_iter()
+        .map(|k| {
+            FallbackPoStCompound::<Tree>::generate_public_inputs(
+                &pub_inputs,
+                &pub_params.vanilla_params,
+                Some(k),
+            )
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let inputs = pad_aggregate_inputs(&inputs, aggregated_proofs_len)?;
+
+    let verifying_key = get_post_verifying_key::<Tree>(post_config)?;
+    let srs_verifier_key =
+        get_window_post_srs_verifier_key::<Tree>(post_config, aggregated_proofs_len)?;
+    let result = FallbackPoStCompound::<Tree>::verify_aggregate_proofs(
+        &srs_verifier_key,
+        &verifying_key,
+        &transcript,
+        inputs.as_slice(),
+        &aggregate_proof,
+        aggregate_version,
+    )?;
+
+    info!("verify_aggregate_window_post_proofs:finish");
+
+    Ok(result)
+}
@@ -1,7 +1,7 @@
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 
-use anyhow::Result;
+use anyhow::{ensure, Result};
 use bellperson::groth16::{self, prepare_verifying_key};
 use blstrs::Bls12;
 use lazy_static::lazy_static;
@@ -15,13 +15,13 @@ use storage_proofs_porep::stacked::{StackedCompound, StackedDrg};
 use storage_proofs_post::fallback::{FallbackPoSt, FallbackPoStCircuit, FallbackPoStCompound};
 use storage_proofs_update::{
     circuit::EmptySectorUpdateCircuit, compound::EmptySectorUpdateCompound, constants::TreeRHasher,
-    EmptySectorUpdate, PublicParams,
+    poseidon, EmptySectorUpdate,
 };
 
 use crate::{
     constants::{DefaultPieceHasher, SUPPORTED_SECTOR_SIZES},
     parameters::{public_params, window_post_public_params, winning_post_public_params},
-    types::{PoRepConfig, PoStConfig, PoStType},
+    types::{PoRepConfig, PoStConfig, PoStType, SectorUpdateConfig, UpdateProofType},
 };
 
 pub type Bls12PreparedVerifyingKey = groth16::PreparedVerifyingKey<Bls12>;
@@ -68,13 +68,15 @@ impl<G> SRSCache<G> {
         let mut num_proofs_to_aggregate = PROOFS_TESTS_MIN_SNARKS;
 
         loop {
-            for sector_size in &SUPPORTED_SECTOR_SIZES {
-                let key = format!(
-                    "STACKED[{}-{}]-{}",
-                    sector_size, num_proofs_to_aggregate, identifier,
-                );
-                trace!("inserting placeholder srs key with hash key {}", key);
-                data.insert(key, OnceCell::new());
+            for proof_type in &["STACKED", "WINDOW_POST"] {
+                for sector_size in &SUPPORTED_SECTOR_SIZES {
+                    let key = format!(
+                        "{}[{}-{}]-{}",
+                        proof_type, sector_size, num_proofs_to_aggregate, identifier,
+                    );
+                    trace!("inserting placeholder srs key with hash key {}", key);
+                    data.insert(key, OnceCell::new());
+                }
             }
 
             num_proofs_to_aggregate <<= 1;
@@ -263,10 +265,13 @@ pub(crate) fn get_post_params<Tree: 'static + MerkleTreeTrait>(
 pub(crate) fn get_empty_sector_update_params<
     Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>,
 >(
-    porep_config: &PoRepConfig,
+    config: &SectorUpdateConfig,
 ) -> Result<Arc<Bls12GrothParams>> {
-    let public_params: storage_proofs_update::PublicParams =
-        PublicParams::from_sector_size(u64::from(porep_config.sector_size));
+    ensure!(
+        config.update_proof_type == UpdateProofType::Sha256,
+        "sector update config is not for the SHA256 variant"
+    );
+    let public_params = config.public_params();
 
     let parameters_generator = || {
         <EmptySectorUpdateCompound<Tree> as CompoundProof<
@@ -277,10 +282,32 @@ pub(crate) fn get_empty_sector_update_params<
     };
 
     lookup_groth_params(
-        format!(
-            "SECTOR-UPDATE[{}]",
-            usize::from(porep_config.padded_bytes_amount())
-        ),
+        format!("SECTOR-UPDATE[{}]", u64::from(config.sector_size)),
+        parameters_generator,
+    )
+}
+
+pub(crate) fn get_empty_sector_update_poseidon_params<
+    Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>,
+>(
+    config: &SectorUpdateConfig,
+) -> Result<Arc<Bls12GrothParams>> {
+    ensure!(
+        config.update_proof_type == UpdateProofType::Poseidon,
+        "sector update config is not for the Poseidon variant"
+    );
+    let public_params = config.public_params();
+
+    let parameters_generator = || {
+        <poseidon::EmptySectorUpdateCompound<Tree> as CompoundProof<
+            poseidon::EmptySectorUpdate<Tree>,
+            poseidon::EmptySectorUpdateCircuit<Tree>,
+        >>::groth_params::<OsRng>(None, &public_params)
+        .map_err(Into::into)
+    };
+
+    lookup_groth_params(
+        format!("SECTOR-UPDATE-POSEIDON[{}]", u64::from(config.sector_size)),
         parameters_generator,
     )
 }
@@ -412,13 +439,72 @@ pub fn get_stacked_srs_verifier_key<Tree: 'static + MerkleTreeTrait>(
     )
 }
 
+pub(crate) fn get_window_post_srs_key<Tree: 'static + MerkleTreeTrait>(
+    post_config: &PoStConfig,
+    num_proofs_to_aggregate: usize,
+) -> Result<Arc<Bls12ProverSRSKey>> {
+    let post_public_params = window_post_public_params::<Tree>(post_config)?;
+
+    let srs_generator = || {
+        trace!(
+            "get_window_post_srs_key specializing WINDOW_POST[{}-{}]",
+            usize::from(post_config.padded_sector_size()),
+            num_proofs_to_aggregate,
+        );
+        <FallbackPoStCompound<Tree> as CompoundProof<
+            FallbackPoSt<'_, Tree>,
+            FallbackPoStCircuit<Tree>,
+        >>::srs_key::<OsRng>(None, &post_public_params, num_proofs_to_aggregate)
+    };
+
+    lookup_srs_key(
+        format!(
+            "WINDOW_POST[{}-{}]",
+            usize::from(post_config.padded_sector_size()),
+            num_proofs_to_aggregate,
+        ),
+        srs_generator,
+    )
+}
+
+pub(crate) fn get_window_post_srs_verifier_key<Tree: 'static + MerkleTreeTrait>(
+    post_config: &PoStConfig,
+    num_proofs_to_aggregate: usize,
+) -> Result<Arc<Bls12VerifierSRSKey>> {
+    let post_public_params = window_post_public_params::<Tree>(post_config)?;
+
+    let srs_verifier_generator = || {
+        trace!(
+            "get_window_post_srs_verifier_key specializing WINDOW_POST[{}-{}]",
+            usize::from(post_config.padded_sector_size()),
+            num_proofs_to_aggregate,
+        );
+        <FallbackPoStCompound<Tree> as CompoundProof<
+            FallbackPoSt<'_, Tree>,
+            FallbackPoStCircuit<Tree>,
+        >>::srs_verifier_key::<OsRng>(None, &post_public_params, num_proofs_to_aggregate)
+    };
+
+    lookup_srs_verifier_key(
+        format!(
+            "WINDOW_POST[{}-{}]",
+            usize::from(post_config.padded_sector_size()),
+            num_proofs_to_aggregate,
+        ),
+        srs_verifier_generator,
+    )
+}
+
 pub(crate) fn get_empty_sector_update_verifying_key<
     Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>,
 >(
-    porep_config: &PoRepConfig,
+    config: &SectorUpdateConfig,
 ) -> Result<Arc<Bls12PreparedVerifyingKey>> {
-    let public_params: storage_proofs_update::PublicParams =
-        PublicParams::from_sector_size(u64::from(porep_config.sector_size));
+    ensure!(
+        config.update_proof_type == UpdateProofType::Sha256,
+        "sector update config is not for the SHA256 variant"
+    );
+    let public_params = config.public_params();
 
     let vk_generator = || {
         let vk = <EmptySectorUpdateCompound<Tree> as CompoundProof<
@@ -429,10 +515,32 @@ pub(crate) fn get_empty_sector_update_verifying_key<
     };
 
     lookup_verifying_key(
-        format!(
-            "SECTOR-UPDATE[{}]",
-            usize::from(porep_config.padded_bytes_amount())
-        ),
+        format!("SECTOR-UPDATE[{}]", u64::from(config.sector_size)),
+        vk_generator,
+    )
+}
+
+pub(crate) fn get_empty_sector_update_poseidon_verifying_key<
+    Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>,
+>(
+    config: &SectorUpdateConfig,
+) -> Result<Arc<Bls12PreparedVerifyingKey>> {
+    ensure!(
+        config.update_proof_type == UpdateProofType::Poseidon,
+        "sector update config is not for the Poseidon variant"
+    );
+    let public_params = config.public_params();
+
+    let vk_generator = || {
+        let vk = <poseidon::EmptySectorUpdateCompound<Tree> as CompoundProof<
+            poseidon::EmptySectorUpdate<Tree>,
+            poseidon::EmptySectorUpdateCircuit<Tree>,
+        >>::verifying_key::<OsRng>(None, &public_params)?;
+        Ok(prepare_verifying_key(&vk))
+    };
+
+    lookup_verifying_key(
+        format!("SECTOR-UPDATE-POSEIDON[{}]", u64::from(config.sector_size)),
         vk_generator,
     )
 }
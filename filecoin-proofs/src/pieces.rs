@@ -2,6 +2,7 @@ use std::cmp::min;
 use std::collections::HashMap;
 use std::io::{self, Cursor, Read};
 use std::iter::Iterator;
+use std::ops::Range;
 use std::sync::Mutex;
 
 use anyhow::{ensure, Context, Result};
@@ -228,6 +229,56 @@ pub fn zero_padding(size: UnpaddedBytesAmount) -> Result<PieceInfo> {
     PieceInfo::new(commitment, size)
 }
 
+/// Returns the ranges of unpadded bytes of a sector that are known to be zero from the layout of
+/// its pieces, i.e. the alignment between the pieces, the padding after the last piece and the
+/// pieces that are all zeros, as created by `zero_padding`. A sector without pieces is all zeros.
+///
+/// The ranges are sorted and adjacent ranges are merged. The pieces are not checked against the
+/// sector's comm_d, use `verify_pieces` for that.
+pub fn zero_ranges(sector_size: SectorSize, piece_infos: &[PieceInfo]) -> Result<Vec<Range<u64>>> {
+    let mut ranges: Vec<Range<u64>> = Vec::new();
+    let mut add_range = |range: Range<u64>| {
+        if range.is_empty() {
+            return;
+        }
+        match ranges.last_mut() {
+            Some(last) if last.end == range.start => last.end = range.end,
+            _ => ranges.push(range),
+        }
+    };
+
+    let mut written = UnpaddedBytesAmount(0);
+    for piece_info in piece_infos {
+        let alignment = get_piece_alignment(written, piece_info.size);
+        let start = u64::from(written + alignment.left_bytes);
+        let end = start + u64::from(piece_info.size);
+
+        add_range(u64::from(written)..start);
+        if piece_info.commitment == zero_padding(piece_info.size)?.commitment {
+            add_range(start..end);
+        }
+        add_range(end..end + u64::from(alignment.right_bytes));
+
+        written = written + alignment.sum(piece_info.size);
+    }
+
+    let sector_bytes = u64::from(UnpaddedBytesAmount::from(sector_size));
+    ensure!(
+        u64::from(written) <= sector_bytes,
+        "pieces are larger than the sector"
+    );
+    add_range(u64::from(written)..sector_bytes);
+
+    Ok(ranges)
+}
+
+/// Returns true if `range` is contained in one of the `zero_ranges` returned by `zero_ranges`.
+pub fn is_zero_range(zero_ranges: &[Range<u64>], range: &Range<u64>) -> bool {
+    zero_ranges
+        .iter()
+        .any(|zero_range| zero_range.start <= range.start && range.end <= zero_range.end)
+}
+
 /// Join two equally sized `PieceInfo`s together, by hashing them and adding their sizes.
 fn join_piece_infos(mut left: PieceInfo, right: PieceInfo) -> Result<PieceInfo> {
     ensure!(
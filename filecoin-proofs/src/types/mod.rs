@@ -1,8 +1,11 @@
 pub use merkletree::store::StoreConfig;
+pub use storage_proofs_core::compound_proof::CircuitCheck;
 pub use storage_proofs_core::merkle::{MerkleProof, MerkleTreeTrait};
 pub use storage_proofs_porep::stacked::{Labels, PersistentAux, TemporaryAux};
 pub use storage_proofs_update::constants::TreeRHasher;
 
+use std::path::PathBuf;
+
 use filecoin_hashers::Hasher;
 use serde::{Deserialize, Serialize};
 use storage_proofs_core::{merkle::BinaryMerkleTree, sector::SectorId};
@@ -20,6 +23,7 @@ mod post_proof_partitions;
 mod private_replica_info;
 mod public_replica_info;
 mod sector_class;
+mod sector_key_source;
 mod sector_size;
 mod sector_update_config;
 mod update_proof_partitions;
@@ -33,6 +37,7 @@ pub use post_proof_partitions::*;
 pub use private_replica_info::*;
 pub use public_replica_info::*;
 pub use sector_class::*;
+pub use sector_key_source::*;
 pub use sector_size::*;
 pub use sector_update_config::*;
 pub use update_proof_partitions::*;
@@ -76,6 +81,103 @@ pub struct SealCommitOutput {
     pub proof: Vec<u8>,
 }
 
+/// The public data of one sector's seal proof, as used by
+/// `verify_aggregate_seal_commit_proofs_with_records`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SealProofRecord {
+    pub comm_r: Commitment,
+    pub comm_d: Commitment,
+    pub prover_id: ProverId,
+    pub sector_id: SectorId,
+    pub ticket: Ticket,
+    /// The seed used to derive the PoRep challenges, ignored for NI-PoRep.
+    pub seed: Ticket,
+}
+
+/// The public data of one sector's NI-PoRep proof, as used by `verify_ni_porep`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NiSealProofRecord {
+    pub comm_r: Commitment,
+    pub comm_d: Commitment,
+    pub prover_id: ProverId,
+    pub sector_id: SectorId,
+    pub ticket: Ticket,
+}
+
+/// The result of `check_seal_commit_phase1_output`.
+#[derive(Clone, Debug)]
+pub struct SealCircuitCheck {
+    /// Whether the vanilla proofs verify against the public inputs.
+    pub vanilla_proofs_valid: bool,
+    /// The circuit check of every partition.
+    pub partitions: Vec<CircuitCheck>,
+}
+
+impl SealCircuitCheck {
+    /// Returns true if commit phase 2 would produce a valid proof.
+    pub fn is_ok(&self) -> bool {
+        self.vanilla_proofs_valid && self.partitions.iter().all(CircuitCheck::is_ok)
+    }
+}
+
+/// A summary of a sector's synthetic proofs file, as returned by `inspect_synth_proofs`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SynthProofsInfo {
+    pub path: PathBuf,
+    /// Whether the file only contains the proofs selected by `select_synth_proofs`.
+    pub selected: bool,
+    /// The size of the file in bytes.
+    pub file_size: u64,
+    /// The number of challenge proofs in the file.
+    pub num_proofs: usize,
+    pub comm_d: Commitment,
+    pub comm_c: Commitment,
+    pub comm_r_last: Commitment,
+}
+
+/// The roots of a tree_r_last, as built by `rebuild_tree_r_last`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TreeRLastRoots {
+    /// The root of the whole tree.
+    pub comm_r_last: Commitment,
+    /// The roots of the base trees the tree is split into, in replica order.
+    pub base_tree_roots: Vec<Commitment>,
+}
+
+/// The result of `verify_tree_r_last`.
+#[derive(Clone, Debug)]
+pub struct TreeRLastCheck {
+    /// The `comm_r_last` recorded in p_aux.
+    pub expected_comm_r_last: Commitment,
+    /// The root of the cached tree, `None` if any of its files could not be read.
+    pub cached_comm_r_last: Option<Commitment>,
+    /// The roots of the cached base trees, `None` for the ones that could not be read.
+    pub cached_base_tree_roots: Vec<Option<Commitment>>,
+    /// The roots of the tree rebuilt from the replica.
+    pub rebuilt: TreeRLastRoots,
+}
+
+impl TreeRLastCheck {
+    /// Returns the indices of the cached base trees which are missing or differ from the rebuilt
+    /// ones.
+    pub fn mismatched_base_trees(&self) -> Vec<usize> {
+        self.cached_base_tree_roots
+            .iter()
+            .zip(&self.rebuilt.base_tree_roots)
+            .enumerate()
+            .filter(|(_, (cached, rebuilt))| cached.as_ref() != Some(rebuilt))
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// Returns true if the cached tree matches both the replica and p_aux.
+    pub fn is_ok(&self) -> bool {
+        self.rebuilt.comm_r_last == self.expected_comm_r_last
+            && self.cached_comm_r_last == Some(self.expected_comm_r_last)
+            && self.mismatched_base_trees().is_empty()
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SealPreCommitPhase1Output<Tree: MerkleTreeTrait> {
     #[serde(bound(
@@ -96,6 +198,17 @@ pub type AggregateSnarkProof = Vec<u8>;
 pub type VanillaProof<Tree> = fallback::Proof<<Tree as MerkleTreeTrait>::Proof>;
 pub type PartitionProof<Tree> = storage_proofs_update::vanilla::PartitionProof<Tree>;
 
+/// The vanilla partition proofs of an empty sector update together with the public inputs they
+/// were generated for, as returned by `decode_update_partition_proofs`.
+#[derive(Clone)]
+pub struct UpdatePartitionProofs<Tree: MerkleTreeTrait<Hasher = TreeRHasher>> {
+    pub comm_r_old: Commitment,
+    pub comm_r_new: Commitment,
+    pub comm_d_new: Commitment,
+    pub h: usize,
+    pub partition_proofs: Vec<PartitionProof<Tree>>,
+}
+
 #[derive(Debug, Clone, PartialEq)]
 #[repr(transparent)]
 pub struct EmptySectorUpdateProof(pub Vec<u8>);
@@ -108,6 +221,34 @@ pub struct SectorUpdateProofInputs {
     pub comm_d_new: Commitment,
 }
 
+/// The result of `audit_sector_update`.
+#[derive(Clone, Debug)]
+pub struct SectorUpdateAudit {
+    /// The public inputs the update was audited against.
+    pub expected: SectorUpdateProofInputs,
+    /// The comm_r_old computed from comm_c and the sector key.
+    pub computed_comm_r_old: Commitment,
+    /// The comm_r_new computed from comm_c and the replica.
+    pub computed_comm_r_new: Commitment,
+    /// The comm_d_new computed from the new data.
+    pub computed_comm_d_new: Commitment,
+    /// The indices of the nodes whose encoding was checked.
+    pub sampled_nodes: Vec<u64>,
+    /// The sampled nodes of the replica which are not the new data encoded with the sector key.
+    pub mismatched_nodes: Vec<u64>,
+}
+
+impl SectorUpdateAudit {
+    /// Returns true if all computed commitments match the expected ones and all sampled nodes are
+    /// encoded correctly.
+    pub fn is_ok(&self) -> bool {
+        self.computed_comm_r_old == self.expected.comm_r_old
+            && self.computed_comm_r_new == self.expected.comm_r_new
+            && self.computed_comm_d_new == self.expected.comm_d_new
+            && self.mismatched_nodes.is_empty()
+    }
+}
+
 // This FallbackPoStSectorProof is used during Fallback PoSt, but
 // contains only Vanilla proof information and is not a full Fallback
 // PoSt proof.
@@ -0,0 +1,27 @@
+use std::path::Path;
+
+use storage_proofs_core::sector::SectorId;
+
+use crate::types::{Commitment, PoRepConfig, ProverId, Ticket};
+
+/// Where the sector key of an updated sector is read from.
+#[derive(Clone, Copy, Debug)]
+pub enum SectorKeySource<'a> {
+    /// The sector key file, i.e. the replica of the original committed capacity sector.
+    File(&'a Path),
+    /// The sector key file is gone, it's regenerated from the inputs of the original seal. This
+    /// requires running all SDR layers over the whole sector, whose labels are temporarily stored
+    /// in `cache_path`.
+    ///
+    /// Only committed capacity sectors are supported, as the sector key of a sector with data
+    /// can't be regenerated without that data. `comm_d` is the commitment to the data of the
+    /// original sector, a `comm_d` of a sector with data is rejected.
+    Regenerate {
+        porep_config: &'a PoRepConfig,
+        cache_path: &'a Path,
+        prover_id: ProverId,
+        sector_id: SectorId,
+        ticket: Ticket,
+        comm_d: Commitment,
+    },
+}
@@ -1,14 +1,31 @@
+use anyhow::{ensure, Result};
 use storage_proofs_core::util::NODE_SIZE;
-use storage_proofs_update::constants::{h_default, partition_count};
+use storage_proofs_update::{
+    constants::{h_default, hs, partition_count},
+    PublicParams,
+};
 
 use crate::types::{PoRepConfig, SectorSize, UpdateProofPartitions};
 
+/// The variant of the empty sector update proof.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum UpdateProofType {
+    /// TreeDNew is a SHA256 binary tree, i.e. CommDNew is the regular piece commitment of the
+    /// sector. The proof consists of several partitions.
+    #[default]
+    Sha256,
+    /// TreeDNew is a Poseidon tree of the same shape as TreeR. The proof is a single partition,
+    /// whose challenges are derived from CommRNew with Poseidon.
+    Poseidon,
+}
+
 #[derive(Clone, Copy, Debug)]
 pub struct SectorUpdateConfig {
     pub sector_size: SectorSize,
     pub nodes_count: usize,
     pub update_partitions: UpdateProofPartitions,
     pub h: usize,
+    pub update_proof_type: UpdateProofType,
 }
 
 impl SectorUpdateConfig {
@@ -20,6 +37,85 @@ impl SectorUpdateConfig {
             nodes_count,
             update_partitions: UpdateProofPartitions::from(partition_count(nodes_count)),
             h: h_default(nodes_count),
+            update_proof_type: UpdateProofType::Sha256,
+        }
+    }
+
+    /// Returns the config of the Poseidon variant of the empty sector update proof.
+    pub fn from_porep_config_poseidon(porep_config: &PoRepConfig) -> Self {
+        SectorUpdateConfig {
+            update_partitions: UpdateProofPartitions::from(1),
+            update_proof_type: UpdateProofType::Poseidon,
+            ..Self::from_porep_config(porep_config)
         }
     }
+
+    /// Sets the number of high bits `h` taken from each node index when computing rho. It must be
+    /// one of the values allowed for the sector size, see [`hs`]. A smaller `h` means fewer rho
+    /// values need to be computed, but decoding a range needs more of them.
+    pub fn set_h(&mut self, h: usize) -> Result<()> {
+        let allowed = hs(self.nodes_count);
+        ensure!(
+            allowed.contains(&h),
+            "h {} is not allowed for sector size {}, expected one of {:?}",
+            h,
+            u64::from(self.sector_size),
+            allowed,
+        );
+        self.h = h;
+        Ok(())
+    }
+
+    /// Returns an error if the configured `h` is not allowed for the sector size.
+    pub fn ensure_valid_h(&self) -> Result<()> {
+        ensure!(
+            hs(self.nodes_count).contains(&self.h),
+            "h {} is not allowed for sector size {}",
+            self.h,
+            u64::from(self.sector_size),
+        );
+        Ok(())
+    }
+
+    /// Returns the public parameters of the configured proof variant.
+    pub fn public_params(&self) -> PublicParams {
+        match self.update_proof_type {
+            UpdateProofType::Sha256 => PublicParams::from_sector_size(u64::from(self.sector_size)),
+            UpdateProofType::Poseidon => {
+                PublicParams::from_sector_size_poseidon(u64::from(self.sector_size))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use storage_proofs_core::api_version::ApiVersion;
+
+    use crate::constants::{SECTOR_SIZE_2_KIB, SECTOR_SIZE_32_GIB};
+
+    #[test]
+    fn test_set_h() {
+        let porep_config =
+            PoRepConfig::new_groth16(SECTOR_SIZE_32_GIB, [0; 32], ApiVersion::V1_2_0);
+        let mut config = SectorUpdateConfig::from_porep_config(&porep_config);
+        assert_eq!(config.h, 10);
+
+        config.set_h(7).expect("h is allowed");
+        assert_eq!(config.h, 7);
+        config.ensure_valid_h().expect("h is allowed");
+
+        assert!(config.set_h(13).is_err());
+        assert_eq!(config.h, 7);
+
+        let porep_config = PoRepConfig::new_groth16(SECTOR_SIZE_2_KIB, [0; 32], ApiVersion::V1_2_0);
+        let mut config = SectorUpdateConfig::from_porep_config_poseidon(&porep_config);
+        assert!(config.set_h(10).is_err());
+
+        // The field can be set directly, hence the API functions check it.
+        config.h = 10;
+        assert!(config.ensure_valid_h().is_err());
+    }
 }
@@ -1,37 +1,53 @@
 use std::collections::BTreeMap;
-use std::fs::{metadata, read_dir, remove_file, File, OpenOptions};
+use std::fs::{copy, metadata, read, read_dir, remove_file, File, OpenOptions};
 use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::iter;
 use std::path::{Path, PathBuf};
 
 use anyhow::{ensure, Context, Error, Result};
 use bellperson::groth16;
-use bincode::serialize;
+use bincode::{deserialize, serialize};
 use blstrs::{Bls12, Scalar as Fr};
 use ff::Field;
 use filecoin_hashers::Hasher;
 use filecoin_proofs::{
-    add_piece, aggregate_empty_sector_update_proofs, aggregate_seal_commit_proofs, clear_cache,
-    clear_synthetic_proofs, compute_comm_d, decode_from, decode_from_range, encode_into,
-    fauxrep_aux, generate_empty_sector_update_proof,
-    generate_empty_sector_update_proof_with_vanilla, generate_fallback_sector_challenges,
-    generate_partition_proofs, generate_piece_commitment, generate_single_partition_proof,
+    add_piece, aggregate_empty_sector_update_proofs, aggregate_ni_porep,
+    aggregate_seal_commit_proofs, aggregate_window_post_proofs, audit_sector_update,
+    audit_sector_update_with_cache, check_seal_commit_phase1_output, clear_cache,
+    clear_synthetic_proofs, compute_comm_d, decode_fallback_post_sector_proof, decode_from,
+    decode_from_range_with_config, decode_seal_commit_phase1_output,
+    decode_update_partition_proofs, encode_fallback_post_sector_proof, encode_into,
+    encode_into_from_pieces, encode_seal_commit_phase1_output, encode_update_partition_proofs,
+    fauxrep_aux, generate_empty_sector_update_poseidon_proof,
+    generate_empty_sector_update_poseidon_proof_with_vanilla, generate_empty_sector_update_proof,
+    generate_empty_sector_update_proof_in_batches, generate_empty_sector_update_proof_with_config,
+    generate_empty_sector_update_proof_with_vanilla_and_config,
+    generate_fallback_sector_challenges, generate_partition_proofs, generate_piece_commitment,
+    generate_poseidon_partition_proof, generate_single_partition_proof,
     generate_single_vanilla_proof, generate_single_window_post_with_vanilla, generate_synth_proofs,
     generate_tree_c, generate_tree_r_last, generate_window_post, generate_window_post_with_vanilla,
     generate_winning_post, generate_winning_post_sector_challenge,
     generate_winning_post_with_vanilla, get_num_partition_for_fallback_post, get_seal_inputs,
     get_sector_update_h_select_from_porep_config, get_sector_update_inputs,
-    merge_window_post_partition_proofs, remove_encoded_data, seal_commit_phase1,
-    seal_commit_phase2, seal_commit_phase2_circuit_proofs, seal_pre_commit_phase1,
-    seal_pre_commit_phase2, unseal_range, validate_cache_for_commit,
-    validate_cache_for_precommit_phase2, verify_aggregate_seal_commit_proofs,
-    verify_aggregate_sector_update_proofs, verify_empty_sector_update_proof,
-    verify_partition_proofs, verify_seal, verify_single_partition_proof, verify_window_post,
-    verify_winning_post, Commitment, DefaultTreeDomain, EmptySectorUpdateProof, MerkleTreeTrait,
-    PaddedBytesAmount, PieceInfo, PoRepConfig, PoStConfig, PoStType, PrivateReplicaInfo, ProverId,
-    PublicReplicaInfo, SealCommitOutput, SealPreCommitOutput, SealPreCommitPhase1Output,
+    get_unsealed_range_from_updated_sector, get_unsealed_range_from_updated_sector_with_pieces,
+    get_unsealed_range_with_pieces, inspect_synth_proofs, merge_window_post_partition_proofs,
+    rebuild_tree_r_last, reencode_into, regenerate_sector_key, remove_encoded_data,
+    seal_commit_phase1, seal_commit_phase2, seal_commit_phase2_circuit_proofs, seal_ni_porep,
+    seal_pre_commit_phase1, seal_pre_commit_phase2, select_synth_proofs, unseal_range,
+    validate_cache_for_commit, validate_cache_for_precommit_phase2, validate_synth_proofs,
+    verify_aggregate_seal_commit_proofs, verify_aggregate_seal_commit_proofs_with_records,
+    verify_aggregate_sector_update_proofs, verify_aggregate_window_post_proofs,
+    verify_empty_sector_update_poseidon_proof, verify_empty_sector_update_proof,
+    verify_empty_sector_update_proof_with_config, verify_ni_porep, verify_partition_proofs,
+    verify_poseidon_partition_proof, verify_seal, verify_single_partition_proof,
+    verify_tree_r_last, verify_window_post, verify_winning_post, AggregationBuilder, Commitment,
+    DefaultTreeDomain, EmptySectorUpdateProof, MerkleTreeTrait, NiSealProofRecord,
+    PaddedBytesAmount, PersistentAux, PieceInfo, PoRepConfig, PoStConfig, PoStType,
+    PrivateReplicaInfo, ProverId, PublicReplicaInfo, SealCommitOutput, SealCommitPhase1Output,
+    SealPreCommitOutput, SealPreCommitPhase1Output, SealProofRecord, SectorKeySource,
     SectorShape16KiB, SectorShape2KiB, SectorShape32GiB, SectorShape32KiB, SectorShape4KiB,
-    SectorUpdateConfig, SectorUpdateProofInputs, UnpaddedByteIndex, UnpaddedBytesAmount,
-    SECTOR_SIZE_16_KIB, SECTOR_SIZE_2_KIB, SECTOR_SIZE_32_GIB, SECTOR_SIZE_32_KIB,
+    SectorUpdateConfig, SectorUpdateProofInputs, Ticket, UnpaddedByteIndex, UnpaddedBytesAmount,
+    UpdateProofType, SECTOR_SIZE_16_KIB, SECTOR_SIZE_2_KIB, SECTOR_SIZE_32_GIB, SECTOR_SIZE_32_KIB,
     SECTOR_SIZE_4_KIB, WINDOW_POST_CHALLENGE_COUNT, WINDOW_POST_SECTOR_COUNT,
     WINNING_POST_CHALLENGE_COUNT, WINNING_POST_SECTOR_COUNT,
 };
@@ -50,6 +66,7 @@ use storage_proofs_core::{
     sector::SectorId,
     util::NODE_SIZE,
 };
+use storage_proofs_porep::stacked::ColumnProof;
 use storage_proofs_update::constants::TreeRHasher;
 use tempfile::{tempdir, NamedTempFile, TempDir};
 
@@ -58,6 +75,9 @@ use filecoin_proofs::constants::{
     MAX_LEGACY_REGISTERED_SEAL_PROOF_ID,
 };
 
+#[cfg(feature = "big-tests")]
+use storage_proofs_update::constants::hs;
+
 #[cfg(feature = "big-tests")]
 use filecoin_proofs::{
     SectorShape512MiB, SectorShape64GiB, SectorShape8MiB, SECTOR_SIZE_512_MIB, SECTOR_SIZE_64_GIB,
@@ -240,6 +260,26 @@ fn test_seal_lifecycle_upgrade_2kib_base_8() -> Result<()> {
     Ok(())
 }
 
+#[test]
+#[ignore]
+fn test_seal_lifecycle_upgrade_poseidon_2kib_base_8() -> Result<()> {
+    let porep_id =
+        to_porep_id_verified(MAX_LEGACY_REGISTERED_SEAL_PROOF_ID + 1, ApiVersion::V1_2_0);
+    let porep_config = porep_config(SECTOR_SIZE_2_KIB, porep_id, ApiVersion::V1_2_0);
+
+    seal_lifecycle_upgrade_poseidon::<SectorShape2KiB>(&porep_config)
+}
+
+#[test]
+#[ignore]
+fn test_seal_lifecycle_upgrade_poseidon_4kib_sub_8_2() -> Result<()> {
+    let porep_id =
+        to_porep_id_verified(MAX_LEGACY_REGISTERED_SEAL_PROOF_ID + 1, ApiVersion::V1_2_0);
+    let porep_config = porep_config(SECTOR_SIZE_4_KIB, porep_id, ApiVersion::V1_2_0);
+
+    seal_lifecycle_upgrade_poseidon::<SectorShape4KiB>(&porep_config)
+}
+
 #[test]
 #[ignore]
 fn test_seal_lifecycle_4kib_base_8() -> Result<()> {
@@ -484,6 +524,27 @@ fn test_seal_lifecycle_upgrade_512mib_top_8_0_0_v1_1() -> Result<()> {
     seal_lifecycle_upgrade::<SectorShape512MiB>(&porep_config)
 }
 
+#[cfg(feature = "big-tests")]
+#[test]
+fn test_seal_lifecycle_upgrade_8mib_non_default_h() -> Result<()> {
+    let porep_config = PoRepConfig::new_groth16(
+        SECTOR_SIZE_8_MIB,
+        ARBITRARY_POREP_ID_V1_2_0,
+        ApiVersion::V1_2_0,
+    );
+    // Smaller sectors only allow a single `h`, hence this can't be tested with them.
+    let mut config = SectorUpdateConfig::from_porep_config(&porep_config);
+    let h = hs(config.nodes_count)[0];
+    assert_ne!(h, config.h);
+    config.set_h(h)?;
+
+    seal_lifecycle_upgrade_with::<SectorShape8MiB>(
+        &porep_config,
+        config,
+        prove_and_verify_update::<SectorShape8MiB>,
+    )
+}
+
 #[cfg(feature = "big-tests")]
 #[test]
 fn test_seal_lifecycle_32gib_porep_id_v1_top_8_8_0_api_v1() -> Result<()> {
@@ -678,93 +739,206 @@ fn seal_lifecycle<Tree: 'static + MerkleTreeTrait>(porep_config: &PoRepConfig) -
     Ok(())
 }
 
-fn seal_lifecycle_upgrade<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>>(
-    porep_config: &PoRepConfig,
-) -> Result<()> {
-    let mut rng = &mut XorShiftRng::from_seed(TEST_SEED);
-    let prover_fr: DefaultTreeDomain = Fr::random(&mut rng).into();
-    let mut prover_id = [0u8; 32];
-    prover_id.copy_from_slice(AsRef::<[u8]>::as_ref(&prover_fr));
-
-    info!(
-        "Creating seal proof for upgrade with ApiVersion {}",
-        porep_config.api_version
+#[test]
+#[ignore]
+fn test_check_seal_commit_phase1_output_2kib() -> Result<()> {
+    let porep_config = PoRepConfig::new_groth16(
+        SECTOR_SIZE_2_KIB,
+        ARBITRARY_POREP_ID_V1_1_0,
+        ApiVersion::V1_1_0,
     );
-    let (_, replica, _, _) = create_seal_for_upgrade::<_, Tree>(porep_config, &mut rng, prover_id)?;
-    replica.close()?;
+
+    let mut rng = XorShiftRng::from_seed(TEST_SEED);
+    let mut phase1_output =
+        create_seal_commit_phase1_output::<_, SectorShape2KiB>(&porep_config, &mut rng)?;
+
+    let check = check_seal_commit_phase1_output(&porep_config, &phase1_output)?;
+    assert!(check.is_ok(), "{:?}", check);
+    assert_eq!(check.partitions.len(), usize::from(porep_config.partitions));
+
+    // The vanilla proofs were generated for different challenges.
+    phase1_output.seed = rng.gen();
+    let check = check_seal_commit_phase1_output(&porep_config, &phase1_output)?;
+    assert!(!check.vanilla_proofs_valid);
+    assert!(!check.is_ok());
 
     Ok(())
 }
 
 #[test]
 #[ignore]
-fn test_seal_proof_aggregation_2kib() -> Result<()> {
-    let test_inputs = vec![
-        (
-            1,
-            MAX_LEGACY_REGISTERED_SEAL_PROOF_ID + 1,
-            ApiVersion::V1_1_0,
-            vec![],
-        ),
-        (
-            5,
-            MAX_LEGACY_REGISTERED_SEAL_PROOF_ID + 1,
-            ApiVersion::V1_2_0,
-            vec![ApiFeature::SyntheticPoRep],
-        ),
-        (
-            FIP92_MAX_NI_POREP_AGGREGATION_PROOFS,
-            MAX_LEGACY_REGISTERED_SEAL_PROOF_ID + 1,
-            ApiVersion::V1_2_0,
-            vec![ApiFeature::NonInteractivePoRep],
-        ),
-    ];
+fn test_seal_commit_phase1_output_encoding_2kib() -> Result<()> {
+    let porep_config = PoRepConfig::new_groth16(
+        SECTOR_SIZE_2_KIB,
+        ARBITRARY_POREP_ID_V1_1_0,
+        ApiVersion::V1_1_0,
+    );
 
-    for (proofs_to_aggregate, porep_id_num, api_version, api_features) in test_inputs {
-        let porep_id = to_porep_id_verified(porep_id_num, api_version);
-        let porep_config = PoRepConfig::new_groth16_with_features(
-            SECTOR_SIZE_2_KIB,
-            porep_id,
-            api_version,
-            api_features,
-        )?;
+    let mut rng = XorShiftRng::from_seed(TEST_SEED);
+    let phase1_output =
+        create_seal_commit_phase1_output::<_, SectorShape2KiB>(&porep_config, &mut rng)?;
+    let serialized = serialize(&phase1_output)?;
+
+    let encoded = encode_seal_commit_phase1_output(&porep_config, &phase1_output, false)?;
+    let compact = encode_seal_commit_phase1_output(&porep_config, &phase1_output, true)?;
+    assert!(compact.len() < encoded.len());
+
+    for bytes in [&encoded, &compact] {
+        let decoded = decode_seal_commit_phase1_output::<SectorShape2KiB>(&porep_config, bytes)?;
+        assert_eq!(serialize(&decoded)?, serialized);
+
+        // Any corruption is detected by the checksum.
+        let mut corrupted = bytes.clone();
+        let last = corrupted.len() - 40;
+        corrupted[last] ^= 1;
+        assert!(
+            decode_seal_commit_phase1_output::<SectorShape2KiB>(&porep_config, &corrupted).is_err()
+        );
+    }
 
-        aggregate_seal_proofs::<SectorShape2KiB>(&porep_config, proofs_to_aggregate)?;
+    // A different sector size is rejected.
+    let porep_config_4kib = PoRepConfig::new_groth16(
+        SECTOR_SIZE_4_KIB,
+        ARBITRARY_POREP_ID_V1_1_0,
+        ApiVersion::V1_1_0,
+    );
+    assert!(
+        decode_seal_commit_phase1_output::<SectorShape4KiB>(&porep_config_4kib, &compact).is_err()
+    );
+
+    // A truncated parent column is rejected instead of panicking on expansion, even with a
+    // valid checksum. The column is truncated wherever it is opened, as the compact encoding
+    // stores it only once.
+    let mut truncated_output: SealCommitPhase1Output<SectorShape2KiB> = deserialize(&serialized)?;
+    let truncated = truncated_output.vanilla_proofs[0][0]
+        .replica_column_proofs
+        .drg_parents[0]
+        .column()
+        .index();
+    for proof in truncated_output.vanilla_proofs.iter_mut().flatten() {
+        let columns = &mut proof.replica_column_proofs;
+        for column in iter::once(&mut columns.c_x)
+            .chain(&mut columns.drg_parents)
+            .chain(&mut columns.exp_parents)
+            .filter(|column| column.column().index() == truncated)
+        {
+            *column = ColumnProof::new(
+                truncated,
+                column.column().rows()[..1].to_vec(),
+                column.inclusion_proof().clone(),
+            );
+        }
     }
+    let encoded = encode_seal_commit_phase1_output(&porep_config, &truncated_output, true)?;
+    assert!(decode_seal_commit_phase1_output::<SectorShape2KiB>(&porep_config, &encoded).is_err());
+
+    // A seed that doesn't match the vanilla proofs is rejected.
+    let mut wrong_seed_output = phase1_output;
+    wrong_seed_output.seed = rng.gen();
+    let encoded = encode_seal_commit_phase1_output(&porep_config, &wrong_seed_output, true)?;
+    assert!(decode_seal_commit_phase1_output::<SectorShape2KiB>(&porep_config, &encoded).is_err());
 
     Ok(())
 }
 
 #[test]
-#[ignore]
-fn test_seal_proof_aggregation_2kib_failures() -> Result<()> {
-    let test_inputs = vec![
-        (
-            FIP92_MIN_NI_POREP_AGGREGATION_PROOFS - 1,
-            MAX_LEGACY_REGISTERED_SEAL_PROOF_ID + 1,
-            ApiVersion::V1_2_0,
-            vec![ApiFeature::NonInteractivePoRep],
-        ),
-        (
-            FIP92_MAX_NI_POREP_AGGREGATION_PROOFS + 1,
-            MAX_LEGACY_REGISTERED_SEAL_PROOF_ID + 1,
-            ApiVersion::V1_2_0,
-            vec![ApiFeature::NonInteractivePoRep],
-        ),
-    ];
+fn test_encode_into_from_pieces_2kib() -> Result<()> {
+    let mut rng = XorShiftRng::from_seed(TEST_SEED);
+    let SealedSector {
+        porep_config,
+        cache_dir,
+        sealed_file: sealed_sector_file,
+        ..
+    } = seal_2kib_sector(&mut rng)?;
 
-    for (proofs_to_aggregate, porep_id_num, api_version, api_features) in test_inputs {
-        let porep_id = to_porep_id_verified(porep_id_num, api_version);
-        let porep_config = PoRepConfig::new_groth16_with_features(
-            SECTOR_SIZE_2_KIB,
-            porep_id,
-            api_version,
-            api_features,
+    // Two pieces that don't fill the whole sector, the second one needs alignment.
+    let pieces: Vec<Vec<u8>> = [508usize, 1016]
+        .iter()
+        .map(|&len| (0..len).map(|_| rng.gen()).collect())
+        .collect();
+    let mut staged_file = NamedTempFile::new()?;
+    let mut piece_infos = Vec::new();
+    let mut piece_lengths = Vec::new();
+    for piece in &pieces {
+        let piece_size = UnpaddedBytesAmount(piece.len() as u64);
+        let (piece_info, _) = add_piece(&piece[..], &mut staged_file, piece_size, &piece_lengths)?;
+        piece_infos.push(piece_info);
+        piece_lengths.push(piece_size);
+    }
+    staged_file.as_file().set_len(SECTOR_SIZE_2_KIB)?;
+
+    for config in [
+        SectorUpdateConfig::from_porep_config(&porep_config),
+        SectorUpdateConfig::from_porep_config_poseidon(&porep_config),
+    ] {
+        let staged_replica_file = NamedTempFile::new()?;
+        staged_replica_file.as_file().set_len(SECTOR_SIZE_2_KIB)?;
+        let staged_cache_dir = tempdir()?;
+        let expected = encode_into::<SectorShape2KiB>(
+            &config,
+            staged_replica_file.path(),
+            staged_cache_dir.path(),
+            sealed_sector_file.path(),
+            cache_dir.path(),
+            staged_file.path(),
+            &piece_infos,
         )?;
 
-        ensure!(
-            aggregate_seal_proofs::<SectorShape2KiB>(&porep_config, proofs_to_aggregate).is_err(),
-            "test case failure passed unexpectedly"
+        // Stale data in the new replica must not leak into the encoding.
+        let mut streamed_replica_file = NamedTempFile::new()?;
+        streamed_replica_file.write_all(&[0xff; SECTOR_SIZE_2_KIB as usize])?;
+        let streamed_cache_dir = tempdir()?;
+        let mut piece_sources: Vec<&[u8]> = pieces.iter().map(|piece| &piece[..]).collect();
+        let encoded = encode_into_from_pieces::<SectorShape2KiB, _>(
+            &config,
+            streamed_replica_file.path(),
+            streamed_cache_dir.path(),
+            sealed_sector_file.path(),
+            cache_dir.path(),
+            &mut piece_sources,
+            &piece_infos,
+        )?;
+
+        assert_eq!(encoded.comm_r_new, expected.comm_r_new);
+        assert_eq!(encoded.comm_r_last_new, expected.comm_r_last_new);
+        assert_eq!(encoded.comm_d_new, expected.comm_d_new);
+        compare_elements(streamed_replica_file.path(), staged_replica_file.path())?;
+
+        // A piece that doesn't match its piece info is rejected.
+        let mut tampered_piece = pieces[0].clone();
+        tampered_piece[0] ^= 1;
+        let mut piece_sources: Vec<&[u8]> = vec![&tampered_piece, &pieces[1]];
+        let result = encode_into_from_pieces::<SectorShape2KiB, _>(
+            &config,
+            streamed_replica_file.path(),
+            streamed_cache_dir.path(),
+            sealed_sector_file.path(),
+            cache_dir.path(),
+            &mut piece_sources,
+            &piece_infos,
+        );
+        assert!(result.is_err());
+
+        // Pieces that don't fit into the sector are rejected before anything is written.
+        let too_large_piece_infos = vec![piece_infos[1].clone(); 3];
+        let mut piece_sources: Vec<&[u8]> = vec![&pieces[1]; 3];
+        let untouched_replica_file = NamedTempFile::new()?;
+        untouched_replica_file
+            .as_file()
+            .set_len(SECTOR_SIZE_2_KIB)?;
+        let result = encode_into_from_pieces::<SectorShape2KiB, _>(
+            &config,
+            untouched_replica_file.path(),
+            streamed_cache_dir.path(),
+            sealed_sector_file.path(),
+            cache_dir.path(),
+            &mut piece_sources,
+            &too_large_piece_infos,
+        );
+        assert!(result.is_err());
+        assert_eq!(
+            read(untouched_replica_file.path())?,
+            vec![0; SECTOR_SIZE_2_KIB as usize]
         );
     }
 
@@ -772,109 +946,1349 @@ fn test_seal_proof_aggregation_2kib_failures() -> Result<()> {
 }
 
 #[test]
-#[ignore]
-fn test_seal_proof_aggregation_4kib() -> Result<()> {
-    let test_inputs = vec![
-        (
-            7,
-            MAX_LEGACY_REGISTERED_SEAL_PROOF_ID + 1,
-            ApiVersion::V1_1_0,
-            vec![],
-        ),
-        (
-            24,
-            MAX_LEGACY_REGISTERED_SEAL_PROOF_ID + 1,
-            ApiVersion::V1_2_0,
-            vec![ApiFeature::SyntheticPoRep],
-        ),
-        (
-            17,
-            MAX_LEGACY_REGISTERED_SEAL_PROOF_ID + 1,
-            ApiVersion::V1_2_0,
-            vec![ApiFeature::NonInteractivePoRep],
-        ),
-    ];
+fn test_reencode_into_2kib() -> Result<()> {
+    let mut rng = XorShiftRng::from_seed(TEST_SEED);
+    let SealedSector {
+        porep_config,
+        cache_dir,
+        sealed_file: sealed_sector_file,
+        comm_r: comm_r_old,
+        ..
+    } = seal_2kib_sector(&mut rng)?;
+
+    let (first_data_file, first_piece_infos) = generate_staged_data(&mut rng)?;
+    let (second_data_file, second_piece_infos) = generate_staged_data(&mut rng)?;
+
+    for config in [
+        SectorUpdateConfig::from_porep_config(&porep_config),
+        SectorUpdateConfig::from_porep_config_poseidon(&porep_config),
+    ] {
+        let replica_file = NamedTempFile::new()?;
+        replica_file.as_file().set_len(SECTOR_SIZE_2_KIB)?;
+        let replica_cache_dir = tempdir()?;
+        let first = encode_into::<SectorShape2KiB>(
+            &config,
+            replica_file.path(),
+            replica_cache_dir.path(),
+            sealed_sector_file.path(),
+            cache_dir.path(),
+            first_data_file.path(),
+            &first_piece_infos,
+        )?;
 
-    for (proofs_to_aggregate, porep_id_num, api_version, api_features) in test_inputs {
-        let porep_id = to_porep_id_verified(porep_id_num, api_version);
-        let porep_config = PoRepConfig::new_groth16_with_features(
-            SECTOR_SIZE_4_KIB,
-            porep_id,
-            api_version,
-            api_features,
+        let expected_replica_file = NamedTempFile::new()?;
+        expected_replica_file.as_file().set_len(SECTOR_SIZE_2_KIB)?;
+        let expected_cache_dir = tempdir()?;
+        let expected = encode_into::<SectorShape2KiB>(
+            &config,
+            expected_replica_file.path(),
+            expected_cache_dir.path(),
+            sealed_sector_file.path(),
+            cache_dir.path(),
+            second_data_file.path(),
+            &second_piece_infos,
         )?;
 
-        aggregate_seal_proofs::<SectorShape4KiB>(&porep_config, proofs_to_aggregate)?;
+        let new_replica_file = NamedTempFile::new()?;
+        new_replica_file.as_file().set_len(SECTOR_SIZE_2_KIB)?;
+        let new_cache_dir = tempdir()?;
+        let (encoded, proof_inputs) = reencode_into::<SectorShape2KiB>(
+            &config,
+            new_replica_file.path(),
+            new_cache_dir.path(),
+            replica_file.path(),
+            replica_cache_dir.path(),
+            first_data_file.path(),
+            comm_r_old,
+            first.comm_d_new,
+            second_data_file.path(),
+            &second_piece_infos,
+        )?;
+
+        assert_eq!(encoded.comm_r_new, expected.comm_r_new);
+        assert_eq!(encoded.comm_r_last_new, expected.comm_r_last_new);
+        assert_eq!(encoded.comm_d_new, expected.comm_d_new);
+        assert_eq!(proof_inputs.h, config.h);
+        assert_eq!(proof_inputs.comm_r_old, comm_r_old);
+        assert_eq!(proof_inputs.comm_r_new, expected.comm_r_new);
+        assert_eq!(proof_inputs.comm_d_new, expected.comm_d_new);
+        compare_elements(new_replica_file.path(), expected_replica_file.path())?;
+        // The tree built to check the data is not kept around.
+        assert_eq!(
+            read_dir(new_cache_dir.path())?.count(),
+            read_dir(expected_cache_dir.path())?.count()
+        );
+
+        // The staged data must match its piece infos.
+        let result = reencode_into::<SectorShape2KiB>(
+            &config,
+            new_replica_file.path(),
+            new_cache_dir.path(),
+            replica_file.path(),
+            replica_cache_dir.path(),
+            first_data_file.path(),
+            comm_r_old,
+            first.comm_d_new,
+            second_data_file.path(),
+            &first_piece_infos,
+        );
+        assert!(result.is_err());
+
+        // The data must be the one the replica currently encodes.
+        let result = reencode_into::<SectorShape2KiB>(
+            &config,
+            new_replica_file.path(),
+            new_cache_dir.path(),
+            replica_file.path(),
+            replica_cache_dir.path(),
+            second_data_file.path(),
+            comm_r_old,
+            first.comm_d_new,
+            second_data_file.path(),
+            &second_piece_infos,
+        );
+        assert!(result.is_err());
     }
 
     Ok(())
 }
 
 #[test]
-#[ignore]
-fn test_seal_proof_aggregation_32kib() -> Result<()> {
-    let test_inputs = vec![
-        (
-            220,
-            MAX_LEGACY_REGISTERED_SEAL_PROOF_ID + 1,
-            ApiVersion::V1_1_0,
-            vec![],
-        ),
-        (
-            500,
-            MAX_LEGACY_REGISTERED_SEAL_PROOF_ID + 1,
-            ApiVersion::V1_2_0,
-            vec![ApiFeature::SyntheticPoRep],
-        ),
-        (
-            5,
-            MAX_LEGACY_REGISTERED_SEAL_PROOF_ID + 1,
-            ApiVersion::V1_2_0,
-            vec![ApiFeature::NonInteractivePoRep],
-        ),
-    ];
+fn test_audit_sector_update_2kib() -> Result<()> {
+    let mut rng = XorShiftRng::from_seed(TEST_SEED);
+    let SealedSector {
+        porep_config,
+        cache_dir,
+        sealed_file: sealed_sector_file,
+        comm_r: comm_r_old,
+        ..
+    } = seal_2kib_sector(&mut rng)?;
+    let p_aux: PersistentAux<DefaultTreeDomain> =
+        deserialize(&read(cache_dir.path().join(CacheKey::PAux.to_string()))?)?;
+    let comm_c: Commitment = p_aux.comm_c.into();
+
+    let (data_file, piece_infos) = generate_staged_data(&mut rng)?;
+    let seed = rng.gen();
 
-    for (proofs_to_aggregate, porep_id_num, api_version, api_features) in test_inputs {
-        let porep_id = to_porep_id_verified(porep_id_num, api_version);
-        let porep_config = PoRepConfig::new_groth16_with_features(
-            SECTOR_SIZE_32_KIB,
-            porep_id,
-            api_version,
-            api_features,
+    for config in [
+        SectorUpdateConfig::from_porep_config(&porep_config),
+        SectorUpdateConfig::from_porep_config_poseidon(&porep_config),
+    ] {
+        let replica_file = NamedTempFile::new()?;
+        replica_file.as_file().set_len(SECTOR_SIZE_2_KIB)?;
+        let replica_cache_dir = tempdir()?;
+        let encoded = encode_into::<SectorShape2KiB>(
+            &config,
+            replica_file.path(),
+            replica_cache_dir.path(),
+            sealed_sector_file.path(),
+            cache_dir.path(),
+            data_file.path(),
+            &piece_infos,
         )?;
 
-        aggregate_seal_proofs::<SectorShape32KiB>(&porep_config, proofs_to_aggregate)?;
+        let audit = |replica_path: &Path, comm_c: Commitment| {
+            let scratch_dir = tempdir()?;
+            let report = audit_sector_update::<SectorShape2KiB>(
+                &config,
+                replica_path,
+                sealed_sector_file.path(),
+                data_file.path(),
+                scratch_dir.path(),
+                comm_c,
+                comm_r_old,
+                encoded.comm_r_new,
+                encoded.comm_d_new,
+                20,
+                seed,
+            );
+            // The rebuilt trees are not kept around.
+            assert_eq!(read_dir(scratch_dir.path())?.count(), 0);
+            report
+        };
+
+        let report = audit(replica_file.path(), comm_c)?;
+        assert!(report.is_ok());
+        assert_eq!(report.computed_comm_r_old, comm_r_old);
+        assert_eq!(report.computed_comm_r_new, encoded.comm_r_new);
+        assert_eq!(report.computed_comm_d_new, encoded.comm_d_new);
+        assert_eq!(report.sampled_nodes.len(), 20);
+
+        // The comm_c can be read from the cache of the original sector instead.
+        let scratch_dir = tempdir()?;
+        let report = audit_sector_update_with_cache::<SectorShape2KiB>(
+            &config,
+            replica_file.path(),
+            sealed_sector_file.path(),
+            cache_dir.path(),
+            data_file.path(),
+            scratch_dir.path(),
+            comm_r_old,
+            encoded.comm_r_new,
+            encoded.comm_d_new,
+            20,
+            seed,
+        )?;
+        assert!(report.is_ok());
+        assert_eq!(report.computed_comm_r_old, comm_r_old);
+
+        // A wrong comm_c results in wrong replica commitments, but the encoding is still fine.
+        let report = audit(replica_file.path(), [0; 32])?;
+        assert!(!report.is_ok());
+        assert_ne!(report.computed_comm_r_old, comm_r_old);
+        assert_ne!(report.computed_comm_r_new, encoded.comm_r_new);
+        assert_eq!(report.computed_comm_d_new, encoded.comm_d_new);
+        assert!(report.mismatched_nodes.is_empty());
+
+        // A corrupted node of the replica is found if it's sampled.
+        let corrupted_node = report.sampled_nodes[0];
+        let corrupted_replica_file = NamedTempFile::new()?;
+        copy(replica_file.path(), corrupted_replica_file.path())?;
+        let mut file = OpenOptions::new()
+            .write(true)
+            .open(corrupted_replica_file.path())?;
+        file.seek(SeekFrom::Start(corrupted_node * NODE_SIZE as u64))?;
+        file.write_all(&[1; NODE_SIZE])?;
+        drop(file);
+        let report = audit(corrupted_replica_file.path(), comm_c)?;
+        assert!(!report.is_ok());
+        assert_eq!(report.computed_comm_r_old, comm_r_old);
+        assert_ne!(report.computed_comm_r_new, encoded.comm_r_new);
+        assert!(report.mismatched_nodes.contains(&corrupted_node));
     }
 
     Ok(())
 }
 
-//#[test]
-//#[ignore]
-//fn test_seal_proof_aggregation_818_32gib_porep_id_v1_1_base_8() -> Result<()> {
-//    let proofs_to_aggregate = 818; // Requires auto-padding
-//
-//    let porep_id = ARBITRARY_POREP_ID_V1_1_0;
-//    assert!(!is_legacy_porep_id(porep_id));
-//    let verified = aggregate_seal_proofs::<SectorShape32GiB>(
-//        SECTOR_SIZE_32_GIB,
-//        &porep_id,
-//        ApiVersion::V1_1_0,
-//        proofs_to_aggregate,
-//    )?;
-//    assert!(verified);
-//
-//    Ok(())
-//}
-
-//#[test]
-//#[ignore]
-//fn test_seal_proof_aggregation_818_64gib_porep_id_v1_1_base_8() -> Result<()> {
-//    let proofs_to_aggregate = 818; // Requires auto-padding
-//
-//    let porep_id = ARBITRARY_POREP_ID_V1_1_0;
-//    assert!(!is_legacy_porep_id(porep_id));
-//    let verified = aggregate_seal_proofs::<SectorShape64GiB>(
+#[test]
+fn test_update_partition_proofs_encoding_2kib() -> Result<()> {
+    let mut rng = XorShiftRng::from_seed(TEST_SEED);
+    let SealedSector {
+        porep_config,
+        cache_dir,
+        sealed_file: sealed_sector_file,
+        comm_r: comm_r_old,
+        ..
+    } = seal_2kib_sector(&mut rng)?;
+    let config = SectorUpdateConfig::from_porep_config(&porep_config);
+
+    let (staged_data_file, piece_infos) = generate_staged_data(&mut rng)?;
+    let replica_file = NamedTempFile::new()?;
+    replica_file.as_file().set_len(SECTOR_SIZE_2_KIB)?;
+    let replica_cache_dir = tempdir()?;
+    let encoded = encode_into::<SectorShape2KiB>(
+        &config,
+        replica_file.path(),
+        replica_cache_dir.path(),
+        sealed_sector_file.path(),
+        cache_dir.path(),
+        staged_data_file.path(),
+        &piece_infos,
+    )?;
+    let partition_proofs = generate_partition_proofs::<SectorShape2KiB>(
+        config,
+        comm_r_old,
+        encoded.comm_r_new,
+        encoded.comm_d_new,
+        sealed_sector_file.path(),
+        cache_dir.path(),
+        replica_file.path(),
+        replica_cache_dir.path(),
+    )?;
+
+    let bytes = encode_update_partition_proofs::<SectorShape2KiB>(
+        &config,
+        comm_r_old,
+        encoded.comm_r_new,
+        encoded.comm_d_new,
+        &partition_proofs,
+    )?;
+    let decoded = decode_update_partition_proofs::<SectorShape2KiB>(&config, &bytes)?;
+    assert_eq!(decoded.comm_r_old, comm_r_old);
+    assert_eq!(decoded.comm_r_new, encoded.comm_r_new);
+    assert_eq!(decoded.comm_d_new, encoded.comm_d_new);
+    assert_eq!(decoded.h, config.h);
+    assert_eq!(
+        serialize(&decoded.partition_proofs)?,
+        serialize(&partition_proofs)?
+    );
+
+    // A corrupted encoding is rejected by the checksum.
+    let mut corrupted = bytes.clone();
+    let last = corrupted.len() - 40;
+    corrupted[last] ^= 1;
+    assert!(decode_update_partition_proofs::<SectorShape2KiB>(&config, &corrupted).is_err());
+
+    // A body length that overflows is rejected instead of panicking.
+    let mut overflowing = bytes.clone();
+    overflowing[115..123].copy_from_slice(&u64::MAX.to_le_bytes());
+    assert!(decode_update_partition_proofs::<SectorShape2KiB>(&config, &overflowing).is_err());
+
+    // Proofs for different commitments are rejected, even with a valid checksum.
+    let mismatched = encode_update_partition_proofs::<SectorShape2KiB>(
+        &config,
+        comm_r_old,
+        encoded.comm_r_new,
+        comm_r_old,
+        &partition_proofs,
+    )?;
+    assert!(decode_update_partition_proofs::<SectorShape2KiB>(&config, &mismatched).is_err());
+
+    // The sector size must match.
+    let other_config = SectorUpdateConfig::from_porep_config(&PoRepConfig::new_groth16(
+        SECTOR_SIZE_4_KIB,
+        ARBITRARY_POREP_ID_V1_1_0,
+        ApiVersion::V1_1_0,
+    ));
+    assert!(decode_update_partition_proofs::<SectorShape2KiB>(&other_config, &bytes).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_regenerate_sector_key_2kib() -> Result<()> {
+    let porep_config = PoRepConfig::new_groth16(
+        SECTOR_SIZE_2_KIB,
+        ARBITRARY_POREP_ID_V1_1_0,
+        ApiVersion::V1_1_0,
+    );
+    let mut rng = XorShiftRng::from_seed(TEST_SEED);
+    let prover_id = [1; 32];
+
+    let (data_file, data_piece_infos) = generate_staged_data(&mut rng)?;
+    let cc_file = NamedTempFile::new()?;
+    cc_file.as_file().set_len(SECTOR_SIZE_2_KIB)?;
+    let cc_piece_infos = vec![PieceInfo::new(
+        compute_comm_d(porep_config.sector_size, &[])?,
+        porep_config.unpadded_bytes_amount(),
+    )?];
+
+    for (original_data_file, piece_infos) in
+        [(data_file, data_piece_infos), (cc_file, cc_piece_infos)]
+    {
+        let sector_id = rng.gen::<u64>().into();
+        let ticket = rng.gen();
+        let sealed_sector_file = NamedTempFile::new()?;
+        let cache_dir = tempdir()?;
+        let phase1_output = seal_pre_commit_phase1::<_, _, _, SectorShape2KiB>(
+            &porep_config,
+            cache_dir.path(),
+            original_data_file.path(),
+            sealed_sector_file.path(),
+            prover_id,
+            sector_id,
+            ticket,
+            &piece_infos,
+        )?;
+        let comm_d = seal_pre_commit_phase2(
+            &porep_config,
+            phase1_output,
+            cache_dir.path(),
+            sealed_sector_file.path(),
+        )?
+        .comm_d;
+        let is_cc = comm_d == compute_comm_d(porep_config.sector_size, &[])?;
+
+        let labels_dir = tempdir()?;
+        let sector_key_file = NamedTempFile::new()?;
+        regenerate_sector_key::<SectorShape2KiB, _>(
+            &porep_config,
+            labels_dir.path(),
+            prover_id,
+            sector_id,
+            ticket,
+            comm_d,
+            (!is_cc).then(|| original_data_file.path()),
+            sector_key_file.as_file(),
+        )?;
+        compare_elements(sector_key_file.path(), sealed_sector_file.path())?;
+        // The labels are only stored temporarily.
+        assert_eq!(read_dir(labels_dir.path())?.count(), 0);
+
+        if is_cc {
+            // Passing the zero data explicitly results in the same sector key.
+            let explicit_sector_key_file = NamedTempFile::new()?;
+            regenerate_sector_key::<SectorShape2KiB, _>(
+                &porep_config,
+                labels_dir.path(),
+                prover_id,
+                sector_id,
+                ticket,
+                comm_d,
+                Some(original_data_file.path()),
+                explicit_sector_key_file.as_file(),
+            )?;
+            compare_elements(explicit_sector_key_file.path(), sealed_sector_file.path())?;
+        } else {
+            // A sector with data can't be regenerated without it.
+            let result = regenerate_sector_key::<SectorShape2KiB, _>(
+                &porep_config,
+                labels_dir.path(),
+                prover_id,
+                sector_id,
+                ticket,
+                comm_d,
+                None,
+                io::sink(),
+            );
+            assert!(result.is_err());
+        }
+    }
+
+    Ok(())
+}
+
+// Writes a random full sector piece as staged data.
+fn generate_staged_data(rng: &mut XorShiftRng) -> Result<(NamedTempFile, Vec<PieceInfo>)> {
+    let piece_size = UnpaddedBytesAmount::from(PaddedBytesAmount(SECTOR_SIZE_2_KIB));
+    let piece: Vec<u8> = (0..u64::from(piece_size)).map(|_| rng.gen()).collect();
+    let mut staged_file = NamedTempFile::new()?;
+    let (piece_info, _) = add_piece(&piece[..], &mut staged_file, piece_size, &[])?;
+
+    Ok((staged_file, vec![piece_info]))
+}
+
+/// A sealed 2KiB sector, whose replica serves as sector key in the update tests.
+struct SealedSector {
+    porep_config: PoRepConfig,
+    prover_id: ProverId,
+    sector_id: SectorId,
+    ticket: Ticket,
+    cache_dir: TempDir,
+    sealed_file: NamedTempFile,
+    comm_r: Commitment,
+    comm_d: Commitment,
+}
+
+// Seals a 2KiB sector with random data.
+fn seal_2kib_sector(rng: &mut XorShiftRng) -> Result<SealedSector> {
+    let (staged_data_file, piece_infos) = generate_staged_data(rng)?;
+    seal_2kib_sector_with_data(rng, staged_data_file.path(), &piece_infos)
+}
+
+// Seals a 2KiB sector with the given fr32-padded data.
+fn seal_2kib_sector_with_data(
+    rng: &mut XorShiftRng,
+    staged_data_path: &Path,
+    piece_infos: &[PieceInfo],
+) -> Result<SealedSector> {
+    let porep_config = PoRepConfig::new_groth16(
+        SECTOR_SIZE_2_KIB,
+        ARBITRARY_POREP_ID_V1_1_0,
+        ApiVersion::V1_1_0,
+    );
+    let prover_id = [1; 32];
+    let sector_id = rng.gen::<u64>().into();
+    let ticket = rng.gen();
+
+    let sealed_file = NamedTempFile::new()?;
+    let cache_dir = tempdir()?;
+    let phase1_output = seal_pre_commit_phase1::<_, _, _, SectorShape2KiB>(
+        &porep_config,
+        cache_dir.path(),
+        staged_data_path,
+        sealed_file.path(),
+        prover_id,
+        sector_id,
+        ticket,
+        piece_infos,
+    )?;
+    let SealPreCommitOutput { comm_r, comm_d } = seal_pre_commit_phase2(
+        &porep_config,
+        phase1_output,
+        cache_dir.path(),
+        sealed_file.path(),
+    )?;
+
+    Ok(SealedSector {
+        porep_config,
+        prover_id,
+        sector_id,
+        ticket,
+        cache_dir,
+        sealed_file,
+        comm_r,
+        comm_d,
+    })
+}
+
+#[test]
+fn test_get_unsealed_range_from_updated_sector_2kib() -> Result<()> {
+    // Seal a committed capacity sector, so that its sector key can be regenerated.
+    let mut rng = XorShiftRng::from_seed(TEST_SEED);
+    let cc_file = NamedTempFile::new()?;
+    cc_file.as_file().set_len(SECTOR_SIZE_2_KIB)?;
+    let cc_piece_infos = vec![PieceInfo::new(
+        compute_comm_d(SECTOR_SIZE_2_KIB.into(), &[])?,
+        UnpaddedBytesAmount::from(PaddedBytesAmount(SECTOR_SIZE_2_KIB)),
+    )?];
+    let SealedSector {
+        porep_config,
+        prover_id,
+        sector_id,
+        ticket,
+        cache_dir,
+        sealed_file: sealed_sector_file,
+        comm_r: comm_r_old,
+        comm_d,
+    } = seal_2kib_sector_with_data(&mut rng, cc_file.path(), &cc_piece_infos)?;
+    let config = SectorUpdateConfig::from_porep_config(&porep_config);
+
+    let pieces: Vec<Vec<u8>> = [508usize, 1016]
+        .iter()
+        .map(|&len| (0..len).map(|_| rng.gen()).collect())
+        .collect();
+    let piece_infos = pieces
+        .iter()
+        .map(|piece| generate_piece_commitment(&piece[..], UnpaddedBytesAmount(piece.len() as u64)))
+        .collect::<Result<Vec<_>>>()?;
+    let replica_file = NamedTempFile::new()?;
+    replica_file.as_file().set_len(SECTOR_SIZE_2_KIB)?;
+    let replica_cache_dir = tempdir()?;
+    let mut piece_sources: Vec<&[u8]> = pieces.iter().map(|piece| &piece[..]).collect();
+    let encoded = encode_into_from_pieces::<SectorShape2KiB, _>(
+        &config,
+        replica_file.path(),
+        replica_cache_dir.path(),
+        sealed_sector_file.path(),
+        cache_dir.path(),
+        &mut piece_sources,
+        &piece_infos,
+    )?;
+
+    let labels_cache_dir = tempdir()?;
+    let sector_key_sources = [
+        SectorKeySource::File(sealed_sector_file.path()),
+        SectorKeySource::Regenerate {
+            porep_config: &porep_config,
+            cache_path: labels_cache_dir.path(),
+            prover_id,
+            sector_id,
+            ticket,
+            comm_d,
+        },
+    ];
+    // The second piece is aligned to its size, the last range doesn't start at an fr32 block.
+    let ranges = [
+        (0, 508, &pieces[0][..]),
+        (1016, 1016, &pieces[1][..]),
+        (1019, 300, &pieces[1][3..303]),
+    ];
+    for sector_key in sector_key_sources {
+        for (offset, num_bytes, expected) in ranges {
+            let output_file = NamedTempFile::new()?;
+            let written = get_unsealed_range_from_updated_sector::<SectorShape2KiB>(
+                &config,
+                replica_file.path(),
+                sector_key,
+                output_file.path(),
+                comm_r_old,
+                encoded.comm_d_new,
+                UnpaddedByteIndex(offset),
+                UnpaddedBytesAmount(num_bytes),
+            )?;
+            assert_eq!(written, UnpaddedBytesAmount(num_bytes));
+
+            let mut contents = Vec::new();
+            File::open(output_file.path())?.read_to_end(&mut contents)?;
+            assert_eq!(contents, expected);
+        }
+    }
+    // The regenerated labels are not kept around.
+    assert_eq!(read_dir(labels_cache_dir.path())?.count(), 0);
+
+    // The sector key of a sector with data can't be regenerated.
+    let output_file = NamedTempFile::new()?;
+    assert!(get_unsealed_range_from_updated_sector::<SectorShape2KiB>(
+        &config,
+        replica_file.path(),
+        SectorKeySource::Regenerate {
+            porep_config: &porep_config,
+            cache_path: labels_cache_dir.path(),
+            prover_id,
+            sector_id,
+            ticket,
+            comm_d: encoded.comm_d_new,
+        },
+        output_file.path(),
+        comm_r_old,
+        encoded.comm_d_new,
+        UnpaddedByteIndex(0),
+        UnpaddedBytesAmount(508),
+    )
+    .is_err());
+
+    // With the piece layout, the alignment between the pieces is known to be zero. Reading only
+    // from it doesn't need the sector key at all, so a missing one doesn't matter.
+    let missing_sector_key = labels_cache_dir.path().join("missing");
+    let ranges_with_pieces = [
+        (600, 300, SectorKeySource::File(&missing_sector_key)),
+        (0, 1016, SectorKeySource::File(sealed_sector_file.path())),
+        (400, 1000, SectorKeySource::File(sealed_sector_file.path())),
+    ];
+    let mut unsealed = pieces[0].clone();
+    unsealed.resize(1016, 0);
+    unsealed.extend_from_slice(&pieces[1]);
+    for (offset, num_bytes, sector_key) in ranges_with_pieces {
+        let output_file = NamedTempFile::new()?;
+        let written = get_unsealed_range_from_updated_sector_with_pieces::<SectorShape2KiB>(
+            &config,
+            replica_file.path(),
+            sector_key,
+            output_file.path(),
+            comm_r_old,
+            encoded.comm_d_new,
+            &piece_infos,
+            UnpaddedByteIndex(offset),
+            UnpaddedBytesAmount(num_bytes),
+        )?;
+        assert_eq!(written, UnpaddedBytesAmount(num_bytes));
+
+        let mut contents = Vec::new();
+        File::open(output_file.path())?.read_to_end(&mut contents)?;
+        assert_eq!(
+            contents,
+            &unsealed[offset as usize..(offset + num_bytes) as usize]
+        );
+    }
+    // Pieces that don't match comm_d_new are rejected.
+    let output_file = NamedTempFile::new()?;
+    assert!(
+        get_unsealed_range_from_updated_sector_with_pieces::<SectorShape2KiB>(
+            &config,
+            replica_file.path(),
+            SectorKeySource::File(sealed_sector_file.path()),
+            output_file.path(),
+            comm_r_old,
+            encoded.comm_d_new,
+            &piece_infos[..1],
+            UnpaddedByteIndex(600),
+            UnpaddedBytesAmount(300),
+        )
+        .is_err()
+    );
+
+    // Ranges beyond the sector are rejected.
+    let output_file = NamedTempFile::new()?;
+    assert!(get_unsealed_range_from_updated_sector::<SectorShape2KiB>(
+        &config,
+        replica_file.path(),
+        SectorKeySource::File(sealed_sector_file.path()),
+        output_file.path(),
+        comm_r_old,
+        encoded.comm_d_new,
+        UnpaddedByteIndex(2000),
+        UnpaddedBytesAmount(100),
+    )
+    .is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_get_unsealed_range_with_pieces_2kib() -> Result<()> {
+    let porep_config = PoRepConfig::new_groth16(
+        SECTOR_SIZE_2_KIB,
+        ARBITRARY_POREP_ID_V1_1_0,
+        ApiVersion::V1_1_0,
+    );
+
+    // A sector that is half filled with data, the rest is a zero piece.
+    let mut rng = XorShiftRng::from_seed(TEST_SEED);
+    let prover_id = [1; 32];
+    let sector_id = rng.gen::<u64>().into();
+    let ticket = rng.gen();
+    let piece_size = UnpaddedBytesAmount(1016);
+    let piece: Vec<u8> = (0..u64::from(piece_size)).map(|_| rng.gen()).collect();
+    let mut staged_sector_file = NamedTempFile::new()?;
+    let (piece_info, _) = add_piece(&piece[..], &mut staged_sector_file, piece_size, &[])?;
+    let (padding_info, _) = add_piece(
+        io::repeat(0).take(piece_size.into()),
+        &mut staged_sector_file,
+        piece_size,
+        &[piece_size],
+    )?;
+    let piece_infos = vec![piece_info, padding_info];
+
+    let sealed_sector_file = NamedTempFile::new()?;
+    let cache_dir = tempdir()?;
+    let phase1_output = seal_pre_commit_phase1::<_, _, _, SectorShape2KiB>(
+        &porep_config,
+        cache_dir.path(),
+        staged_sector_file.path(),
+        sealed_sector_file.path(),
+        prover_id,
+        sector_id,
+        ticket,
+        &piece_infos,
+    )?;
+    let comm_d = seal_pre_commit_phase2(
+        &porep_config,
+        phase1_output,
+        cache_dir.path(),
+        sealed_sector_file.path(),
+    )?
+    .comm_d;
+
+    // Reading from the zero piece doesn't unseal the sector, so a missing replica doesn't
+    // matter.
+    let missing_replica = cache_dir.path().join("missing");
+    let output_file = NamedTempFile::new()?;
+    let written = get_unsealed_range_with_pieces::<_, SectorShape2KiB>(
+        &porep_config,
+        cache_dir.path(),
+        &missing_replica,
+        output_file.path(),
+        prover_id,
+        sector_id,
+        comm_d,
+        ticket,
+        &piece_infos,
+        UnpaddedByteIndex(1100),
+        UnpaddedBytesAmount(500),
+    )?;
+    assert_eq!(written, UnpaddedBytesAmount(500));
+    let mut contents = Vec::new();
+    File::open(output_file.path())?.read_to_end(&mut contents)?;
+    assert_eq!(contents, vec![0; 500]);
+
+    // Ranges with data are unsealed as usual.
+    let written = get_unsealed_range_with_pieces::<_, SectorShape2KiB>(
+        &porep_config,
+        cache_dir.path(),
+        sealed_sector_file.path(),
+        output_file.path(),
+        prover_id,
+        sector_id,
+        comm_d,
+        ticket,
+        &piece_infos,
+        UnpaddedByteIndex(508),
+        UnpaddedBytesAmount(600),
+    )?;
+    assert_eq!(written, UnpaddedBytesAmount(600));
+    let mut contents = Vec::new();
+    File::open(output_file.path())?.read_to_end(&mut contents)?;
+    assert_eq!(&contents[..508], &piece[508..]);
+    assert_eq!(&contents[508..], &[0; 92][..]);
+
+    // Pieces that don't match comm_d are rejected.
+    assert!(get_unsealed_range_with_pieces::<_, SectorShape2KiB>(
+        &porep_config,
+        cache_dir.path(),
+        sealed_sector_file.path(),
+        output_file.path(),
+        prover_id,
+        sector_id,
+        comm_d,
+        ticket,
+        &piece_infos[..1],
+        UnpaddedByteIndex(1100),
+        UnpaddedBytesAmount(500),
+    )
+    .is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_rebuild_tree_r_last_4kib_sub_8_2() -> Result<()> {
+    let porep_config = PoRepConfig::new_groth16(
+        SECTOR_SIZE_4_KIB,
+        ARBITRARY_POREP_ID_V1_1_0,
+        ApiVersion::V1_1_0,
+    );
+
+    let mut rng = XorShiftRng::from_seed(TEST_SEED);
+    let (mut piece_file, _) = generate_piece_file(SECTOR_SIZE_4_KIB)?;
+    let sealed_sector_file = NamedTempFile::new()?;
+    let cache_dir = tempdir()?;
+    let (_, phase1_output) = run_seal_pre_commit_phase1::<SectorShape4KiB>(
+        &porep_config,
+        [1; 32],
+        rng.gen::<u64>().into(),
+        rng.gen(),
+        &cache_dir,
+        &mut piece_file,
+        &sealed_sector_file,
+    )?;
+    seal_pre_commit_phase2(
+        &porep_config,
+        phase1_output,
+        cache_dir.path(),
+        sealed_sector_file.path(),
+    )?;
+
+    let scratch_dir = tempdir()?;
+    let check = verify_tree_r_last::<_, _, _, SectorShape4KiB>(
+        &porep_config,
+        &sealed_sector_file,
+        &cache_dir,
+        &scratch_dir,
+    )?;
+    assert!(check.is_ok());
+    assert_eq!(check.rebuilt.base_tree_roots.len(), 2);
+    // The rebuilt tree is not kept around.
+    assert_eq!(read_dir(scratch_dir.path())?.count(), 0);
+
+    // Corrupt the root of the second base tree.
+    let base_tree_path =
+        StoreConfig::data_path(cache_dir.path(), &format!("{}-1", CacheKey::CommRLastTree));
+    let mut base_tree_file = OpenOptions::new().write(true).open(&base_tree_path)?;
+    base_tree_file.seek(SeekFrom::End(-(NODE_SIZE as i64)))?;
+    base_tree_file.write_all(&[0; NODE_SIZE])?;
+    drop(base_tree_file);
+
+    let check = verify_tree_r_last::<_, _, _, SectorShape4KiB>(
+        &porep_config,
+        &sealed_sector_file,
+        &cache_dir,
+        tempdir()?,
+    )?;
+    assert!(!check.is_ok());
+    assert_eq!(check.mismatched_base_trees(), vec![1]);
+    assert_eq!(check.rebuilt.comm_r_last, check.expected_comm_r_last);
+
+    // Lose the first base tree entirely.
+    remove_file(StoreConfig::data_path(
+        cache_dir.path(),
+        &format!("{}-0", CacheKey::CommRLastTree),
+    ))?;
+    let check = verify_tree_r_last::<_, _, _, SectorShape4KiB>(
+        &porep_config,
+        &sealed_sector_file,
+        &cache_dir,
+        tempdir()?,
+    )?;
+    assert_eq!(check.cached_comm_r_last, None);
+    assert_eq!(check.mismatched_base_trees(), vec![0, 1]);
+
+    // Rebuilding restores the cache.
+    let roots = rebuild_tree_r_last::<_, _, SectorShape4KiB>(
+        &porep_config,
+        &sealed_sector_file,
+        &cache_dir,
+    )?;
+    assert_eq!(roots.comm_r_last, check.expected_comm_r_last);
+    let check = verify_tree_r_last::<_, _, _, SectorShape4KiB>(
+        &porep_config,
+        &sealed_sector_file,
+        &cache_dir,
+        tempdir()?,
+    )?;
+    assert!(check.is_ok());
+    assert_eq!(check.rebuilt, roots);
+
+    Ok(())
+}
+
+fn seal_lifecycle_upgrade<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>>(
+    porep_config: &PoRepConfig,
+) -> Result<()> {
+    info!(
+        "Creating seal proof for upgrade with ApiVersion {}",
+        porep_config.api_version
+    );
+    seal_lifecycle_upgrade_with::<Tree>(
+        porep_config,
+        SectorUpdateConfig::from_porep_config(porep_config),
+        prove_and_verify_update::<Tree>,
+    )
+}
+
+fn seal_lifecycle_upgrade_poseidon<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>>(
+    porep_config: &PoRepConfig,
+) -> Result<()> {
+    info!(
+        "Creating seal proof for Poseidon upgrade with ApiVersion {}",
+        porep_config.api_version
+    );
+    seal_lifecycle_upgrade_with::<Tree>(
+        porep_config,
+        SectorUpdateConfig::from_porep_config_poseidon(porep_config),
+        prove_and_verify_poseidon_update::<Tree>,
+    )
+}
+
+fn seal_lifecycle_upgrade_with<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>>(
+    porep_config: &PoRepConfig,
+    config: SectorUpdateConfig,
+    prove_and_verify: ProveAndVerifyUpdate,
+) -> Result<()> {
+    let mut rng = &mut XorShiftRng::from_seed(TEST_SEED);
+    let prover_fr: DefaultTreeDomain = Fr::random(&mut rng).into();
+    let mut prover_id = [0u8; 32];
+    prover_id.copy_from_slice(AsRef::<[u8]>::as_ref(&prover_fr));
+
+    let (_, replica, _, _) = create_seal_for_upgrade::<_, Tree>(
+        porep_config,
+        config,
+        prove_and_verify,
+        &mut rng,
+        prover_id,
+    )?;
+    replica.close()?;
+
+    Ok(())
+}
+
+#[test]
+#[ignore]
+fn test_seal_proof_aggregation_2kib() -> Result<()> {
+    let test_inputs = vec![
+        (
+            1,
+            MAX_LEGACY_REGISTERED_SEAL_PROOF_ID + 1,
+            ApiVersion::V1_1_0,
+            vec![],
+        ),
+        (
+            5,
+            MAX_LEGACY_REGISTERED_SEAL_PROOF_ID + 1,
+            ApiVersion::V1_2_0,
+            vec![ApiFeature::SyntheticPoRep],
+        ),
+        (
+            FIP92_MAX_NI_POREP_AGGREGATION_PROOFS,
+            MAX_LEGACY_REGISTERED_SEAL_PROOF_ID + 1,
+            ApiVersion::V1_2_0,
+            vec![ApiFeature::NonInteractivePoRep],
+        ),
+    ];
+
+    for (proofs_to_aggregate, porep_id_num, api_version, api_features) in test_inputs {
+        let porep_id = to_porep_id_verified(porep_id_num, api_version);
+        let porep_config = PoRepConfig::new_groth16_with_features(
+            SECTOR_SIZE_2_KIB,
+            porep_id,
+            api_version,
+            api_features,
+        )?;
+
+        aggregate_seal_proofs::<SectorShape2KiB>(&porep_config, proofs_to_aggregate)?;
+    }
+
+    Ok(())
+}
+
+#[test]
+#[ignore]
+fn test_seal_proof_aggregation_2kib_failures() -> Result<()> {
+    let test_inputs = vec![
+        (
+            FIP92_MIN_NI_POREP_AGGREGATION_PROOFS - 1,
+            MAX_LEGACY_REGISTERED_SEAL_PROOF_ID + 1,
+            ApiVersion::V1_2_0,
+            vec![ApiFeature::NonInteractivePoRep],
+        ),
+        (
+            FIP92_MAX_NI_POREP_AGGREGATION_PROOFS + 1,
+            MAX_LEGACY_REGISTERED_SEAL_PROOF_ID + 1,
+            ApiVersion::V1_2_0,
+            vec![ApiFeature::NonInteractivePoRep],
+        ),
+    ];
+
+    for (proofs_to_aggregate, porep_id_num, api_version, api_features) in test_inputs {
+        let porep_id = to_porep_id_verified(porep_id_num, api_version);
+        let porep_config = PoRepConfig::new_groth16_with_features(
+            SECTOR_SIZE_2_KIB,
+            porep_id,
+            api_version,
+            api_features,
+        )?;
+
+        ensure!(
+            aggregate_seal_proofs::<SectorShape2KiB>(&porep_config, proofs_to_aggregate).is_err(),
+            "test case failure passed unexpectedly"
+        );
+    }
+
+    Ok(())
+}
+
+#[test]
+#[ignore]
+fn test_ni_porep_lifecycle_2kib() -> Result<()> {
+    let porep_id =
+        to_porep_id_verified(MAX_LEGACY_REGISTERED_SEAL_PROOF_ID + 1, ApiVersion::V1_2_0);
+    let porep_config = PoRepConfig::new_groth16_with_features(
+        SECTOR_SIZE_2_KIB,
+        porep_id,
+        ApiVersion::V1_2_0,
+        vec![ApiFeature::NonInteractivePoRep],
+    )?;
+
+    let mut rng = XorShiftRng::from_seed(TEST_SEED);
+    let prover_id: ProverId = rng.gen();
+    let mut comm_rs = Vec::new();
+    let mut commit_outputs = Vec::new();
+    let mut records = Vec::new();
+    for _ in 0..3 {
+        let (commit_output, record) =
+            create_ni_porep_proof::<_, SectorShape2KiB>(&mut rng, &porep_config, prover_id)?;
+        comm_rs.push(record.comm_r);
+        commit_outputs.push(commit_output);
+        records.push(record);
+    }
+
+    // A single sector's proof is an aggregate of one.
+    let single_proof =
+        aggregate_ni_porep::<SectorShape2KiB>(&porep_config, &comm_rs[..1], &commit_outputs[..1])?;
+    assert!(verify_ni_porep::<SectorShape2KiB>(
+        &porep_config,
+        single_proof,
+        &records[..1]
+    )?);
+
+    let aggregate_proof =
+        aggregate_ni_porep::<SectorShape2KiB>(&porep_config, &comm_rs, &commit_outputs)?;
+    assert!(verify_ni_porep::<SectorShape2KiB>(
+        &porep_config,
+        aggregate_proof.clone(),
+        &records
+    )?);
+
+    records.swap(0, 1);
+    assert!(!verify_ni_porep::<SectorShape2KiB>(
+        &porep_config,
+        aggregate_proof,
+        &records
+    )?);
+
+    Ok(())
+}
+#[test]
+fn test_verify_aggregate_seal_with_records_malformed() -> Result<()> {
+    let porep_id =
+        to_porep_id_verified(MAX_LEGACY_REGISTERED_SEAL_PROOF_ID + 1, ApiVersion::V1_2_0);
+    let porep_config = porep_config(SECTOR_SIZE_2_KIB, porep_id, ApiVersion::V1_2_0);
+
+    let record = SealProofRecord {
+        comm_r: [1u8; 32],
+        comm_d: [2u8; 32],
+        prover_id: [3u8; 32],
+        sector_id: SectorId::from(4),
+        ticket: [5u8; 32],
+        seed: [6u8; 32],
+    };
+    let malformed = [
+        SealProofRecord {
+            comm_d: [0u8; 32],
+            ..record.clone()
+        },
+        SealProofRecord {
+            comm_r: [0xffu8; 32],
+            ..record.clone()
+        },
+        SealProofRecord {
+            seed: [0u8; 32],
+            ..record.clone()
+        },
+    ];
+
+    // The records are checked before the aggregate proof is even read.
+    for bad_record in malformed {
+        let records = [record.clone(), bad_record, record.clone()];
+        let err = verify_aggregate_seal_commit_proofs_with_records::<SectorShape2KiB>(
+            &porep_config,
+            vec![0u8; 16],
+            &records,
+            groth16::aggregate::AggregateVersion::V2,
+        )
+        .expect_err("malformed record was accepted");
+        ensure!(
+            err.to_string().contains("malformed seal record 1"),
+            "unexpected error: {:#}",
+            err
+        );
+    }
+
+    let err = verify_aggregate_seal_commit_proofs_with_records::<SectorShape2KiB>(
+        &porep_config,
+        vec![0u8; 16],
+        &[record],
+        groth16::aggregate::AggregateVersion::V2,
+    )
+    .expect_err("invalid aggregate proof was accepted");
+    ensure!(
+        err.to_string().contains("invalid aggregate proof"),
+        "unexpected error: {:#}",
+        err
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_synth_proofs_inspect_validate_select_2kib() -> Result<()> {
+    fil_logger::maybe_init();
+
+    let porep_id =
+        to_porep_id_verified(MAX_LEGACY_REGISTERED_SEAL_PROOF_ID + 1, ApiVersion::V1_2_0);
+    let porep_config = PoRepConfig::new_groth16_with_features(
+        SECTOR_SIZE_2_KIB,
+        porep_id,
+        ApiVersion::V1_2_0,
+        vec![ApiFeature::SyntheticPoRep],
+    )?;
+
+    let mut rng = XorShiftRng::from_seed(TEST_SEED);
+    let (mut piece_file, _piece_bytes) = generate_piece_file(SECTOR_SIZE_2_KIB)?;
+    let sealed_sector_file = NamedTempFile::new()?;
+    let cache_dir = tempdir().expect("failed to create temp dir");
+
+    let prover_id: ProverId = rng.gen();
+    let ticket = rng.gen();
+    let seed = rng.gen();
+    let sector_id = rng.gen::<u64>().into();
+
+    let (piece_infos, phase1_output) = run_seal_pre_commit_phase1::<SectorShape2KiB>(
+        &porep_config,
+        prover_id,
+        sector_id,
+        ticket,
+        &cache_dir,
+        &mut piece_file,
+        &sealed_sector_file,
+    )?;
+    let pre_commit_output = seal_pre_commit_phase2(
+        &porep_config,
+        phase1_output,
+        cache_dir.path(),
+        sealed_sector_file.path(),
+    )?;
+    generate_synth_proofs::<_, SectorShape2KiB>(
+        &porep_config,
+        cache_dir.path(),
+        sealed_sector_file.path(),
+        prover_id,
+        sector_id,
+        ticket,
+        pre_commit_output.clone(),
+        &piece_infos,
+    )?;
+    clear_cache(cache_dir.path())?;
+
+    let full_info = inspect_synth_proofs::<SectorShape2KiB>(&porep_config, cache_dir.path())?;
+    assert!(!full_info.selected);
+    assert_eq!(full_info.comm_d, pre_commit_output.comm_d);
+    assert_eq!(
+        full_info.file_size,
+        metadata(&full_info.path)?.len(),
+        "file size mismatch"
+    );
+
+    let validated_info = validate_synth_proofs::<SectorShape2KiB>(
+        &porep_config,
+        cache_dir.path(),
+        prover_id,
+        sector_id,
+        ticket,
+        &pre_commit_output,
+    )?;
+    assert_eq!(validated_info, full_info);
+    assert!(validate_synth_proofs::<SectorShape2KiB>(
+        &porep_config,
+        cache_dir.path(),
+        prover_id,
+        sector_id,
+        rng.gen(),
+        &pre_commit_output,
+    )
+    .is_err());
+
+    let full_output = seal_commit_phase1::<_, SectorShape2KiB>(
+        &porep_config,
+        cache_dir.path(),
+        sealed_sector_file.path(),
+        prover_id,
+        sector_id,
+        ticket,
+        seed,
+        pre_commit_output.clone(),
+        &piece_infos,
+    )?;
+
+    // Selecting keeps the full file, so the proofs can be selected again for another seed.
+    select_synth_proofs::<SectorShape2KiB>(
+        &porep_config,
+        cache_dir.path(),
+        prover_id,
+        sector_id,
+        ticket,
+        rng.gen(),
+        &pre_commit_output,
+    )?;
+    let selected_info = select_synth_proofs::<SectorShape2KiB>(
+        &porep_config,
+        cache_dir.path(),
+        prover_id,
+        sector_id,
+        ticket,
+        seed,
+        &pre_commit_output,
+    )?;
+    assert!(selected_info.selected);
+    assert!(full_info.path.exists());
+    assert!(selected_info.path.exists());
+    assert!(selected_info.num_proofs < full_info.num_proofs);
+    assert!(selected_info.file_size < full_info.file_size);
+    assert_eq!(selected_info.comm_c, full_info.comm_c);
+    assert_eq!(selected_info.comm_r_last, full_info.comm_r_last);
+    assert_eq!(
+        inspect_synth_proofs::<SectorShape2KiB>(&porep_config, cache_dir.path())?,
+        full_info
+    );
+
+    // Once the seed is final the caller may remove the full file.
+    remove_file(&full_info.path)?;
+    assert_eq!(
+        inspect_synth_proofs::<SectorShape2KiB>(&porep_config, cache_dir.path())?,
+        selected_info
+    );
+
+    // The selected proofs produce the same commit phase1 output as the full file.
+    let selected_output = seal_commit_phase1::<_, SectorShape2KiB>(
+        &porep_config,
+        cache_dir.path(),
+        sealed_sector_file.path(),
+        prover_id,
+        sector_id,
+        ticket,
+        seed,
+        pre_commit_output.clone(),
+        &piece_infos,
+    )?;
+    assert_eq!(
+        serialize(&selected_output.vanilla_proofs)?,
+        serialize(&full_output.vanilla_proofs)?
+    );
+
+    // A different seed selects different challenges.
+    assert!(seal_commit_phase1::<_, SectorShape2KiB>(
+        &porep_config,
+        cache_dir.path(),
+        sealed_sector_file.path(),
+        prover_id,
+        sector_id,
+        ticket,
+        rng.gen(),
+        pre_commit_output,
+        &piece_infos,
+    )
+    .is_err());
+
+    clear_synthetic_proofs(cache_dir.path())?;
+    assert!(!selected_info.path.exists());
+
+    Ok(())
+}
+
+#[test]
+#[ignore]
+fn test_seal_proof_aggregation_4kib() -> Result<()> {
+    let test_inputs = vec![
+        (
+            7,
+            MAX_LEGACY_REGISTERED_SEAL_PROOF_ID + 1,
+            ApiVersion::V1_1_0,
+            vec![],
+        ),
+        (
+            24,
+            MAX_LEGACY_REGISTERED_SEAL_PROOF_ID + 1,
+            ApiVersion::V1_2_0,
+            vec![ApiFeature::SyntheticPoRep],
+        ),
+        (
+            17,
+            MAX_LEGACY_REGISTERED_SEAL_PROOF_ID + 1,
+            ApiVersion::V1_2_0,
+            vec![ApiFeature::NonInteractivePoRep],
+        ),
+    ];
+
+    for (proofs_to_aggregate, porep_id_num, api_version, api_features) in test_inputs {
+        let porep_id = to_porep_id_verified(porep_id_num, api_version);
+        let porep_config = PoRepConfig::new_groth16_with_features(
+            SECTOR_SIZE_4_KIB,
+            porep_id,
+            api_version,
+            api_features,
+        )?;
+
+        aggregate_seal_proofs::<SectorShape4KiB>(&porep_config, proofs_to_aggregate)?;
+    }
+
+    Ok(())
+}
+
+#[test]
+#[ignore]
+fn test_seal_proof_aggregation_32kib() -> Result<()> {
+    let test_inputs = vec![
+        (
+            220,
+            MAX_LEGACY_REGISTERED_SEAL_PROOF_ID + 1,
+            ApiVersion::V1_1_0,
+            vec![],
+        ),
+        (
+            500,
+            MAX_LEGACY_REGISTERED_SEAL_PROOF_ID + 1,
+            ApiVersion::V1_2_0,
+            vec![ApiFeature::SyntheticPoRep],
+        ),
+        (
+            5,
+            MAX_LEGACY_REGISTERED_SEAL_PROOF_ID + 1,
+            ApiVersion::V1_2_0,
+            vec![ApiFeature::NonInteractivePoRep],
+        ),
+    ];
+
+    for (proofs_to_aggregate, porep_id_num, api_version, api_features) in test_inputs {
+        let porep_id = to_porep_id_verified(porep_id_num, api_version);
+        let porep_config = PoRepConfig::new_groth16_with_features(
+            SECTOR_SIZE_32_KIB,
+            porep_id,
+            api_version,
+            api_features,
+        )?;
+
+        aggregate_seal_proofs::<SectorShape32KiB>(&porep_config, proofs_to_aggregate)?;
+    }
+
+    Ok(())
+}
+
+//#[test]
+//#[ignore]
+//fn test_seal_proof_aggregation_818_32gib_porep_id_v1_1_base_8() -> Result<()> {
+//    let proofs_to_aggregate = 818; // Requires auto-padding
+//
+//    let porep_id = ARBITRARY_POREP_ID_V1_1_0;
+//    assert!(!is_legacy_porep_id(porep_id));
+//    let verified = aggregate_seal_proofs::<SectorShape32GiB>(
+//        SECTOR_SIZE_32_GIB,
+//        &porep_id,
+//        ApiVersion::V1_1_0,
+//        proofs_to_aggregate,
+//    )?;
+//    assert!(verified);
+//
+//    Ok(())
+//}
+
+//#[test]
+//#[ignore]
+//fn test_seal_proof_aggregation_818_64gib_porep_id_v1_1_base_8() -> Result<()> {
+//    let proofs_to_aggregate = 818; // Requires auto-padding
+//
+//    let porep_id = ARBITRARY_POREP_ID_V1_1_0;
+//    assert!(!is_legacy_porep_id(porep_id));
+//    let verified = aggregate_seal_proofs::<SectorShape64GiB>(
 //        SECTOR_SIZE_64_GIB,
 //        &porep_id,
 //        ApiVersion::V1_1_0,
@@ -1020,15 +2434,16 @@ fn aggregate_seal_proofs<Tree: 'static + MerkleTreeTrait>(
         let mut seeds = Vec::with_capacity(num_proofs_to_aggregate);
         let mut comm_rs = Vec::with_capacity(num_proofs_to_aggregate);
 
-        let (commit_output, commit_input, seed, comm_r) =
+        let (commit_output, commit_input, record) =
             create_seal_for_aggregation::<_, Tree>(&mut rng, porep_config, prover_id)?;
 
         for _ in 0..num_proofs_to_aggregate {
             commit_outputs.push(commit_output.clone());
             commit_inputs.extend(commit_input.clone());
-            seeds.push(seed);
-            comm_rs.push(comm_r);
+            seeds.push(record.seed);
+            comm_rs.push(record.comm_r);
         }
+        let records = vec![record; num_proofs_to_aggregate];
 
         let aggregate_proof = aggregate_seal_commit_proofs::<Tree>(
             porep_config,
@@ -1047,6 +2462,47 @@ fn aggregate_seal_proofs<Tree: 'static + MerkleTreeTrait>(
             aggregate_version,
         )?);
 
+        // The public inputs can also be derived from the records.
+        assert!(verify_aggregate_seal_commit_proofs_with_records::<Tree>(
+            porep_config,
+            aggregate_proof.clone(),
+            &records,
+            aggregate_version,
+        )?);
+        let mut wrong_records = records.clone();
+        wrong_records[0].sector_id = (u64::from(wrong_records[0].sector_id) + 1).into();
+        assert!(!verify_aggregate_seal_commit_proofs_with_records::<Tree>(
+            porep_config,
+            aggregate_proof.clone(),
+            &wrong_records,
+            aggregate_version,
+        )?);
+
+        // The same aggregate, collected incrementally and resumed from disk.
+        let builder_dir = tempdir()?;
+        let builder_path = builder_dir.path().join("aggregation");
+        let half = num_proofs_to_aggregate / 2;
+        let sectors = || comm_rs.iter().zip(&seeds).zip(&commit_outputs);
+        let mut builder = AggregationBuilder::new_seal(porep_config);
+        for ((comm_r, seed), commit_output) in sectors().take(half) {
+            builder.append_seal(*comm_r, *seed, commit_output)?;
+        }
+        builder.save(&builder_path)?;
+        let mut builder = AggregationBuilder::load(&builder_path)?;
+        for ((comm_r, seed), commit_output) in sectors().skip(half) {
+            builder.append_seal(*comm_r, *seed, commit_output)?;
+        }
+        assert_eq!(builder.len(), num_proofs_to_aggregate);
+        let builder_aggregate_proof = builder.finalize(aggregate_version)?;
+        assert!(verify_aggregate_seal_commit_proofs::<Tree>(
+            porep_config,
+            builder_aggregate_proof,
+            &comm_rs,
+            &seeds,
+            commit_inputs.clone(),
+            aggregate_version,
+        )?);
+
         // This ensures that once we generate an snarkpack proof
         // with one version, it cannot verify with another.
         let conflicting_aggregate_version = match aggregate_version {
@@ -1720,6 +3176,136 @@ fn test_window_post_partition_matching_2kib_base_8() -> Result<()> {
     Ok(())
 }
 
+#[test]
+#[ignore]
+fn test_window_post_aggregation_2kib_base_8() -> Result<()> {
+    let sector_size = SECTOR_SIZE_2_KIB;
+    let sector_count = *WINDOW_POST_SECTOR_COUNT
+        .read()
+        .expect("WINDOW_POST_SECTOR_COUNT poisoned")
+        .get(&sector_size)
+        .expect("unknown sector size");
+
+    // A single partition is padded to two proofs, three partitions are padded to four.
+    aggregate_window_post::<SectorShape2KiB>(sector_size, sector_count, sector_count)?;
+    aggregate_window_post::<SectorShape2KiB>(sector_size, 2 * sector_count + 1, sector_count)?;
+
+    Ok(())
+}
+
+fn aggregate_window_post<Tree: 'static + MerkleTreeTrait>(
+    sector_size: u64,
+    total_sector_count: usize,
+    sector_count: usize,
+) -> Result<()> {
+    let mut rng = XorShiftRng::from_seed(TEST_SEED);
+    let api_version = ApiVersion::V1_2_0;
+    let aggregate_version = groth16::aggregate::AggregateVersion::V2;
+
+    let prover_fr: <Tree::Hasher as Hasher>::Domain = Fr::random(&mut rng).into();
+    let mut prover_id = [0u8; 32];
+    prover_id.copy_from_slice(AsRef::<[u8]>::as_ref(&prover_fr));
+
+    let mut sectors = Vec::with_capacity(total_sector_count);
+    let mut pub_replicas = BTreeMap::new();
+    let mut priv_replicas = BTreeMap::new();
+    for _ in 0..total_sector_count {
+        let (sector_id, replica, comm_r, cache_dir) = create_fake_seal::<_, Tree>(
+            &mut rng,
+            sector_size,
+            &ARBITRARY_POREP_ID_V1_2_0,
+            api_version,
+        )?;
+        priv_replicas.insert(
+            sector_id,
+            PrivateReplicaInfo::<Tree>::new(
+                replica.path().into(),
+                comm_r,
+                cache_dir.path().into(),
+            )?,
+        );
+        pub_replicas.insert(sector_id, PublicReplicaInfo::new(comm_r)?);
+        sectors.push((replica, cache_dir));
+    }
+
+    let random_fr: <Tree::Hasher as Hasher>::Domain = Fr::random(&mut rng).into();
+    let mut randomness = [0u8; 32];
+    randomness.copy_from_slice(AsRef::<[u8]>::as_ref(&random_fr));
+
+    let config = PoStConfig {
+        sector_size: sector_size.into(),
+        sector_count,
+        challenge_count: WINDOW_POST_CHALLENGE_COUNT,
+        typ: PoStType::Window,
+        priority: false,
+        api_version,
+    };
+
+    let proof = generate_window_post::<Tree>(&config, &randomness, &priv_replicas, prover_id)?;
+    let aggregate_proof = aggregate_window_post_proofs::<Tree>(
+        &config,
+        &randomness,
+        prover_id,
+        &pub_replicas,
+        &proof,
+        aggregate_version,
+    )?;
+    let valid = verify_aggregate_window_post_proofs::<Tree>(
+        &config,
+        &randomness,
+        prover_id,
+        &pub_replicas,
+        &aggregate_proof,
+        aggregate_version,
+    )?;
+    assert!(valid, "aggregate proof did not verify");
+
+    // The aggregate proof is bound to the randomness, the prover id and the sectors.
+    let mut wrong_randomness = randomness;
+    wrong_randomness[0] ^= 1;
+    assert!(!verify_aggregate_window_post_proofs::<Tree>(
+        &config,
+        &wrong_randomness,
+        prover_id,
+        &pub_replicas,
+        &aggregate_proof,
+        aggregate_version,
+    )?);
+
+    let mut wrong_prover_id = prover_id;
+    wrong_prover_id[0] ^= 1;
+    assert!(!verify_aggregate_window_post_proofs::<Tree>(
+        &config,
+        &randomness,
+        wrong_prover_id,
+        &pub_replicas,
+        &aggregate_proof,
+        aggregate_version,
+    )?);
+
+    let mut wrong_replicas = pub_replicas.clone();
+    let (sector_id, _) = wrong_replicas
+        .pop_first()
+        .expect("at least one sector exists");
+    let (_, _, comm_r, _) = create_fake_seal::<_, Tree>(
+        &mut rng,
+        sector_size,
+        &ARBITRARY_POREP_ID_V1_2_0,
+        api_version,
+    )?;
+    wrong_replicas.insert(sector_id, PublicReplicaInfo::new(comm_r)?);
+    assert!(!verify_aggregate_window_post_proofs::<Tree>(
+        &config,
+        &randomness,
+        prover_id,
+        &wrong_replicas,
+        &aggregate_proof,
+        aggregate_version,
+    )?);
+
+    Ok(())
+}
+
 #[allow(clippy::iter_kv_map)]
 fn partition_window_post<Tree: 'static + MerkleTreeTrait>(
     sector_size: u64,
@@ -1855,6 +3441,63 @@ fn set_readonly_flag(path: &Path, readonly: bool) {
     }
 }
 
+#[test]
+fn test_fallback_post_sector_proof_encoding_2kib() -> Result<()> {
+    fallback_post_sector_proof_encoding::<SectorShape2KiB>(SECTOR_SIZE_2_KIB)
+}
+
+#[test]
+fn test_fallback_post_sector_proof_encoding_4kib_sub_8_2() -> Result<()> {
+    fallback_post_sector_proof_encoding::<SectorShape4KiB>(SECTOR_SIZE_4_KIB)
+}
+
+fn fallback_post_sector_proof_encoding<Tree: 'static + MerkleTreeTrait>(
+    sector_size: u64,
+) -> Result<()> {
+    let mut rng = XorShiftRng::from_seed(TEST_SEED);
+    let api_version = ApiVersion::V1_2_0;
+    let (sector_id, replica, comm_r, cache_dir) = create_fake_seal::<_, Tree>(
+        &mut rng,
+        sector_size,
+        &ARBITRARY_POREP_ID_V1_2_0,
+        api_version,
+    )?;
+    let replica_info =
+        PrivateReplicaInfo::<Tree>::new(replica.path().into(), comm_r, cache_dir.path().into())?;
+
+    let config = PoStConfig {
+        sector_size: sector_size.into(),
+        sector_count: 1,
+        challenge_count: WINDOW_POST_CHALLENGE_COUNT,
+        typ: PoStType::Window,
+        priority: false,
+        api_version,
+    };
+    let randomness = [7u8; 32];
+    let challenges =
+        generate_fallback_sector_challenges::<Tree>(&config, &randomness, &[sector_id], [0u8; 32])?;
+    let vanilla_proof = generate_single_vanilla_proof::<Tree>(
+        &config,
+        sector_id,
+        &replica_info,
+        &challenges[&sector_id],
+    )?;
+
+    // The challenges open the same tree, so the multiproof is smaller than the single proofs.
+    let encoded = encode_fallback_post_sector_proof(&vanilla_proof)?;
+    assert!(encoded.len() < serialize(&vanilla_proof)?.len());
+    let decoded = decode_fallback_post_sector_proof::<Tree>(&encoded)?;
+    assert_eq!(serialize(&decoded)?, serialize(&vanilla_proof)?);
+
+    // Any corruption is detected by the checksum.
+    let mut corrupted = encoded;
+    let last = corrupted.len() - 40;
+    corrupted[last] ^= 1;
+    assert!(decode_fallback_post_sector_proof::<Tree>(&corrupted).is_err());
+
+    Ok(())
+}
+
 #[allow(clippy::iter_kv_map)]
 fn window_post<Tree: 'static + MerkleTreeTrait>(
     sector_size: u64,
@@ -2308,13 +3951,141 @@ fn create_seal<R: Rng, Tree: 'static + MerkleTreeTrait>(
     porep_config: &PoRepConfig,
     rng: &mut R,
     prover_id: ProverId,
-    skip_proof: bool,
-) -> Result<(SectorId, NamedTempFile, Commitment, TempDir)> {
+    skip_proof: bool,
+) -> Result<(SectorId, NamedTempFile, Commitment, TempDir)> {
+    fil_logger::maybe_init();
+
+    let (mut piece_file, piece_bytes) = generate_piece_file(porep_config.sector_size.into())?;
+    let sealed_sector_file = NamedTempFile::new()?;
+    let cache_dir = tempdir().expect("failed to create temp dir");
+
+    let ticket = rng.gen();
+    let seed = rng.gen();
+    let sector_id = rng.gen::<u64>().into();
+
+    let (piece_infos, phase1_output) = run_seal_pre_commit_phase1::<Tree>(
+        porep_config,
+        prover_id,
+        sector_id,
+        ticket,
+        &cache_dir,
+        &mut piece_file,
+        &sealed_sector_file,
+    )?;
+
+    let num_layers = phase1_output.labels.len();
+    let pre_commit_output = seal_pre_commit_phase2(
+        porep_config,
+        phase1_output,
+        cache_dir.path(),
+        sealed_sector_file.path(),
+    )?;
+
+    // Check if creating only the tree_r_last generates the same output as the full pre commit
+    // phase 2 process.
+    let tree_r_last_dir = tempdir().expect("failed to create temp dir");
+    generate_tree_r_last::<_, _, Tree>(
+        porep_config.sector_size.into(),
+        &sealed_sector_file,
+        &tree_r_last_dir,
+    )?;
+    compare_trees::<Tree>(&tree_r_last_dir, &cache_dir, CacheKey::CommRLastTree)?;
+
+    // Check if creating only the tree_c generates the same output as the full pre commit phase 2
+    // process.
+    let tree_c_dir = tempdir().expect("failed to create temp dir");
+    generate_tree_c::<_, _, Tree>(
+        porep_config.sector_size.into(),
+        &cache_dir,
+        &tree_c_dir,
+        num_layers,
+    )?;
+    compare_trees::<Tree>(&tree_c_dir, &cache_dir, CacheKey::CommCTree)?;
+
+    let comm_r = pre_commit_output.comm_r;
+
+    if skip_proof {
+        if porep_config.feature_enabled(ApiFeature::SyntheticPoRep) {
+            clear_synthetic_proofs(cache_dir.path())?;
+        }
+        clear_cache(cache_dir.path())?;
+    } else {
+        proof_and_unseal::<Tree>(
+            porep_config,
+            cache_dir.path(),
+            &sealed_sector_file,
+            prover_id,
+            sector_id,
+            ticket,
+            seed,
+            pre_commit_output,
+            &piece_infos,
+            &piece_bytes,
+        )
+        .expect("failed to proof_and_unseal");
+    }
+
+    Ok((sector_id, sealed_sector_file, comm_r, cache_dir))
+}
+
+/// Runs all sealing steps up to and including commit phase 1 on a random piece.
+fn create_seal_commit_phase1_output<R: Rng, Tree: 'static + MerkleTreeTrait>(
+    porep_config: &PoRepConfig,
+    rng: &mut R,
+) -> Result<SealCommitPhase1Output<Tree>> {
+    fil_logger::maybe_init();
+
+    let prover_fr: DefaultTreeDomain = Fr::random(&mut *rng).into();
+    let mut prover_id = [0u8; 32];
+    prover_id.copy_from_slice(AsRef::<[u8]>::as_ref(&prover_fr));
+
+    let (mut piece_file, _piece_bytes) = generate_piece_file(porep_config.sector_size.into())?;
+    let sealed_sector_file = NamedTempFile::new()?;
+    let cache_dir = tempdir().expect("failed to create temp dir");
+
+    let ticket = rng.gen();
+    let seed = rng.gen();
+    let sector_id = rng.gen::<u64>().into();
+
+    let (piece_infos, phase1_output) = run_seal_pre_commit_phase1::<Tree>(
+        porep_config,
+        prover_id,
+        sector_id,
+        ticket,
+        &cache_dir,
+        &mut piece_file,
+        &sealed_sector_file,
+    )?;
+    let pre_commit_output = seal_pre_commit_phase2(
+        porep_config,
+        phase1_output,
+        cache_dir.path(),
+        sealed_sector_file.path(),
+    )?;
+    seal_commit_phase1::<_, Tree>(
+        porep_config,
+        cache_dir.path(),
+        sealed_sector_file.path(),
+        prover_id,
+        sector_id,
+        ticket,
+        seed,
+        pre_commit_output,
+        &piece_infos,
+    )
+}
+
+fn create_seal_for_aggregation<R: Rng, Tree: 'static + MerkleTreeTrait>(
+    rng: &mut R,
+    porep_config: &PoRepConfig,
+    prover_id: ProverId,
+) -> Result<(SealCommitOutput, Vec<Vec<Fr>>, SealProofRecord)> {
     fil_logger::maybe_init();
 
-    let (mut piece_file, piece_bytes) = generate_piece_file(porep_config.sector_size.into())?;
+    let sector_size = porep_config.sector_size.into();
+    let (mut piece_file, _piece_bytes) = generate_piece_file(sector_size)?;
     let sealed_sector_file = NamedTempFile::new()?;
-    let cache_dir = tempdir().expect("failed to create temp dir");
+    let cache_dir = tempfile::tempdir().expect("failed to create temp dir");
 
     let ticket = rng.gen();
     let seed = rng.gen();
@@ -2330,7 +4101,6 @@ fn create_seal<R: Rng, Tree: 'static + MerkleTreeTrait>(
         &sealed_sector_file,
     )?;
 
-    let num_layers = phase1_output.labels.len();
     let pre_commit_output = seal_pre_commit_phase2(
         porep_config,
         phase1_output,
@@ -2338,58 +4108,38 @@ fn create_seal<R: Rng, Tree: 'static + MerkleTreeTrait>(
         sealed_sector_file.path(),
     )?;
 
-    // Check if creating only the tree_r_last generates the same output as the full pre commit
-    // phase 2 process.
-    let tree_r_last_dir = tempdir().expect("failed to create temp dir");
-    generate_tree_r_last::<_, _, Tree>(
-        porep_config.sector_size.into(),
-        &sealed_sector_file,
-        &tree_r_last_dir,
-    )?;
-    compare_trees::<Tree>(&tree_r_last_dir, &cache_dir, CacheKey::CommRLastTree)?;
+    validate_cache_for_commit::<_, _, Tree>(cache_dir.path(), sealed_sector_file.path())?;
 
-    // Check if creating only the tree_c generates the same output as the full pre commit phase 2
-    // process.
-    let tree_c_dir = tempdir().expect("failed to create temp dir");
-    generate_tree_c::<_, _, Tree>(
-        porep_config.sector_size.into(),
-        &cache_dir,
-        &tree_c_dir,
-        num_layers,
+    let aggregation_enabled = true;
+    let (commit_output, commit_inputs, seed, comm_r) = generate_proof::<Tree>(
+        porep_config,
+        cache_dir.path(),
+        &sealed_sector_file,
+        prover_id,
+        sector_id,
+        ticket,
+        seed,
+        &pre_commit_output,
+        &piece_infos,
+        aggregation_enabled,
     )?;
-    compare_trees::<Tree>(&tree_c_dir, &cache_dir, CacheKey::CommCTree)?;
-
-    let comm_r = pre_commit_output.comm_r;
-
-    if skip_proof {
-        if porep_config.feature_enabled(ApiFeature::SyntheticPoRep) {
-            clear_synthetic_proofs(cache_dir.path())?;
-        }
-        clear_cache(cache_dir.path())?;
-    } else {
-        proof_and_unseal::<Tree>(
-            porep_config,
-            cache_dir.path(),
-            &sealed_sector_file,
-            prover_id,
-            sector_id,
-            ticket,
-            seed,
-            pre_commit_output,
-            &piece_infos,
-            &piece_bytes,
-        )
-        .expect("failed to proof_and_unseal");
-    }
+    let record = SealProofRecord {
+        comm_r,
+        comm_d: pre_commit_output.comm_d,
+        prover_id,
+        sector_id,
+        ticket,
+        seed,
+    };
 
-    Ok((sector_id, sealed_sector_file, comm_r, cache_dir))
+    Ok((commit_output, commit_inputs, record))
 }
 
-fn create_seal_for_aggregation<R: Rng, Tree: 'static + MerkleTreeTrait>(
+fn create_ni_porep_proof<R: Rng, Tree: 'static + MerkleTreeTrait>(
     rng: &mut R,
     porep_config: &PoRepConfig,
     prover_id: ProverId,
-) -> Result<(SealCommitOutput, Vec<Vec<Fr>>, [u8; 32], [u8; 32])> {
+) -> Result<(SealCommitOutput, NiSealProofRecord)> {
     fil_logger::maybe_init();
 
     let sector_size = porep_config.sector_size.into();
@@ -2398,7 +4148,6 @@ fn create_seal_for_aggregation<R: Rng, Tree: 'static + MerkleTreeTrait>(
     let cache_dir = tempfile::tempdir().expect("failed to create temp dir");
 
     let ticket = rng.gen();
-    let seed = rng.gen();
     let sector_id = rng.gen::<u64>().into();
 
     let (piece_infos, phase1_output) = run_seal_pre_commit_phase1::<Tree>(
@@ -2410,7 +4159,6 @@ fn create_seal_for_aggregation<R: Rng, Tree: 'static + MerkleTreeTrait>(
         &mut piece_file,
         &sealed_sector_file,
     )?;
-
     let pre_commit_output = seal_pre_commit_phase2(
         porep_config,
         phase1_output,
@@ -2418,21 +4166,25 @@ fn create_seal_for_aggregation<R: Rng, Tree: 'static + MerkleTreeTrait>(
         sealed_sector_file.path(),
     )?;
 
-    validate_cache_for_commit::<_, _, Tree>(cache_dir.path(), sealed_sector_file.path())?;
-
-    let aggregation_enabled = true;
-    generate_proof::<Tree>(
+    let commit_output = seal_ni_porep::<_, Tree>(
         porep_config,
         cache_dir.path(),
-        &sealed_sector_file,
+        sealed_sector_file.path(),
         prover_id,
         sector_id,
         ticket,
-        seed,
-        &pre_commit_output,
+        pre_commit_output.clone(),
         &piece_infos,
-        aggregation_enabled,
-    )
+    )?;
+    let record = NiSealProofRecord {
+        comm_r: pre_commit_output.comm_r,
+        comm_d: pre_commit_output.comm_d,
+        prover_id,
+        sector_id,
+        ticket,
+    };
+
+    Ok((commit_output, record))
 }
 
 fn compare_elements(path1: &Path, path2: &Path) -> Result<(), Error> {
@@ -2508,7 +4260,7 @@ fn compare_trees<Tree: 'static + MerkleTreeTrait>(
 /// The decoding is done in several arbitrarily sized parts.
 fn decode_from_range_in_parts<R: Rng>(
     rng: &mut R,
-    nodes_count: usize,
+    config: &SectorUpdateConfig,
     comm_d: Commitment,
     comm_r: Commitment,
     mut input_file: &NamedTempFile,
@@ -2517,6 +4269,7 @@ fn decode_from_range_in_parts<R: Rng>(
 ) -> Result<()> {
     const MAX_NUM_NODES: usize = 10;
 
+    let nodes_count = config.nodes_count;
     let mut offset = 0;
     while offset < nodes_count {
         // Select a number of nodes that is between 1 and 10.
@@ -2531,8 +4284,8 @@ fn decode_from_range_in_parts<R: Rng>(
         sector_key_file
             .seek(SeekFrom::Start((offset * NODE_SIZE) as u64))
             .expect("failed to seek sector key");
-        decode_from_range(
-            nodes_count,
+        decode_from_range_with_config(
+            config,
             comm_d,
             comm_r,
             input_file,
@@ -2546,8 +4299,27 @@ fn decode_from_range_in_parts<R: Rng>(
     Ok(())
 }
 
+/// An updated sector, as seen by the proving functions of [`ProveAndVerifyUpdate`].
+struct UpdatedSector<'a> {
+    comm_r_old: Commitment,
+    comm_r_new: Commitment,
+    comm_d_new: Commitment,
+    sector_key_path: &'a Path,
+    sector_key_cache_path: &'a Path,
+    replica_path: &'a Path,
+    replica_cache_path: &'a Path,
+}
+
+/// Generates and verifies the partition proofs and the compound proofs of an updated sector, with
+/// the functions of one update proof type.
+type ProveAndVerifyUpdate = fn(SectorUpdateConfig, &UpdatedSector<'_>) -> Result<()>;
+
+/// Seals a CC sector, updates it with `config`, proves and verifies the update with
+/// `prove_and_verify`, and checks that the data can be decoded and removed again.
 fn create_seal_for_upgrade<R: Rng, Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>>(
     porep_config: &PoRepConfig,
+    config: SectorUpdateConfig,
+    prove_and_verify: ProveAndVerifyUpdate,
     rng: &mut R,
     prover_id: ProverId,
 ) -> Result<(SectorId, NamedTempFile, Commitment, TempDir)> {
@@ -2558,7 +4330,6 @@ fn create_seal_for_upgrade<R: Rng, Tree: 'static + MerkleTreeTrait<Hasher = Tree
     let sealed_sector_file = NamedTempFile::new()?;
     let cache_dir = tempdir().expect("failed to create temp dir");
 
-    let config = SectorUpdateConfig::from_porep_config(porep_config);
     let ticket = rng.gen();
     let sector_id = rng.gen::<u64>().into();
 
@@ -2644,86 +4415,26 @@ fn create_seal_for_upgrade<R: Rng, Tree: 'static + MerkleTreeTrait<Hasher = Tree
         &new_piece_infos,
     )?;
 
-    // Generate a single partition proof
-    let partition_proof = generate_single_partition_proof::<Tree>(
-        config,
-        0, // first partition
-        comm_r,
-        encoded.comm_r_new,
-        encoded.comm_d_new,
-        sealed_sector_file.path(), /* sector key file */
-        cache_dir.path(),          /* sector key path needed for p_aux and t_aux */
-        new_sealed_sector_file.path(),
-        new_cache_dir.path(),
-    )?;
-
-    // Verify the single partition proof
-    let proof_is_valid = verify_single_partition_proof::<Tree>(
-        config,
-        0, // first partition
-        partition_proof,
-        comm_r,
-        encoded.comm_r_new,
-        encoded.comm_d_new,
-    )?;
-    ensure!(proof_is_valid, "Partition proof (single) failed to verify");
-
-    // Generate all partition proofs
-    let partition_proofs = generate_partition_proofs::<Tree>(
-        config,
-        comm_r,
-        encoded.comm_r_new,
-        encoded.comm_d_new,
-        sealed_sector_file.path(), /* sector key file */
-        cache_dir.path(),          /* sector key path needed for p_aux and t_aux */
-        new_sealed_sector_file.path(),
-        new_cache_dir.path(),
-    )?;
+    if config.update_proof_type == UpdateProofType::Poseidon {
+        // CommDNew is a Poseidon root, hence it differs from the piece commitment.
+        ensure!(
+            encoded.comm_d_new != new_piece_infos[0].commitment,
+            "Poseidon CommDNew must not be the SHA256 CommD"
+        );
+    }
 
-    // Verify all partition proofs
-    let proofs_are_valid = verify_partition_proofs::<Tree>(
+    prove_and_verify(
         config,
-        &partition_proofs,
-        comm_r,
-        encoded.comm_r_new,
-        encoded.comm_d_new,
-    )?;
-    ensure!(proofs_are_valid, "Partition proofs failed to verify");
-
-    let proof = generate_empty_sector_update_proof_with_vanilla::<Tree>(
-        porep_config,
-        partition_proofs,
-        comm_r,
-        encoded.comm_r_new,
-        encoded.comm_d_new,
-    )?;
-    let valid = verify_empty_sector_update_proof::<Tree>(
-        porep_config,
-        &proof.0,
-        comm_r,
-        encoded.comm_r_new,
-        encoded.comm_d_new,
-    )?;
-    ensure!(valid, "Compound proof failed to verify");
-
-    let proof = generate_empty_sector_update_proof::<Tree>(
-        porep_config,
-        comm_r,
-        encoded.comm_r_new,
-        encoded.comm_d_new,
-        sealed_sector_file.path(), /* sector key file */
-        cache_dir.path(),          /* sector key path needed for p_aux and t_aux */
-        new_sealed_sector_file.path(),
-        new_cache_dir.path(),
-    )?;
-    let valid = verify_empty_sector_update_proof::<Tree>(
-        porep_config,
-        &proof.0,
-        comm_r,
-        encoded.comm_r_new,
-        encoded.comm_d_new,
+        &UpdatedSector {
+            comm_r_old: comm_r,
+            comm_r_new: encoded.comm_r_new,
+            comm_d_new: encoded.comm_d_new,
+            sector_key_path: sealed_sector_file.path(),
+            sector_key_cache_path: cache_dir.path(),
+            replica_path: new_sealed_sector_file.path(),
+            replica_cache_path: new_cache_dir.path(),
+        },
     )?;
-    ensure!(valid, "Compound proof failed to verify");
 
     let decoded_sector_file = NamedTempFile::new()?;
     // New replica (new_sealed_sector_file) is currently 0 bytes --
@@ -2755,7 +4466,7 @@ fn create_seal_for_upgrade<R: Rng, Tree: 'static + MerkleTreeTrait<Hasher = Tree
     let mut decoded_sector_in_parts_file = NamedTempFile::new()?;
     decode_from_range_in_parts(
         rng,
-        sector_size as usize / NODE_SIZE,
+        &config,
         encoded.comm_d_new,
         comm_r,
         &new_sealed_sector_file,
@@ -2812,6 +4523,197 @@ fn create_seal_for_upgrade<R: Rng, Tree: 'static + MerkleTreeTrait<Hasher = Tree
     Ok((sector_id, sealed_sector_file, comm_r, cache_dir))
 }
 
+fn prove_and_verify_update<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>>(
+    config: SectorUpdateConfig,
+    sector: &UpdatedSector<'_>,
+) -> Result<()> {
+    // Generate a single partition proof
+    let partition_proof = generate_single_partition_proof::<Tree>(
+        config,
+        0, // first partition
+        sector.comm_r_old,
+        sector.comm_r_new,
+        sector.comm_d_new,
+        sector.sector_key_path,
+        sector.sector_key_cache_path,
+        sector.replica_path,
+        sector.replica_cache_path,
+    )?;
+
+    // Verify the single partition proof
+    let proof_is_valid = verify_single_partition_proof::<Tree>(
+        config,
+        0, // first partition
+        partition_proof,
+        sector.comm_r_old,
+        sector.comm_r_new,
+        sector.comm_d_new,
+    )?;
+    ensure!(proof_is_valid, "Partition proof (single) failed to verify");
+
+    // Generate all partition proofs
+    let partition_proofs = generate_partition_proofs::<Tree>(
+        config,
+        sector.comm_r_old,
+        sector.comm_r_new,
+        sector.comm_d_new,
+        sector.sector_key_path,
+        sector.sector_key_cache_path,
+        sector.replica_path,
+        sector.replica_cache_path,
+    )?;
+
+    // Verify all partition proofs
+    let proofs_are_valid = verify_partition_proofs::<Tree>(
+        config,
+        &partition_proofs,
+        sector.comm_r_old,
+        sector.comm_r_new,
+        sector.comm_d_new,
+    )?;
+    ensure!(proofs_are_valid, "Partition proofs failed to verify");
+
+    let proof = generate_empty_sector_update_proof_with_vanilla_and_config::<Tree>(
+        &config,
+        partition_proofs,
+        sector.comm_r_old,
+        sector.comm_r_new,
+        sector.comm_d_new,
+    )?;
+    let valid = verify_empty_sector_update_proof_with_config::<Tree>(
+        &config,
+        &proof.0,
+        sector.comm_r_old,
+        sector.comm_r_new,
+        sector.comm_d_new,
+    )?;
+    ensure!(valid, "Compound proof failed to verify");
+
+    let proof = generate_empty_sector_update_proof_with_config::<Tree>(
+        &config,
+        sector.comm_r_old,
+        sector.comm_r_new,
+        sector.comm_d_new,
+        sector.sector_key_path,
+        sector.sector_key_cache_path,
+        sector.replica_path,
+        sector.replica_cache_path,
+    )?;
+    let valid = verify_empty_sector_update_proof_with_config::<Tree>(
+        &config,
+        &proof.0,
+        sector.comm_r_old,
+        sector.comm_r_new,
+        sector.comm_d_new,
+    )?;
+    ensure!(valid, "Compound proof failed to verify");
+
+    // Proving one partition at a time results in a proof of the same form.
+    let batched_proof = generate_empty_sector_update_proof_in_batches::<Tree>(
+        &config,
+        sector.comm_r_old,
+        sector.comm_r_new,
+        sector.comm_d_new,
+        sector.sector_key_path,
+        sector.sector_key_cache_path,
+        sector.replica_path,
+        sector.replica_cache_path,
+        1,
+    )?;
+    ensure!(
+        batched_proof.0.len() == proof.0.len(),
+        "Batched compound proof has a different length"
+    );
+    let valid = verify_empty_sector_update_proof_with_config::<Tree>(
+        &config,
+        &batched_proof.0,
+        sector.comm_r_old,
+        sector.comm_r_new,
+        sector.comm_d_new,
+    )?;
+    ensure!(valid, "Batched compound proof failed to verify");
+
+    Ok(())
+}
+
+fn prove_and_verify_poseidon_update<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>>(
+    config: SectorUpdateConfig,
+    sector: &UpdatedSector<'_>,
+) -> Result<()> {
+    // The SHA256 proving functions reject the Poseidon config.
+    ensure!(
+        generate_partition_proofs::<Tree>(
+            config,
+            sector.comm_r_old,
+            sector.comm_r_new,
+            sector.comm_d_new,
+            sector.sector_key_path,
+            sector.sector_key_cache_path,
+            sector.replica_path,
+            sector.replica_cache_path,
+        )
+        .is_err(),
+        "SHA256 partition proofs must not accept a Poseidon config"
+    );
+
+    let partition_proof = generate_poseidon_partition_proof::<Tree>(
+        config,
+        sector.comm_r_old,
+        sector.comm_r_new,
+        sector.comm_d_new,
+        sector.sector_key_path,
+        sector.sector_key_cache_path,
+        sector.replica_path,
+        sector.replica_cache_path,
+    )?;
+
+    let proof_is_valid = verify_poseidon_partition_proof::<Tree>(
+        config,
+        partition_proof.clone(),
+        sector.comm_r_old,
+        sector.comm_r_new,
+        sector.comm_d_new,
+    )?;
+    ensure!(proof_is_valid, "Poseidon partition proof failed to verify");
+
+    let proof = generate_empty_sector_update_poseidon_proof_with_vanilla::<Tree>(
+        config,
+        partition_proof,
+        sector.comm_r_old,
+        sector.comm_r_new,
+        sector.comm_d_new,
+    )?;
+    let valid = verify_empty_sector_update_poseidon_proof::<Tree>(
+        config,
+        &proof.0,
+        sector.comm_r_old,
+        sector.comm_r_new,
+        sector.comm_d_new,
+    )?;
+    ensure!(valid, "Poseidon compound proof failed to verify");
+
+    let proof = generate_empty_sector_update_poseidon_proof::<Tree>(
+        config,
+        sector.comm_r_old,
+        sector.comm_r_new,
+        sector.comm_d_new,
+        sector.sector_key_path,
+        sector.sector_key_cache_path,
+        sector.replica_path,
+        sector.replica_cache_path,
+    )?;
+    let valid = verify_empty_sector_update_poseidon_proof::<Tree>(
+        config,
+        &proof.0,
+        sector.comm_r_old,
+        sector.comm_r_new,
+        sector.comm_d_new,
+    )?;
+    ensure!(valid, "Poseidon compound proof failed to verify");
+
+    Ok(())
+}
+
 fn create_seal_for_upgrade_aggregation<
     R: Rng,
     Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>,
@@ -6,8 +6,8 @@ use blstrs::Scalar as Fr;
 use filecoin_proofs::{
     add_piece, commitment_from_fr,
     pieces::{
-        compute_comm_d, get_piece_alignment, get_piece_start_byte, piece_hash, verify_pieces,
-        zero_padding, EmptySource, PieceAlignment,
+        compute_comm_d, get_piece_alignment, get_piece_start_byte, is_zero_range, piece_hash,
+        verify_pieces, zero_padding, zero_ranges, EmptySource, PieceAlignment,
     },
     Commitment, DataTree, DefaultPieceHasher, PaddedBytesAmount, PieceInfo, SectorSize,
     UnpaddedByteIndex, UnpaddedBytesAmount, DRG_DEGREE, EXP_DEGREE, TEST_SEED,
@@ -101,6 +101,30 @@ fn test_get_piece_start_byte() {
     );
 }
 
+#[test]
+fn test_zero_ranges() -> Result<()> {
+    let sector_size = SectorSize(2048);
+    assert_eq!(zero_ranges(sector_size, &[])?, vec![0..2032]);
+
+    let mut rng = XorShiftRng::from_seed(TEST_SEED);
+    let piece_infos = vec![
+        PieceInfo::new(rng.gen(), UnpaddedBytesAmount(127))?,
+        PieceInfo::new(rng.gen(), UnpaddedBytesAmount(508))?,
+        zero_padding(UnpaddedBytesAmount(254))?,
+    ];
+    // The alignment before the second piece is zero, as well as the zero piece, which is merged
+    // with the padding after it.
+    let ranges = zero_ranges(sector_size, &piece_infos)?;
+    assert_eq!(ranges, vec![127..508, 1016..2032]);
+
+    assert!(is_zero_range(&ranges, &(127..508)));
+    assert!(is_zero_range(&ranges, &(1100..2032)));
+    assert!(!is_zero_range(&ranges, &(100..200)));
+    assert!(!is_zero_range(&ranges, &(127..1100)));
+
+    Ok(())
+}
+
 #[test]
 fn test_verify_simple_pieces() {
     let rng = &mut XorShiftRng::from_seed(TEST_SEED);
@@ -12,6 +12,11 @@ mod platform;
 mod sha256;
 #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
 mod sha256_intrinsics;
+#[cfg(target_arch = "x86_64")]
+mod sha256_multi;
 mod sha256_utils;
 
-pub use sha256::Sha256;
+pub use platform::{Backend, Implementation, UnknownBackend, BACKEND_ENV_VAR};
+pub use sha256::{
+    backend, compress256_x4, compress256_x8, init, report, self_test, BackendReport, Sha256,
+};
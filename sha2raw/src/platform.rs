@@ -1,5 +1,15 @@
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Instant;
+
+use log::{error, warn};
+
 #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
 use crate::sha256_intrinsics;
+#[cfg(target_arch = "x86_64")]
+use crate::sha256_multi;
 use crate::sha256_utils;
 
 #[allow(dead_code)]
@@ -12,10 +22,272 @@ enum Platform {
     Sha,
 }
 
-#[derive(Clone, Copy, Debug)]
-pub struct Implementation(Platform);
+/// The environment variable that forces a specific backend, see [`Backend`] for the values.
+/// `auto`, or leaving it unset, selects the fastest backend the machine supports.
+pub const BACKEND_ENV_VAR: &str = "FIL_PROOFS_SHA2RAW_BACKEND";
+
+/// The single stream SHA-256 implementations.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum Backend {
+    /// Plain Rust, available everywhere.
+    Portable,
+    /// The `sha2-asm` assembly implementation, available with the `asm` feature.
+    Asm,
+    /// The x86_64 SHA extensions.
+    ShaNi,
+}
+
+impl Backend {
+    pub const ALL: [Backend; 3] = [Backend::Portable, Backend::Asm, Backend::ShaNi];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Backend::Portable => "portable",
+            Backend::Asm => "asm",
+            Backend::ShaNi => "sha-ni",
+        }
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct UnknownBackend(String);
+
+impl fmt::Display for UnknownBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "unknown sha2raw backend {:?}, expected one of auto, portable, asm, sha-ni",
+            self.0
+        )
+    }
+}
+
+impl Error for UnknownBackend {}
+
+impl FromStr for Backend {
+    type Err = UnknownBackend;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Backend::ALL
+            .iter()
+            .copied()
+            .find(|backend| backend.name().eq_ignore_ascii_case(s))
+            .ok_or_else(|| UnknownBackend(s.to_string()))
+    }
+}
+
+/// How several independent messages are hashed at once.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Lanes {
+    /// One message after the other, with the single stream platform.
+    Serial,
+    #[cfg(target_arch = "x86_64")]
+    Sse2,
+    #[cfg(target_arch = "x86_64")]
+    Avx2,
+}
+
+impl Lanes {
+    fn detect() -> Self {
+        #[cfg(target_arch = "x86_64")]
+        {
+            cpufeatures::new!(cpuid_avx2, "avx2");
+
+            if cpuid_avx2::get() {
+                return Lanes::Avx2;
+            }
+            // SSE2 is part of the x86_64 baseline.
+            return Lanes::Sse2;
+        }
+
+        #[allow(unreachable_code)]
+        Lanes::Serial
+    }
+
+    /// The number of messages hashed at once.
+    fn width(self) -> usize {
+        match self {
+            Lanes::Serial => 1,
+            #[cfg(target_arch = "x86_64")]
+            Lanes::Sse2 => 4,
+            #[cfg(target_arch = "x86_64")]
+            Lanes::Avx2 => 8,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Implementation(Platform, Lanes);
 
 impl Implementation {
+    /// Selects the backend forced by [`BACKEND_ENV_VAR`], or detects the fastest one otherwise.
+    /// See [`Implementation::select_backend`].
+    pub fn select() -> Self {
+        Self::select_backend(&env::var(BACKEND_ENV_VAR).unwrap_or_default())
+    }
+
+    /// Selects the backend named `name`, or detects the fastest one if `name` is empty, `auto`,
+    /// unknown or not supported on this machine. The selected implementation is checked against
+    /// the portable one, and replaced by it if it produces different results.
+    pub fn select_backend(name: &str) -> Self {
+        let implementation = if name.is_empty() || name.eq_ignore_ascii_case("auto") {
+            Self::detect()
+        } else {
+            match name.parse() {
+                Ok(backend) => Self::for_backend(backend).unwrap_or_else(|| {
+                    warn!(
+                        "sha2raw backend {} is not supported on this machine, detecting",
+                        backend
+                    );
+                    Self::detect()
+                }),
+                Err(err) => {
+                    warn!("{}, detecting", err);
+                    Self::detect()
+                }
+            }
+        };
+
+        // Every supported backend is tested once, the selected implementation first.
+        let mut results = vec![(implementation, implementation.self_test())];
+        for backend in Backend::ALL.iter().copied() {
+            if let Some(other) = Self::for_backend(backend) {
+                if other != implementation {
+                    results.push((other, other.self_test()));
+                }
+            }
+        }
+        for (other, passed) in &results {
+            if !passed {
+                warn!("sha2raw backend {} failed its self-test", other.backend());
+            }
+        }
+
+        if results[0].1 {
+            implementation
+        } else {
+            error!(
+                "sha2raw backend {} failed its self-test, falling back to portable",
+                implementation.backend()
+            );
+            Self::portable()
+        }
+    }
+
+    /// Returns the implementation of `backend`, if it is supported on this machine. Forcing the
+    /// portable backend also disables the vector lanes of the multi-buffer functions.
+    pub fn for_backend(backend: Backend) -> Option<Self> {
+        match backend {
+            Backend::Portable => Some(Self::portable()),
+            #[cfg(feature = "asm")]
+            Backend::Asm => Self::asm_if_supported(),
+            #[cfg(target_arch = "x86_64")]
+            Backend::ShaNi => Self::sha_if_supported(),
+            #[allow(unreachable_patterns)]
+            _ => None,
+        }
+    }
+
+    pub fn backend(self) -> Backend {
+        match self.0 {
+            Platform::Portable => Backend::Portable,
+            #[cfg(feature = "asm")]
+            Platform::Asm => Backend::Asm,
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            Platform::Sha => Backend::ShaNi,
+        }
+    }
+
+    /// The number of messages the multi-buffer functions hash at once.
+    pub fn lanes(self) -> usize {
+        self.1.width()
+    }
+
+    /// Checks that this implementation, including its multi-buffer functions, produces the same
+    /// results as the portable implementation.
+    pub fn self_test(self) -> bool {
+        let portable = Self::portable();
+
+        // A fixed xorshift sequence, to keep the test deterministic.
+        let mut x = 0x9e37_79b9u32;
+        let mut next = move || {
+            x ^= x << 13;
+            x ^= x >> 17;
+            x ^= x << 5;
+            x
+        };
+
+        let mut inputs = [[0u8; 64 * 4]; 8];
+        let mut states = [[0u32; 8]; 8];
+        for (input, state) in inputs.iter_mut().zip(states.iter_mut()) {
+            input
+                .iter_mut()
+                .for_each(|byte| *byte = next().to_le_bytes()[0]);
+            state.iter_mut().for_each(|word| *word = next());
+        }
+
+        for num_blocks in 1..=4 {
+            let halves: Vec<Vec<&[u8]>> = inputs
+                .iter()
+                .map(|input| input[..num_blocks * 64].chunks(32).collect())
+                .collect();
+
+            let mut expected = states;
+            for (state, blocks) in expected.iter_mut().zip(halves.iter()) {
+                portable.compress256(state, blocks);
+            }
+
+            let mut single = states;
+            for (state, blocks) in single.iter_mut().zip(halves.iter()) {
+                self.compress256(state, blocks);
+            }
+
+            let mut x8 = states;
+            let [s0, s1, s2, s3, s4, s5, s6, s7] = &mut x8;
+            self.compress256_x8(
+                [s0, s1, s2, s3, s4, s5, s6, s7],
+                std::array::from_fn(|lane| &halves[lane][..]),
+            );
+
+            let mut x4 = states;
+            let [s0, s1, s2, s3, ..] = &mut x4;
+            self.compress256_x4(
+                [s0, s1, s2, s3],
+                std::array::from_fn(|lane| &halves[lane][..]),
+            );
+
+            if single != expected || x8 != expected || x4[..4] != expected[..4] {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    /// Measures the single stream throughput of this implementation in bytes per second, by
+    /// hashing `bytes` bytes (rounded up to whole blocks).
+    pub fn throughput(self, bytes: usize) -> f64 {
+        let data = [0x5au8; 64 * 16];
+        let halves: Vec<&[u8]> = data.chunks(32).collect();
+        let rounds = bytes.div_ceil(data.len()).max(1);
+
+        let mut state = [0u32; 8];
+        let start = Instant::now();
+        for _ in 0..rounds {
+            self.compress256(&mut state, &halves);
+        }
+        let elapsed = start.elapsed().as_secs_f64();
+        std::hint::black_box(state);
+
+        (rounds * data.len()) as f64 / elapsed.max(f64::MIN_POSITIVE)
+    }
+
     pub fn detect() -> Self {
         // Try the different implementations in order of how fast/modern they are.
         #[cfg(target_arch = "x86_64")]
@@ -31,11 +303,17 @@ impl Implementation {
             }
         }
 
-        Self::portable()
+        Self::portable().with_multi_buffer()
     }
 
     pub fn portable() -> Self {
-        Implementation(Platform::Portable)
+        Implementation(Platform::Portable, Lanes::Serial)
+    }
+
+    /// Hashes independent messages in parallel vector lanes if the CPU supports it. SHA-NI is not
+    /// combined with it, as hashing one message after the other is as fast with SHA-NI.
+    fn with_multi_buffer(self) -> Self {
+        Implementation(self.0, Lanes::detect())
     }
 
     #[cfg(target_arch = "x86_64")]
@@ -56,7 +334,7 @@ impl Implementation {
 
         // Make sure this computer actually supports it
         if is_runtime_ok {
-            return Some(Implementation(Platform::Sha));
+            return Some(Implementation(Platform::Sha, Lanes::Serial));
         }
 
         None
@@ -64,7 +342,7 @@ impl Implementation {
 
     #[cfg(feature = "asm")]
     pub fn asm_if_supported() -> Option<Self> {
-        Some(Implementation(Platform::Asm))
+        Some(Implementation(Platform::Asm, Lanes::Serial).with_multi_buffer())
     }
 
     #[inline]
@@ -88,4 +366,158 @@ impl Implementation {
             }
         }
     }
+
+    /// Processes the blocks of 4 independent messages, updating their respective states. Every
+    /// block is given as two 32 byte halves, and all messages must contain the same number of
+    /// blocks.
+    #[inline]
+    pub fn compress256_x4(self, mut states: [&mut [u32; 8]; 4], blocks: [&[&[u8]]; 4]) {
+        match self.1 {
+            Lanes::Serial => {
+                for (state, blocks) in states.iter_mut().zip(blocks.iter()) {
+                    self.compress256(state, blocks);
+                }
+            }
+            #[cfg(target_arch = "x86_64")]
+            Lanes::Sse2 | Lanes::Avx2 => unsafe { sha256_multi::sse2::compress256(states, blocks) },
+        }
+    }
+
+    /// Processes the blocks of 8 independent messages, updating their respective states. Every
+    /// block is given as two 32 byte halves, and all messages must contain the same number of
+    /// blocks.
+    #[inline]
+    pub fn compress256_x8(self, mut states: [&mut [u32; 8]; 8], blocks: [&[&[u8]]; 8]) {
+        match self.1 {
+            Lanes::Serial => {
+                for (state, blocks) in states.iter_mut().zip(blocks.iter()) {
+                    self.compress256(state, blocks);
+                }
+            }
+            #[cfg(target_arch = "x86_64")]
+            Lanes::Sse2 => {
+                let [s0, s1, s2, s3, s4, s5, s6, s7] = states;
+                let [b0, b1, b2, b3, b4, b5, b6, b7] = blocks;
+                unsafe {
+                    sha256_multi::sse2::compress256([s0, s1, s2, s3], [b0, b1, b2, b3]);
+                    sha256_multi::sse2::compress256([s4, s5, s6, s7], [b4, b5, b6, b7]);
+                }
+            }
+            #[cfg(target_arch = "x86_64")]
+            Lanes::Avx2 => unsafe { sha256_multi::avx2::compress256(states, blocks) },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::{RngCore, SeedableRng};
+    use rand_xorshift::XorShiftRng;
+
+    fn available_lanes() -> Vec<Lanes> {
+        #[allow(unused_mut)]
+        let mut lanes = vec![Lanes::Serial];
+        #[cfg(target_arch = "x86_64")]
+        {
+            lanes.push(Lanes::Sse2);
+            if Lanes::detect() == Lanes::Avx2 {
+                lanes.push(Lanes::Avx2);
+            }
+        }
+        lanes
+    }
+
+    #[test]
+    fn test_compress256_lanes() {
+        let rng = &mut XorShiftRng::from_seed([
+            0x59, 0x62, 0xbe, 0x5d, 0x76, 0x3d, 0x31, 0x8d, 0x17, 0xdb, 0x37, 0x32, 0x54, 0x06,
+            0xbc, 0xe5,
+        ]);
+        let portable = Implementation::portable();
+
+        for lanes in available_lanes() {
+            let implementation = Implementation(Platform::Portable, lanes);
+
+            for num_blocks in 0..5 {
+                let mut inputs = [[0u8; 64 * 4]; 8];
+                let mut states = [[0u32; 8]; 8];
+                for (input, state) in inputs.iter_mut().zip(states.iter_mut()) {
+                    rng.fill_bytes(input);
+                    state.iter_mut().for_each(|word| *word = rng.next_u32());
+                }
+                let halves: Vec<Vec<&[u8]>> = inputs
+                    .iter()
+                    .map(|input| input[..num_blocks * 64].chunks(32).collect())
+                    .collect();
+
+                let mut expected = states;
+                for (state, blocks) in expected.iter_mut().zip(halves.iter()) {
+                    portable.compress256(state, blocks);
+                }
+
+                let mut states_x8 = states;
+                let [s0, s1, s2, s3, s4, s5, s6, s7] = &mut states_x8;
+                implementation.compress256_x8(
+                    [s0, s1, s2, s3, s4, s5, s6, s7],
+                    std::array::from_fn(|lane| &halves[lane][..]),
+                );
+                assert_eq!(states_x8, expected, "{:?} x8, {} blocks", lanes, num_blocks);
+
+                let mut states_x4 = states;
+                let [s0, s1, s2, s3, ..] = &mut states_x4;
+                implementation.compress256_x4(
+                    [s0, s1, s2, s3],
+                    std::array::from_fn(|lane| &halves[lane][..]),
+                );
+                assert_eq!(
+                    states_x4[..4],
+                    expected[..4],
+                    "{:?} x4, {} blocks",
+                    lanes,
+                    num_blocks
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_backend_names() {
+        for backend in Backend::ALL.iter().copied() {
+            assert_eq!(backend.name().parse(), Ok(backend));
+            assert_eq!(backend.to_string().to_uppercase().parse(), Ok(backend));
+        }
+        assert!("sha512".parse::<Backend>().is_err());
+    }
+
+    #[test]
+    fn test_self_test() {
+        for backend in Backend::ALL.iter().copied() {
+            if let Some(implementation) = Implementation::for_backend(backend) {
+                assert_eq!(implementation.backend(), backend);
+                assert!(
+                    implementation.self_test(),
+                    "{} failed its self-test",
+                    backend
+                );
+                assert!(implementation.throughput(1 << 16) > 0.0);
+            }
+        }
+        assert!(Implementation::detect().self_test());
+    }
+
+    #[test]
+    fn test_select_forced_backend() {
+        let implementation = Implementation::select_backend("portable");
+        assert_eq!(implementation.backend(), Backend::Portable);
+        assert_eq!(implementation.lanes(), 1);
+
+        for name in ["", "auto", "AUTO", "no-such-backend"] {
+            assert_eq!(
+                Implementation::select_backend(name).backend(),
+                Implementation::detect().backend()
+            );
+        }
+    }
 }
@@ -1,10 +1,19 @@
+use std::sync::OnceLock;
+
 use byteorder::{ByteOrder, BE};
-use lazy_static::lazy_static;
+use log::warn;
+
+use crate::{
+    consts::H256,
+    platform::{Backend, Implementation},
+};
 
-use crate::{consts::H256, platform::Implementation};
+static IMPL: OnceLock<Implementation> = OnceLock::new();
 
-lazy_static! {
-    static ref IMPL: Implementation = Implementation::detect();
+/// Returns the implementation in use, selecting it from [`crate::BACKEND_ENV_VAR`] if [`init`]
+/// wasn't called before.
+fn implementation() -> &'static Implementation {
+    IMPL.get_or_init(Implementation::select)
 }
 
 #[derive(Clone)]
@@ -38,7 +47,7 @@ impl Sha256 {
 
         self.len += (blocks.len() as u64) << 8;
 
-        IMPL.compress256(&mut self.state, blocks);
+        implementation().compress256(&mut self.state, blocks);
     }
 
     pub fn finish(mut self) -> [u8; 32] {
@@ -52,7 +61,7 @@ impl Sha256 {
         let l = self.len;
         block1[32 - 8..].copy_from_slice(&l.to_be_bytes()[..]);
 
-        IMPL.compress256(&mut self.state, &[&block0[..], &block1[..]][..]);
+        implementation().compress256(&mut self.state, &[&block0[..], &block1[..]][..]);
 
         let mut out = [0u8; 32];
         BE::write_u32_into(&self.state, &mut out);
@@ -71,7 +80,7 @@ impl Sha256 {
         let l = self.len + 256;
         block1[32 - 8..].copy_from_slice(&l.to_be_bytes()[..]);
 
-        IMPL.compress256(&mut self.state, &[block0, &block1[..]][..]);
+        implementation().compress256(&mut self.state, &[block0, &block1[..]][..]);
 
         let mut out = [0u8; 32];
         BE::write_u32_into(&self.state, &mut out);
@@ -81,6 +90,82 @@ impl Sha256 {
 
 opaque_debug::implement!(Sha256);
 
+/// The backend in use, along with its measured throughput.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BackendReport {
+    pub backend: Backend,
+    /// The number of messages `compress256_x4` and `compress256_x8` hash at once.
+    pub lanes: usize,
+    /// The single stream throughput in bytes per second.
+    pub throughput: f64,
+}
+
+/// Returns the backend the hashing functions of this crate use. It is selected on first use, see
+/// [`Implementation::select`], unless [`init`] was called before.
+pub fn backend() -> Backend {
+    implementation().backend()
+}
+
+/// Selects the backend named `name` for the hashing functions of this crate, see
+/// [`Implementation::select_backend`]. An empty name falls back to [`crate::BACKEND_ENV_VAR`].
+///
+/// The backend is selected once per process: if hashing already started, or `init` was called
+/// before, the backend in use is kept and a warning is logged if it differs from `name`.
+/// Returns the backend in use.
+pub fn init(name: &str) -> Backend {
+    let mut selected = false;
+    let implementation = IMPL.get_or_init(|| {
+        selected = true;
+        if name.is_empty() {
+            Implementation::select()
+        } else {
+            Implementation::select_backend(name)
+        }
+    });
+
+    let backend = implementation.backend();
+    let forced = !name.is_empty() && !name.eq_ignore_ascii_case("auto");
+    if !selected && forced && name.parse::<Backend>().ok() != Some(backend) {
+        warn!(
+            "sha2raw backend {} is already in use, ignoring {:?}",
+            backend, name
+        );
+    }
+    backend
+}
+
+/// Reports the backend in use, measuring its throughput by hashing `bytes` bytes.
+pub fn report(bytes: usize) -> BackendReport {
+    BackendReport {
+        backend: implementation().backend(),
+        lanes: implementation().lanes(),
+        throughput: implementation().throughput(bytes),
+    }
+}
+
+/// Checks every backend supported on this machine against the portable implementation.
+pub fn self_test() -> Vec<(Backend, bool)> {
+    Backend::ALL
+        .iter()
+        .filter_map(|backend| Implementation::for_backend(*backend))
+        .map(|implementation| (implementation.backend(), implementation.self_test()))
+        .collect()
+}
+
+/// Processes the blocks of 4 independent messages at once, updating their respective states.
+/// Every block is given as two 32 byte halves, and all messages must contain the same number of
+/// blocks. Padding is left to the caller.
+pub fn compress256_x4(states: [&mut [u32; 8]; 4], blocks: [&[&[u8]]; 4]) {
+    implementation().compress256_x4(states, blocks);
+}
+
+/// Processes the blocks of 8 independent messages at once, updating their respective states.
+/// Every block is given as two 32 byte halves, and all messages must contain the same number of
+/// blocks. Padding is left to the caller.
+pub fn compress256_x8(states: [&mut [u32; 8]; 8], blocks: [&[&[u8]]; 8]) {
+    implementation().compress256_x8(states, blocks);
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
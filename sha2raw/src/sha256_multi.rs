@@ -0,0 +1,203 @@
+//! Multi-buffer SHA-256: hashes several independent messages at once, one message per 32-bit
+//! vector lane. SSE2 processes 4 messages, AVX2 processes 8.
+//!
+//! There is no AVX-512 (16 lane) kernel yet, as the AVX-512 intrinsics are not stable on the
+//! toolchain this crate is built with.
+
+#![allow(clippy::cast_ptr_alignment)] // Safe to cast without alignment checks as the loads and stores do not require alignment.
+
+macro_rules! impl_compress256_lanes {
+    (
+        $module:ident,
+        $feature:literal,
+        $lanes:literal,
+        $vec:ident,
+        $loadu:ident,
+        $storeu:ident,
+        $set1:ident,
+        $add:ident,
+        $xor:ident,
+        $and:ident,
+        $andnot:ident,
+        $or:ident,
+        $srli:ident,
+        $slli:ident
+    ) => {
+        pub mod $module {
+            use std::arch::x86_64::{
+                $add, $and, $andnot, $loadu, $or, $set1, $slli, $srli, $storeu, $vec, $xor,
+            };
+            use std::array;
+
+            use byteorder::{ByteOrder, BE};
+
+            use crate::consts::K32;
+
+            pub const LANES: usize = $lanes;
+
+            #[inline]
+            #[target_feature(enable = $feature)]
+            unsafe fn load(words: &[u32; LANES]) -> $vec {
+                $loadu(words.as_ptr() as *const $vec)
+            }
+
+            #[inline]
+            #[target_feature(enable = $feature)]
+            unsafe fn store(v: $vec) -> [u32; LANES] {
+                let mut words = [0u32; LANES];
+                $storeu(words.as_mut_ptr() as *mut $vec, v);
+                words
+            }
+
+            #[inline]
+            #[target_feature(enable = $feature)]
+            unsafe fn splat(word: u32) -> $vec {
+                $set1(word as i32)
+            }
+
+            #[inline]
+            #[target_feature(enable = $feature)]
+            unsafe fn add(a: $vec, b: $vec) -> $vec {
+                $add(a, b)
+            }
+
+            #[inline]
+            #[target_feature(enable = $feature)]
+            unsafe fn rotr<const R: i32, const L: i32>(x: $vec) -> $vec {
+                $or($srli::<R>(x), $slli::<L>(x))
+            }
+
+            #[inline]
+            #[target_feature(enable = $feature)]
+            unsafe fn big_sigma0(a: $vec) -> $vec {
+                $xor($xor(rotr::<2, 30>(a), rotr::<13, 19>(a)), rotr::<22, 10>(a))
+            }
+
+            #[inline]
+            #[target_feature(enable = $feature)]
+            unsafe fn big_sigma1(e: $vec) -> $vec {
+                $xor($xor(rotr::<6, 26>(e), rotr::<11, 21>(e)), rotr::<25, 7>(e))
+            }
+
+            #[inline]
+            #[target_feature(enable = $feature)]
+            unsafe fn sigma0(w: $vec) -> $vec {
+                $xor($xor(rotr::<7, 25>(w), rotr::<18, 14>(w)), $srli::<3>(w))
+            }
+
+            #[inline]
+            #[target_feature(enable = $feature)]
+            unsafe fn sigma1(w: $vec) -> $vec {
+                $xor($xor(rotr::<17, 15>(w), rotr::<19, 13>(w)), $srli::<10>(w))
+            }
+
+            #[inline]
+            #[target_feature(enable = $feature)]
+            unsafe fn ch(e: $vec, f: $vec, g: $vec) -> $vec {
+                $xor($and(e, f), $andnot(e, g))
+            }
+
+            #[inline]
+            #[target_feature(enable = $feature)]
+            unsafe fn maj(a: $vec, b: $vec, c: $vec) -> $vec {
+                $xor($xor($and(a, b), $and(a, c)), $and(b, c))
+            }
+
+            /// Processes the blocks of `LANES` independent messages, one message per lane. As for
+            /// the single stream implementations, every block is given as two 32 byte halves,
+            /// and all lanes must contain the same number of blocks.
+            #[target_feature(enable = $feature)]
+            pub unsafe fn compress256(states: [&mut [u32; 8]; LANES], blocks: [&[&[u8]]; LANES]) {
+                let num_halves = blocks[0].len();
+                assert_eq!(num_halves % 2, 0);
+                assert!(
+                    blocks.iter().all(|lane| lane.len() == num_halves),
+                    "all lanes must contain the same number of blocks"
+                );
+
+                let mut state = [splat(0); 8];
+                for (i, word) in state.iter_mut().enumerate() {
+                    *word = load(&array::from_fn(|lane| states[lane][i]));
+                }
+
+                let mut w = [splat(0); 16];
+                for block in 0..num_halves / 2 {
+                    for (t, word) in w.iter_mut().enumerate() {
+                        *word = load(&array::from_fn(|lane| {
+                            BE::read_u32(&blocks[lane][2 * block + t / 8][(t % 8) * 4..])
+                        }));
+                    }
+
+                    let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = state;
+                    for (t, k) in K32.iter().enumerate() {
+                        if t >= 16 {
+                            // w[t] = σ1(w[t - 2]) + w[t - 7] + σ0(w[t - 15]) + w[t - 16]
+                            w[t % 16] = add(
+                                add(w[t % 16], sigma0(w[(t + 1) % 16])),
+                                add(w[(t + 9) % 16], sigma1(w[(t + 14) % 16])),
+                            );
+                        }
+
+                        let t1 = add(
+                            add(add(h, big_sigma1(e)), add(ch(e, f, g), splat(*k))),
+                            w[t % 16],
+                        );
+                        let t2 = add(big_sigma0(a), maj(a, b, c));
+                        h = g;
+                        g = f;
+                        f = e;
+                        e = add(d, t1);
+                        d = c;
+                        c = b;
+                        b = a;
+                        a = add(t1, t2);
+                    }
+
+                    for (word, v) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+                        *word = add(*word, v);
+                    }
+                }
+
+                for (i, word) in state.iter().enumerate() {
+                    for (lane, value) in store(*word).iter().enumerate() {
+                        states[lane][i] = *value;
+                    }
+                }
+            }
+        }
+    };
+}
+
+impl_compress256_lanes!(
+    sse2,
+    "sse2",
+    4,
+    __m128i,
+    _mm_loadu_si128,
+    _mm_storeu_si128,
+    _mm_set1_epi32,
+    _mm_add_epi32,
+    _mm_xor_si128,
+    _mm_and_si128,
+    _mm_andnot_si128,
+    _mm_or_si128,
+    _mm_srli_epi32,
+    _mm_slli_epi32
+);
+
+impl_compress256_lanes!(
+    avx2,
+    "avx2",
+    8,
+    __m256i,
+    _mm256_loadu_si256,
+    _mm256_storeu_si256,
+    _mm256_set1_epi32,
+    _mm256_add_epi32,
+    _mm256_xor_si256,
+    _mm256_and_si256,
+    _mm256_andnot_si256,
+    _mm256_or_si256,
+    _mm256_srli_epi32,
+    _mm256_slli_epi32
+);
@@ -0,0 +1,102 @@
+use bellperson::{
+    gadgets::boolean::{AllocatedBit, Boolean},
+    groth16::{create_random_proof, generate_random_parameters},
+    util_cs::bench_cs::BenchCS,
+    Circuit, ConstraintSystem, SynthesisError,
+};
+use blstrs::{Bls12, Scalar as Fr};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use filecoin_hashers::blake3::blake3_circuit;
+use rand::{thread_rng, Rng};
+
+struct Blake3Example<'a> {
+    data: &'a [Option<bool>],
+}
+
+impl Circuit<Fr> for Blake3Example<'_> {
+    fn synthesize<CS: ConstraintSystem<Fr>>(self, cs: &mut CS) -> Result<(), SynthesisError> {
+        let data: Vec<Boolean> = self
+            .data
+            .iter()
+            .enumerate()
+            .map(|(i, b)| {
+                Ok(Boolean::from(AllocatedBit::alloc(
+                    cs.namespace(|| format!("bit {}", i)),
+                    *b,
+                )?))
+            })
+            .collect::<Result<Vec<_>, SynthesisError>>()?;
+
+        let cs = cs.namespace(|| "blake3");
+        let _res = blake3_circuit(cs, &data)?;
+        Ok(())
+    }
+}
+
+fn blake3_benchmark(c: &mut Criterion) {
+    let params = vec![32, 64, 10 * 32];
+
+    let mut group = c.benchmark_group("non-circuit");
+    for bytes in params {
+        group.bench_function(format!("hash-blake3-{}", bytes), |b| {
+            let mut rng = thread_rng();
+            let data: Vec<u8> = (0..bytes).map(|_| rng.gen()).collect();
+
+            b.iter(|| black_box(blake3::hash(&data)))
+        });
+    }
+
+    group.finish();
+}
+
+fn blake3_circuit_benchmark(c: &mut Criterion) {
+    let mut rng1 = thread_rng();
+    let groth_params =
+        generate_random_parameters::<Bls12, _, _>(Blake3Example { data: &[None; 256] }, &mut rng1)
+            .unwrap();
+
+    let params = vec![32];
+
+    let mut group = c.benchmark_group("hash-blake3-circuit");
+    for bytes in params {
+        group.bench_function(format!("create-proof-{}", bytes), |b| {
+            let mut rng = thread_rng();
+            let data: Vec<Option<bool>> = (0..bytes * 8).map(|_| Some(rng.gen())).collect();
+
+            b.iter(|| {
+                let proof = create_random_proof(
+                    Blake3Example {
+                        data: data.as_slice(),
+                    },
+                    &groth_params,
+                    &mut rng,
+                )
+                .unwrap();
+
+                black_box(proof)
+            });
+        });
+        group
+            .bench_function("synthesize", |b| {
+                let mut rng = thread_rng();
+                let data: Vec<Option<bool>> = (0..bytes * 8).map(|_| Some(rng.gen())).collect();
+                b.iter(|| {
+                    let mut cs = BenchCS::<Fr>::new();
+
+                    Blake3Example {
+                        data: data.as_slice(),
+                    }
+                    .synthesize(&mut cs)
+                    .unwrap();
+
+                    black_box(cs)
+                });
+            })
+            .sample_size(20);
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, blake3_benchmark, blake3_circuit_benchmark);
+criterion_main!(benches);
@@ -74,6 +74,36 @@ fn sha256_raw_benchmark(c: &mut Criterion) {
     group.finish();
 }
 
+fn sha256_raw_x8_benchmark(c: &mut Criterion) {
+    let params = vec![64, 10 * 32, 38 * 32];
+
+    let mut group = c.benchmark_group("hash-sha256-raw-x8");
+    for bytes in params {
+        group
+            .bench_function(format!("non-circuit-{}", bytes), |b| {
+                let mut rng = thread_rng();
+                let data: Vec<Vec<u8>> = (0..8)
+                    .map(|_| (0..bytes).map(|_| rng.gen()).collect())
+                    .collect();
+                let chunks: Vec<Vec<&[u8]>> =
+                    data.iter().map(|data| data.chunks(32).collect()).collect();
+                let mut states = [[0u32; 8]; 8];
+
+                b.iter(|| {
+                    let [s0, s1, s2, s3, s4, s5, s6, s7] = &mut states;
+                    sha2raw::compress256_x8(
+                        [s0, s1, s2, s3, s4, s5, s6, s7],
+                        std::array::from_fn(|lane| &chunks[lane][..]),
+                    );
+                    black_box(&states);
+                })
+            })
+            .throughput(Throughput::Bytes(8 * bytes as u64));
+    }
+
+    group.finish();
+}
+
 fn sha256_circuit_benchmark(c: &mut Criterion) {
     let mut rng1 = thread_rng();
 
@@ -131,6 +161,7 @@ criterion_group!(
     benches,
     sha256_benchmark,
     sha256_raw_benchmark,
+    sha256_raw_x8_benchmark,
     sha256_circuit_benchmark
 );
 criterion_main!(benches);
@@ -10,6 +10,7 @@ use bellperson::{
         create_random_proof_batch, create_random_proof_batch_in_priority, verify_proofs_batch,
         PreparedVerifyingKey,
     },
+    util_cs::test_cs::TestConstraintSystem,
     Circuit,
 };
 use blstrs::{Bls12, Scalar as Fr};
@@ -56,6 +57,30 @@ pub trait CircuitComponent {
     type ComponentPrivateInputs: Default + Clone;
 }
 
+/// The result of synthesizing the circuit of a single partition into a `TestConstraintSystem`, see
+/// `CompoundProof::check_circuits`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CircuitCheck {
+    pub partition: usize,
+    pub num_constraints: usize,
+    /// The number of public inputs allocated by the circuit, including the implicit `ONE` input.
+    pub num_inputs: usize,
+    /// The error returned by the circuit synthesis, if it failed.
+    pub synthesis_error: Option<String>,
+    /// The namespace path of the first constraint that isn't satisfied by the witness.
+    pub unsatisfied_constraint: Option<String>,
+    /// Whether the public inputs allocated by the circuit equal the ones returned by
+    /// `CompoundProof::generate_public_inputs`, which are the ones used for verification.
+    pub inputs_match: bool,
+}
+
+impl CircuitCheck {
+    /// Returns true if a Groth16 proof generated from this circuit would verify.
+    pub fn is_ok(&self) -> bool {
+        self.synthesis_error.is_none() && self.unsatisfied_constraint.is_none() && self.inputs_match
+    }
+}
+
 /// The CompoundProof trait bundles a proof::ProofScheme and a bellperson::Circuit together.
 /// It provides methods equivalent to those provided by proof::ProofScheme (setup, prove, verify).
 /// See documentation at proof::ProofScheme for details.
@@ -243,6 +268,26 @@ where
         pub_params: &S::PublicParams,
         groth_params: &Bls12GrothParams,
         priority: bool,
+    ) -> Result<Vec<groth16::Proof<Bls12>>> {
+        Self::circuit_proofs_for_partitions(
+            pub_in,
+            vanilla_proofs,
+            0,
+            pub_params,
+            groth_params,
+            priority,
+        )
+    }
+
+    /// Same as `circuit_proofs`, but the vanilla proofs are the ones of consecutive partitions
+    /// starting at `first_partition`. This allows proving the partitions of a proof in batches.
+    fn circuit_proofs_for_partitions(
+        pub_in: &S::PublicInputs,
+        vanilla_proofs: Vec<S::Proof>,
+        first_partition: usize,
+        pub_params: &S::PublicParams,
+        groth_params: &Bls12GrothParams,
+        priority: bool,
     ) -> Result<Vec<groth16::Proof<Bls12>>> {
         let mut rng = OsRng;
         ensure!(
@@ -259,7 +304,7 @@ where
                     C::ComponentPrivateInputs::default(),
                     &vanilla_proof,
                     pub_params,
-                    Some(k),
+                    Some(first_partition + k),
                 )
             })
             .collect::<Result<Vec<_>>>()?;
@@ -429,6 +474,55 @@ where
         Ok(verifier_srs)
     }
 
+    /// Synthesizes the circuit of every partition from the given vanilla proofs, the same way
+    /// `circuit_proofs` does, but into a `TestConstraintSystem` instead of proving it. It is meant
+    /// for debugging proofs that don't verify: the returned checks show whether the witness
+    /// satisfies the circuit and whether the circuit agrees with the public inputs that are used
+    /// for verification.
+    ///
+    /// The partitions are synthesized one after another, as the `TestConstraintSystem` keeps all
+    /// constraints in memory.
+    fn check_circuits(
+        pub_in: &S::PublicInputs,
+        vanilla_proofs: &[S::Proof],
+        pub_params: &S::PublicParams,
+    ) -> Result<Vec<CircuitCheck>> {
+        ensure!(
+            !vanilla_proofs.is_empty(),
+            "cannot check a circuit over missing vanilla proofs"
+        );
+
+        vanilla_proofs
+            .iter()
+            .enumerate()
+            .map(|(k, vanilla_proof)| {
+                info!("checking circuit of partition {}", k);
+                let inputs = Self::generate_public_inputs(pub_in, pub_params, Some(k))?;
+                let circuit = Self::circuit(
+                    pub_in,
+                    C::ComponentPrivateInputs::default(),
+                    vanilla_proof,
+                    pub_params,
+                    Some(k),
+                )?;
+
+                let mut cs = TestConstraintSystem::<Fr>::new();
+                let synthesis_error = circuit.synthesize(&mut cs).err().map(|e| e.to_string());
+                // `TestConstraintSystem::verify` panics on a length mismatch.
+                let inputs_match = cs.num_inputs() == inputs.len() + 1 && cs.verify(&inputs);
+
+                Ok(CircuitCheck {
+                    partition: k,
+                    num_constraints: cs.num_constraints(),
+                    num_inputs: cs.num_inputs(),
+                    synthesis_error,
+                    unsatisfied_constraint: cs.which_is_unsatisfied().map(Into::into),
+                    inputs_match,
+                })
+            })
+            .collect()
+    }
+
     fn circuit_for_test(
         public_parameters: &PublicParams<'a, S>,
         public_inputs: &S::PublicInputs,
@@ -1,17 +1,21 @@
 #![allow(clippy::len_without_is_empty)]
 
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::Debug;
 use std::marker::PhantomData;
 use std::slice::Iter;
 
-use anyhow::{ensure, Result};
+use anyhow::{ensure, Context, Result};
 use blstrs::Scalar as Fr;
 use filecoin_hashers::{Hasher, PoseidonArity};
 use generic_array::typenum::{Unsigned, U0};
 use merkletree::hash::Algorithm;
-use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde::{
+    de::{self, DeserializeOwned},
+    Deserialize, Deserializer, Serialize,
+};
 
-use crate::drgraph::graph_height;
+use crate::{drgraph::graph_height, merkle::MerkleTreeTrait};
 
 /// Trait to abstract over the concept of Merkle Proof.
 pub trait MerkleProofTrait: Clone + Serialize + DeserializeOwned + Debug + Sync + Send {
@@ -537,6 +541,341 @@ impl<
     }
 }
 
+/// A batch of inclusion proofs into the same Merkle tree, which stores every node only once.
+///
+/// Independent proofs of several challenges repeat the siblings they have in common, mostly in the
+/// upper levels of the tree. Siblings that can be computed from the challenged leaves and the
+/// other stored nodes are not stored at all. The stored nodes are ordered by level, starting at
+/// the leaves, and within a level by their position.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MerkleMultiProof<
+    H: Hasher,
+    BaseArity: PoseidonArity,
+    SubTreeArity: PoseidonArity = U0,
+    TopTreeArity: PoseidonArity = U0,
+> {
+    #[serde(bound(
+        serialize = "H::Domain: Serialize",
+        deserialize = "H::Domain: Deserialize<'de>"
+    ))]
+    root: H::Domain,
+    /// The number of levels of the base tree(s).
+    #[serde(deserialize_with = "deserialize_base_path_len::<_, BaseArity>")]
+    base_path_len: usize,
+    /// The challenged leaves with their index, sorted by index.
+    #[serde(bound(
+        serialize = "H::Domain: Serialize",
+        deserialize = "H::Domain: Deserialize<'de>"
+    ))]
+    leaves: Vec<(usize, H::Domain)>,
+    #[serde(bound(
+        serialize = "H::Domain: Serialize",
+        deserialize = "H::Domain: Deserialize<'de>"
+    ))]
+    nodes: Vec<H::Domain>,
+    #[serde(skip)]
+    _arities: PhantomData<(BaseArity, SubTreeArity, TopTreeArity)>,
+}
+
+impl<
+        H: Hasher,
+        BaseArity: 'static + PoseidonArity,
+        SubTreeArity: 'static + PoseidonArity,
+        TopTreeArity: 'static + PoseidonArity,
+    > MerkleMultiProof<H, BaseArity, SubTreeArity, TopTreeArity>
+{
+    /// Generates a multiproof for the given challenges. Like `gen_cached_proof`, it works with
+    /// trees whose lower levels were discarded, those levels are rebuilt as needed.
+    pub fn generate<Tree>(
+        tree: &Tree,
+        challenges: &[usize],
+        rows_to_discard: Option<usize>,
+    ) -> Result<Self>
+    where
+        Tree: MerkleTreeTrait<
+            Hasher = H,
+            Arity = BaseArity,
+            SubTreeArity = SubTreeArity,
+            TopTreeArity = TopTreeArity,
+        >,
+    {
+        let challenges: BTreeSet<usize> = challenges.iter().copied().collect();
+        let proofs = challenges
+            .into_iter()
+            .map(|challenge| tree.gen_cached_proof(challenge, rows_to_discard))
+            .collect::<Result<Vec<_>>>()?;
+        Self::from_proofs(&proofs)
+    }
+
+    /// Merges independent proofs into the same tree into a multiproof.
+    pub fn from_proofs<P>(proofs: &[P]) -> Result<Self>
+    where
+        P: MerkleProofTrait<
+            Hasher = H,
+            Arity = BaseArity,
+            SubTreeArity = SubTreeArity,
+            TopTreeArity = TopTreeArity,
+        >,
+    {
+        ensure!(
+            !proofs.is_empty(),
+            "cannot create a multiproof without proofs"
+        );
+
+        let root = proofs[0].root();
+        let path_len = proofs[0].path().len();
+        let upper_path_len = Self::upper_arities().len();
+        ensure!(path_len >= upper_path_len, "proof path is too short");
+        let base_path_len = path_len - upper_path_len;
+        let arities = Self::arities(base_path_len);
+
+        // All nodes contained in the proofs, per level.
+        let mut leaves = BTreeMap::new();
+        let mut known = vec![BTreeMap::new(); arities.len()];
+        for proof in proofs {
+            ensure!(
+                proof.root() == root,
+                "all proofs must be into the same tree"
+            );
+            let path = proof.path();
+            ensure!(
+                path.len() == path_len,
+                "all proofs must have the same length"
+            );
+
+            let mut pos = Self::path_position(&path, &arities)?;
+            insert_node(&mut leaves, pos, proof.leaf())?;
+            for (level, ((siblings, index), &arity)) in path.into_iter().zip(&arities).enumerate() {
+                let parent = pos / arity;
+                let sibling_positions = (0..arity).filter(|i| *i != index);
+                for (i, sibling) in sibling_positions.zip(siblings) {
+                    insert_node(&mut known[level], parent * arity + i, sibling)?;
+                }
+                pos = parent;
+            }
+        }
+
+        // Only keep the nodes that cannot be computed.
+        let mut nodes = Vec::new();
+        let mut computable: BTreeSet<usize> = leaves.keys().copied().collect();
+        for (level, &arity) in arities.iter().enumerate() {
+            let parents: BTreeSet<usize> = computable.iter().map(|pos| pos / arity).collect();
+            for parent in &parents {
+                for pos in parent * arity..(parent + 1) * arity {
+                    if !computable.contains(&pos) {
+                        nodes.push(known[level][&pos]);
+                    }
+                }
+            }
+            computable = parents;
+        }
+
+        Ok(MerkleMultiProof {
+            root,
+            base_path_len,
+            leaves: leaves.into_iter().collect(),
+            nodes,
+            _arities: PhantomData,
+        })
+    }
+
+    /// Returns the position of the leaf proven by `proof`. Unlike `MerkleProofTrait::path_index`,
+    /// it takes the arities of the sub-tree and top-tree levels into account.
+    pub fn leaf_position<P>(proof: &P) -> Result<usize>
+    where
+        P: MerkleProofTrait<
+            Hasher = H,
+            Arity = BaseArity,
+            SubTreeArity = SubTreeArity,
+            TopTreeArity = TopTreeArity,
+        >,
+    {
+        let path = proof.path();
+        let upper_path_len = Self::upper_arities().len();
+        ensure!(path.len() >= upper_path_len, "proof path is too short");
+        Self::path_position(&path, &Self::arities(path.len() - upper_path_len))
+    }
+
+    /// Checks the indexes and siblings of every level of `path` before folding the indexes into
+    /// the position of its leaf.
+    fn path_position(path: &[(Vec<H::Domain>, usize)], arities: &[usize]) -> Result<usize> {
+        for (level, ((siblings, index), &arity)) in path.iter().zip(arities).enumerate() {
+            ensure!(
+                *index < arity && siblings.len() == arity - 1,
+                "invalid proof path at level {}",
+                level
+            );
+        }
+        path.iter()
+            .zip(arities)
+            .rev()
+            .try_fold(0usize, |acc, ((_, index), &arity)| {
+                acc.checked_mul(arity)?.checked_add(*index)
+            })
+            .context("proof path is too long")
+    }
+
+    /// Returns true if the leaves and nodes hash up to the root.
+    pub fn verify(&self) -> bool {
+        match self.levels() {
+            Ok(levels) => levels
+                .last()
+                .and_then(|top| top.get(&0))
+                .map_or(false, |root| *root == self.root),
+            Err(_) => false,
+        }
+    }
+
+    /// Returns the individual proofs of all challenges, sorted by challenge.
+    pub fn proofs(&self) -> Result<Vec<MerkleProof<H, BaseArity, SubTreeArity, TopTreeArity>>> {
+        let levels = self.levels()?;
+        let arities = Self::arities(self.base_path_len);
+
+        Ok(self
+            .leaves
+            .iter()
+            .map(|(challenge, leaf)| {
+                let mut pos = *challenge;
+                let path = arities
+                    .iter()
+                    .enumerate()
+                    .map(|(level, &arity)| {
+                        let (parent, index) = (pos / arity, pos % arity);
+                        let siblings = (0..arity)
+                            .filter(|i| *i != index)
+                            .map(|i| levels[level][&(parent * arity + i)])
+                            .collect();
+                        pos = parent;
+                        (siblings, index)
+                    })
+                    .collect();
+                MerkleProof::from_parts(*leaf, self.root, path)
+            })
+            .collect())
+    }
+
+    pub fn root(&self) -> H::Domain {
+        self.root
+    }
+
+    /// The challenged leaves with their index, sorted by index.
+    pub fn leaves(&self) -> &[(usize, H::Domain)] {
+        &self.leaves
+    }
+
+    /// The number of stored nodes, not including the leaves and the root.
+    pub fn num_nodes(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// The arities of the sub-tree and top-tree levels.
+    fn upper_arities() -> Vec<usize> {
+        [SubTreeArity::to_usize(), TopTreeArity::to_usize()]
+            .iter()
+            .copied()
+            .take_while(|arity| *arity > 0)
+            .collect()
+    }
+
+    /// The arity of every level, starting at the leaves.
+    fn arities(base_path_len: usize) -> Vec<usize> {
+        let mut arities = vec![BaseArity::to_usize(); base_path_len];
+        arities.extend(Self::upper_arities());
+        arities
+    }
+
+    /// Computes the nodes of every level that are needed to hash up to the root. The last level
+    /// only contains the root.
+    fn levels(&self) -> Result<Vec<BTreeMap<usize, H::Domain>>> {
+        let arities = Self::arities(self.base_path_len);
+        // Positions below the number of leaves can't overflow when walking up the levels.
+        let leafs = arities
+            .iter()
+            .try_fold(1usize, |acc, &arity| acc.checked_mul(arity))
+            .context("multiproof tree is too large")?;
+        ensure!(
+            self.leaves.windows(2).all(|pair| pair[0].0 < pair[1].0),
+            "multiproof challenges are not strictly increasing"
+        );
+        ensure!(
+            self.leaves.iter().all(|(pos, _)| *pos < leafs),
+            "multiproof challenges are out of range"
+        );
+
+        let mut stored_nodes = self.nodes.iter();
+        let mut hasher = H::Function::default();
+
+        let mut levels = Vec::with_capacity(arities.len() + 1);
+        let mut current: BTreeMap<usize, H::Domain> = self.leaves.iter().copied().collect();
+        for (level, &arity) in arities.iter().enumerate() {
+            // The height restarts at the sub-tree and top-tree levels, like in `InclusionPath`.
+            let height = if level < self.base_path_len { level } else { 0 };
+
+            let mut level_nodes = current.clone();
+            let mut next = BTreeMap::new();
+            let parents: BTreeSet<usize> = current.keys().map(|pos| pos / arity).collect();
+            for parent in parents {
+                let children = (parent * arity..(parent + 1) * arity)
+                    .map(|pos| match current.get(&pos) {
+                        Some(node) => Ok(*node),
+                        None => {
+                            let node = *stored_nodes.next().context("multiproof is incomplete")?;
+                            level_nodes.insert(pos, node);
+                            Ok(node)
+                        }
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                hasher.reset();
+                next.insert(parent, hasher.multi_node(&children, height));
+            }
+
+            levels.push(level_nodes);
+            current = next;
+        }
+        ensure!(
+            stored_nodes.next().is_none(),
+            "multiproof contains unused nodes"
+        );
+        ensure!(
+            current.len() == 1 && current.contains_key(&0),
+            "multiproof challenges are out of range"
+        );
+        levels.push(current);
+
+        Ok(levels)
+    }
+}
+
+/// Deserializes the number of base tree levels of a `MerkleMultiProof`. It is bounded by the height
+/// of the largest tree whose leaf positions fit into a `usize`, so that malformed input can't make
+/// the proof allocate an arbitrary number of levels.
+fn deserialize_base_path_len<'de, D, BaseArity>(deserializer: D) -> Result<usize, D::Error>
+where
+    D: Deserializer<'de>,
+    BaseArity: PoseidonArity,
+{
+    let base_path_len = usize::deserialize(deserializer)?;
+    let max_base_path_len = usize::BITS / BaseArity::to_u32().trailing_zeros();
+    if base_path_len > max_base_path_len as usize {
+        return Err(de::Error::custom(format!(
+            "base path length {} exceeds the maximum of {}",
+            base_path_len, max_base_path_len
+        )));
+    }
+    Ok(base_path_len)
+}
+
+/// Inserts a node at the given position, nodes which are already known must not differ.
+fn insert_node<D: PartialEq>(nodes: &mut BTreeMap<usize, D>, pos: usize, node: D) -> Result<()> {
+    match nodes.get(&pos) {
+        Some(existing) => ensure!(*existing == node, "proofs disagree on node {}", pos),
+        None => {
+            nodes.insert(pos, node);
+        }
+    }
+    Ok(())
+}
+
 /// Converts a merkle_light proof to a SingleProof
 fn proof_to_single<H: Hasher, Arity: PoseidonArity, TargetArity: PoseidonArity>(
     proof: &merkletree::proof::Proof<H::Domain, Arity>,
@@ -768,9 +1107,14 @@ mod tests {
     };
     use generic_array::typenum::{U2, U4, U8};
     use rand::thread_rng;
-
-    use crate::merkle::{
-        generate_tree, get_base_tree_count, DiskStore, MerkleTreeTrait, MerkleTreeWrapper,
+    use tempfile::tempdir;
+
+    use crate::{
+        merkle::{
+            generate_tree, get_base_tree_count, DiskStore, LCTree, MerkleTreeTrait,
+            MerkleTreeWrapper,
+        },
+        util::default_rows_to_discard,
     };
 
     fn merklepath<Tree: 'static + MerkleTreeTrait>() {
@@ -965,4 +1309,197 @@ mod tests {
             >,
         >();
     }
+
+    fn multiproof<Tree: 'static + MerkleTreeTrait>() {
+        let nodes = 64 * get_base_tree_count::<Tree>();
+        let challenges = [0, 1, 5, 17, 17, nodes / 2, nodes - 1];
+
+        let mut rng = thread_rng();
+        let (_, tree) = generate_tree::<Tree, _>(&mut rng, nodes, None);
+
+        let multiproof = MerkleMultiProof::<
+            Tree::Hasher,
+            Tree::Arity,
+            Tree::SubTreeArity,
+            Tree::TopTreeArity,
+        >::generate(&tree, &challenges, Some(0))
+        .expect("failed to generate multiproof");
+        assert!(multiproof.verify(), "failed to verify multiproof");
+        assert_eq!(multiproof.root(), tree.root());
+
+        let proofs = multiproof.proofs().expect("failed to expand multiproof");
+        assert_eq!(proofs.len(), 6, "duplicate challenges must be merged");
+        let mut num_siblings = 0;
+        for ((challenge, leaf), proof) in multiproof.leaves().iter().zip(&proofs) {
+            let expected = tree.gen_proof(*challenge).expect("gen_proof failure");
+            assert_eq!(proof.leaf(), *leaf);
+            assert_eq!(proof.leaf(), expected.leaf());
+            assert_eq!(proof.root(), expected.root());
+            assert_eq!(proof.path(), expected.path());
+            assert!(proof.verify());
+            num_siblings += expected.path().iter().map(|(s, _)| s.len()).sum::<usize>();
+        }
+        assert!(multiproof.num_nodes() < num_siblings);
+
+        // Round trip through serialization.
+        let json = serde_json::to_string(&multiproof).expect("serialize failure");
+        let decoded: MerkleMultiProof<
+            Tree::Hasher,
+            Tree::Arity,
+            Tree::SubTreeArity,
+            Tree::TopTreeArity,
+        > = serde_json::from_str(&json).expect("deserialize failure");
+        assert!(decoded.verify());
+
+        // A malformed number of levels is rejected instead of being allocated.
+        let mut value: serde_json::Value = serde_json::from_str(&json).expect("parse failure");
+        value["base_path_len"] = serde_json::Value::from(u64::MAX);
+        assert!(serde_json::from_value::<
+            MerkleMultiProof<Tree::Hasher, Tree::Arity, Tree::SubTreeArity, Tree::TopTreeArity>,
+        >(value)
+        .is_err());
+
+        // Tampering with any node must be detected.
+        let mut tampered = multiproof.clone();
+        tampered.nodes[0] = tampered.leaves[0].1;
+        assert!(!tampered.verify());
+        let mut tampered = multiproof.clone();
+        tampered.nodes.pop();
+        assert!(!tampered.verify());
+        assert!(tampered.proofs().is_err());
+
+        // Challenges out of range or out of order are rejected.
+        let mut tampered = multiproof.clone();
+        tampered.leaves.last_mut().expect("no leaves").0 = usize::MAX;
+        assert!(!tampered.verify());
+        assert!(tampered.proofs().is_err());
+        let mut tampered = multiproof.clone();
+        tampered.leaves.swap(0, 1);
+        assert!(!tampered.verify());
+        assert!(tampered.proofs().is_err());
+
+        // Proofs into different trees cannot be merged.
+        let (_, other_tree) = generate_tree::<Tree, _>(&mut rng, nodes, None);
+        let mixed = vec![
+            tree.gen_proof(1).expect("gen_proof failure"),
+            other_tree.gen_proof(2).expect("gen_proof failure"),
+        ];
+        assert!(MerkleMultiProof::from_proofs(&mixed).is_err());
+
+        // The leaf position takes the arity of every level into account.
+        for challenge in challenges {
+            let proof = tree.gen_proof(challenge).expect("gen_proof failure");
+            assert_eq!(
+                MerkleMultiProof::leaf_position(&proof).expect("invalid proof path"),
+                challenge
+            );
+        }
+
+        // An index beyond the arity of its level is rejected before it is used.
+        let proof = tree.gen_proof(1).expect("gen_proof failure");
+        let mut path = proof.path();
+        let top = path.last_mut().expect("empty path");
+        top.1 = top.0.len() + 1;
+        let invalid = vec![MerkleProof::<
+            Tree::Hasher,
+            Tree::Arity,
+            Tree::SubTreeArity,
+            Tree::TopTreeArity,
+        >::from_parts(proof.leaf(), proof.root(), path)];
+        assert!(MerkleMultiProof::leaf_position(&invalid[0]).is_err());
+        assert!(MerkleMultiProof::from_proofs(&invalid).is_err());
+    }
+
+    fn multiproof_cached<Tree: 'static + MerkleTreeTrait>() {
+        let nodes = 64 * get_base_tree_count::<Tree>();
+        let rows_to_discard = default_rows_to_discard(
+            nodes / get_base_tree_count::<Tree>(),
+            Tree::Arity::to_usize(),
+        );
+        let challenges = [3, 4, 9, nodes - 2];
+
+        let mut rng = thread_rng();
+        let temp_dir = tempdir().expect("tempdir failure");
+        let (_, tree) =
+            generate_tree::<Tree, _>(&mut rng, nodes, Some(temp_dir.path().to_path_buf()));
+
+        let multiproof = MerkleMultiProof::<
+            Tree::Hasher,
+            Tree::Arity,
+            Tree::SubTreeArity,
+            Tree::TopTreeArity,
+        >::generate(&tree, &challenges, Some(rows_to_discard))
+        .expect("failed to generate multiproof");
+        assert!(multiproof.verify(), "failed to verify multiproof");
+
+        let proofs = multiproof.proofs().expect("failed to expand multiproof");
+        for (challenge, proof) in challenges.iter().zip(&proofs) {
+            let expected = tree
+                .gen_cached_proof(*challenge, Some(rows_to_discard))
+                .expect("gen_cached_proof failure");
+            assert_eq!(proof.path(), expected.path());
+        }
+    }
+
+    #[test]
+    fn multiproof_cached_poseidon_8() {
+        multiproof_cached::<LCTree<PoseidonHasher, U8, U0, U0>>();
+    }
+
+    #[test]
+    fn multiproof_cached_poseidon_8_4_2() {
+        multiproof_cached::<LCTree<PoseidonHasher, U8, U4, U2>>();
+    }
+
+    #[test]
+    fn multiproof_poseidon_8() {
+        multiproof::<
+            MerkleTreeWrapper<
+                PoseidonHasher,
+                DiskStore<<PoseidonHasher as Hasher>::Domain>,
+                U8,
+                U0,
+                U0,
+            >,
+        >();
+    }
+
+    #[test]
+    fn multiproof_poseidon_8_2() {
+        multiproof::<
+            MerkleTreeWrapper<
+                PoseidonHasher,
+                DiskStore<<PoseidonHasher as Hasher>::Domain>,
+                U8,
+                U2,
+                U0,
+            >,
+        >();
+    }
+
+    #[test]
+    fn multiproof_poseidon_8_4_2() {
+        multiproof::<
+            MerkleTreeWrapper<
+                PoseidonHasher,
+                DiskStore<<PoseidonHasher as Hasher>::Domain>,
+                U8,
+                U4,
+                U2,
+            >,
+        >();
+    }
+
+    #[test]
+    fn multiproof_sha256_2() {
+        multiproof::<
+            MerkleTreeWrapper<
+                Sha256Hasher,
+                DiskStore<<Sha256Hasher as Hasher>::Domain>,
+                U2,
+                U0,
+                U0,
+            >,
+        >();
+    }
 }
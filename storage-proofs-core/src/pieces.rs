@@ -106,7 +106,13 @@ fn subtree_capacity(pos: usize, total: usize) -> Result<usize> {
 mod tests {
     use super::*;
 
-    use filecoin_hashers::poseidon::PoseidonHasher;
+    use filecoin_hashers::{
+        blake3::{Blake3Domain, Blake3Function, Blake3Hasher},
+        poseidon::PoseidonHasher,
+        sha256::Sha256Hasher,
+        HashFunction,
+    };
+    use merkletree::merkle::Element;
 
     #[test]
     fn test_subtree_capacity() {
@@ -169,4 +175,23 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_generate_piece_commitment_bytes_from_source_blake3() {
+        let data: Vec<u8> = (0..64).map(|i| i as u8 & 0b0011_1111).collect();
+
+        let comm_p =
+            generate_piece_commitment_bytes_from_source::<Blake3Hasher>(&mut &data[..], 64)
+                .expect("failed to generate blake3 piece commitment");
+        let expected = Blake3Function::hash2(
+            &Blake3Domain::from_slice(&data[..32]),
+            &Blake3Domain::from_slice(&data[32..]),
+        );
+        assert_eq!(comm_p, <[u8; 32]>::from(expected));
+
+        let sha256_comm_p =
+            generate_piece_commitment_bytes_from_source::<Sha256Hasher>(&mut &data[..], 64)
+                .expect("failed to generate sha256 piece commitment");
+        assert_ne!(comm_p, sha256_comm_p);
+    }
 }
@@ -35,6 +35,10 @@ pub struct Settings {
     pub multicore_sdr_producers: usize,
     pub multicore_sdr_producer_stride: u64,
     pub multicore_sdr_lookahead: usize,
+    /// The SHA-256 backend used for SDR labeling, see `sha2raw::init`, set with the
+    /// `FIL_PROOFS_SHA2RAW_BACKEND` environment variable. Empty leaves the selection to sha2raw,
+    /// which detects the fastest one.
+    pub sha2raw_backend: String,
 }
 
 impl Default for Settings {
@@ -59,6 +63,7 @@ impl Default for Settings {
             multicore_sdr_producers: 3,
             multicore_sdr_producer_stride: 128,
             multicore_sdr_lookahead: 800,
+            sha2raw_backend: String::new(),
         }
     }
 }
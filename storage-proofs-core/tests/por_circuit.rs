@@ -9,6 +9,7 @@ use blstrs::Scalar as Fr;
 use ff::Field;
 use filecoin_hashers::{
     blake2s::Blake2sHasher,
+    blake3::Blake3Hasher,
     poseidon::{PoseidonDomain, PoseidonHasher},
     sha256::Sha256Hasher,
     Domain, Hasher, PoseidonArity,
@@ -44,6 +45,11 @@ fn test_por_circuit_blake2s_base_2() {
     test_por_circuit::<TreeBase<Blake2sHasher, U2>>(3, 129_135);
 }
 
+#[test]
+fn test_por_circuit_blake3_base_2() {
+    test_por_circuit::<TreeBase<Blake3Hasher, U2>>(3, 91_323);
+}
+
 #[test]
 fn test_por_circuit_sha256_base_2() {
     test_por_circuit::<TreeBase<Sha256Hasher, U2>>(3, 272_295);
@@ -59,6 +65,11 @@ fn test_por_circuit_blake2s_base_4() {
     test_por_circuit::<TreeBase<Blake2sHasher, U4>>(3, 130_296);
 }
 
+#[test]
+fn test_por_circuit_blake3_base_4() {
+    test_por_circuit::<TreeBase<Blake3Hasher, U4>>(3, 91_716);
+}
+
 #[test]
 fn test_por_circuit_sha256_base_4() {
     test_por_circuit::<TreeBase<Sha256Hasher, U4>>(3, 216_258);
@@ -74,6 +85,11 @@ fn test_por_circuit_blake2s_base_8() {
     test_por_circuit::<TreeBase<Blake2sHasher, U8>>(3, 174_503);
 }
 
+#[test]
+fn test_por_circuit_blake3_base_8() {
+    test_por_circuit::<TreeBase<Blake3Hasher, U8>>(3, 122_551);
+}
+
 #[test]
 fn test_por_circuit_sha256_base_8() {
     test_por_circuit::<TreeBase<Sha256Hasher, U8>>(3, 250_987);
@@ -21,7 +21,7 @@ use storage_proofs_core::{
     },
     multi_proof::MultiProof,
     por,
-    proof::NoRequirements,
+    proof::{NoRequirements, ProofScheme},
     util::data_at_node,
     TEST_SEED,
 };
@@ -106,6 +106,81 @@ fn por_compound<Tree: 'static + MerkleTreeTrait>() {
     assert!(cs.verify(&inputs));
 }
 
+#[test]
+fn test_por_compound_check_circuits() {
+    type Tree = TreeBase<PoseidonHasher, U8>;
+
+    let mut rng = XorShiftRng::from_seed(TEST_SEED);
+
+    let leaves = 64 * get_base_tree_count::<Tree>();
+    let (data, tree) = generate_tree::<Tree, _>(&mut rng, leaves, None);
+
+    let public_inputs = por::PublicInputs {
+        challenge: 2,
+        commitment: Some(tree.root()),
+    };
+
+    let setup_params = compound_proof::SetupParams {
+        vanilla_params: por::SetupParams {
+            leaves,
+            private: false,
+        },
+        partitions: None,
+        priority: false,
+    };
+    let public_params = PoRCompound::<Tree>::setup(&setup_params).expect("setup failed");
+
+    let private_inputs = por::PrivateInputs::<Tree>::new(
+        bytes_into_fr(
+            data_at_node(data.as_slice(), public_inputs.challenge).expect("data_at_node failure"),
+        )
+        .expect("failed to create Fr from node data")
+        .into(),
+        &tree,
+    );
+    let vanilla_proof = por::PoR::<Tree>::prove(
+        &public_params.vanilla_params,
+        &public_inputs,
+        &private_inputs,
+    )
+    .expect("failed to generate vanilla proof");
+
+    let checks = PoRCompound::<Tree>::check_circuits(
+        &public_inputs,
+        &[vanilla_proof.clone()],
+        &public_params.vanilla_params,
+    )
+    .expect("failed to check circuits");
+    assert_eq!(checks.len(), 1);
+    assert!(checks[0].is_ok(), "{:?}", checks[0]);
+
+    // The proof doesn't match the challenge that is used as public input.
+    let wrong_public_inputs = por::PublicInputs {
+        challenge: 3,
+        ..public_inputs.clone()
+    };
+    let checks = PoRCompound::<Tree>::check_circuits(
+        &wrong_public_inputs,
+        &[vanilla_proof.clone()],
+        &public_params.vanilla_params,
+    )
+    .expect("failed to check circuits");
+    assert!(!checks[0].inputs_match);
+    assert!(!checks[0].is_ok());
+
+    // The leaf doesn't match the Merkle path.
+    let mut wrong_vanilla_proof = vanilla_proof;
+    wrong_vanilla_proof.data = Fr::random(&mut rng).into();
+    let checks = PoRCompound::<Tree>::check_circuits(
+        &public_inputs,
+        &[wrong_vanilla_proof],
+        &public_params.vanilla_params,
+    )
+    .expect("failed to check circuits");
+    assert!(checks[0].inputs_match);
+    assert!(checks[0].unsatisfied_constraint.is_some());
+}
+
 #[ignore]
 #[test]
 fn test_por_compound_poseidon_base_2_private_root() {
@@ -3,7 +3,8 @@ use std::convert::Into;
 use blstrs::Scalar as Fr;
 use ff::Field;
 use filecoin_hashers::{
-    blake2s::Blake2sHasher, poseidon::PoseidonHasher, sha256::Sha256Hasher, Domain, Hasher,
+    blake2s::Blake2sHasher, blake3::Blake3Hasher, poseidon::PoseidonHasher, sha256::Sha256Hasher,
+    Domain, Hasher,
 };
 use fr32::fr_into_bytes;
 use generic_array::typenum::{U0, U2, U4};
@@ -36,6 +37,11 @@ fn test_por_blake2s_base_2() {
     test_por::<TreeBase<Blake2sHasher, U2>>();
 }
 
+#[test]
+fn test_por_blake3_base_2() {
+    test_por::<TreeBase<Blake3Hasher, U2>>();
+}
+
 #[test]
 fn test_por_poseidon_base_4() {
     test_por::<TreeBase<PoseidonHasher, U4>>();
@@ -51,6 +57,11 @@ fn test_por_blake2s_base_4() {
     test_por::<TreeBase<Blake2sHasher, U4>>();
 }
 
+#[test]
+fn test_por_blake3_base_4() {
+    test_por::<TreeBase<Blake3Hasher, U4>>();
+}
+
 fn test_por<Tree: MerkleTreeTrait>() {
     let mut rng = XorShiftRng::from_seed(TEST_SEED);
 
@@ -99,6 +110,11 @@ fn test_por_validates_proof_blake2s_base_2() {
     test_por_validates_proof::<TreeBase<Blake2sHasher, U2>>();
 }
 
+#[test]
+fn test_por_validates_proof_blake3_base_2() {
+    test_por_validates_proof::<TreeBase<Blake3Hasher, U2>>();
+}
+
 #[test]
 fn test_por_validates_proof_poseidon_base_2() {
     test_por_validates_proof::<TreeBase<PoseidonHasher, U2>>();
@@ -114,6 +130,11 @@ fn test_por_validates_proof_blake2s_base_4() {
     test_por_validates_proof::<TreeBase<Blake2sHasher, U4>>();
 }
 
+#[test]
+fn test_por_validates_proof_blake3_base_4() {
+    test_por_validates_proof::<TreeBase<Blake3Hasher, U4>>();
+}
+
 #[test]
 fn test_por_validates_proof_poseidon_base_4() {
     test_por_validates_proof::<TreeBase<PoseidonHasher, U4>>();
@@ -183,6 +204,11 @@ fn test_por_validates_challenge_blake2s_base_2() {
     test_por_validates_challenge::<TreeBase<Blake2sHasher, U2>>();
 }
 
+#[test]
+fn test_por_validates_challenge_blake3_base_2() {
+    test_por_validates_challenge::<TreeBase<Blake3Hasher, U2>>();
+}
+
 #[test]
 fn test_por_validates_challenge_poseidon_base_2() {
     test_por_validates_challenge::<TreeBase<PoseidonHasher, U2>>();
@@ -198,6 +224,11 @@ fn test_por_validates_challenge_blake2s_base_4() {
     test_por_validates_challenge::<TreeBase<Blake2sHasher, U4>>();
 }
 
+#[test]
+fn test_por_validates_challenge_blake3_base_4() {
+    test_por_validates_challenge::<TreeBase<Blake3Hasher, U4>>();
+}
+
 #[test]
 fn test_por_validates_challenge_poseidon_base_4() {
     test_por_validates_challenge::<TreeBase<PoseidonHasher, U4>>();
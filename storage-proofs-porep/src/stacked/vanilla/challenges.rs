@@ -1,3 +1,6 @@
+use std::fmt::Debug;
+use std::sync::Arc;
+
 use blstrs::Scalar as Fr;
 use log::trace;
 use num_bigint::BigUint;
@@ -13,6 +16,37 @@ fn bigint_to_challenge(bigint: BigUint, sector_nodes: usize) -> usize {
     non_zero_node.to_u32_digits()[0] as usize
 }
 
+/// A porep challenge schedule: derives the nodes challenged in each partition.
+///
+/// The vanilla prover and verifier and the circuit's public inputs all derive their challenges
+/// through `PublicInputs::challenges`, which delegates to this trait, so an experimental schedule
+/// only has to implement it and be wrapped in `Challenges::Custom`. Note that the Groth16
+/// parameters of a circuit are only identified by the number of challenges per partition, not by
+/// the schedule.
+pub trait ChallengeDerivation: Debug + Send + Sync {
+    /// Returns the number of challenges in every partition.
+    fn num_challenges_per_partition(&self) -> usize;
+
+    /// Returns the challenges of partition `k`. `replica_id` and `comm_r` are the byte encodings
+    /// of the replica's domain elements, `comm_r` and the porep challenge `seed` are `None` if
+    /// they are not known yet.
+    fn derive(
+        &self,
+        sector_nodes: usize,
+        replica_id: &[u8; 32],
+        comm_r: Option<&[u8; 32]>,
+        seed: Option<&[u8; 32]>,
+        k: usize,
+    ) -> Vec<usize>;
+}
+
+/// Returns the byte encoding of a domain element, as passed to `ChallengeDerivation::derive`.
+pub(crate) fn domain_bytes<D: Domain>(element: &D) -> [u8; 32] {
+    let mut bytes = [0u8; 32];
+    bytes.copy_from_slice(element.as_ref());
+    bytes
+}
+
 #[derive(Clone, Debug)]
 pub struct InteractiveChallenges {
     challenges_per_partition: usize,
@@ -38,13 +72,23 @@ impl InteractiveChallenges {
         replica_id: &D,
         seed: &[u8; 32],
         k: u8,
+    ) -> Vec<usize> {
+        self.derive_from_bytes(sector_nodes, &domain_bytes(replica_id), seed, k)
+    }
+
+    fn derive_from_bytes(
+        &self,
+        sector_nodes: usize,
+        replica_id: &[u8; 32],
+        seed: &[u8; 32],
+        k: u8,
     ) -> Vec<usize> {
         (0..self.challenges_per_partition)
             .map(|i| {
                 let j: u32 = ((self.challenges_per_partition * k as usize) + i) as u32;
 
                 let hash = Sha256::new()
-                    .chain_update(replica_id.into_bytes())
+                    .chain_update(replica_id)
                     .chain_update(seed)
                     .chain_update(j.to_le_bytes())
                     .finalize();
@@ -56,6 +100,24 @@ impl InteractiveChallenges {
     }
 }
 
+impl ChallengeDerivation for InteractiveChallenges {
+    fn num_challenges_per_partition(&self) -> usize {
+        self.challenges_per_partition
+    }
+
+    fn derive(
+        &self,
+        sector_nodes: usize,
+        replica_id: &[u8; 32],
+        _comm_r: Option<&[u8; 32]>,
+        seed: Option<&[u8; 32]>,
+        k: usize,
+    ) -> Vec<usize> {
+        let seed = seed.expect("challenge seed must be set for interactive porep");
+        self.derive_from_bytes(sector_nodes, replica_id, seed, k as u8)
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct SynthChallenges {
     challenges_per_partition: usize,
@@ -77,10 +139,12 @@ impl SynthChallenges {
         seed: &[u8; 32],
         k: u8,
     ) -> Vec<usize> {
-        let replica_id: Fr = (*replica_id).into();
-        let comm_r: Fr = (*comm_r).into();
-        SynthChallengeGenerator::default(sector_nodes, &replica_id, &comm_r)
-            .gen_porep_partition_challenges(self.challenges_per_partition, seed, k as usize)
+        SynthChallengeGenerator::default_from_bytes(
+            sector_nodes,
+            domain_bytes(replica_id),
+            domain_bytes(comm_r),
+        )
+        .gen_porep_partition_challenges(self.challenges_per_partition, seed, k as usize)
     }
 
     /// Returns the synthetic challenge indexes of the porep challenges for partition `k`.
@@ -96,10 +160,12 @@ impl SynthChallenges {
             "generating porep partition synthetic challenge indexes (k = {})",
             k,
         );
-        let replica_id: Fr = (*replica_id).into();
-        let comm_r: Fr = (*comm_r).into();
-        SynthChallengeGenerator::default(sector_nodes, &replica_id, &comm_r)
-            .gen_partition_synth_indexes(self.challenges_per_partition, seed, k as usize)
+        SynthChallengeGenerator::default_from_bytes(
+            sector_nodes,
+            domain_bytes(replica_id),
+            domain_bytes(comm_r),
+        )
+        .gen_partition_synth_indexes(self.challenges_per_partition, seed, k as usize)
     }
 
     /// Returns the entire synthetic challenge set.
@@ -108,9 +174,19 @@ impl SynthChallenges {
         replica_id: &D,
         comm_r: &D,
     ) -> Vec<usize> {
-        let replica_id: Fr = (*replica_id).into();
-        let comm_r: Fr = (*comm_r).into();
-        let synth = SynthChallengeGenerator::default(sector_nodes, &replica_id, &comm_r);
+        Self::derive_synthetic_from_bytes(
+            sector_nodes,
+            domain_bytes(replica_id),
+            domain_bytes(comm_r),
+        )
+    }
+
+    fn derive_synthetic_from_bytes(
+        sector_nodes: usize,
+        replica_id: [u8; 32],
+        comm_r: [u8; 32],
+    ) -> Vec<usize> {
+        let synth = SynthChallengeGenerator::default_from_bytes(sector_nodes, replica_id, comm_r);
         trace!(
             "generating entire synthetic challenge set (num_synth_challenges = {})",
             synth.num_synth_challenges,
@@ -119,6 +195,38 @@ impl SynthChallenges {
     }
 }
 
+impl ChallengeDerivation for SynthChallenges {
+    fn num_challenges_per_partition(&self) -> usize {
+        self.challenges_per_partition
+    }
+
+    /// Returns the porep challenges for partition `k` if `seed` is set, otherwise the entire
+    /// synthetic challenge set, which is always generated in a single partition `k = 0`.
+    fn derive(
+        &self,
+        sector_nodes: usize,
+        replica_id: &[u8; 32],
+        comm_r: Option<&[u8; 32]>,
+        seed: Option<&[u8; 32]>,
+        k: usize,
+    ) -> Vec<usize> {
+        let comm_r = *comm_r.expect("comm_r must be set prior to deriving synth porep challenges");
+        let synth = SynthChallengeGenerator::default_from_bytes(sector_nodes, *replica_id, comm_r);
+        match seed {
+            Some(seed) => {
+                synth.gen_porep_partition_challenges(self.challenges_per_partition, seed, k)
+            }
+            None => {
+                assert_eq!(
+                    k, 0,
+                    "synth challenge generation is always using a single partition",
+                );
+                Self::derive_synthetic_from_bytes(sector_nodes, *replica_id, comm_r)
+            }
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct NiChallenges {
     challenges_per_partition: usize,
@@ -137,11 +245,26 @@ impl NiChallenges {
         replica_id: &D,
         comm_r: &D,
         k: u8,
+    ) -> Vec<usize> {
+        self.derive_from_bytes(
+            sector_nodes,
+            &domain_bytes(replica_id),
+            &domain_bytes(comm_r),
+            k,
+        )
+    }
+
+    fn derive_from_bytes(
+        &self,
+        sector_nodes: usize,
+        replica_id: &[u8; 32],
+        comm_r: &[u8; 32],
+        k: u8,
     ) -> Vec<usize> {
         const TAG: &[u8] = b"filecoin.io|PoRep|1|NonInteractive|1";
         let hash_init = Sha256::new()
             .chain_update(TAG)
-            .chain_update(replica_id.into_bytes())
+            .chain_update(replica_id)
             .chain_update(comm_r);
         (0..self.challenges_per_partition)
             .map(|i| {
@@ -156,11 +279,31 @@ impl NiChallenges {
     }
 }
 
+impl ChallengeDerivation for NiChallenges {
+    fn num_challenges_per_partition(&self) -> usize {
+        self.challenges_per_partition
+    }
+
+    fn derive(
+        &self,
+        sector_nodes: usize,
+        replica_id: &[u8; 32],
+        comm_r: Option<&[u8; 32]>,
+        _seed: Option<&[u8; 32]>,
+        k: usize,
+    ) -> Vec<usize> {
+        let comm_r = comm_r.expect("comm_r must be set prior to generating ni porep challenges");
+        self.derive_from_bytes(sector_nodes, replica_id, comm_r, k as u8)
+    }
+}
+
 #[derive(Clone, Debug)]
 pub enum Challenges {
     Interactive(InteractiveChallenges),
     Synth(SynthChallenges),
     Ni(NiChallenges),
+    /// An experimental challenge schedule.
+    Custom(Arc<dyn ChallengeDerivation>),
 }
 
 impl Challenges {
@@ -176,19 +319,23 @@ impl Challenges {
         Self::Ni(NiChallenges::new(challenges_per_partition))
     }
 
-    pub fn num_challenges_per_partition(&self) -> usize {
+    pub fn new_custom<C: 'static + ChallengeDerivation>(derivation: C) -> Self {
+        Self::Custom(Arc::new(derivation))
+    }
+
+    /// Returns the challenge schedule.
+    pub fn derivation(&self) -> &dyn ChallengeDerivation {
         match self {
-            Self::Interactive(InteractiveChallenges {
-                challenges_per_partition,
-            })
-            | Self::Synth(SynthChallenges {
-                challenges_per_partition,
-            })
-            | Self::Ni(NiChallenges {
-                challenges_per_partition,
-            }) => *challenges_per_partition,
+            Self::Interactive(challenges) => challenges,
+            Self::Synth(challenges) => challenges,
+            Self::Ni(challenges) => challenges,
+            Self::Custom(derivation) => derivation.as_ref(),
         }
     }
+
+    pub fn num_challenges_per_partition(&self) -> usize {
+        self.derivation().num_challenges_per_partition()
+    }
 }
 
 #[derive(Debug, Default)]
@@ -211,6 +358,8 @@ pub(crate) mod synthetic {
 
     pub const SYNTHETIC_POREP_VANILLA_PROOFS_KEY: &str = "syn-porep-vanilla-proofs";
     pub const SYNTHETIC_POREP_VANILLA_PROOFS_EXT: &str = "dat";
+    /// The key of the file which holds only the synthetic proofs selected as porep challenges.
+    pub const SYNTHETIC_POREP_SELECTED_PROOFS_KEY: &str = "syn-porep-selected-proofs";
 
     // Default synthetic challenge count for production sector sizes.
     const DEFAULT_SYNTH_CHALLENGE_COUNT: usize = 1 << 18;
@@ -296,13 +445,26 @@ pub(crate) mod synthetic {
             replica_id: &Fr,
             comm_r: &Fr,
             num_synth_challenges: usize,
+        ) -> Self {
+            Self::new_from_bytes(
+                sector_nodes,
+                replica_id.to_repr(),
+                comm_r.to_repr(),
+                num_synth_challenges,
+            )
+        }
+
+        /// Creates a generator from the byte encodings of `replica_id` and `comm_r`.
+        pub fn new_from_bytes(
+            sector_nodes: usize,
+            replica_id: [u8; 32],
+            comm_r: [u8; 32],
+            num_synth_challenges: usize,
         ) -> Self {
             assert!(
                 num_synth_challenges < 1 << 32,
                 "num_synth_challenges must not exceed u32",
             );
-            let replica_id = replica_id.to_repr();
-            let comm_r = comm_r.to_repr();
             let chacha20 = chacha20_gen(&replica_id, &comm_r);
             Self {
                 sector_nodes,
@@ -319,6 +481,15 @@ pub(crate) mod synthetic {
             Self::new(sector_nodes, replica_id, comm_r, num_synth_challenges)
         }
 
+        pub fn default_from_bytes(
+            sector_nodes: usize,
+            replica_id: [u8; 32],
+            comm_r: [u8; 32],
+        ) -> Self {
+            let num_synth_challenges = min(sector_nodes, DEFAULT_SYNTH_CHALLENGE_COUNT);
+            Self::new_from_bytes(sector_nodes, replica_id, comm_r, num_synth_challenges)
+        }
+
         /// Seeks to the `i`-th synthetic challenge; seeking to `i` results in the next call to
         /// `SynthChallengeGenerator::next` returning the `i`-th synthetic challenge.
         pub(super) fn seek(&mut self, i: usize) {
@@ -547,4 +718,64 @@ mod test {
             synth.gen_porep_challenges(num_porep_challenges, &porep_challenge_randomness);
         assert_eq!(porep_challenges, expected_porep_challenges);
     }
+
+    #[test]
+    fn test_challenge_derivation_matches_schemes() {
+        let mut rng = thread_rng();
+        let sector_nodes = 1 << 10;
+        let replica_id: Sha256Domain = Fr::from(rng.next_u64()).into();
+        let comm_r: Sha256Domain = Fr::from(rng.next_u64()).into();
+        let seed: [u8; 32] = rng.gen();
+        let replica_id_bytes = domain_bytes(&replica_id);
+        let comm_r_bytes = domain_bytes(&comm_r);
+
+        let interactive = Challenges::new_interactive(5);
+        let synth = SynthChallenges::new(5);
+        let ni = Challenges::new_non_interactive(5);
+        for k in 0..3 {
+            assert_eq!(
+                interactive.derivation().derive(
+                    sector_nodes,
+                    &replica_id_bytes,
+                    None,
+                    Some(&seed),
+                    k
+                ),
+                InteractiveChallenges::new(5).derive(sector_nodes, &replica_id, &seed, k as u8),
+            );
+            assert_eq!(
+                synth.derive(sector_nodes, &replica_id, &comm_r, &seed, k as u8),
+                ChallengeDerivation::derive(
+                    &synth,
+                    sector_nodes,
+                    &replica_id_bytes,
+                    Some(&comm_r_bytes),
+                    Some(&seed),
+                    k
+                ),
+            );
+            assert_eq!(
+                ni.derivation().derive(
+                    sector_nodes,
+                    &replica_id_bytes,
+                    Some(&comm_r_bytes),
+                    None,
+                    k
+                ),
+                NiChallenges::new(5).derive(sector_nodes, &replica_id, &comm_r, k as u8),
+            );
+        }
+        assert_eq!(
+            ChallengeDerivation::derive(
+                &synth,
+                sector_nodes,
+                &replica_id_bytes,
+                Some(&comm_r_bytes),
+                None,
+                0
+            ),
+            SynthChallengeGenerator::default(sector_nodes, &replica_id.into(), &comm_r.into())
+                .collect::<Vec<_>>(),
+        );
+    }
 }
@@ -19,12 +19,14 @@ mod memory_handling;
 mod params;
 mod proof;
 mod proof_scheme;
+mod synth_proofs;
 #[cfg(feature = "multicore-sdr")]
 mod utils;
 
 pub use challenges::{
-    synthetic::SYNTHETIC_POREP_VANILLA_PROOFS_EXT, synthetic::SYNTHETIC_POREP_VANILLA_PROOFS_KEY,
-    ChallengeRequirements, Challenges,
+    synthetic::SYNTHETIC_POREP_SELECTED_PROOFS_KEY, synthetic::SYNTHETIC_POREP_VANILLA_PROOFS_EXT,
+    synthetic::SYNTHETIC_POREP_VANILLA_PROOFS_KEY, ChallengeDerivation, ChallengeRequirements,
+    Challenges, InteractiveChallenges, NiChallenges, SynthChallenges,
 };
 pub use clear_files::{clear_cache_dir, clear_synthetic_proofs};
 pub use column::Column;
@@ -34,3 +36,4 @@ pub use graph::{StackedBucketGraph, StackedGraph, EXP_DEGREE};
 pub use labeling_proof::LabelingProof;
 pub use params::*;
 pub use proof::{StackedDrg, TreeRElementData, TOTAL_PARENTS};
+pub use synth_proofs::SynthProofsInfo;
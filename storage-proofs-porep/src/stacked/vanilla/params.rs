@@ -4,7 +4,7 @@ use std::marker::PhantomData;
 use std::mem;
 use std::path::{Path, PathBuf};
 
-use anyhow::Context;
+use anyhow::{ensure, Context};
 use filecoin_hashers::{Domain, Hasher};
 use fr32::bytes_into_fr_repr_safe;
 use generic_array::typenum::{Unsigned, U2};
@@ -29,9 +29,9 @@ use storage_proofs_core::{
 };
 
 use crate::stacked::vanilla::{
-    challenges::SynthChallenges, Challenges, Column, ColumnProof, EncodingProof, LabelingProof,
-    StackedBucketGraph, EXP_DEGREE, SYNTHETIC_POREP_VANILLA_PROOFS_EXT,
-    SYNTHETIC_POREP_VANILLA_PROOFS_KEY, TOTAL_PARENTS,
+    challenges::domain_bytes, Challenges, Column, ColumnProof, EncodingProof, LabelingProof,
+    StackedBucketGraph, EXP_DEGREE, SYNTHETIC_POREP_SELECTED_PROOFS_KEY,
+    SYNTHETIC_POREP_VANILLA_PROOFS_EXT, SYNTHETIC_POREP_VANILLA_PROOFS_KEY, TOTAL_PARENTS,
 };
 
 pub const BINARY_ARITY: usize = 2;
@@ -146,57 +146,24 @@ pub struct PublicInputs<T: Domain, S: Domain> {
 }
 
 impl<T: Domain, S: Domain> PublicInputs<T, S> {
-    /// If the porep challenge randomness `self.seed` is set, this method returns the porep
-    /// challenges for partition `k` (for synth and non-synth poreps); otherwise if `self.seed` is
-    /// `None`, returns the entire synthetic challenge set. Note synthetic challenges are generated
-    /// in a single partition `k = 0`.
+    /// Returns the porep challenges for partition `k`, as derived by the challenge schedule. For
+    /// synthetic poreps, if the porep challenge randomness `self.seed` is `None`, this returns the
+    /// entire synthetic challenge set instead. Note synthetic challenges are generated in a single
+    /// partition `k = 0`.
     pub fn challenges(
         &self,
         challenges: &Challenges,
         sector_nodes: usize,
         k: Option<usize>,
     ) -> Vec<usize> {
-        let k = k.unwrap_or(0);
-        match challenges {
-            Challenges::Interactive(interactive_challenges) => {
-                let seed = self
-                    .seed
-                    .expect("challenge seed must be set for interactive porep");
-                interactive_challenges.derive(sector_nodes, &self.replica_id, &seed, k as u8)
-            }
-            Challenges::Synth(synth_challenges) => {
-                let comm_r = self
-                    .tau
-                    .as_ref()
-                    .expect("comm_r must be set prior to deriving synth porep challenges")
-                    .comm_r;
-
-                match self.seed.as_ref() {
-                    Some(seed) => synth_challenges.derive(
-                        sector_nodes,
-                        &self.replica_id,
-                        &comm_r,
-                        seed,
-                        k as u8,
-                    ),
-                    None => {
-                        assert_eq!(
-                            k, 0,
-                            "synth challenge generation is always using a single partition",
-                        );
-                        SynthChallenges::derive_synthetic(sector_nodes, &self.replica_id, &comm_r)
-                    }
-                }
-            }
-            Challenges::Ni(ni_challenges) => {
-                let comm_r = self
-                    .tau
-                    .as_ref()
-                    .expect("comm_r must be set prior to generating ni porep challenges")
-                    .comm_r;
-                ni_challenges.derive(sector_nodes, &self.replica_id, &comm_r, k as u8)
-            }
-        }
+        let comm_r = self.tau.as_ref().map(|tau| domain_bytes(&tau.comm_r));
+        challenges.derivation().derive(
+            sector_nodes,
+            &domain_bytes(&self.replica_id),
+            comm_r.as_ref(),
+            self.seed.as_ref(),
+            k.unwrap_or(0),
+        )
     }
 }
 
@@ -341,8 +308,10 @@ impl<Tree: MerkleTreeTrait, G: Hasher> Proof<Tree, G> {
         true
     }
 
+    /// Builds a proof from its Merkle and column proofs. The labeling and encoding proofs are
+    /// derived from the column proofs of the challenge's parents.
     #[allow(clippy::type_complexity)]
-    fn from_parts(
+    pub fn from_parts(
         proof_d: MerkleProof<G, U2>,
         col_proof: ColumnProof<
             MerkleProof<Tree::Hasher, Tree::Arity, Tree::SubTreeArity, Tree::TopTreeArity>,
@@ -781,6 +750,102 @@ impl SynthProofs {
             .collect()
     }
 
+    /// Reads the TreeD, TreeC and TreeR roots from `reader`.
+    #[allow(clippy::type_complexity)]
+    pub fn read_roots<Tree, G, R>(
+        mut reader: R,
+    ) -> Result<(
+        G::Domain,
+        <Tree::Hasher as Hasher>::Domain,
+        <Tree::Hasher as Hasher>::Domain,
+    )>
+    where
+        Tree: MerkleTreeTrait,
+        G: Hasher,
+        R: Read + Seek,
+    {
+        reader.rewind()?;
+        let mut buf_32 = [0u8; 32];
+        reader.read_exact(&mut buf_32)?;
+        let root_d = G::Domain::try_from_bytes(&buf_32)?;
+        reader.read_exact(&mut buf_32)?;
+        let root_c = <Tree::Hasher as Hasher>::Domain::try_from_bytes(&buf_32)?;
+        reader.read_exact(&mut buf_32)?;
+        let root_r = <Tree::Hasher as Hasher>::Domain::try_from_bytes(&buf_32)?;
+        Ok((root_d, root_c, root_r))
+    }
+
+    /// Returns the number of proofs in a synthetic proofs file of `file_size` bytes.
+    pub fn num_proofs<Tree: MerkleTreeTrait>(
+        file_size: u64,
+        sector_nodes: usize,
+        num_layers: usize,
+    ) -> Result<usize> {
+        let roots_size = (3 * NODE_SIZE) as u64;
+        let proof_size = Self::proof_size::<Tree>(sector_nodes, num_layers) as u64;
+        ensure!(
+            file_size >= roots_size && (file_size - roots_size) % proof_size == 0,
+            "invalid synthetic proofs file size {} for proofs of {} bytes",
+            file_size,
+            proof_size,
+        );
+        Ok(((file_size - roots_size) / proof_size) as usize)
+    }
+
+    /// Reads the challenge (node index) of the `proof_index`-th proof from `reader`.
+    pub fn read_challenge<Tree, R>(
+        mut reader: R,
+        sector_nodes: usize,
+        num_layers: usize,
+        proof_index: usize,
+    ) -> Result<u64>
+    where
+        Tree: MerkleTreeTrait,
+        R: Read + Seek,
+    {
+        let offset =
+            3 * NODE_SIZE + proof_index * Self::proof_size::<Tree>(sector_nodes, num_layers);
+        reader.seek(SeekFrom::Start(offset as u64))?;
+        let mut buf_8 = [0u8; 8];
+        reader.read_exact(&mut buf_8)?;
+        Ok(u64::from_le_bytes(buf_8))
+    }
+
+    /// Copies the roots and a subset of synthetic proofs, specified by synthetic proof indexes
+    /// `selected_proofs`, from `reader` to `writer`, without deserializing the proofs. The result
+    /// is a synthetic proofs file which contains the selected proofs in the given order.
+    pub fn copy_selected<Tree, R, W>(
+        mut reader: R,
+        mut writer: W,
+        sector_nodes: usize,
+        num_layers: usize,
+        selected_proofs: impl Iterator<Item = usize>,
+    ) -> Result<()>
+    where
+        Tree: MerkleTreeTrait,
+        R: Read + Seek,
+        W: Write,
+    {
+        let roots_size = 3 * NODE_SIZE;
+        let proof_size = Self::proof_size::<Tree>(sector_nodes, num_layers);
+
+        let mut buf = vec![0u8; roots_size];
+        reader.rewind()?;
+        reader.read_exact(&mut buf)?;
+        writer.write_all(&buf)?;
+
+        buf.resize(proof_size, 0);
+        for proof_index in selected_proofs {
+            let offset = roots_size + proof_index * proof_size;
+            reader.seek(SeekFrom::Start(offset as u64))?;
+            reader.read_exact(&mut buf)?;
+            writer.write_all(&buf)?;
+        }
+
+        writer.flush()?;
+        Ok(())
+    }
+
     /// Returns the size of a single challenge's serialized synthetic proof.
     pub fn proof_size<Tree: MerkleTreeTrait>(sector_nodes: usize, num_layers: usize) -> usize {
         // The number of node indices associated with each challenge proof: one node index for the
@@ -957,6 +1022,13 @@ impl<Tree: MerkleTreeTrait, G: Hasher> TemporaryAux<Tree, G> {
             SYNTHETIC_POREP_VANILLA_PROOFS_KEY, SYNTHETIC_POREP_VANILLA_PROOFS_EXT
         ))
     }
+
+    pub fn selected_synth_proofs_path(&self) -> PathBuf {
+        self.tree_d_config.path.clone().join(format!(
+            "{}.{}",
+            SYNTHETIC_POREP_SELECTED_PROOFS_KEY, SYNTHETIC_POREP_VANILLA_PROOFS_EXT
+        ))
+    }
 }
 
 #[derive(Debug)]
@@ -1086,6 +1158,10 @@ impl<Tree: MerkleTreeTrait, G: Hasher> TemporaryAuxCache<Tree, G> {
     pub fn synth_proofs_path(&self) -> PathBuf {
         self.t_aux.synth_proofs_path()
     }
+
+    pub fn selected_synth_proofs_path(&self) -> PathBuf {
+        self.t_aux.selected_synth_proofs_path()
+    }
 }
 
 type VerifyCallback = fn(&StoreConfig, usize, usize) -> Result<()>;
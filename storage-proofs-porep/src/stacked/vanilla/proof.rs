@@ -20,8 +20,6 @@ use merkletree::{
 use rayon::prelude::{
     IndexedParallelIterator, IntoParallelIterator, ParallelIterator, ParallelSliceMut,
 };
-#[cfg(any(feature = "cuda", feature = "multicore-sdr", feature = "opencl"))]
-use storage_proofs_core::settings::SETTINGS;
 use storage_proofs_core::{
     cache_key::CacheKey,
     data::Data,
@@ -33,6 +31,7 @@ use storage_proofs_core::{
         split_config_and_replica, BinaryMerkleTree, DiskTree, LCTree, MerkleProofTrait,
         MerkleTreeTrait,
     },
+    settings::SETTINGS,
     util::{default_rows_to_discard, NODE_SIZE},
 };
 use yastl::Pool;
@@ -119,36 +118,6 @@ impl<'a, Tree: 'static + MerkleTreeTrait, G: 'static + Hasher> StackedDrg<'a, Tr
         assert!(pub_inputs.tau.is_some());
 
         match challenges {
-            Challenges::Interactive(interactive_challenges) => {
-                info!("generating interactive vanilla proofs");
-
-                let seed = pub_inputs
-                    .seed
-                    .expect("seed must be set for interactive vanilla proofs");
-
-                (0..partition_count)
-                    .map(|k| {
-                        trace!("proving partition {}/{}", k + 1, partition_count);
-
-                        // Derive the set of challenges we are proving over.
-                        let challenge_positions = interactive_challenges.derive(
-                            graph.size(),
-                            &pub_inputs.replica_id,
-                            &seed,
-                            k as u8,
-                        );
-
-                        Self::prove_layers_generate(
-                            graph,
-                            pub_inputs,
-                            p_aux.comm_c,
-                            t_aux,
-                            challenge_positions,
-                            num_layers,
-                        )
-                    })
-                    .collect::<Result<Vec<Vec<Proof<Tree, G>>>>>()
-            }
             Challenges::Synth(synth_challenges) => {
                 // If there are no synthetic vanilla proofs stored on disk yet, generate them.
                 if pub_inputs.seed.is_none() {
@@ -181,6 +150,20 @@ impl<'a, Tree: 'static + MerkleTreeTrait, G: 'static + Hasher> StackedDrg<'a, Tr
                     )?;
                     Ok(vec![vec![]; partition_count])
                 }
+                // If only the synthetic vanilla proofs selected for the porep challenges are
+                // stored on disk, read those.
+                else if !t_aux.synth_proofs_path().exists()
+                    && t_aux.selected_synth_proofs_path().exists()
+                {
+                    Self::read_porep_proofs_from_selected_synth(
+                        graph.size(),
+                        pub_inputs,
+                        synth_challenges,
+                        num_layers,
+                        &t_aux.selected_synth_proofs_path(),
+                        partition_count,
+                    )
+                }
                 // Else the synthetic vanilla proofs are stored on disk, read and return the proofs
                 // corresponding to the porep challlenge set.
                 else {
@@ -201,21 +184,16 @@ impl<'a, Tree: 'static + MerkleTreeTrait, G: 'static + Hasher> StackedDrg<'a, Tr
                     })
                 }
             }
-            Challenges::Ni(ni_challenges) => {
-                info!("generating non-interactive vanilla proofs");
+            Challenges::Interactive(_) | Challenges::Ni(_) | Challenges::Custom(_) => {
+                info!("generating vanilla proofs");
 
-                let comm_r = pub_inputs.tau.as_ref().expect("tau is set").comm_r;
                 (0..partition_count)
                     .map(|k| {
                         trace!("proving partition {}/{}", k + 1, partition_count);
 
                         // Derive the set of challenges we are proving over.
-                        let challenge_positions = ni_challenges.derive(
-                            graph.size(),
-                            &pub_inputs.replica_id,
-                            &comm_r,
-                            k as u8,
-                        );
+                        let challenge_positions =
+                            pub_inputs.challenges(challenges, graph.size(), Some(k));
 
                         Self::prove_layers_generate(
                             graph,
@@ -677,6 +655,10 @@ impl<'a, Tree: 'static + MerkleTreeTrait, G: 'static + Hasher> StackedDrg<'a, Tr
         P: AsRef<Path>,
     {
         let mut parent_cache = graph.parent_cache()?;
+        info!(
+            "using sha2raw backend {}",
+            sha2raw::init(&SETTINGS.sha2raw_backend)
+        );
 
         #[cfg(feature = "multicore-sdr")]
         {
@@ -722,6 +704,10 @@ impl<'a, Tree: 'static + MerkleTreeTrait, G: 'static + Hasher> StackedDrg<'a, Tr
         config: StoreConfig,
     ) -> Result<LabelsCache<Tree>> {
         let mut parent_cache = graph.parent_cache()?;
+        info!(
+            "using sha2raw backend {}",
+            sha2raw::init(&SETTINGS.sha2raw_backend)
+        );
 
         #[cfg(feature = "multicore-sdr")]
         {
@@ -0,0 +1,242 @@
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+
+use anyhow::{ensure, Context};
+use filecoin_hashers::{Domain, HashFunction, Hasher};
+use log::info;
+use storage_proofs_core::{
+    error::Result,
+    merkle::{MerkleProofTrait, MerkleTreeTrait},
+};
+
+use crate::stacked::vanilla::{
+    challenges::SynthChallenges,
+    params::{Proof, PublicInputs, SynthProofs},
+    proof::StackedDrg,
+};
+
+/// A summary of a synthetic proofs file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SynthProofsInfo<D: Domain, E: Domain> {
+    /// The size of the file in bytes.
+    pub file_size: u64,
+    /// The number of challenge proofs in the file.
+    pub num_proofs: usize,
+    pub comm_d: E,
+    pub comm_c: D,
+    pub comm_r_last: D,
+}
+
+type Info<Tree, G> =
+    SynthProofsInfo<<<Tree as MerkleTreeTrait>::Hasher as Hasher>::Domain, <G as Hasher>::Domain>;
+type Inputs<Tree, G> =
+    PublicInputs<<<Tree as MerkleTreeTrait>::Hasher as Hasher>::Domain, <G as Hasher>::Domain>;
+
+impl<Tree: 'static + MerkleTreeTrait, G: 'static + Hasher> StackedDrg<'_, Tree, G> {
+    /// Reads the roots and counts the proofs of the synthetic proofs file at `path`, without
+    /// checking the proofs.
+    pub fn inspect_synth_proofs(
+        path: &Path,
+        sector_nodes: usize,
+        num_layers: usize,
+    ) -> Result<Info<Tree, G>> {
+        let file_size = fs::metadata(path)
+            .with_context(|| format!("failed to stat synthetic proofs file: {:?}", path))?
+            .len();
+        let num_proofs = SynthProofs::num_proofs::<Tree>(file_size, sector_nodes, num_layers)?;
+
+        let file = File::open(path)
+            .map(BufReader::new)
+            .with_context(|| format!("failed to open synthetic proofs file: {:?}", path))?;
+        let (comm_d, comm_c, comm_r_last) = SynthProofs::read_roots::<Tree, G, _>(file)
+            .with_context(|| format!("failed to read synthetic proofs roots: {:?}", path))?;
+
+        Ok(SynthProofsInfo {
+            file_size,
+            num_proofs,
+            comm_d,
+            comm_c,
+            comm_r_last,
+        })
+    }
+
+    /// Checks that the synthetic proofs file at `path` belongs to the replica described by
+    /// `pub_inputs`: its roots must match comm_d and comm_r, and it must contain one proof for
+    /// every synthetic challenge, in order. The Merkle paths themselves are not verified.
+    pub fn validate_synth_proofs(
+        path: &Path,
+        sector_nodes: usize,
+        num_layers: usize,
+        pub_inputs: &Inputs<Tree, G>,
+    ) -> Result<Info<Tree, G>> {
+        let tau = pub_inputs
+            .tau
+            .as_ref()
+            .context("comm_r must be set to validate synthetic proofs")?;
+
+        let info = Self::inspect_synth_proofs(path, sector_nodes, num_layers)?;
+        ensure!(
+            info.comm_d == tau.comm_d,
+            "synthetic proofs comm_d does not match the replica's comm_d"
+        );
+        ensure!(
+            <Tree::Hasher as Hasher>::Function::hash2(&info.comm_c, &info.comm_r_last)
+                == tau.comm_r,
+            "synthetic proofs comm_c and comm_r_last do not match the replica's comm_r"
+        );
+
+        let synth_challenges =
+            SynthChallenges::derive_synthetic(sector_nodes, &pub_inputs.replica_id, &tau.comm_r);
+        ensure!(
+            info.num_proofs == synth_challenges.len(),
+            "synthetic proofs file contains {} proofs, expected {}",
+            info.num_proofs,
+            synth_challenges.len()
+        );
+
+        let mut file = File::open(path)
+            .map(BufReader::new)
+            .with_context(|| format!("failed to open synthetic proofs file: {:?}", path))?;
+        for (i, challenge) in synth_challenges.into_iter().enumerate() {
+            let proof_challenge =
+                SynthProofs::read_challenge::<Tree, _>(&mut file, sector_nodes, num_layers, i)?;
+            ensure!(
+                proof_challenge == challenge as u64,
+                "synthetic proof {} is for challenge {}, expected {}",
+                i,
+                proof_challenge,
+                challenge
+            );
+        }
+
+        Ok(info)
+    }
+
+    /// Writes the proofs of the porep challenges selected by `pub_inputs.seed` from the synthetic
+    /// proofs file at `path` into a new file at `selected_path`, partition by partition. The new
+    /// file is only a fraction of the size, and can be used to generate the porep proofs in place
+    /// of the full one.
+    #[allow(clippy::too_many_arguments)]
+    pub fn select_synth_proofs(
+        path: &Path,
+        selected_path: &Path,
+        sector_nodes: usize,
+        num_layers: usize,
+        pub_inputs: &Inputs<Tree, G>,
+        challenges: &SynthChallenges,
+        partition_count: usize,
+    ) -> Result<Info<Tree, G>> {
+        let seed = pub_inputs
+            .seed
+            .as_ref()
+            .context("porep challenge seed must be set to select synthetic proofs")?;
+
+        let info = Self::validate_synth_proofs(path, sector_nodes, num_layers, pub_inputs)?;
+        let comm_r = pub_inputs.tau.as_ref().expect("tau is set").comm_r;
+        let selected_indexes = (0..partition_count as u8).flat_map(|k| {
+            challenges.derive_indexes(sector_nodes, &pub_inputs.replica_id, &comm_r, seed, k)
+        });
+
+        info!(
+            "writing selected synthetic proofs from {:?} to {:?}",
+            path, selected_path
+        );
+        let reader = File::open(path)
+            .map(BufReader::new)
+            .with_context(|| format!("failed to open synthetic proofs file: {:?}", path))?;
+        let mut tmp_path = selected_path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        let file = File::create(&tmp_path)
+            .with_context(|| format!("failed to create file: {:?}", tmp_path))?;
+        let mut writer = BufWriter::new(file);
+        SynthProofs::copy_selected::<Tree, _, _>(
+            reader,
+            &mut writer,
+            sector_nodes,
+            num_layers,
+            selected_indexes,
+        )
+        .with_context(|| format!("failed to write selected synthetic proofs: {:?}", tmp_path))?;
+        writer
+            .into_inner()
+            .map_err(|err| err.into_error())?
+            .sync_all()?;
+        fs::rename(&tmp_path, selected_path)
+            .with_context(|| format!("failed to move {:?} to {:?}", tmp_path, selected_path))?;
+
+        let selected_info = Self::inspect_synth_proofs(selected_path, sector_nodes, num_layers)?;
+        ensure!(
+            selected_info.comm_c == info.comm_c && selected_info.comm_r_last == info.comm_r_last,
+            "selected synthetic proofs roots mismatch"
+        );
+
+        Ok(selected_info)
+    }
+
+    /// Reads the porep proofs from a file written by `select_synth_proofs`, checking that they
+    /// were selected with the same seed.
+    pub(crate) fn read_porep_proofs_from_selected_synth(
+        sector_nodes: usize,
+        pub_inputs: &Inputs<Tree, G>,
+        challenges: &SynthChallenges,
+        num_layers: usize,
+        path: &Path,
+        partition_count: usize,
+    ) -> Result<Vec<Vec<Proof<Tree, G>>>> {
+        let seed = pub_inputs
+            .seed
+            .as_ref()
+            .context("porep challenge seed must be set to read selected synthetic proofs")?;
+        let comm_r = pub_inputs
+            .tau
+            .as_ref()
+            .map(|tau| tau.comm_r)
+            .context("comm_r must be set to read selected synthetic proofs")?;
+        info!(
+            "reading selected synthetic vanilla proofs from file: {:?}",
+            path
+        );
+
+        let mut file = File::open(path).map(BufReader::new).with_context(|| {
+            format!(
+                "failed to open selected synthetic vanilla proofs file: {:?}",
+                path
+            )
+        })?;
+
+        let mut first_proof = 0;
+        (0..partition_count as u8)
+            .map(|k| {
+                let partition_challenges =
+                    challenges.derive(sector_nodes, &pub_inputs.replica_id, &comm_r, seed, k);
+                let proof_indexes = first_proof..first_proof + partition_challenges.len();
+                first_proof = proof_indexes.end;
+
+                let proofs = SynthProofs::read::<Tree, G, _>(
+                    &mut file,
+                    sector_nodes,
+                    num_layers,
+                    proof_indexes,
+                )
+                .with_context(|| {
+                    format!(
+                        "failed to read partition k={} selected synthetic proofs from file: {:?}",
+                        k, path,
+                    )
+                })?;
+                ensure!(
+                    proofs
+                        .iter()
+                        .map(|proof| proof.comm_d_proofs.path_index())
+                        .eq(partition_challenges),
+                    "selected synthetic proofs of partition k={} do not match the porep challenges, \
+                     they were selected with a different seed",
+                    k
+                );
+
+                Ok(proofs)
+            })
+            .collect()
+    }
+}
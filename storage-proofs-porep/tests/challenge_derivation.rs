@@ -0,0 +1,206 @@
+use std::convert::TryInto;
+
+use bellperson::{util_cs::test_cs::TestConstraintSystem, Circuit, ConstraintSystem};
+use blstrs::Scalar as Fr;
+use ff::Field;
+use filecoin_hashers::{poseidon::PoseidonHasher, sha256::Sha256Hasher, Hasher};
+use fr32::fr_into_bytes;
+use generic_array::typenum::{U0, U8};
+use rand::{Rng, SeedableRng};
+use rand_xorshift::XorShiftRng;
+use sha2::{Digest, Sha256};
+use storage_proofs_core::{
+    api_version::ApiVersion,
+    compound_proof::CompoundProof,
+    drgraph::BASE_DEGREE,
+    merkle::{DiskTree, MerkleProofTrait, MerkleTreeTrait},
+    proof::ProofScheme,
+    test_helper::setup_replica,
+    TEST_SEED,
+};
+use storage_proofs_porep::stacked::{
+    ChallengeDerivation, Challenges, PrivateInputs, PublicInputs, SetupParams, StackedCompound,
+    StackedDrg, TemporaryAuxCache, EXP_DEGREE,
+};
+use tempfile::tempdir;
+
+mod common;
+
+type Tree = DiskTree<PoseidonHasher, U8, U0, U0>;
+
+const SECTOR_NODES: usize = 64;
+const PARTITIONS: usize = 2;
+
+/// An example experimental schedule: every partition samples its own slice of the sector.
+#[derive(Debug)]
+struct SlicedChallenges {
+    challenges_per_partition: usize,
+    partitions: usize,
+}
+
+impl ChallengeDerivation for SlicedChallenges {
+    fn num_challenges_per_partition(&self) -> usize {
+        self.challenges_per_partition
+    }
+
+    fn derive(
+        &self,
+        sector_nodes: usize,
+        replica_id: &[u8; 32],
+        _comm_r: Option<&[u8; 32]>,
+        seed: Option<&[u8; 32]>,
+        k: usize,
+    ) -> Vec<usize> {
+        let seed = seed.expect("seed must be set");
+        let slice_nodes = sector_nodes / self.partitions;
+        (0..self.challenges_per_partition)
+            .map(|i| {
+                let hash = Sha256::new()
+                    .chain_update(replica_id)
+                    .chain_update(seed)
+                    .chain_update((k as u32).to_le_bytes())
+                    .chain_update((i as u32).to_le_bytes())
+                    .finalize();
+                let offset = u64::from_le_bytes(hash[..8].try_into().unwrap()) as usize;
+                // Never challenge the first node of a slice.
+                k * slice_nodes + 1 + offset % (slice_nodes - 1)
+            })
+            .collect()
+    }
+}
+
+#[test]
+fn test_challenge_derivation_interactive() {
+    test_vanilla_and_circuit_agree(Challenges::new_interactive(1), false);
+}
+
+#[test]
+fn test_challenge_derivation_synthetic() {
+    test_vanilla_and_circuit_agree(Challenges::new_synthetic(1), true);
+}
+
+#[test]
+fn test_challenge_derivation_non_interactive() {
+    test_vanilla_and_circuit_agree(Challenges::new_non_interactive(1), false);
+}
+
+#[test]
+fn test_challenge_derivation_custom() {
+    let challenges = Challenges::new_custom(SlicedChallenges {
+        challenges_per_partition: 1,
+        partitions: PARTITIONS,
+    });
+    test_vanilla_and_circuit_agree(challenges, false);
+}
+
+/// Proves a small replica with the given challenge schedule and checks that the vanilla proofs
+/// verify, are for the challenges the schedule derives, and that the circuit of every partition
+/// is satisfied by them and has the public inputs the verifier derives.
+fn test_vanilla_and_circuit_agree(challenges: Challenges, synthetic: bool) {
+    let mut rng = XorShiftRng::from_seed(TEST_SEED);
+
+    let replica_id: Fr = Fr::random(&mut rng);
+    let data: Vec<u8> = (0..SECTOR_NODES)
+        .flat_map(|_| fr_into_bytes(&Fr::random(&mut rng)))
+        .collect();
+
+    let cache_dir = tempdir().unwrap();
+    let replica_path = cache_dir.path().join("replica-path");
+    let mut mmapped_data = setup_replica(&data, &replica_path);
+
+    let sp = SetupParams {
+        nodes: SECTOR_NODES,
+        degree: BASE_DEGREE,
+        expansion_degree: EXP_DEGREE,
+        porep_id: [44; 32],
+        challenges: challenges.clone(),
+        num_layers: 2,
+        api_version: ApiVersion::V1_2_0,
+        api_features: vec![],
+    };
+
+    let pp = StackedDrg::<Tree, Sha256Hasher>::setup(&sp).expect("setup failed");
+    let (tau, (p_aux, t_aux)) = common::transform_and_replicate_layers::<Tree, Sha256Hasher>(
+        &pp,
+        &replica_id.into(),
+        (mmapped_data.as_mut()).into(),
+        cache_dir.path().to_path_buf(),
+        replica_path.clone(),
+    );
+    drop(mmapped_data);
+
+    let t_aux = TemporaryAuxCache::<Tree, Sha256Hasher>::new(&t_aux, replica_path, false)
+        .expect("failed to restore contents of t_aux");
+    let priv_inputs = PrivateInputs::<Tree, Sha256Hasher> { p_aux, t_aux };
+
+    let mut pub_inputs = PublicInputs::<
+        <<Tree as MerkleTreeTrait>::Hasher as Hasher>::Domain,
+        <Sha256Hasher as Hasher>::Domain,
+    > {
+        replica_id: replica_id.into(),
+        seed: None,
+        tau: Some(tau),
+        k: None,
+    };
+    if synthetic {
+        // Write the synthetic proofs, from which the porep proofs are read.
+        StackedDrg::<Tree, Sha256Hasher>::prove_all_partitions(
+            &pp,
+            &pub_inputs,
+            &priv_inputs,
+            PARTITIONS,
+        )
+        .expect("failed to generate synthetic proofs");
+    }
+    pub_inputs.seed = Some(rng.gen());
+
+    let proofs = StackedDrg::<Tree, Sha256Hasher>::prove_all_partitions(
+        &pp,
+        &pub_inputs,
+        &priv_inputs,
+        PARTITIONS,
+    )
+    .expect("failed to generate partition proofs");
+    assert!(
+        StackedDrg::<Tree, Sha256Hasher>::verify_all_partitions(&pp, &pub_inputs, &proofs)
+            .expect("failed to verify partition proofs"),
+        "vanilla proofs do not verify"
+    );
+
+    for (k, partition_proofs) in proofs.iter().enumerate() {
+        let expected_challenges = pub_inputs.challenges(&challenges, SECTOR_NODES, Some(k));
+        assert_eq!(
+            expected_challenges.len(),
+            challenges.num_challenges_per_partition()
+        );
+        let proven_challenges: Vec<usize> = partition_proofs
+            .iter()
+            .map(|proof| proof.comm_d_proofs.path_index())
+            .collect();
+        assert_eq!(proven_challenges, expected_challenges, "partition {}", k);
+
+        let mut cs = TestConstraintSystem::<Fr>::new();
+        StackedCompound::<Tree, Sha256Hasher>::circuit(
+            &pub_inputs,
+            (),
+            partition_proofs,
+            &pp,
+            Some(k),
+        )
+        .expect("circuit failed")
+        .synthesize(&mut cs.namespace(|| "stacked drgporep"))
+        .expect("failed to synthesize circuit");
+        assert!(cs.is_satisfied(), "constraints not satisfied");
+
+        let generated_inputs = <StackedCompound<Tree, Sha256Hasher> as CompoundProof<
+            StackedDrg<'_, Tree, Sha256Hasher>,
+            _,
+        >>::generate_public_inputs(&pub_inputs, &pp, Some(k))
+        .expect("failed to generate public inputs");
+        assert!(
+            cs.verify(&generated_inputs),
+            "partition {} circuit inputs do not match the verifier's",
+            k
+        );
+    }
+}
@@ -3,7 +3,8 @@ use std::fs::remove_file;
 use blstrs::Scalar as Fr;
 use ff::{Field, PrimeField};
 use filecoin_hashers::{
-    blake2s::Blake2sHasher, poseidon::PoseidonHasher, sha256::Sha256Hasher, Domain, Hasher,
+    blake2s::Blake2sHasher, blake3::Blake3Hasher, poseidon::PoseidonHasher, sha256::Sha256Hasher,
+    Domain, Hasher,
 };
 use fr32::fr_into_bytes;
 use generic_array::typenum::{U0, U2, U4, U8};
@@ -329,7 +330,22 @@ fn test_prove_verify_fixed(n: usize) {
     test_prove_verify::<DiskTree<PoseidonHasher, U8, U8, U2>>(n, challenges);
 }
 
+#[test]
+fn test_stacked_porep_prove_verify_blake3_tree_d() {
+    test_prove_verify_with_tree_d::<DiskTree<PoseidonHasher, U8, U0, U0>, Blake3Hasher>(
+        64,
+        Challenges::new_interactive(5),
+    );
+}
+
 fn test_prove_verify<Tree: 'static + MerkleTreeTrait>(n: usize, challenges: Challenges) {
+    test_prove_verify_with_tree_d::<Tree, Blake2sHasher>(n, challenges);
+}
+
+fn test_prove_verify_with_tree_d<Tree: 'static + MerkleTreeTrait, G: 'static + Hasher>(
+    n: usize,
+    challenges: Challenges,
+) {
     // This will be called multiple times, only the first one succeeds, and that is ok.
     // femme::pretty::Logger::new()
     //     .start(log::LevelFilter::Trace)
@@ -369,8 +385,8 @@ fn test_prove_verify<Tree: 'static + MerkleTreeTrait>(n: usize, challenges: Chal
         api_features: vec![],
     };
 
-    let pp = StackedDrg::<Tree, Blake2sHasher>::setup(&sp).expect("setup failed");
-    let (tau, (p_aux, t_aux)) = common::transform_and_replicate_layers::<Tree, Blake2sHasher>(
+    let pp = StackedDrg::<Tree, G>::setup(&sp).expect("setup failed");
+    let (tau, (p_aux, t_aux)) = common::transform_and_replicate_layers::<Tree, G>(
         &pp,
         &replica_id,
         (mmapped_data.as_mut()).into(),
@@ -383,35 +399,27 @@ fn test_prove_verify<Tree: 'static + MerkleTreeTrait>(n: usize, challenges: Chal
     assert_ne!(data, copied, "replication did not change data");
 
     let seed = rng.gen();
-    let pub_inputs =
-        PublicInputs::<<Tree::Hasher as Hasher>::Domain, <Blake2sHasher as Hasher>::Domain> {
-            replica_id,
-            seed: Some(seed),
-            tau: Some(tau),
-            k: None,
-        };
+    let pub_inputs = PublicInputs::<<Tree::Hasher as Hasher>::Domain, <G as Hasher>::Domain> {
+        replica_id,
+        seed: Some(seed),
+        tau: Some(tau),
+        k: None,
+    };
 
     // Convert TemporaryAux to TemporaryAuxCache, which instantiates all
     // elements based on the configs stored in TemporaryAux.
-    let t_aux = TemporaryAuxCache::<Tree, Blake2sHasher>::new(&t_aux, replica_path, false)
+    let t_aux = TemporaryAuxCache::<Tree, G>::new(&t_aux, replica_path, false)
         .expect("failed to restore contents of t_aux");
 
     let priv_inputs = PrivateInputs { p_aux, t_aux };
 
-    let all_partition_proofs = &StackedDrg::<Tree, Blake2sHasher>::prove_all_partitions(
-        &pp,
-        &pub_inputs,
-        &priv_inputs,
-        partitions,
-    )
-    .expect("failed to generate partition proofs");
+    let all_partition_proofs =
+        &StackedDrg::<Tree, G>::prove_all_partitions(&pp, &pub_inputs, &priv_inputs, partitions)
+            .expect("failed to generate partition proofs");
 
-    let proofs_are_valid = StackedDrg::<Tree, Blake2sHasher>::verify_all_partitions(
-        &pp,
-        &pub_inputs,
-        all_partition_proofs,
-    )
-    .expect("failed to verify partition proofs");
+    let proofs_are_valid =
+        StackedDrg::<Tree, G>::verify_all_partitions(&pp, &pub_inputs, all_partition_proofs)
+            .expect("failed to verify partition proofs");
 
     // Discard cached MTs that are no longer needed.
     stacked::clear_cache_dir(cache_dir.path()).expect("cached files delete failed");
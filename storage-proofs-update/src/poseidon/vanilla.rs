@@ -1,15 +1,28 @@
+use std::fs::{metadata, remove_file};
 use std::marker::PhantomData;
+use std::path::Path;
 
+use anyhow::{ensure, Context};
+use blstrs::Scalar as Fr;
+use filecoin_hashers::{HashFunction, Hasher};
+use generic_array::typenum::{Unsigned, U0};
+use log::{info, trace};
+use merkletree::{merkle::get_merkle_tree_leafs, store::StoreConfig};
+use rayon::iter::{IndexedParallelIterator, IntoParallelIterator, ParallelIterator};
 use serde::{Deserialize, Serialize};
 use storage_proofs_core::{
     error::Result,
-    merkle::{MerkleProof, MerkleTreeTrait},
+    merkle::{
+        create_base_merkle_tree, create_disk_tree, get_base_tree_count, split_config, DiskTree,
+        MerkleProof, MerkleProofTrait, MerkleTreeTrait,
+    },
     proof::ProofScheme,
+    util::NODE_SIZE,
 };
 
 use crate::{
-    constants::{TreeRDomain, TreeRHasher},
-    PrivateInputs, PublicParams, SetupParams,
+    constants::{hs, TreeDDomain, TreeRDomain, TreeRHasher},
+    phi, rho, Challenges, PrivateInputs, PublicParams, SetupParams,
 };
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -62,6 +75,30 @@ where
     }
 }
 
+impl<TreeR> ChallengeProof<TreeR>
+where
+    TreeR: MerkleTreeTrait<Hasher = TreeRHasher>,
+{
+    pub fn verify_merkle_proofs(
+        &self,
+        c: u32,
+        root_r_old: &TreeRDomain,
+        comm_d_new: &TreeRDomain,
+        root_r_new: &TreeRDomain,
+    ) -> bool {
+        let c = c as usize;
+        self.proof_r_old.path_index() == c
+            && self.proof_d_new.path_index() == c
+            && self.proof_r_new.path_index() == c
+            && self.proof_r_old.root() == *root_r_old
+            && self.proof_d_new.root() == *comm_d_new
+            && self.proof_r_new.root() == *root_r_new
+            && self.proof_r_old.verify()
+            && self.proof_d_new.verify()
+            && self.proof_r_new.verify()
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct PartitionProof<TreeR>
 where
@@ -115,36 +152,186 @@ where
     }
 
     fn prove(
-        _pub_params: &Self::PublicParams,
-        _pub_inputs: &Self::PublicInputs,
-        _priv_inputs: &Self::PrivateInputs,
+        pub_params: &Self::PublicParams,
+        pub_inputs: &Self::PublicInputs,
+        priv_inputs: &Self::PrivateInputs,
     ) -> Result<Self::Proof> {
-        unimplemented!("EmptySectorUpdate-Poseidon vanilla is not yet implemented");
+        let PublicParams { sector_nodes, .. } = *pub_params;
+
+        let PrivateInputs {
+            tree_r_old_config,
+            old_replica_path,
+            tree_d_new_config,
+            tree_r_new_config,
+            replica_path,
+            ..
+        } = priv_inputs;
+
+        ensure!(
+            metadata(old_replica_path)?.is_file(),
+            "old_replica_path must be a file"
+        );
+        ensure!(
+            metadata(replica_path)?.is_file(),
+            "replica_path must be a file"
+        );
+
+        let tree_d_new = Self::instantiate_tree_d(sector_nodes, tree_d_new_config)?;
+        let tree_r_old = crate::EmptySectorUpdate::<TreeR>::instantiate_tree_r(
+            tree_r_old_config,
+            old_replica_path,
+            "TreeROld",
+        )?;
+        let tree_r_new = crate::EmptySectorUpdate::<TreeR>::instantiate_tree_r(
+            tree_r_new_config,
+            replica_path,
+            "TreeRNew",
+        )?;
+
+        info!(
+            "Proving EmptySectorUpdate-Poseidon vanilla proof (sector_nodes={})",
+            sector_nodes,
+        );
+
+        let challenges: Vec<usize> = Challenges::new_poseidon(sector_nodes, pub_inputs.comm_r_new)
+            .map(|c| c as usize)
+            .collect();
+
+        let tree_r_rows_to_discard = Some(tree_r_old_config.rows_to_discard);
+
+        let challenge_proofs = challenges
+            .into_par_iter()
+            .map(|c| {
+                let proof_d_new = tree_d_new.gen_proof(c)?;
+                let proof_r_new = tree_r_new.gen_cached_proof(c, tree_r_rows_to_discard)?;
+                let proof_r_old = tree_r_old.gen_cached_proof(c, tree_r_rows_to_discard)?;
+                ensure!(
+                    proof_d_new.verify(),
+                    "invalid TreeDNew Merkle proof for c={}",
+                    c
+                );
+                ensure!(
+                    proof_r_new.verify(),
+                    "invalid TreeRNew Merkle proof for c={}",
+                    c
+                );
+                ensure!(
+                    proof_r_old.verify(),
+                    "invalid TreeROld Merkle proof for c={}",
+                    c
+                );
+                Ok(ChallengeProof {
+                    proof_r_old,
+                    proof_d_new,
+                    proof_r_new,
+                })
+            })
+            .collect::<Result<Vec<ChallengeProof<TreeR>>>>()?;
+
+        info!("finished generating EmptySectorUpdate-Poseidon challenge-proofs");
+
+        Ok(PartitionProof {
+            comm_c: priv_inputs.comm_c,
+            challenge_proofs,
+        })
     }
 
     fn prove_all_partitions(
-        _pub_params: &Self::PublicParams,
-        _pub_inputs: &Self::PublicInputs,
-        _priv_inputs: &Self::PrivateInputs,
-        _partition_count: usize,
+        pub_params: &Self::PublicParams,
+        pub_inputs: &Self::PublicInputs,
+        priv_inputs: &Self::PrivateInputs,
+        partition_count: usize,
     ) -> Result<Vec<Self::Proof>> {
-        unimplemented!("EmptySectorUpdate-Poseidon vanilla is not yet implemented");
+        ensure!(
+            partition_count == 1,
+            "EmptySectorUpdate-Poseidon proofs are single partition (found: {})",
+            partition_count,
+        );
+        Ok(vec![Self::prove(pub_params, pub_inputs, priv_inputs)?])
     }
 
     fn verify(
-        _pub_params: &Self::PublicParams,
-        _pub_inputs: &Self::PublicInputs,
-        _proof: &Self::Proof,
+        pub_params: &Self::PublicParams,
+        pub_inputs: &Self::PublicInputs,
+        proof: &Self::Proof,
     ) -> Result<bool> {
-        unimplemented!("EmptySectorUpdate-Poseidon vanilla is not yet implemented");
+        let PublicParams {
+            sector_nodes,
+            challenge_count,
+            challenge_bit_len,
+            ..
+        } = *pub_params;
+
+        let PublicInputs {
+            comm_r_old,
+            comm_d_new,
+            comm_r_new,
+            h,
+        } = *pub_inputs;
+
+        // Ensure that public-inputs are valid.
+        ensure!(hs(sector_nodes).contains(&h), "invalid `h` for sector-size");
+
+        let PartitionProof {
+            comm_c,
+            challenge_proofs,
+        } = proof;
+
+        // Check for malformed proof.
+        ensure!(
+            challenge_proofs.len() == challenge_count,
+            "invalid number of challenge proofs"
+        );
+
+        let root_r_old = challenge_proofs[0].proof_r_old.root();
+        let root_r_new = challenge_proofs[0].proof_r_new.root();
+
+        // Verify that the TreeROld and TreeRNew Merkle proofs roots agree with the public CommC,
+        // CommROld, and CommRNew.
+        let comm_r_old_calc = <TreeRHasher as Hasher>::Function::hash2(comm_c, &root_r_old);
+        let comm_r_new_calc = <TreeRHasher as Hasher>::Function::hash2(comm_c, &root_r_new);
+        if comm_r_old_calc != comm_r_old || comm_r_new_calc != comm_r_new {
+            return Ok(false);
+        }
+
+        let phi = phi(&comm_d_new, &comm_r_old);
+
+        let challenges: Vec<u32> = Challenges::new_poseidon(sector_nodes, comm_r_new).collect();
+        let get_high_bits_shr = challenge_bit_len - h;
+
+        let challenge_proofs_are_valid = challenges
+            .into_par_iter()
+            .zip(challenge_proofs.into_par_iter())
+            .all(|(c, challenge_proof)| {
+                // Verify TreeROld, TreeDNew, and TreeRNew Merkle proofs.
+                if !challenge_proof.verify_merkle_proofs(c, &root_r_old, &comm_d_new, &root_r_new) {
+                    return false;
+                }
+
+                // Verify replica encoding.
+                let label_r_old: Fr = challenge_proof.proof_r_old.leaf().into();
+                let label_d_new: Fr = challenge_proof.proof_d_new.leaf().into();
+                let label_r_new = challenge_proof.proof_r_new.leaf();
+                let c_high = c >> get_high_bits_shr;
+                let rho = rho(&phi, c_high);
+                let label_r_new_calc: TreeRDomain = (label_r_old + label_d_new * rho).into();
+                label_r_new_calc == label_r_new
+            });
+
+        Ok(challenge_proofs_are_valid)
     }
 
     fn verify_all_partitions(
-        _pub_params: &Self::PublicParams,
-        _pub_inputs: &Self::PublicInputs,
-        _partition_proofs: &[Self::Proof],
+        pub_params: &Self::PublicParams,
+        pub_inputs: &Self::PublicInputs,
+        partition_proofs: &[Self::Proof],
     ) -> Result<bool> {
-        unimplemented!("EmptySectorUpdate-Poseidon vanilla is not yet implemented");
+        ensure!(
+            partition_proofs.len() == 1,
+            "EmptySectorUpdate-Poseidon proofs are single partition (found: {})",
+            partition_proofs.len(),
+        );
+        Self::verify(pub_params, pub_inputs, &partition_proofs[0])
     }
 
     fn with_partition(pub_inputs: Self::PublicInputs, k: Option<usize>) -> Self::PublicInputs {
@@ -154,3 +341,179 @@ where
         pub_inputs
     }
 }
+
+impl<TreeR> EmptySectorUpdate<TreeR>
+where
+    TreeR: 'static + MerkleTreeTrait<Hasher = TreeRHasher>,
+{
+    /// Instantiates TreeDNew, which has the same shape as TreeR, from its base-trees stored on
+    /// disk. `tree_d_new_config` is the config of a single base-tree, it is split into one config
+    /// per base-tree in the same way as TreeR's config.
+    pub fn instantiate_tree_d(
+        tree_d_leafs: usize,
+        tree_d_new_config: &StoreConfig,
+    ) -> Result<DiskTree<TreeRHasher, TreeR::Arity, TreeR::SubTreeArity, TreeR::TopTreeArity>> {
+        let tree_d_base_arity = TreeR::Arity::to_usize();
+        let tree_d_base_tree_nodes = tree_d_new_config.size.expect("config size failure");
+        let tree_d_base_tree_count = get_base_tree_count::<TreeR>();
+        ensure!(
+            get_merkle_tree_leafs(tree_d_base_tree_nodes, tree_d_base_arity)?
+                * tree_d_base_tree_count
+                == tree_d_leafs,
+            "TreeDNew config size does not match the sector-size"
+        );
+        trace!(
+            "Instantiating TreeDNew: leafs={}, base_tree_count={}, base_store_size={}",
+            tree_d_leafs,
+            tree_d_base_tree_count,
+            tree_d_base_tree_nodes,
+        );
+        let tree_d_configs = split_config(tree_d_new_config.clone(), tree_d_base_tree_count)?;
+        create_disk_tree::<
+            DiskTree<TreeRHasher, TreeR::Arity, TreeR::SubTreeArity, TreeR::TopTreeArity>,
+        >(tree_d_base_tree_nodes, &tree_d_configs)
+        .context("tree_d")
+    }
+
+    /// Builds and persists the base-trees of TreeDNew over `data` and returns TreeDNew's root,
+    /// i.e. `comm_d_new`.
+    pub fn create_tree_d(
+        nodes_count: usize,
+        tree_d_new_config: StoreConfig,
+        data: &[u8],
+    ) -> Result<TreeRDomain> {
+        let tree_d_base_tree_count = get_base_tree_count::<TreeR>();
+        let tree_d_base_tree_leafs = nodes_count / tree_d_base_tree_count;
+        ensure!(
+            data.len() == nodes_count * NODE_SIZE,
+            "data length does not match the sector-size"
+        );
+
+        let tree_d_configs = split_config(tree_d_new_config.clone(), tree_d_base_tree_count)?;
+        for (config, base_tree_data) in tree_d_configs
+            .into_iter()
+            .zip(data.chunks(tree_d_base_tree_leafs * NODE_SIZE))
+        {
+            create_base_merkle_tree::<DiskTree<TreeRHasher, TreeR::Arity, U0, U0>>(
+                Some(config),
+                tree_d_base_tree_leafs,
+                base_tree_data,
+            )?;
+        }
+
+        let tree_d = Self::instantiate_tree_d(nodes_count, &tree_d_new_config)?;
+        Ok(tree_d.root())
+    }
+
+    /// Builds TreeDNew over `data` next to the tree of `tree_d_config` and returns its root,
+    /// without keeping the tree.
+    fn tree_d_root(
+        nodes_count: usize,
+        tree_d_config: &StoreConfig,
+        data: &[u8],
+    ) -> Result<TreeRDomain> {
+        let scratch_config = StoreConfig {
+            id: format!("{}-scratch", tree_d_config.id),
+            ..tree_d_config.clone()
+        };
+        let root = Self::create_tree_d(nodes_count, scratch_config.clone(), data);
+        for config in split_config(scratch_config, get_base_tree_count::<TreeR>())? {
+            let path = StoreConfig::data_path(&config.path, &config.id);
+            if path.exists() {
+                remove_file(&path)
+                    .with_context(|| format!("could not remove scratch tree {:?}", path))?;
+            }
+        }
+        root
+    }
+
+    /// Encodes the staged data into the new replica. In contrast to `EmptySectorUpdate`,
+    /// TreeDNew is a Poseidon tree with the same shape as TreeR.
+    ///
+    /// Returns tuple of (comm_r_new, comm_r_last_new, comm_d_new)
+    #[allow(clippy::too_many_arguments)]
+    pub fn encode_into(
+        nodes_count: usize,
+        tree_d_new_config: StoreConfig,
+        tree_r_last_new_config: StoreConfig,
+        comm_c: TreeRDomain,
+        comm_r_last_old: TreeRDomain,
+        new_replica_path: &Path,
+        sector_key_path: &Path,
+        staged_data_path: &Path,
+        h: usize,
+    ) -> Result<(TreeRDomain, TreeRDomain, TreeRDomain)> {
+        crate::EmptySectorUpdate::<TreeR>::encode_into_with_tree_d(
+            nodes_count,
+            tree_r_last_new_config,
+            comm_c,
+            comm_r_last_old,
+            new_replica_path,
+            sector_key_path,
+            staged_data_path,
+            h,
+            |staged_data| Self::create_tree_d(nodes_count, tree_d_new_config, staged_data),
+        )
+    }
+
+    /// Encodes the data that was already written into the new replica, in place. See
+    /// `EmptySectorUpdate::encode_in_place`.
+    ///
+    /// Returns tuple of (comm_r_new, comm_r_last_new, comm_d_new)
+    #[allow(clippy::too_many_arguments)]
+    pub fn encode_in_place(
+        nodes_count: usize,
+        tree_d_new_config: StoreConfig,
+        tree_r_last_new_config: StoreConfig,
+        comm_c: TreeRDomain,
+        comm_r_last_old: TreeRDomain,
+        new_replica_path: &Path,
+        sector_key_path: &Path,
+        h: usize,
+    ) -> Result<(TreeRDomain, TreeRDomain, TreeRDomain)> {
+        crate::EmptySectorUpdate::<TreeR>::encode_in_place_with_tree_d(
+            nodes_count,
+            tree_r_last_new_config,
+            comm_c,
+            comm_r_last_old,
+            new_replica_path,
+            sector_key_path,
+            h,
+            |staged_data| Self::create_tree_d(nodes_count, tree_d_new_config, staged_data),
+        )
+    }
+
+    /// Re-encodes an already updated replica with new data. See
+    /// `EmptySectorUpdate::reencode_into`.
+    ///
+    /// Returns tuple of (comm_r_new, comm_r_last_new, comm_d_new)
+    #[allow(clippy::too_many_arguments)]
+    pub fn reencode_into(
+        nodes_count: usize,
+        tree_d_new_config: StoreConfig,
+        tree_r_last_new_config: StoreConfig,
+        comm_c: TreeRDomain,
+        comm_r_old: TreeRDomain,
+        comm_d: TreeDDomain,
+        new_replica_path: &Path,
+        replica_path: &Path,
+        data_path: &Path,
+        staged_data_path: &Path,
+        h: usize,
+    ) -> Result<(TreeRDomain, TreeRDomain, TreeRDomain)> {
+        crate::EmptySectorUpdate::<TreeR>::reencode_into_with_tree_d(
+            nodes_count,
+            tree_r_last_new_config,
+            comm_c,
+            comm_r_old,
+            comm_d,
+            new_replica_path,
+            replica_path,
+            data_path,
+            staged_data_path,
+            h,
+            |data| Self::tree_d_root(nodes_count, &tree_d_new_config, data),
+            |staged_data| Self::create_tree_d(nodes_count, tree_d_new_config.clone(), staged_data),
+        )
+    }
+}
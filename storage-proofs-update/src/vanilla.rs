@@ -2,7 +2,7 @@ use std::collections::HashMap;
 use std::fs::{metadata, OpenOptions};
 use std::iter::FromIterator;
 use std::marker::PhantomData;
-use std::ops::RangeInclusive;
+use std::ops::{Range, RangeInclusive};
 use std::path::{Path, PathBuf};
 
 use anyhow::{ensure, Context, Error};
@@ -443,39 +443,12 @@ where
         priv_inputs: &Self::PrivateInputs,
         _partition_count: usize,
     ) -> Result<Vec<Self::Proof>> {
-        let PublicParams {
-            sector_nodes,
-            partition_count,
-            ..
-        } = *pub_params;
-
-        let PrivateInputs {
-            tree_r_old_config,
-            old_replica_path,
-            tree_d_new_config,
-            tree_r_new_config,
-            replica_path,
-            ..
-        } = priv_inputs;
-
-        let tree_d_new = Self::instantiate_tree_d(sector_nodes, tree_d_new_config)?;
-        let tree_r_old = Self::instantiate_tree_r(tree_r_old_config, old_replica_path, "TreeROld")?;
-        let tree_r_new = Self::instantiate_tree_r(tree_r_new_config, replica_path, "TreeRNew")?;
-
-        let vanilla_proofs = (0..partition_count)
-            .into_par_iter()
-            .map(|k| {
-                let pub_inputs = Self::with_partition(pub_inputs.clone(), Some(k));
-                Self::prove_inner(
-                    pub_params,
-                    &pub_inputs,
-                    priv_inputs,
-                    &tree_r_old,
-                    &tree_d_new,
-                    &tree_r_new,
-                )
-            })
-            .collect::<Result<Vec<PartitionProof<TreeR>>>>()?;
+        let vanilla_proofs = Self::prove_partitions(
+            pub_params,
+            pub_inputs,
+            priv_inputs,
+            0..pub_params.partition_count,
+        )?;
 
         info!("Finished generating all partition proofs");
 
@@ -740,6 +713,55 @@ where
         )
     }
 
+    /// Generates the vanilla proofs of the given partitions. The trees are instantiated for each
+    /// call, hence proving a sector in batches of partitions only holds one batch in memory.
+    pub fn prove_partitions(
+        pub_params: &PublicParams,
+        pub_inputs: &PublicInputs,
+        priv_inputs: &PrivateInputs,
+        partitions: Range<usize>,
+    ) -> Result<Vec<PartitionProof<TreeR>>> {
+        let PublicParams {
+            sector_nodes,
+            partition_count,
+            ..
+        } = *pub_params;
+
+        ensure!(
+            partitions.end <= partition_count,
+            "partitions {:?} exceed partition-count for sector-size",
+            partitions
+        );
+
+        let PrivateInputs {
+            tree_r_old_config,
+            old_replica_path,
+            tree_d_new_config,
+            tree_r_new_config,
+            replica_path,
+            ..
+        } = priv_inputs;
+
+        let tree_d_new = Self::instantiate_tree_d(sector_nodes, tree_d_new_config)?;
+        let tree_r_old = Self::instantiate_tree_r(tree_r_old_config, old_replica_path, "TreeROld")?;
+        let tree_r_new = Self::instantiate_tree_r(tree_r_new_config, replica_path, "TreeRNew")?;
+
+        partitions
+            .into_par_iter()
+            .map(|k| {
+                let pub_inputs = Self::with_partition(pub_inputs.clone(), Some(k));
+                Self::prove_inner(
+                    pub_params,
+                    &pub_inputs,
+                    priv_inputs,
+                    &tree_r_old,
+                    &tree_d_new,
+                    &tree_r_new,
+                )
+            })
+            .collect()
+    }
+
     // Generates a partition proof given instantiated trees TreeROld, TreeDNew, and TreeRNew.
     pub fn prove_inner(
         pub_params: &PublicParams,
@@ -881,9 +903,46 @@ where
         staged_data_path: &Path,
         h: usize,
     ) -> Result<(TreeRDomain, TreeRDomain, TreeDDomain)> {
-        let tree_count = get_base_tree_count::<TreeR>();
-        let base_tree_nodes_count = nodes_count / tree_count;
+        Self::encode_into_with_tree_d(
+            nodes_count,
+            tree_r_last_new_config,
+            comm_c,
+            comm_r_last_old,
+            new_replica_path,
+            sector_key_path,
+            staged_data_path,
+            h,
+            |staged_data| {
+                // Generate tree_d over the staged_data.
+                let tree_d = create_base_merkle_tree::<BinaryMerkleTree<TreeDHasher>>(
+                    Some(tree_d_new_config),
+                    nodes_count,
+                    staged_data,
+                )?;
+                Ok(tree_d.root())
+            },
+        )
+    }
 
+    /// Encodes the staged data into the new replica, where `build_tree_d` builds TreeDNew over
+    /// the staged data and returns its root, i.e. `comm_d_new`.
+    ///
+    /// Returns tuple of (comm_r_new, comm_r_last_new, comm_d_new)
+    pub(crate) fn encode_into_with_tree_d<D, F>(
+        nodes_count: usize,
+        tree_r_last_new_config: StoreConfig,
+        comm_c: TreeRDomain,
+        comm_r_last_old: TreeRDomain,
+        new_replica_path: &Path,
+        sector_key_path: &Path,
+        staged_data_path: &Path,
+        h: usize,
+        build_tree_d: F,
+    ) -> Result<(TreeRDomain, TreeRDomain, D)>
+    where
+        D: Domain,
+        F: FnOnce(&[u8]) -> Result<D>,
+    {
         let new_replica_path_metadata = metadata(new_replica_path)?;
         let sector_key_path_metadata = metadata(sector_key_path)?;
         let staged_data_path_metadata = metadata(staged_data_path)?;
@@ -936,19 +995,154 @@ where
         let mut new_data = Data::from_path(staged_data_path.to_path_buf());
         new_data.ensure_data_of_len(sector_key_path_metadata.len() as usize)?;
 
-        // Generate tree_d over the staged_data.
-        let tree_d = create_base_merkle_tree::<BinaryMerkleTree<TreeDHasher>>(
-            Some(tree_d_new_config),
-            tree_count * base_tree_nodes_count,
-            new_data.as_ref(),
+        let comm_d_new = build_tree_d(new_data.as_ref())?;
+
+        let (comm_r_new, comm_r_last_new) = Self::encode_replica(
+            nodes_count,
+            tree_r_last_new_config,
+            comm_c,
+            comm_r_last_old,
+            &comm_d_new,
+            new_replica_path,
+            &mut new_replica_data,
+            &sector_key_data,
+            Some(&staged_data),
+            h,
         )?;
 
-        let comm_d_new = tree_d.root();
+        Ok((comm_r_new, comm_r_last_new, comm_d_new))
+    }
 
-        let comm_r_old = <TreeRHasher as Hasher>::Function::hash2(&comm_c, &comm_r_last_old);
-        let phi = phi(&comm_d_new, &comm_r_old);
+    /// Encodes the data that was already written (fr32-padded) into the new replica, in place.
+    /// The new replica file is thus used as the staging area of the data, so that no separate
+    /// staged data file needs to be written and read. All of the data needs to be known before
+    /// the encoding can start, as it's randomized by `comm_d_new`.
+    ///
+    /// Returns tuple of (comm_r_new, comm_r_last_new, comm_d_new)
+    #[allow(clippy::too_many_arguments)]
+    pub fn encode_in_place(
+        nodes_count: usize,
+        tree_d_new_config: StoreConfig,
+        tree_r_last_new_config: StoreConfig,
+        comm_c: TreeRDomain,
+        comm_r_last_old: TreeRDomain,
+        new_replica_path: &Path,
+        sector_key_path: &Path,
+        h: usize,
+    ) -> Result<(TreeRDomain, TreeRDomain, TreeDDomain)> {
+        Self::encode_in_place_with_tree_d(
+            nodes_count,
+            tree_r_last_new_config,
+            comm_c,
+            comm_r_last_old,
+            new_replica_path,
+            sector_key_path,
+            h,
+            |staged_data| {
+                let tree_d = create_base_merkle_tree::<BinaryMerkleTree<TreeDHasher>>(
+                    Some(tree_d_new_config),
+                    nodes_count,
+                    staged_data,
+                )?;
+                Ok(tree_d.root())
+            },
+        )
+    }
+
+    /// Same as [`Self::encode_into_with_tree_d`], but the staged data is read from the new
+    /// replica, which is then encoded in place.
+    ///
+    /// Returns tuple of (comm_r_new, comm_r_last_new, comm_d_new)
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn encode_in_place_with_tree_d<D, F>(
+        nodes_count: usize,
+        tree_r_last_new_config: StoreConfig,
+        comm_c: TreeRDomain,
+        comm_r_last_old: TreeRDomain,
+        new_replica_path: &Path,
+        sector_key_path: &Path,
+        h: usize,
+        build_tree_d: F,
+    ) -> Result<(TreeRDomain, TreeRDomain, D)>
+    where
+        D: Domain,
+        F: FnOnce(&[u8]) -> Result<D>,
+    {
+        let new_replica_path_metadata = metadata(new_replica_path)?;
+        let sector_key_path_metadata = metadata(sector_key_path)?;
 
-        let end = staged_data_path_metadata.len();
+        ensure!(
+            new_replica_path_metadata.is_file(),
+            "new_replica_path must be a file"
+        );
+        ensure!(
+            sector_key_path_metadata.is_file(),
+            "sector_key_path must be a file"
+        );
+        ensure!(
+            new_replica_path_metadata.len() == sector_key_path_metadata.len(),
+            "New replica and sector key file size mis-match (must be equal)"
+        );
+        ensure!(
+            new_replica_path_metadata.len() == (nodes_count * FR_SIZE) as u64,
+            "New replica must be exactly {} bytes",
+            nodes_count * FR_SIZE
+        );
+
+        info!(
+            "new replica path {:?}, len {}",
+            new_replica_path,
+            new_replica_path_metadata.len()
+        );
+        info!(
+            "sector key path {:?}, len {}",
+            sector_key_path,
+            sector_key_path_metadata.len()
+        );
+
+        let sector_key_data = mmap_read(sector_key_path)?;
+        let mut new_replica_data = mmap_write(new_replica_path)?;
+
+        let comm_d_new = build_tree_d(&new_replica_data)?;
+
+        let (comm_r_new, comm_r_last_new) = Self::encode_replica(
+            nodes_count,
+            tree_r_last_new_config,
+            comm_c,
+            comm_r_last_old,
+            &comm_d_new,
+            new_replica_path,
+            &mut new_replica_data,
+            &sector_key_data,
+            None,
+            h,
+        )?;
+
+        Ok((comm_r_new, comm_r_last_new, comm_d_new))
+    }
+
+    // Encodes the staged data into the new replica and builds TreeRLast over it. If no
+    // `staged_data` is given, it is read from the new replica itself, i.e. encoded in place.
+    //
+    // Returns tuple of (comm_r_new, comm_r_last_new)
+    #[allow(clippy::too_many_arguments)]
+    fn encode_replica<D: Domain>(
+        nodes_count: usize,
+        tree_r_last_new_config: StoreConfig,
+        comm_c: TreeRDomain,
+        comm_r_last_old: TreeRDomain,
+        comm_d_new: &D,
+        new_replica_path: &Path,
+        new_replica_data: &mut MmapMut,
+        sector_key_data: &[u8],
+        staged_data: Option<&[u8]>,
+        h: usize,
+    ) -> Result<(TreeRDomain, TreeRDomain)> {
+        let tree_count = get_base_tree_count::<TreeR>();
+        let base_tree_nodes_count = nodes_count / tree_count;
+
+        let comm_r_old = <TreeRHasher as Hasher>::Function::hash2(&comm_c, &comm_r_last_old);
+        let phi = phi(comm_d_new, &comm_r_old);
 
         // chunk_size is the number of Fr elements to process in parallel chunks.
         let chunk_size: usize = std::cmp::min(base_tree_nodes_count, CHUNK_SIZE_MIN);
@@ -960,13 +1154,14 @@ where
         // Precompute all rho values.
         let rhos = Rhos::new(&phi, h, nodes_count);
 
-        Vec::from_iter((0..end).step_by(data_block_size))
-            .into_par_iter()
-            .zip(new_replica_data.par_chunks_mut(data_block_size))
-            .try_for_each(|(chunk_index, replica_data)| -> Result<()> {
-                for i in (0..data_block_size as u64).step_by(FR_SIZE) {
-                    let input_index = (chunk_index as usize) + i as usize;
-                    let output_index = i as usize;
+        new_replica_data
+            .par_chunks_mut(data_block_size)
+            .enumerate()
+            .try_for_each(|(block_index, replica_data)| -> Result<()> {
+                let chunk_index = block_index * data_block_size;
+                for i in (0..replica_data.len()).step_by(FR_SIZE) {
+                    let input_index = chunk_index + i;
+                    let output_index = i;
 
                     // Get the `h` high bits from the node-index.
                     let node_index = input_index / FR_SIZE;
@@ -974,8 +1169,12 @@ where
 
                     let sector_key_fr =
                         bytes_into_fr(&sector_key_data[input_index..input_index + FR_SIZE])?;
-                    let staged_data_fr =
-                        bytes_into_fr(&staged_data[input_index..input_index + FR_SIZE])?;
+                    let staged_data_fr = match staged_data {
+                        Some(staged_data) => {
+                            bytes_into_fr(&staged_data[input_index..input_index + FR_SIZE])?
+                        }
+                        None => bytes_into_fr(&replica_data[output_index..output_index + FR_SIZE])?,
+                    };
 
                     let new_replica_fr = sector_key_fr + (staged_data_fr * rho);
                     fr_into_bytes_slice(
@@ -988,9 +1187,31 @@ where
             })?;
         new_replica_data.flush()?;
 
+        Self::build_tree_r_last(
+            nodes_count,
+            tree_r_last_new_config,
+            comm_c,
+            new_replica_path,
+            new_replica_data,
+        )
+    }
+
+    // Builds TreeRLast over the new replica.
+    //
+    // Returns tuple of (comm_r_new, comm_r_last_new)
+    fn build_tree_r_last(
+        nodes_count: usize,
+        tree_r_last_new_config: StoreConfig,
+        comm_c: TreeRDomain,
+        new_replica_path: &Path,
+        new_replica_data: &[u8],
+    ) -> Result<(TreeRDomain, TreeRDomain)> {
+        let tree_count = get_base_tree_count::<TreeR>();
+        let base_tree_nodes_count = nodes_count / tree_count;
+
         // Open the new written replica data as a DiskStore.
         let new_replica_store: DiskStore<TreeRDomain> =
-            DiskStore::new_from_slice(nodes_count, &new_replica_data[0..])?;
+            DiskStore::new_from_slice(nodes_count, new_replica_data)?;
 
         // This argument is currently unused by this invocation, but required for the API.
         let mut unused_data = Data::empty();
@@ -1008,6 +1229,194 @@ where
         let comm_r_last_new = tree_r_last.root();
         let comm_r_new = <TreeRHasher as Hasher>::Function::hash2(&comm_c, &comm_r_last_new);
 
+        Ok((comm_r_new, comm_r_last_new))
+    }
+
+    /// Re-encodes an already updated replica with new data, i.e. it replaces the data the
+    /// replica currently encodes, without the need of the sector key. Each node of the new
+    /// replica is `replica - data * rho + new_data * rho_new`, where `data` is the currently
+    /// encoded data (committed to by `comm_d`) and `rho`, `rho_new` are derived from `comm_d` and
+    /// `comm_d_new` respectively. The result is the same as encoding the new data into the sector
+    /// key.
+    ///
+    /// Returns tuple of (comm_r_new, comm_r_last_new, comm_d_new)
+    #[allow(clippy::too_many_arguments)]
+    pub fn reencode_into(
+        nodes_count: usize,
+        tree_d_new_config: StoreConfig,
+        tree_r_last_new_config: StoreConfig,
+        comm_c: TreeRDomain,
+        comm_r_old: TreeRDomain,
+        comm_d: TreeDDomain,
+        new_replica_path: &Path,
+        replica_path: &Path,
+        data_path: &Path,
+        staged_data_path: &Path,
+        h: usize,
+    ) -> Result<(TreeRDomain, TreeRDomain, TreeDDomain)> {
+        Self::reencode_into_with_tree_d(
+            nodes_count,
+            tree_r_last_new_config,
+            comm_c,
+            comm_r_old,
+            comm_d,
+            new_replica_path,
+            replica_path,
+            data_path,
+            staged_data_path,
+            h,
+            |data| {
+                let tree_d = create_base_merkle_tree::<BinaryMerkleTree<TreeDHasher>>(
+                    None,
+                    nodes_count,
+                    data,
+                )?;
+                Ok(tree_d.root())
+            },
+            |staged_data| {
+                let tree_d = create_base_merkle_tree::<BinaryMerkleTree<TreeDHasher>>(
+                    Some(tree_d_new_config),
+                    nodes_count,
+                    staged_data,
+                )?;
+                Ok(tree_d.root())
+            },
+        )
+    }
+
+    /// Same as [`Self::reencode_into`], where `build_tree_d` builds TreeDNew over the staged data
+    /// and returns its root, i.e. `comm_d_new`. `data_tree_d_root` returns the root of the same
+    /// kind of TreeD over the data the replica currently encodes, which must match `comm_d`.
+    ///
+    /// Returns tuple of (comm_r_new, comm_r_last_new, comm_d_new)
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn reencode_into_with_tree_d<D, G, F>(
+        nodes_count: usize,
+        tree_r_last_new_config: StoreConfig,
+        comm_c: TreeRDomain,
+        comm_r_old: TreeRDomain,
+        comm_d: TreeDDomain,
+        new_replica_path: &Path,
+        replica_path: &Path,
+        data_path: &Path,
+        staged_data_path: &Path,
+        h: usize,
+        data_tree_d_root: G,
+        build_tree_d: F,
+    ) -> Result<(TreeRDomain, TreeRDomain, D)>
+    where
+        D: Domain,
+        G: FnOnce(&[u8]) -> Result<D>,
+        F: FnOnce(&[u8]) -> Result<D>,
+    {
+        let sector_bytes = (nodes_count * FR_SIZE) as u64;
+
+        let new_replica_path_metadata = metadata(new_replica_path)?;
+        let replica_path_metadata = metadata(replica_path)?;
+        let data_path_metadata = metadata(data_path)?;
+        let staged_data_path_metadata = metadata(staged_data_path)?;
+
+        ensure!(
+            new_replica_path_metadata.is_file(),
+            "new_replica_path must be a file"
+        );
+        ensure!(
+            replica_path_metadata.is_file(),
+            "replica_path must be a file"
+        );
+        ensure!(data_path_metadata.is_file(), "data_path must be a file");
+        ensure!(
+            staged_data_path_metadata.is_file(),
+            "staged_data_path must be a file"
+        );
+        ensure!(
+            new_replica_path_metadata.len() == sector_bytes
+                && replica_path_metadata.len() == sector_bytes,
+            "New replica and replica must be exactly {} bytes",
+            sector_bytes
+        );
+        ensure!(
+            data_path_metadata.len() >= sector_bytes
+                && staged_data_path_metadata.len() >= sector_bytes,
+            "Data and staged data must be at least {} bytes",
+            sector_bytes
+        );
+
+        info!(
+            "new replica path {:?}, len {}",
+            new_replica_path,
+            new_replica_path_metadata.len()
+        );
+        info!(
+            "replica path {:?}, len {}",
+            replica_path,
+            replica_path_metadata.len()
+        );
+        info!(
+            "data path {:?}, len {}",
+            data_path,
+            data_path_metadata.len()
+        );
+        info!(
+            "staged data path {:?}, len {}",
+            staged_data_path,
+            staged_data_path_metadata.len()
+        );
+
+        let replica_data = mmap_read(replica_path)?;
+        let data = mmap_read(data_path)?;
+        let staged_data = mmap_read(staged_data_path)?;
+        let mut new_replica_data = mmap_write(new_replica_path)?;
+
+        // Removing data other than the one that is encoded would corrupt the new replica.
+        let computed_comm_d = data_tree_d_root(&data[..sector_bytes as usize])?;
+        ensure!(
+            computed_comm_d.into_bytes() == comm_d.into_bytes(),
+            "data does not match comm_d"
+        );
+
+        let comm_d_new = build_tree_d(&staged_data[..sector_bytes as usize])?;
+
+        let tree_count = get_base_tree_count::<TreeR>();
+        let base_tree_nodes_count = nodes_count / tree_count;
+        let chunk_size: usize = std::cmp::min(base_tree_nodes_count, CHUNK_SIZE_MIN);
+        let data_block_size: usize = chunk_size * FR_SIZE;
+
+        let rhos = Rhos::new(&phi(&comm_d, &comm_r_old), h, nodes_count);
+        let rhos_new = Rhos::new(&phi(&comm_d_new, &comm_r_old), h, nodes_count);
+
+        new_replica_data
+            .par_chunks_mut(data_block_size)
+            .enumerate()
+            .try_for_each(|(block_index, new_replica_block)| -> Result<()> {
+                let chunk_index = block_index * data_block_size;
+                for i in (0..new_replica_block.len()).step_by(FR_SIZE) {
+                    let input_index = chunk_index + i;
+                    let node_index = input_index / FR_SIZE;
+
+                    let replica_fr =
+                        bytes_into_fr(&replica_data[input_index..input_index + FR_SIZE])?;
+                    let data_fr = bytes_into_fr(&data[input_index..input_index + FR_SIZE])?;
+                    let staged_data_fr =
+                        bytes_into_fr(&staged_data[input_index..input_index + FR_SIZE])?;
+
+                    let new_replica_fr = replica_fr - (data_fr * rhos.get(node_index))
+                        + (staged_data_fr * rhos_new.get(node_index));
+                    fr_into_bytes_slice(&new_replica_fr, &mut new_replica_block[i..i + FR_SIZE]);
+                }
+
+                Ok(())
+            })?;
+        new_replica_data.flush()?;
+
+        let (comm_r_new, comm_r_last_new) = Self::build_tree_r_last(
+            nodes_count,
+            tree_r_last_new_config,
+            comm_c,
+            new_replica_path,
+            &new_replica_data,
+        )?;
+
         Ok((comm_r_new, comm_r_last_new, comm_d_new))
     }
 
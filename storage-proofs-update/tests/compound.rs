@@ -22,7 +22,7 @@ use storage_proofs_update::{
         TreeRDomain, TreeRHasher, SECTOR_SIZE_16_KIB, SECTOR_SIZE_1_KIB, SECTOR_SIZE_2_KIB,
         SECTOR_SIZE_32_KIB, SECTOR_SIZE_4_KIB, SECTOR_SIZE_8_KIB,
     },
-    phi, EmptySectorUpdateCompound, PrivateInputs, PublicInputs, SetupParams,
+    phi, EmptySectorUpdate, EmptySectorUpdateCompound, PrivateInputs, PublicInputs, SetupParams,
 };
 use tempfile::tempdir;
 
@@ -230,6 +230,40 @@ where
     .expect("failed while verifying");
 
     assert!(is_valid);
+
+    // Proving one partition at a time results in an equally valid proof.
+    let mut batched_proofs = Vec::with_capacity(partition_count);
+    for k in 0..partition_count {
+        let vanilla_proofs = EmptySectorUpdate::<TreeR<U, V, W>>::prove_partitions(
+            &pub_params_compound.vanilla_params,
+            &pub_inputs,
+            &priv_inputs,
+            k..k + 1,
+        )
+        .expect("failed while proving partition");
+        batched_proofs.extend(
+            EmptySectorUpdateCompound::<TreeR<U, V, W>>::circuit_proofs_for_partitions(
+                &pub_inputs,
+                vanilla_proofs,
+                k,
+                &pub_params_compound.vanilla_params,
+                &blank_groth_params,
+                true,
+            )
+            .expect("failed while proving partition circuit"),
+        );
+    }
+    let multi_proof = MultiProof::new(batched_proofs, &prepared_verifying_key);
+
+    let is_valid = EmptySectorUpdateCompound::<TreeR<U, V, W>>::verify(
+        &pub_params_compound,
+        &pub_inputs,
+        &multi_proof,
+        &(),
+    )
+    .expect("failed while verifying");
+
+    assert!(is_valid);
 }
 
 #[test]
@@ -0,0 +1,327 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use bellperson::groth16;
+use filecoin_hashers::{Domain, HashFunction, Hasher, PoseidonArity};
+use generic_array::typenum::{U0, U2, U4, U8};
+use merkletree::{merkle::get_merkle_tree_len, store::StoreConfig};
+use rand::SeedableRng;
+use rand_xorshift::XorShiftRng;
+use storage_proofs_core::{
+    compound_proof::{self, CompoundProof},
+    merkle::{
+        create_lc_tree, get_base_tree_count, split_config_and_replica, LCTree, MerkleTreeTrait,
+    },
+    multi_proof::MultiProof,
+    proof::ProofScheme,
+    util::default_rows_to_discard,
+    TEST_SEED,
+};
+use storage_proofs_update::{
+    constants::{
+        hs, validate_tree_r_shape, TreeRBaseTree, TreeRDomain, TreeRHasher, SECTOR_SIZE_16_KIB,
+        SECTOR_SIZE_1_KIB, SECTOR_SIZE_2_KIB, SECTOR_SIZE_32_KIB, SECTOR_SIZE_4_KIB,
+        SECTOR_SIZE_8_KIB,
+    },
+    poseidon::{
+        vanilla::{PartitionProof, PublicInputs},
+        EmptySectorUpdate, EmptySectorUpdateCompound,
+    },
+    PrivateInputs, PublicParams, SetupParams,
+};
+use tempfile::{tempdir, TempDir};
+
+const HS_INDEX: usize = 2;
+
+type TreeR<U, V, W> = LCTree<TreeRHasher, U, V, W>;
+
+struct EncodedSector {
+    _tmp_dir: TempDir,
+    pub_inputs: PublicInputs,
+    priv_inputs: PrivateInputs,
+}
+
+fn write_random_nodes(path: &Path, sector_nodes: usize, rng: &mut XorShiftRng) -> Vec<u8> {
+    let data: Vec<u8> = (0..sector_nodes)
+        .flat_map(|_| TreeRDomain::random(rng).into_bytes())
+        .collect();
+    fs::write(path, &data).expect("failed to write data to disk");
+    data
+}
+
+// Creates a random sector key with its TreeROld, then encodes random staged data into a new
+// replica using `EmptySectorUpdate-Poseidon` encoding.
+fn encode_sector<U, V, W>(sector_nodes: usize) -> EncodedSector
+where
+    U: PoseidonArity,
+    V: PoseidonArity,
+    W: PoseidonArity,
+{
+    validate_tree_r_shape::<TreeR<U, V, W>>(sector_nodes);
+
+    let base_arity = U::to_usize();
+
+    let base_tree_count = get_base_tree_count::<TreeR<U, V, W>>();
+    let leafs_per_base_tree = sector_nodes / base_tree_count;
+    let base_tree_leafs_byte_len = leafs_per_base_tree << 5;
+    let tree_r_rows_to_discard = default_rows_to_discard(leafs_per_base_tree, base_arity);
+    // Total number of nodes in each base-tree of TreeR and TreeD.
+    let base_tree_nodes = get_merkle_tree_len(leafs_per_base_tree, base_arity).unwrap();
+
+    let h = hs(sector_nodes)[HS_INDEX];
+
+    let mut rng = XorShiftRng::from_seed(TEST_SEED);
+    let comm_c = TreeRDomain::random(&mut rng);
+
+    // Merkle tree storage directory.
+    let tmp_dir = tempdir().unwrap();
+    let tmp_path = tmp_dir.path();
+
+    // Create random old replica, i.e. the sector key, and its TreeROld.
+    let replica_old_path = tmp_path.join("replica_old");
+    let replica_old = write_random_nodes(&replica_old_path, sector_nodes, &mut rng);
+    let tree_r_old_config = StoreConfig {
+        path: tmp_path.into(),
+        id: "tree-r-old".to_string(),
+        size: Some(base_tree_nodes),
+        rows_to_discard: tree_r_rows_to_discard,
+    };
+    let (tree_r_old_configs, replica_old_config) = split_config_and_replica(
+        tree_r_old_config.clone(),
+        replica_old_path.clone(),
+        leafs_per_base_tree,
+        base_tree_count,
+    )
+    .expect("failed to split store config");
+    for (base_tree_config, leafs_offset) in tree_r_old_configs
+        .iter()
+        .zip(replica_old_config.offsets.iter().copied())
+    {
+        let leafs = &replica_old[leafs_offset..leafs_offset + base_tree_leafs_byte_len];
+        let _base_tree =
+            TreeRBaseTree::from_byte_slice_with_config(leafs, base_tree_config.clone())
+                .expect("failed to create base-tree");
+    }
+    let tree_r_old =
+        create_lc_tree::<TreeR<U, V, W>>(base_tree_nodes, &tree_r_old_configs, &replica_old_config)
+            .expect("failed to create TreeROld");
+    let root_r_old = tree_r_old.root();
+    let comm_r_old = <TreeRHasher as Hasher>::Function::hash2(&comm_c, &root_r_old);
+
+    // Create random staged data and an empty new replica of the same length.
+    let staged_data_path = tmp_path.join("staged_data");
+    write_random_nodes(&staged_data_path, sector_nodes, &mut rng);
+    let replica_new_path: PathBuf = tmp_path.join("replica_new");
+    fs::write(&replica_new_path, vec![0u8; sector_nodes << 5])
+        .expect("failed to write replica_new to disk");
+
+    let tree_d_new_config = StoreConfig {
+        path: tmp_path.into(),
+        id: "tree-d-new".to_string(),
+        size: Some(base_tree_nodes),
+        rows_to_discard: 0,
+    };
+    let tree_r_new_config = StoreConfig {
+        path: tmp_path.into(),
+        id: "tree-r-new".to_string(),
+        size: Some(base_tree_nodes),
+        rows_to_discard: tree_r_rows_to_discard,
+    };
+
+    let (comm_r_new, _comm_r_last_new, comm_d_new) =
+        EmptySectorUpdate::<TreeR<U, V, W>>::encode_into(
+            sector_nodes,
+            tree_d_new_config.clone(),
+            tree_r_new_config.clone(),
+            comm_c,
+            root_r_old,
+            &replica_new_path,
+            &replica_old_path,
+            &staged_data_path,
+            h,
+        )
+        .expect("failed to encode new replica");
+
+    let tree_d_new =
+        EmptySectorUpdate::<TreeR<U, V, W>>::instantiate_tree_d(sector_nodes, &tree_d_new_config)
+            .expect("failed to instantiate TreeDNew");
+    assert_eq!(tree_d_new.root(), comm_d_new);
+
+    EncodedSector {
+        _tmp_dir: tmp_dir,
+        pub_inputs: PublicInputs {
+            comm_r_old,
+            comm_d_new,
+            comm_r_new,
+            h,
+        },
+        priv_inputs: PrivateInputs {
+            comm_c,
+            tree_r_old_config,
+            old_replica_path: replica_old_path,
+            tree_d_new_config,
+            tree_r_new_config,
+            replica_path: replica_new_path,
+        },
+    }
+}
+
+fn test_empty_sector_update_poseidon_vanilla<U, V, W>(sector_nodes: usize)
+where
+    U: PoseidonArity,
+    V: PoseidonArity,
+    W: PoseidonArity,
+{
+    let EncodedSector {
+        _tmp_dir,
+        pub_inputs,
+        priv_inputs,
+    } = encode_sector::<U, V, W>(sector_nodes);
+
+    let pub_params = PublicParams::from_sector_size_poseidon((sector_nodes as u64) << 5);
+
+    let proofs = EmptySectorUpdate::<TreeR<U, V, W>>::prove_all_partitions(
+        &pub_params,
+        &pub_inputs,
+        &priv_inputs,
+        1,
+    )
+    .expect("failed while proving");
+    assert_eq!(proofs.len(), 1);
+    assert_eq!(proofs[0].challenge_proofs.len(), pub_params.challenge_count);
+
+    let is_valid = EmptySectorUpdate::<TreeR<U, V, W>>::verify_all_partitions(
+        &pub_params,
+        &pub_inputs,
+        &proofs,
+    )
+    .expect("failed while verifying");
+    assert!(is_valid);
+
+    // A proof must not verify against a different CommDNew.
+    let mut wrong_pub_inputs = pub_inputs.clone();
+    wrong_pub_inputs.comm_d_new = wrong_pub_inputs.comm_r_new;
+    let is_valid =
+        EmptySectorUpdate::<TreeR<U, V, W>>::verify(&pub_params, &wrong_pub_inputs, &proofs[0])
+            .expect("failed while verifying");
+    assert!(!is_valid);
+
+    // A proof with a tampered CommC must not verify.
+    let mut wrong_proof: PartitionProof<TreeR<U, V, W>> = proofs[0].clone();
+    wrong_proof.comm_c = pub_inputs.comm_d_new;
+    let is_valid =
+        EmptySectorUpdate::<TreeR<U, V, W>>::verify(&pub_params, &pub_inputs, &wrong_proof)
+            .expect("failed while verifying");
+    assert!(!is_valid);
+}
+
+fn test_empty_sector_update_poseidon_compound<U, V, W>(sector_nodes: usize)
+where
+    U: PoseidonArity,
+    V: PoseidonArity,
+    W: PoseidonArity,
+{
+    let EncodedSector {
+        _tmp_dir,
+        pub_inputs,
+        priv_inputs,
+    } = encode_sector::<U, V, W>(sector_nodes);
+
+    let mut rng = XorShiftRng::from_seed(TEST_SEED);
+
+    // Setup compound-proof.
+    let setup_params_compound = compound_proof::SetupParams {
+        vanilla_params: SetupParams {
+            sector_bytes: (sector_nodes as u64) << 5,
+        },
+        partitions: Some(1),
+        priority: true,
+    };
+    let pub_params_compound =
+        EmptySectorUpdateCompound::<TreeR<U, V, W>>::setup(&setup_params_compound).unwrap();
+
+    let blank_groth_params = EmptySectorUpdateCompound::<TreeR<U, V, W>>::groth_params(
+        Some(&mut rng),
+        &pub_params_compound.vanilla_params,
+    )
+    .expect("failed to generate groth params");
+
+    let proofs = EmptySectorUpdateCompound::<TreeR<U, V, W>>::prove(
+        &pub_params_compound,
+        &pub_inputs,
+        &priv_inputs,
+        &blank_groth_params,
+    )
+    .expect("failed while proving");
+
+    // Don't try to generate the groth parameters, as they should already have been generated by
+    // the `groth_params()` call above.
+    let verifying_key = EmptySectorUpdateCompound::<TreeR<U, V, W>>::verifying_key::<XorShiftRng>(
+        None,
+        &pub_params_compound.vanilla_params,
+    )
+    .expect("failed to get veriyfing key");
+    let prepared_verifying_key = groth16::prepare_verifying_key(&verifying_key);
+    let multi_proof = MultiProof::new(proofs, &prepared_verifying_key);
+
+    let is_valid = EmptySectorUpdateCompound::<TreeR<U, V, W>>::verify(
+        &pub_params_compound,
+        &pub_inputs,
+        &multi_proof,
+        &(),
+    )
+    .expect("failed while verifying");
+
+    assert!(is_valid);
+}
+
+#[test]
+fn test_empty_sector_update_poseidon_vanilla_1kib() {
+    test_empty_sector_update_poseidon_vanilla::<U8, U4, U0>(SECTOR_SIZE_1_KIB);
+}
+
+#[test]
+fn test_empty_sector_update_poseidon_vanilla_2kib() {
+    test_empty_sector_update_poseidon_vanilla::<U8, U0, U0>(SECTOR_SIZE_2_KIB);
+}
+
+#[test]
+fn test_empty_sector_update_poseidon_vanilla_32kib() {
+    test_empty_sector_update_poseidon_vanilla::<U8, U8, U2>(SECTOR_SIZE_32_KIB);
+}
+
+#[test]
+#[ignore]
+fn test_empty_sector_update_poseidon_compound_1kib() {
+    test_empty_sector_update_poseidon_compound::<U8, U4, U0>(SECTOR_SIZE_1_KIB);
+}
+
+#[test]
+#[ignore]
+fn test_empty_sector_update_poseidon_compound_2kib() {
+    test_empty_sector_update_poseidon_compound::<U8, U0, U0>(SECTOR_SIZE_2_KIB);
+}
+
+#[test]
+#[ignore]
+fn test_empty_sector_update_poseidon_compound_4kib() {
+    test_empty_sector_update_poseidon_compound::<U8, U2, U0>(SECTOR_SIZE_4_KIB);
+}
+
+#[test]
+#[ignore]
+fn test_empty_sector_update_poseidon_compound_8kib() {
+    test_empty_sector_update_poseidon_compound::<U8, U4, U0>(SECTOR_SIZE_8_KIB);
+}
+
+#[test]
+#[ignore]
+fn test_empty_sector_update_poseidon_compound_16kib() {
+    test_empty_sector_update_poseidon_compound::<U8, U8, U0>(SECTOR_SIZE_16_KIB);
+}
+
+#[test]
+#[ignore]
+fn test_empty_sector_update_poseidon_compound_32kib() {
+    test_empty_sector_update_poseidon_compound::<U8, U8, U2>(SECTOR_SIZE_32_KIB);
+}